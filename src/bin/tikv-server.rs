@@ -312,6 +312,36 @@ fn build_cfg(matches: &Matches, config: &toml::Value, cluster_id: u64, addr: &st
                           config,
                           Some(128 * 1024),
                           |v| v.as_integer()) as usize;
+    cfg.snap_chunk_size = get_integer_value("",
+                                            "server.snap-chunk-size",
+                                            matches,
+                                            config,
+                                            Some(4 * 1024 * 1024),
+                                            |v| v.as_integer()) as usize;
+    cfg.end_point_max_scan_keys = get_integer_value("",
+                                                    "server.end-point-max-scan-keys",
+                                                    matches,
+                                                    config,
+                                                    Some(1024 * 1024),
+                                                    |v| v.as_integer()) as usize;
+    cfg.end_point_concurrency = get_integer_value("",
+                                                  "server.end-point-concurrency",
+                                                  matches,
+                                                  config,
+                                                  Some(8),
+                                                  |v| v.as_integer()) as usize;
+    cfg.end_point_max_tasks_per_worker =
+        get_integer_value("",
+                          "server.end-point-max-tasks-per-worker",
+                          matches,
+                          config,
+                          Some(256),
+                          |v| v.as_integer()) as usize;
+
+    cfg.strict_batch_keys = config.lookup("server.strict-batch-keys")
+        .unwrap_or(&toml::Value::Boolean(false))
+        .as_bool()
+        .unwrap_or(false);
 
     cfg.store_cfg.notify_capacity =
         get_integer_value("",
@@ -348,6 +378,13 @@ fn build_cfg(matches: &Matches, config: &toml::Value, cluster_id: u64, addr: &st
                           config,
                           Some(8 * 1024 * 1024),
                           |v| v.as_integer()) as u64;
+    cfg.store_cfg.split_check_concurrency =
+        get_integer_value("",
+                          "raftstore.split-check-concurrency",
+                          matches,
+                          config,
+                          Some(1),
+                          |v| v.as_integer()) as usize;
 
     cfg.store_cfg.pd_heartbeat_tick_interval =
         get_integer_value("pd-heartbeat-tick-interval",
@@ -357,6 +394,14 @@ fn build_cfg(matches: &Matches, config: &toml::Value, cluster_id: u64, addr: &st
                           Some(5000),
                           |v| v.as_integer()) as u64;
 
+    cfg.store_cfg.pd_heartbeat_sub_ticks =
+        get_integer_value("",
+                          "raftstore.pd-heartbeat-sub-ticks",
+                          matches,
+                          config,
+                          Some(1),
+                          |v| v.as_integer()) as u64;
+
     cfg.store_cfg.pd_store_heartbeat_tick_interval =
         get_integer_value("pd-store-heartbeat-tick-interval",
                           "raftstore.pd-store-heartbeat-tick-interval",
@@ -365,6 +410,188 @@ fn build_cfg(matches: &Matches, config: &toml::Value, cluster_id: u64, addr: &st
                           Some(10000),
                           |v| v.as_integer()) as u64;
 
+    cfg.store_cfg.raft_log_size_warn_threshold =
+        get_integer_value("",
+                          "raftstore.raft-log-size-warn-threshold",
+                          matches,
+                          config,
+                          Some(512 * 1024 * 1024),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.max_regions_per_store =
+        get_integer_value("",
+                          "raftstore.max-regions-per-store",
+                          matches,
+                          config,
+                          Some(0),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.apply_sync_interval_ms =
+        get_integer_value("",
+                          "raftstore.apply-sync-interval-ms",
+                          matches,
+                          config,
+                          Some(0),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.slow_log_threshold_ms =
+        get_integer_value("",
+                          "raftstore.slow-log-threshold-ms",
+                          matches,
+                          config,
+                          Some(1000),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.consistency_check_interval =
+        get_integer_value("",
+                          "raftstore.consistency-check-interval",
+                          matches,
+                          config,
+                          Some(0),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.raft_entry_cache_size =
+        get_integer_value("",
+                          "raftstore.raft-entry-cache-size",
+                          matches,
+                          config,
+                          Some(256 * 1024),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.sync_log = config.lookup("raftstore.sync-log")
+        .unwrap_or(&toml::Value::Boolean(false))
+        .as_bool()
+        .unwrap_or(false);
+
+    cfg.store_cfg.tick_jitter_ratio = config.lookup("raftstore.tick-jitter-ratio")
+        .unwrap_or(&toml::Value::Float(0.1))
+        .as_float()
+        .unwrap_or(0.1);
+
+    cfg.store_cfg.raft_check_quorum = config.lookup("raftstore.raft-check-quorum")
+        .unwrap_or(&toml::Value::Boolean(true))
+        .as_bool()
+        .unwrap_or(true);
+
+    cfg.store_cfg.raft_pre_vote = config.lookup("raftstore.raft-pre-vote")
+        .unwrap_or(&toml::Value::Boolean(true))
+        .as_bool()
+        .unwrap_or(true);
+
+    cfg.store_cfg.rebuild_region_index = config.lookup("raftstore.rebuild-region-index")
+        .unwrap_or(&toml::Value::Boolean(false))
+        .as_bool()
+        .unwrap_or(false);
+
+    cfg.store_cfg.snap_sync = config.lookup("raftstore.snap-sync")
+        .unwrap_or(&toml::Value::Boolean(true))
+        .as_bool()
+        .unwrap_or(true);
+
+    cfg.store_cfg.read_only = config.lookup("raftstore.read-only")
+        .unwrap_or(&toml::Value::Boolean(false))
+        .as_bool()
+        .unwrap_or(false);
+
+    cfg.store_cfg.concurrent_snap_limit =
+        get_integer_value("",
+                          "raftstore.concurrent-snap-limit",
+                          matches,
+                          config,
+                          Some(16),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.concurrent_apply_snap_limit =
+        get_integer_value("",
+                          "raftstore.concurrent-apply-snap-limit",
+                          matches,
+                          config,
+                          Some(16),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.delete_batch_size =
+        get_integer_value("",
+                          "raftstore.delete-batch-size",
+                          matches,
+                          config,
+                          Some(4096),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.raft_randomize_election = config.lookup("raftstore.raft-randomize-election")
+        .unwrap_or(&toml::Value::Boolean(true))
+        .as_bool()
+        .unwrap_or(true);
+
+    cfg.store_cfg.max_ready_regions_per_tick =
+        get_integer_value("",
+                          "raftstore.max-ready-regions-per-tick",
+                          matches,
+                          config,
+                          Some(4096),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.snap_gc_timeout_per_mb =
+        get_integer_value("",
+                          "raftstore.snap-gc-timeout-per-mb",
+                          matches,
+                          config,
+                          Some(1),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.max_snap_gc_timeout =
+        get_integer_value("",
+                          "raftstore.max-snap-gc-timeout",
+                          matches,
+                          config,
+                          Some(60 * 60),
+                          |v| v.as_integer()) as u64;
+
+    cfg.store_cfg.panic_on_apply_regression =
+        config.lookup("raftstore.panic-on-apply-regression")
+            .unwrap_or(&toml::Value::Boolean(false))
+            .as_bool()
+            .unwrap_or(false);
+
+    cfg.store_cfg.max_admin_results_per_ready =
+        get_integer_value("",
+                          "raftstore.max-admin-results-per-ready",
+                          matches,
+                          config,
+                          Some(1),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.region_max_pending_proposals =
+        get_integer_value("",
+                          "raftstore.region-max-pending-proposals",
+                          matches,
+                          config,
+                          Some(1024),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.store_busy_pending_tasks =
+        get_integer_value("",
+                          "raftstore.store-busy-pending-tasks",
+                          matches,
+                          config,
+                          Some(1024),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.store_busy_pending_ready_regions =
+        get_integer_value("",
+                          "raftstore.store-busy-pending-ready-regions",
+                          matches,
+                          config,
+                          Some(4 * 4096),
+                          |v| v.as_integer()) as usize;
+
+    cfg.store_cfg.store_busy_backoff_ms =
+        get_integer_value("",
+                          "raftstore.store-busy-backoff-ms",
+                          matches,
+                          config,
+                          Some(100),
+                          |v| v.as_integer()) as u64;
+
     cfg
 }
 
@@ -422,9 +649,11 @@ fn get_store_path(matches: &Matches, config: &toml::Value) -> String {
 
 fn run_local_server(listener: TcpListener, store: Storage, config: &Config) {
     let mut event_loop = create_event_loop(config).unwrap();
+    let ch = SendCh::new(event_loop.channel(), config.notify_capacity);
     let router = Arc::new(RwLock::new(MockRaftStoreRouter));
     let snap_mgr = store::new_snap_mgr(TEMP_DIR, None);
     let mut svr = Server::new(&mut event_loop,
+                              ch,
                               config,
                               listener,
                               store,
@@ -437,7 +666,7 @@ fn run_local_server(listener: TcpListener, store: Storage, config: &Config) {
 
 fn run_raft_server(listener: TcpListener, matches: &Matches, config: &toml::Value, cfg: &Config) {
     let mut event_loop = create_event_loop(cfg).unwrap();
-    let ch = SendCh::new(event_loop.channel());
+    let ch = SendCh::new(event_loop.channel(), cfg.notify_capacity);
     let etcd_endpoints = get_string_value("etcd",
                                           "etcd.endpoints",
                                           matches,
@@ -468,6 +697,7 @@ fn run_raft_server(listener: TcpListener, matches: &Matches, config: &toml::Valu
     info!("tikv server config: {:?}", cfg);
     initial_metric(matches, config, Some(node_id));
     let mut svr = Server::new(&mut event_loop,
+                              ch,
                               cfg,
                               listener,
                               store,
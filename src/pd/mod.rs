@@ -34,6 +34,16 @@ pub type Key = Vec<u8>;
 
 pub const INVALID_ID: u64 = 0;
 
+// Read and write flow of a region since its last heartbeat,
+// pd uses it to find out the hot regions.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RegionFlow {
+    pub written_bytes: u64,
+    pub written_keys: u64,
+    pub read_bytes: u64,
+    pub read_keys: u64,
+}
+
 // Client to communicate with placement driver (pd) for special cluster.
 // Because now one pd only supports one cluster, so it is no need to pass
 // cluster id in trait interface every time, so passing the cluster id when
@@ -85,9 +95,11 @@ pub trait PdClient: Send + Sync {
     fn get_region(&self, key: &[u8]) -> Result<metapb::Region>;
 
     // Leader for a region will use this to heartbeat Pd.
+    // flow is the read and write flow of the region since last heartbeat.
     fn region_heartbeat(&self,
                         region: metapb::Region,
-                        leader: metapb::Peer)
+                        leader: metapb::Peer,
+                        flow: RegionFlow)
                         -> Result<pdpb::RegionHeartbeatResponse>;
 
     // Ask pd for split, pd will returns the new split region id.
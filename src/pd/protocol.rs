@@ -13,7 +13,7 @@
 
 use uuid::Uuid;
 use kvproto::{metapb, pdpb};
-use super::{Error, Result, RpcClient};
+use super::{Error, Result, RpcClient, RegionFlow};
 
 impl super::PdClient for RpcClient {
     fn bootstrap_cluster(&self, store: metapb::Store, region: metapb::Region) -> Result<()> {
@@ -92,11 +92,16 @@ impl super::PdClient for RpcClient {
 
     fn region_heartbeat(&self,
                         region: metapb::Region,
-                        leader: metapb::Peer)
+                        leader: metapb::Peer,
+                        flow: RegionFlow)
                         -> Result<pdpb::RegionHeartbeatResponse> {
         let mut heartbeat = pdpb::RegionHeartbeatRequest::new();
         heartbeat.set_region(region);
         heartbeat.set_leader(leader);
+        heartbeat.set_bytes_written(flow.written_bytes);
+        heartbeat.set_keys_written(flow.written_keys);
+        heartbeat.set_bytes_read(flow.read_bytes);
+        heartbeat.set_keys_read(flow.read_keys);
 
         let mut req = self.new_request(pdpb::CommandType::RegionHeartbeat);
         req.set_region_heartbeat(heartbeat);
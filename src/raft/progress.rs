@@ -79,6 +79,10 @@ pub struct Progress {
     // When a leader receives a reply, the previous inflights should
     // be freed by calling inflights.freeTo.
     pub ins: Inflights,
+
+    // is_learner is true if the peer only replicates the log. A learner
+    // never votes, never campaigns and is not counted in any quorum.
+    pub is_learner: bool,
 }
 
 
@@ -42,6 +42,7 @@ pub enum StateRole {
     Follower,
     Candidate,
     Leader,
+    PreCandidate,
 }
 
 impl Default for StateRole {
@@ -52,6 +53,18 @@ impl Default for StateRole {
 
 pub const INVALID_ID: u64 = 0;
 
+/// CampaignType represents the type of campaigning.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CampaignType {
+    /// the first phase of a normal election when Config.pre_vote is true.
+    PreElection,
+    /// a normal (time-based) election, or the second phase of the
+    /// election when Config.pre_vote is true.
+    Election,
+    /// the type of leader transfer, which skips the pre-vote phase.
+    Transfer,
+}
+
 /// Config contains the parameters to start a raft.
 #[derive(Default)]
 pub struct Config {
@@ -66,6 +79,10 @@ pub struct Config {
     /// peer is private and only used for testing right now.
     pub peers: Vec<u64>,
 
+    /// learners contains the IDs of the non-voting nodes in the raft cluster.
+    /// Like peers, it should only be set when starting a new raft cluster.
+    pub learners: Vec<u64>,
+
     /// ElectionTick is the number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
@@ -73,6 +90,11 @@ pub struct Config {
     /// HeartbeatTick. We suggest election_tick = 10 * HeartbeatTick to avoid
     /// unnecessary leader switching
     pub election_tick: usize,
+    /// min_election_tick and max_election_tick bound the randomized election
+    /// timeout to [min_election_tick, max_election_tick). If they are 0, the
+    /// timeout is randomized in [election_tick, 2 * election_tick).
+    pub min_election_tick: usize,
+    pub max_election_tick: usize,
     /// HeartbeatTick is the number of node.tick invocations that must pass between
     /// heartbeats. That is, a leader sends heartbeat messages to maintain its
     /// leadership every heartbeat ticks.
@@ -99,11 +121,32 @@ pub struct Config {
     /// quorum is not active for an electionTimeout.
     pub check_quorum: bool,
 
+    /// pre_vote enables the Pre-Vote algorithm described in raft thesis section
+    /// 9.6. This prevents disruption when a node that has been partitioned away
+    /// rejoins the cluster.
+    pub pre_vote: bool,
+
     /// tag is only used for logging
     pub tag: String,
 }
 
 impl Config {
+    pub fn min_election_tick(&self) -> usize {
+        if self.min_election_tick == 0 {
+            self.election_tick
+        } else {
+            self.min_election_tick
+        }
+    }
+
+    pub fn max_election_tick(&self) -> usize {
+        if self.max_election_tick == 0 {
+            2 * self.election_tick
+        } else {
+            self.max_election_tick
+        }
+    }
+
     pub fn validate(&self) -> Result<()> {
         if self.id == INVALID_ID {
             return Err(Error::ConfigInvalid("invalid node id".to_owned()));
@@ -118,6 +161,16 @@ impl Config {
                 .to_owned()));
         }
 
+        if self.min_election_tick() < self.election_tick {
+            return Err(Error::ConfigInvalid("min election tick must not be less than election tick"
+                .to_owned()));
+        }
+
+        if self.min_election_tick() >= self.max_election_tick() {
+            return Err(Error::ConfigInvalid("min election tick must be less than max election tick"
+                .to_owned()));
+        }
+
         if self.max_inflight_msgs == 0 {
             return Err(Error::ConfigInvalid("max inflight messages must be greater than 0"
                 .to_owned()));
@@ -177,11 +230,16 @@ pub struct Raft<T: Storage> {
 
     pub check_quorum: bool,
 
+    pub pre_vote: bool,
+
     heartbeat_timeout: usize,
     election_timeout: usize,
 
+    min_election_timeout: usize,
+    max_election_timeout: usize,
+
     // randomized_election_timeout is a random number between
-    // [election_timeout, 2 * election_timeout - 1]. It gets reset
+    // [min_election_timeout, max_election_timeout - 1]. It gets reset
     // when raft changes its state to follower or candidate.
     randomized_election_timeout: usize,
 
@@ -217,25 +275,30 @@ impl<T: Storage> Raft<T> {
         let rs = store.initial_state().expect("");
         let raft_log = RaftLog::new(store);
         let mut peers: &[u64] = &c.peers;
-        if !rs.conf_state.get_nodes().is_empty() {
-            if !peers.is_empty() {
+        let mut learners: &[u64] = &c.learners;
+        if !rs.conf_state.get_nodes().is_empty() || !rs.conf_state.get_learners().is_empty() {
+            if !peers.is_empty() || !learners.is_empty() {
                 // TODO: the peers argument is always nil except in
                 // tests; the argument should be removed and these tests should be
                 // updated to specify their nodes through a snap
                 panic!("cannot specify both new(peers) and ConfState.Nodes")
             }
             peers = rs.conf_state.get_nodes();
+            learners = rs.conf_state.get_learners();
         }
         let mut r = Raft {
             id: c.id,
             raft_log: raft_log,
             max_inflight: c.max_inflight_msgs,
             max_msg_size: c.max_size_per_msg,
-            prs: HashMap::with_capacity(peers.len()),
+            prs: HashMap::with_capacity(peers.len() + learners.len()),
             state: StateRole::Follower,
             check_quorum: c.check_quorum,
+            pre_vote: c.pre_vote,
             heartbeat_timeout: c.heartbeat_tick,
             election_timeout: c.election_tick,
+            min_election_timeout: c.min_election_tick(),
+            max_election_timeout: c.max_election_tick(),
             votes: Default::default(),
             msgs: Default::default(),
             leader_id: Default::default(),
@@ -252,6 +315,11 @@ impl<T: Storage> Raft<T> {
         for p in peers {
             r.prs.insert(*p, new_progress(1, r.max_inflight));
         }
+        for p in learners {
+            let mut pr = new_progress(1, r.max_inflight);
+            pr.is_learner = true;
+            r.prs.insert(*p, pr);
+        }
         if rs.hard_state != HardState::new() {
             r.load_state(rs.hard_state);
         }
@@ -260,10 +328,11 @@ impl<T: Storage> Raft<T> {
         }
         let term = r.term;
         r.become_follower(term, INVALID_ID);
-        info!("{} newRaft [peers: {:?}, term: {:?}, commit: {}, applied: {}, last_index: {}, \
-               last_term: {}]",
+        info!("{} newRaft [peers: {:?}, learners: {:?}, term: {:?}, commit: {}, applied: {}, \
+               last_index: {}, last_term: {}]",
               r.tag,
               r.nodes(),
+              r.learners(),
               r.term,
               r.raft_log.committed,
               r.raft_log.get_applied(),
@@ -303,32 +372,53 @@ impl<T: Storage> Raft<T> {
     }
 
     fn quorum(&self) -> usize {
-        self.prs.len() / 2 + 1
+        self.prs.values().filter(|p| !p.is_learner).count() / 2 + 1
     }
 
     pub fn get_election_timeout(&self) -> usize {
         self.election_timeout
     }
 
+    pub fn get_randomized_election_timeout(&self) -> usize {
+        self.randomized_election_timeout
+    }
+
     pub fn get_heartbeat_timeout(&self) -> usize {
         self.heartbeat_timeout
     }
 
     pub fn nodes(&self) -> Vec<u64> {
-        let mut nodes = Vec::with_capacity(self.prs.len());
-        nodes.extend(self.prs.keys());
+        let mut nodes: Vec<_> =
+            self.prs.iter().filter(|&(_, p)| !p.is_learner).map(|(id, _)| *id).collect();
         nodes.sort();
         nodes
     }
 
+    pub fn learners(&self) -> Vec<u64> {
+        let mut learners: Vec<_> =
+            self.prs.iter().filter(|&(_, p)| p.is_learner).map(|(id, _)| *id).collect();
+        learners.sort();
+        learners
+    }
+
     // send persists state to stable storage and then sends to its mailbox.
     fn send(&mut self, mut m: Message) {
         m.set_from(self.id);
-        // do not attach term to MsgPropose
-        // proposals are a way to forward to the leader and
-        // should be treated as local message.
-        if m.get_msg_type() != MessageType::MsgPropose {
-            m.set_term(self.term);
+        match m.get_msg_type() {
+            // pre-vote requests and responses carry the term they are about,
+            // which may differ from our current term, so it must be set by caller.
+            MessageType::MsgRequestPreVote |
+            MessageType::MsgRequestPreVoteResponse => {
+                assert!(m.get_term() != 0,
+                        "{} term should be set when sending {:?}",
+                        self.tag,
+                        m.get_msg_type());
+            }
+            // do not attach term to MsgPropose
+            // proposals are a way to forward to the leader and
+            // should be treated as local message.
+            MessageType::MsgPropose => {}
+            _ => m.set_term(self.term),
         }
         self.msgs.push(m);
     }
@@ -473,7 +563,7 @@ impl<T: Storage> Raft<T> {
     pub fn maybe_commit(&mut self) -> bool {
         // TODO: optimize
         let mut mis = Vec::with_capacity(self.prs.len());
-        for p in self.prs.values() {
+        for p in self.prs.values().filter(|p| !p.is_learner) {
             mis.push(p.matched);
         }
         // reverse sort
@@ -521,7 +611,9 @@ impl<T: Storage> Raft<T> {
 
     pub fn tick(&mut self) {
         match self.state {
-            StateRole::Candidate | StateRole::Follower => self.tick_election(),
+            StateRole::Candidate | StateRole::PreCandidate | StateRole::Follower => {
+                self.tick_election()
+            }
             StateRole::Leader => self.tick_heartbeat(),
         }
     }
@@ -587,6 +679,19 @@ impl<T: Storage> Raft<T> {
         info!("{} became candidate at term {}", self.tag, self.term);
     }
 
+    // TODO: revoke pub when there is a better way to test.
+    pub fn become_pre_candidate(&mut self) {
+        assert!(self.state != StateRole::Leader,
+                "invalid transition [leader -> pre-candidate]");
+        // Becoming a pre-candidate changes our state,
+        // but doesn't change anything else. In particular it does not increase
+        // self.term or change self.vote.
+        self.state = StateRole::PreCandidate;
+        self.votes = HashMap::new();
+        self.leader_id = INVALID_ID;
+        info!("{} became pre-candidate at term {}", self.tag, self.term);
+    }
+
     // TODO: revoke pub when there is a better way to test.
     pub fn become_leader(&mut self) {
         assert!(self.state != StateRole::Follower,
@@ -611,26 +716,41 @@ impl<T: Storage> Raft<T> {
         info!("{} became leader at term {}", self.tag, self.term);
     }
 
-    fn campaign(&mut self) {
-        self.become_candidate();
+    fn campaign(&mut self, campaign_type: CampaignType) {
+        let (vote_msg, term) = if campaign_type == CampaignType::PreElection {
+            self.become_pre_candidate();
+            // Pre-vote RPCs are sent for next term before we've incremented self.term.
+            (MessageType::MsgRequestPreVote, self.term + 1)
+        } else {
+            self.become_candidate();
+            (MessageType::MsgRequestVote, self.term)
+        };
         let id = self.id;
         let poll_res = self.poll(id, true);
         if self.quorum() == poll_res {
-            self.become_leader();
+            // We won the election after voting for ourselves (which must mean that
+            // this is a single-node cluster). Advance to the next state.
+            if campaign_type == CampaignType::PreElection {
+                self.campaign(CampaignType::Election);
+            } else {
+                self.become_leader();
+            }
             return;
         }
-        let ids: Vec<_> = self.prs.keys().cloned().collect();
+        let ids = self.nodes();
         for id in ids {
             if id == self.id {
                 continue;
             }
-            info!("{} [logterm: {}, index: {}] sent vote request to {} at term {}",
+            info!("{} [logterm: {}, index: {}] sent {:?} request to {} at term {}",
                   self.tag,
                   self.raft_log.last_term(),
                   self.raft_log.last_index(),
+                  vote_msg,
                   id,
                   self.term);
-            let mut m = new_message(id, MessageType::MsgRequestVote, None);
+            let mut m = new_message(id, vote_msg, None);
+            m.set_term(term);
             m.set_index(self.raft_log.last_index());
             m.set_log_term(self.raft_log.last_term());
             self.send(m);
@@ -657,11 +777,17 @@ impl<T: Storage> Raft<T> {
         if m.get_msg_type() == MessageType::MsgHup {
             if self.state == StateRole::Leader {
                 debug!("{} ignoring MsgHup because already leader", self.tag);
+            } else if !self.promotable() {
+                debug!("{} ignoring MsgHup because it is not promotable", self.tag);
             } else {
                 info!("{} is starting a new election at term {}",
                       self.tag,
                       self.term);
-                self.campaign();
+                if self.pre_vote {
+                    self.campaign(CampaignType::PreElection);
+                } else {
+                    self.campaign(CampaignType::Election);
+                }
             }
             return Ok(());
         }
@@ -676,19 +802,55 @@ impl<T: Storage> Raft<T> {
         if m.get_term() == 0 {
             // local message
         } else if m.get_term() > self.term {
-            let leader_id = if m.get_msg_type() == MessageType::MsgRequestVote {
-                INVALID_ID
-            } else {
-                m.get_from()
-            };
-            info!("{} [term: {}] received a {:?} message with higher term from {} [term: {}]",
-                  self.tag,
-                  self.term,
-                  m.get_msg_type(),
-                  m.get_from(),
-                  m.get_term());
-            self.become_follower(m.get_term(), leader_id);
+            match m.get_msg_type() {
+                // Never change our term in response to a pre-vote.
+                MessageType::MsgRequestPreVote => {}
+                // We send pre-vote requests with a term in our future. If the
+                // pre-vote is granted, we will increment our term when we get a
+                // quorum. If it is not, the term comes from the node that
+                // rejected our vote so we should become a follower at the new
+                // term.
+                MessageType::MsgRequestPreVoteResponse if !m.get_reject() => {}
+                t => {
+                    let leader_id = if t == MessageType::MsgRequestVote ||
+                                       t == MessageType::MsgRequestPreVoteResponse {
+                        INVALID_ID
+                    } else {
+                        m.get_from()
+                    };
+                    info!("{} [term: {}] received a {:?} message with higher term from {} \
+                           [term: {}]",
+                          self.tag,
+                          self.term,
+                          t,
+                          m.get_from(),
+                          m.get_term());
+                    self.become_follower(m.get_term(), leader_id);
+                }
+            }
         } else if m.get_term() < self.term {
+            if self.pre_vote &&
+               (m.get_msg_type() == MessageType::MsgHeartbeat ||
+                m.get_msg_type() == MessageType::MsgAppend) {
+                // With pre-vote, a node that was partitioned away with a higher
+                // term can not win an election because its log is behind, and it
+                // would keep ignoring the lower term leader forever. Respond with
+                // our term so that the stale leader steps down and a new election
+                // can bring the cluster back together.
+                let to_send = new_message(m.get_from(), MessageType::MsgAppendResponse, None);
+                self.send(to_send);
+            } else if m.get_msg_type() == MessageType::MsgRequestPreVote {
+                // Reject with our current term so that a node whose pre-vote term
+                // is behind learns about it.
+                self.log_vote_reject(&m);
+                let mut to_send = new_message(m.get_from(),
+                                              MessageType::MsgRequestPreVoteResponse,
+                                              None);
+                to_send.set_term(self.term);
+                to_send.set_reject(true);
+                self.send(to_send);
+                return Ok(());
+            }
             // ignore
             info!("{} [term: {}] ignored a {:?} message with lower term from {} [term: {}]",
                   self.tag,
@@ -700,14 +862,42 @@ impl<T: Storage> Raft<T> {
         }
 
         assert!(self.allow_step);
+        if m.get_msg_type() == MessageType::MsgRequestPreVote {
+            self.handle_pre_vote(m);
+            return Ok(());
+        }
         match self.state {
-            StateRole::Candidate => self.step_candidate(m),
+            StateRole::Candidate | StateRole::PreCandidate => self.step_candidate(m),
             StateRole::Follower => self.step_follower(m),
             StateRole::Leader => self.step_leader(m),
         }
         Ok(())
     }
 
+    // handle_pre_vote answers a pre-vote request. A pre-vote never changes our
+    // term or vote, it only tells the sender whether it could win a real election.
+    fn handle_pre_vote(&mut self, m: Message) {
+        let t = MessageType::MsgRequestPreVoteResponse;
+        // Reject if we still hear from a leader within an election timeout,
+        // so an isolated peer can't disrupt a healthy group when it comes back.
+        let in_lease = self.leader_id != INVALID_ID &&
+                       self.election_elapsed < self.election_timeout;
+        if !in_lease && m.get_term() > self.term &&
+           self.raft_log.is_up_to_date(m.get_index(), m.get_log_term()) {
+            self.log_vote_approve(&m);
+            let mut to_send = new_message(m.get_from(), t, None);
+            to_send.set_term(m.get_term());
+            to_send.set_reject(false);
+            self.send(to_send);
+        } else {
+            self.log_vote_reject(&m);
+            let mut to_send = new_message(m.get_from(), t, None);
+            to_send.set_term(self.term);
+            to_send.set_reject(true);
+            self.send(to_send);
+        }
+    }
+
     fn handle_append_response(&mut self,
                               m: &Message,
                               old_paused: &mut bool,
@@ -796,6 +986,12 @@ impl<T: Storage> Raft<T> {
                    self.tag);
             return;
         }
+        if self.prs[&lead_transferee].is_learner {
+            debug!("{} ignored transferring leadership to learner {}",
+                   self.tag,
+                   lead_transferee);
+            return;
+        }
         // Transfer leadership to third party.
         info!("{} [term {}] starts to transfer leadership to {}",
               self.tag,
@@ -1020,17 +1216,34 @@ impl<T: Storage> Raft<T> {
                 to_send.set_reject(true);
                 self.send(to_send);
             }
-            MessageType::MsgRequestVoteResponse => {
+            MessageType::MsgRequestVoteResponse |
+            MessageType::MsgRequestPreVoteResponse => {
+                // Only handle vote responses corresponding to our candidacy (while in
+                // Candidate, we may get stale MsgRequestPreVoteResponse messages in
+                // this term from our pre-candidate state).
+                let expected = if self.state == StateRole::PreCandidate {
+                    MessageType::MsgRequestPreVoteResponse
+                } else {
+                    MessageType::MsgRequestVoteResponse
+                };
+                if m.get_msg_type() != expected {
+                    return;
+                }
                 let gr = self.poll(m.get_from(), !m.get_reject());
                 let quorum = self.quorum();
-                info!("{} [quorum:{}] has received {} votes and {} vote rejections",
+                info!("{} [quorum:{}] has received {} {:?} votes and {} vote rejections",
                       self.tag,
                       quorum,
                       gr,
+                      m.get_msg_type(),
                       self.votes.len() - gr);
                 if quorum == gr {
-                    self.become_leader();
-                    self.bcast_append();
+                    if self.state == StateRole::PreCandidate {
+                        self.campaign(CampaignType::Election);
+                    } else {
+                        self.become_leader();
+                        self.bcast_append();
+                    }
                 } else if quorum == self.votes.len() - gr {
                     self.become_follower(term, INVALID_ID);
                 }
@@ -1090,13 +1303,22 @@ impl<T: Storage> Raft<T> {
                     self.send(to_send);
                 }
             }
+            MessageType::MsgTimeoutNow if !self.promotable() => {
+                info!("{} [term {}] ignored MsgTimeoutNow from {} as it is not promotable",
+                      self.tag,
+                      self.term,
+                      m.get_from());
+            }
             MessageType::MsgTimeoutNow => {
                 info!("{} [term {}] received MsgTimeoutNow from {} and starts an election to \
                        get leadership.",
                       self.tag,
                       self.term,
                       m.get_from());
-                self.campaign();
+                // Leadership transfers never use pre-vote even if self.pre_vote is true;
+                // we know we are not recovering from a partition so there is no need
+                // for the extra round trip.
+                self.campaign(CampaignType::Transfer);
             }
             _ => {}
         }
@@ -1200,8 +1422,10 @@ impl<T: Storage> Raft<T> {
               self.raft_log.last_term(),
               meta.get_index(),
               meta.get_term());
-        self.prs = HashMap::with_capacity(meta.get_conf_state().get_nodes().len());
-        for &n in meta.get_conf_state().get_nodes() {
+        let cs = meta.get_conf_state();
+        self.prs = HashMap::with_capacity(cs.get_nodes().len() + cs.get_learners().len());
+        let nodes = cs.get_nodes().iter().map(|n| (n, false));
+        for (&n, is_learner) in nodes.chain(cs.get_learners().iter().map(|n| (n, true))) {
             let next_idx = self.raft_log.last_index() + 1;
             let matched = if n == self.id {
                 next_idx - 1
@@ -1209,6 +1433,7 @@ impl<T: Storage> Raft<T> {
                 0
             };
             self.set_progress(n, matched, next_idx);
+            self.prs.get_mut(&n).unwrap().is_learner = is_learner;
             info!("{} restored progress of {} [{:?}]",
                   self.tag,
                   n,
@@ -1231,20 +1456,33 @@ impl<T: Storage> Raft<T> {
     }
 
     // promotable indicates whether state machine can be promoted to leader,
-    // which is true when its own id is in progress list.
+    // which is true when its own id is in progress list and it's not a learner.
     pub fn promotable(&self) -> bool {
-        self.prs.contains_key(&self.id)
+        self.prs.get(&self.id).map_or(false, |p| !p.is_learner)
     }
 
     pub fn add_node(&mut self, id: u64) {
-        if self.prs.contains_key(&id) {
-            // Ignore any redundant addNode calls (which can happen because the
-            // initial bootstrapping entries are applied twice).
+        self.pending_conf = false;
+        if let Some(pr) = self.prs.get_mut(&id) {
+            // Promote the learner to a voter, any other redundant addNode calls
+            // (which can happen because the initial bootstrapping entries are
+            // applied twice) are ignored.
+            pr.is_learner = false;
             return;
         }
         let last_index = self.raft_log.last_index();
         self.set_progress(id, 0, last_index + 1);
+    }
+
+    pub fn add_learner(&mut self, id: u64) {
         self.pending_conf = false;
+        if self.prs.contains_key(&id) {
+            // A voter can't be demoted to a learner.
+            return;
+        }
+        let last_index = self.raft_log.last_index();
+        self.set_progress(id, 0, last_index + 1);
+        self.prs.get_mut(&id).unwrap().is_learner = true;
     }
 
     pub fn remove_node(&mut self, id: u64) {
@@ -1298,15 +1536,15 @@ impl<T: Storage> Raft<T> {
 
     /// `pass_election_timeout` returns true iff `election_elapsed` is greater
     /// than or equal to the randomized election timeout in
-    /// [`election_timeout`, 2 * `election_timeout` - 1].
+    /// [`min_election_timeout`, `max_election_timeout` - 1].
     pub fn pass_election_timeout(&self) -> bool {
         self.election_elapsed >= self.randomized_election_timeout
     }
 
     pub fn reset_randomized_election_timeout(&mut self) {
         let prev_timeout = self.randomized_election_timeout;
-        let timeout = self.election_timeout +
-                      rand::thread_rng().gen_range(0, self.election_timeout);
+        let timeout = rand::thread_rng()
+            .gen_range(self.min_election_timeout, self.max_election_timeout);
         debug!("{} reset election timeout {} -> {} at {}",
                self.tag,
                prev_timeout,
@@ -1323,6 +1561,10 @@ impl<T: Storage> Raft<T> {
         let mut act = 0;
         let self_id = self.id;
         for (id, p) in &mut self.prs {
+            if p.is_learner {
+                p.recent_active = false;
+                continue;
+            }
             if id == &self_id {
                 // self is always active
                 act += 1;
@@ -61,6 +61,7 @@ fn is_response_msg(m: &Message) -> bool {
     match m.get_msg_type() {
         MessageType::MsgAppendResponse |
         MessageType::MsgRequestVoteResponse |
+        MessageType::MsgRequestPreVoteResponse |
         MessageType::MsgHeartbeatResponse |
         MessageType::MsgUnreachable => true,
         _ => false,
@@ -248,16 +249,19 @@ impl<T: Storage> RawNode<T> {
             self.raft.reset_pending_conf();
             let mut cs = ConfState::new();
             cs.set_nodes(self.raft.nodes());
+            cs.set_learners(self.raft.learners());
             return cs;
         }
         let nid = cc.get_node_id();
         assert!(cc.has_change_type(), "unexpected conf type");
         match cc.get_change_type() {
             ConfChangeType::AddNode => self.raft.add_node(nid),
+            ConfChangeType::AddLearnerNode => self.raft.add_learner(nid),
             ConfChangeType::RemoveNode => self.raft.remove_node(nid),
         }
         let mut cs = ConfState::new();
         cs.set_nodes(self.raft.nodes());
+        cs.set_learners(self.raft.learners());
         cs
     }
 
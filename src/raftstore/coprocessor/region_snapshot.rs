@@ -53,6 +53,11 @@ impl RegionSnapshot {
         RegionIterator::new(self.snap.new_iterator(), self.region.clone())
     }
 
+    pub fn iter_cf(&self, cf: &str) -> Result<RegionIterator> {
+        let iter = try!(self.snap.new_iterator_cf(cf));
+        Ok(RegionIterator::new(iter, self.region.clone()))
+    }
+
     // scan scans database using an iterator in range [start_key, end_key), calls function f for
     // each iteration, if f returns false, terminates this scan.
     pub fn scan<F>(&self, start_key: &[u8], end_key: &[u8], f: &mut F) -> Result<()>
@@ -77,6 +82,29 @@ impl RegionSnapshot {
         Ok(())
     }
 
+    /// Get the values of `keys` from the same snapshot, all keys must be in the
+    /// region.
+    pub fn multi_get_value(&self, keys: &[&[u8]]) -> Result<Vec<Option<DBVector>>> {
+        let data_keys = try!(self.data_keys(keys));
+        let data_keys: Vec<_> = data_keys.iter().map(|k| k.as_slice()).collect();
+        self.snap.multi_get_value(&data_keys)
+    }
+
+    pub fn multi_get_value_cf(&self, cf: &str, keys: &[&[u8]]) -> Result<Vec<Option<DBVector>>> {
+        let data_keys = try!(self.data_keys(keys));
+        let data_keys: Vec<_> = data_keys.iter().map(|k| k.as_slice()).collect();
+        self.snap.multi_get_value_cf(cf, &data_keys)
+    }
+
+    fn data_keys(&self, keys: &[&[u8]]) -> Result<Vec<Vec<u8>>> {
+        let mut data_keys = Vec::with_capacity(keys.len());
+        for key in keys {
+            try!(util::check_key_in_region(key, &self.region));
+            data_keys.push(keys::data_key(key));
+        }
+        Ok(data_keys)
+    }
+
     pub fn get_start_key(&self) -> &[u8] {
         self.region.get_start_key()
     }
@@ -18,7 +18,7 @@ use std::io;
 use std::net;
 use std::vec::Vec;
 
-use protobuf::ProtobufError;
+use protobuf::{ProtobufError, RepeatedField};
 
 use util::codec;
 use pd;
@@ -35,6 +35,10 @@ quick_error!{
             description("region is not found")
             display("region {} not found", region_id)
         }
+        RegionTombstone(region_id: u64) {
+            description("region is tombstone")
+            display("region {} is tombstone", region_id)
+        }
         RegionNotInitialized(region_id: u64) {
             description("region has not been initialized yet.")
             display("region {} not initialized yet", region_id)
@@ -106,10 +110,23 @@ quick_error!{
             description("request timeout")
             display("Timeout {}", msg)
         }
-        StaleEpoch(msg: String) {
+        // The regions are the current layout known by the store, clients can
+        // refresh their cache with them instead of asking pd.
+        StaleEpoch(msg: String, new_regions: Vec<metapb::Region>) {
             description("region is stale")
             display("StaleEpoch {}", msg)
         }
+        // The command was proposed, but the leader stepped down before it was
+        // applied. It may still be committed by the new leader, so it must not
+        // be retried blindly.
+        ResultUnknown(region_id: u64) {
+            description("command result is unknown")
+            display("command result is unknown for region {}", region_id)
+        }
+        ServerIsBusy(reason: String, backoff_ms: u64) {
+            description("server is busy")
+            display("ServerIsBusy {}, backoff {}ms", reason, backoff_ms)
+        }
         Coprocessor(err: CopError) {
             from()
             cause(err)
@@ -128,7 +145,10 @@ impl Into<errorpb::Error> for Error {
         errorpb.set_message(error::Error::description(&self).to_owned());
 
         match self {
-            Error::RegionNotFound(region_id) => {
+            Error::RegionNotFound(region_id) |
+            Error::RegionTombstone(region_id) => {
+                // A tombstone region is reported as not found too, clients can
+                // tell them apart by the message.
                 errorpb.mut_region_not_found().set_region_id(region_id);
             }
             Error::NotLeader(region_id, leader) => {
@@ -143,8 +163,14 @@ impl Into<errorpb::Error> for Error {
                 errorpb.mut_key_not_in_region().set_start_key(region.get_start_key().to_vec());
                 errorpb.mut_key_not_in_region().set_end_key(region.get_end_key().to_vec());
             }
-            Error::StaleEpoch(_) => {
-                errorpb.set_stale_epoch(errorpb::StaleEpoch::new());
+            Error::StaleEpoch(_, new_regions) => {
+                let mut stale_epoch = errorpb::StaleEpoch::new();
+                stale_epoch.set_new_regions(RepeatedField::from_vec(new_regions));
+                errorpb.set_stale_epoch(stale_epoch);
+            }
+            Error::ServerIsBusy(reason, backoff_ms) => {
+                errorpb.mut_server_is_busy().set_reason(reason);
+                errorpb.mut_server_is_busy().set_backoff_ms(backoff_ms);
             }
             _ => {}
         };
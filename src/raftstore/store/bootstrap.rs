@@ -53,6 +53,7 @@ pub fn write_region(engine: &DB, region: &metapb::Region) -> Result<()> {
 
     let wb = WriteBatch::new();
     try!(wb.put_msg(&keys::region_state_key(region.get_id()), &state));
+    try!(wb.put(&keys::region_index_key(region.get_id()), &[]));
     try!(write_initial_state(&wb, region.get_id()));
     try!(engine.write(wb));
     Ok(())
@@ -60,7 +61,10 @@ pub fn write_region(engine: &DB, region: &metapb::Region) -> Result<()> {
 
 // Clear first region meta.
 pub fn clear_region(engine: &DB, region_id: u64) -> Result<()> {
-    try!(engine.delete(&keys::region_state_key(region_id)));
+    let wb = WriteBatch::new();
+    try!(wb.delete(&keys::region_state_key(region_id)));
+    try!(wb.delete(&keys::region_index_key(region_id)));
+    try!(engine.write(wb));
     Ok(())
 }
 
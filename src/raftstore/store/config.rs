@@ -12,28 +12,50 @@
 // limitations under the License.
 
 use std::u64;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 
 use raftstore::Result;
+use storage::DEFAULT_CFS;
 
 const RAFT_BASE_TICK_INTERVAL: u64 = 100;
 const RAFT_HEARTBEAT_TICKS: usize = 3;
 const RAFT_ELECTION_TIMEOUT_TICKS: usize = 15;
 const RAFT_MAX_SIZE_PER_MSG: u64 = 1024 * 1024;
 const RAFT_MAX_INFLIGHT_MSGS: usize = 256;
+const RAFT_ENTRY_CACHE_SIZE: u64 = 256 * 1024;
 const RAFT_LOG_GC_INTERVAL: u64 = 5000;
 const RAFT_LOG_GC_THRESHOLD: u64 = 50;
 const RAFT_LOG_GC_LIMIT: u64 = 100000;
+const RAFT_LOG_SIZE_WARN_THRESHOLD: u64 = 512 * 1024 * 1024;
 const SPLIT_REGION_CHECK_TICK_INTERVAL: u64 = 10000;
 const REGION_SPLIT_SIZE: u64 = 64 * 1024 * 1024;
 const REGION_MAX_SIZE: u64 = 80 * 1024 * 1024;
 const REGION_CHECK_DIFF: u64 = 8 * 1024 * 1024;
+const SPLIT_CHECK_CONCURRENCY: usize = 1;
 const PD_HEARTBEAT_TICK_INTERVAL_MS: u64 = 5000;
+const PD_HEARTBEAT_SUB_TICKS: u64 = 1;
 const PD_STORE_HEARTBEAT_TICK_INTERVAL_MS: u64 = 10000;
 const STORE_CAPACITY: u64 = u64::MAX;
 const DEFAULT_NOTIFY_CAPACITY: usize = 4096;
 const DEFAULT_MGR_GC_TICK_INTERVAL_MS: u64 = 60000;
 const DEFAULT_SNAP_GC_TIMEOUT_SECS: u64 = 60 * 10;
+const DEFAULT_SNAP_GC_TIMEOUT_PER_MB_SECS: u64 = 1;
+const DEFAULT_MAX_SNAP_GC_TIMEOUT_SECS: u64 = 60 * 60;
 const DEFAULT_MESSAGES_PER_TICK: usize = 256;
+const DEFAULT_DELETE_BATCH_SIZE: usize = 4096;
+const REGION_COMPACT_CHECK_TICK_INTERVAL: u64 = 5 * 60 * 1000;
+const REGION_COMPACT_TOMBSTONES_THRESHOLD: u64 = 100000;
+const REGION_MAX_PENDING_PROPOSALS: usize = 1024;
+const MAX_READY_REGIONS_PER_TICK: usize = 4096;
+const MAX_ADMIN_RESULTS_PER_READY: usize = 1;
+const STORE_BUSY_PENDING_TASKS: usize = 1024;
+const STORE_BUSY_PENDING_READY_REGIONS: usize = 4 * MAX_READY_REGIONS_PER_TICK;
+const STORE_BUSY_BACKOFF_MS: u64 = 100;
+const DEFAULT_CONCURRENT_SNAP_LIMIT: usize = 16;
+const DEFAULT_CONCURRENT_APPLY_SNAP_LIMIT: usize = 16;
+const TICK_JITTER_RATIO: f64 = 0.1;
+const SLOW_LOG_THRESHOLD_MS: u64 = 1000;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -46,8 +68,22 @@ pub struct Config {
     pub raft_base_tick_interval: u64,
     pub raft_heartbeat_ticks: usize,
     pub raft_election_timeout_ticks: usize,
+    // Randomize the election timeout of each peer in [election timeout,
+    // 2 * election timeout) to avoid split votes.
+    pub raft_randomize_election: bool,
+    // A leader steps down if it can't hear from a quorum in an election
+    // timeout. Disabling it is only meant for single node deployments.
+    pub raft_check_quorum: bool,
+    // Run a pre-vote round before campaigning, so an isolated peer doesn't
+    // bump the term of the region when it rejoins. All the stores must
+    // understand the pre-vote messages before it's enabled.
+    pub raft_pre_vote: bool,
     pub raft_max_size_per_msg: u64,
     pub raft_max_inflight_msgs: usize,
+    // Max size in bytes of the latest raft entries a peer keeps in memory, the
+    // older ones are read from the engine when a lagging follower needs them.
+    // It's per region, so keep it small. 0 disables the cache.
+    pub raft_entry_cache_size: u64,
 
     // Interval to gc unnecessary raft log (ms).
     pub raft_log_gc_tick_interval: u64,
@@ -55,6 +91,9 @@ pub struct Config {
     pub raft_log_gc_threshold: u64,
     // When entry count exceed this value, gc will be forced trigger.
     pub raft_log_gc_limit: u64,
+    // When the uncompacted raft log size of a region exceeds this value (bytes),
+    // a warning will be logged.
+    pub raft_log_size_warn_threshold: u64,
 
     // Interval (ms) to check region whether need to be split or not.
     pub split_region_check_tick_interval: u64,
@@ -66,13 +105,109 @@ pub struct Config {
     /// When size change of region exceed the diff since last check, it
     /// will be checked again whether it should be split.
     pub region_check_size_diff: u64,
+    /// Max number of regions scanned for split check at the same time.
+    pub split_check_concurrency: usize,
+    /// A region is not split any more once the store has this many regions,
+    /// 0 means no limit.
+    pub max_regions_per_store: usize,
     pub pd_heartbeat_tick_interval: u64,
+    /// The pd heartbeat tick interval is divided into this many sub-ticks, every
+    /// sub-tick heartbeats the next 1/pd_heartbeat_sub_ticks of the regions
+    /// round-robin, so the heartbeats don't reach pd in a burst.
+    pub pd_heartbeat_sub_ticks: u64,
     pub pd_store_heartbeat_tick_interval: u64,
     pub snap_mgr_gc_tick_interval: u64,
+    // The snap gc and compact check ticks are randomized within this ratio of
+    // their intervals, so the stores don't delete or compact files at the same
+    // time. Must be in [0, 1).
+    pub tick_jitter_ratio: f64,
     pub snap_gc_timeout: u64,
+    // Big snapshots take longer to transfer, so the gc timeout (secs) of a
+    // sending snapshot is extended by this value for every MB of it, but
+    // never exceeds max_snap_gc_timeout.
+    pub snap_gc_timeout_per_mb: u64,
+    pub max_snap_gc_timeout: u64,
+    // Max number of snapshots sent or received at the same time in each
+    // direction, the others are queued until they are retried.
+    pub concurrent_snap_limit: usize,
+    // Max number of snapshots waiting to be applied or being applied, a new
+    // snapshot is dropped beyond it and the leader sends it again later.
+    pub concurrent_apply_snap_limit: usize,
+    // If true, a snapshot file and its directory are synced before the file
+    // is renamed to its final name, so it's never torn after a crash.
+    pub snap_sync: bool,
 
     pub notify_capacity: usize,
     pub messages_per_tick: usize,
+    // Max number of regions whose raft ready are handled in one event loop
+    // iteration, the others are left to the next iteration.
+    pub max_ready_regions_per_tick: usize,
+    // Max number of split or conf change results of a region handled in one
+    // raft ready pass, the others are deferred to the following passes.
+    pub max_admin_results_per_ready: usize,
+
+    // Max number of keys deleted in one write batch when destroying a region.
+    pub delete_batch_size: usize,
+
+    // Interval (ms) to check whether regions need a range compaction.
+    pub region_compact_check_interval: u64,
+    // When the tombstones left by deleted keys of a region exceed this value,
+    // the range of the region will be compacted.
+    pub region_compact_tombstones_threshold: u64,
+
+    // Interval (ms) to check the consistency of a region led by the store, the
+    // replicas hash the region data and compare with the leader. 0 disables it.
+    // The hash is computed on the raftstore thread when the command is applied,
+    // so the event loop stalls while the whole region is scanned.
+    pub consistency_check_interval: u64,
+
+    // When a region has so many proposals waiting to be applied, new normal
+    // proposals will be rejected with a server busy error.
+    pub region_max_pending_proposals: usize,
+
+    // When a store worker has so many queued tasks, or so many regions are
+    // waiting for their raft ready, the store is overloaded and new writes are
+    // rejected with a server busy error suggesting to back off
+    // store_busy_backoff_ms (ms).
+    pub store_busy_pending_tasks: usize,
+    pub store_busy_pending_ready_regions: usize,
+    pub store_busy_backoff_ms: u64,
+
+    // Panic instead of returning an error when an entry at or below the applied
+    // index is applied, it means the apply state is corrupted.
+    pub panic_on_apply_regression: bool,
+
+    // Applied commands are written without WAL, raft log is replayed after a
+    // crash. If true, commands producing an admin result like conf change or
+    // split are written with WAL and synced instead.
+    pub sync_log: bool,
+
+    // Count of the applied batches written with sync, shared by all the clones
+    // of the config, so the stores started with it can be checked together.
+    pub apply_sync_writes: Arc<AtomicUsize>,
+
+    // If not 0, applied commands are written with WAL instead, and the WAL is
+    // synced at most once per this interval (ms), so at most the writes of the
+    // last interval are lost when the machine crashes.
+    pub apply_sync_interval_ms: u64,
+
+    // Handling raft messages, readies or ticks which takes longer than this
+    // value (ms) is logged as slow.
+    pub slow_log_threshold_ms: u64,
+
+    // The store loads the regions listed in the region index on start. If true,
+    // it scans all the region meta, tombstone ones included, and rebuilds the
+    // index instead, which repairs a broken index.
+    pub rebuild_region_index: bool,
+
+    // A read-only store keeps replicating and applying raft logs, but rejects
+    // all the writes. It reports the flag to PD, which places only learners,
+    // the non-voting replicas, on it.
+    pub read_only: bool,
+
+    // Column families the engine must be opened with, the store refuses to
+    // start if any of them is missing.
+    pub required_cfs: Vec<String>,
 }
 
 impl Default for Config {
@@ -82,21 +217,53 @@ impl Default for Config {
             raft_base_tick_interval: RAFT_BASE_TICK_INTERVAL,
             raft_heartbeat_ticks: RAFT_HEARTBEAT_TICKS,
             raft_election_timeout_ticks: RAFT_ELECTION_TIMEOUT_TICKS,
+            raft_randomize_election: true,
+            raft_check_quorum: true,
+            raft_pre_vote: true,
             raft_max_size_per_msg: RAFT_MAX_SIZE_PER_MSG,
             raft_max_inflight_msgs: RAFT_MAX_INFLIGHT_MSGS,
+            raft_entry_cache_size: RAFT_ENTRY_CACHE_SIZE,
             raft_log_gc_tick_interval: RAFT_LOG_GC_INTERVAL,
             raft_log_gc_threshold: RAFT_LOG_GC_THRESHOLD,
             raft_log_gc_limit: RAFT_LOG_GC_LIMIT,
+            raft_log_size_warn_threshold: RAFT_LOG_SIZE_WARN_THRESHOLD,
             split_region_check_tick_interval: SPLIT_REGION_CHECK_TICK_INTERVAL,
             region_max_size: REGION_MAX_SIZE,
             region_split_size: REGION_SPLIT_SIZE,
             region_check_size_diff: REGION_CHECK_DIFF,
+            split_check_concurrency: SPLIT_CHECK_CONCURRENCY,
+            max_regions_per_store: 0,
             pd_heartbeat_tick_interval: PD_HEARTBEAT_TICK_INTERVAL_MS,
+            pd_heartbeat_sub_ticks: PD_HEARTBEAT_SUB_TICKS,
             pd_store_heartbeat_tick_interval: PD_STORE_HEARTBEAT_TICK_INTERVAL_MS,
             notify_capacity: DEFAULT_NOTIFY_CAPACITY,
             snap_mgr_gc_tick_interval: DEFAULT_MGR_GC_TICK_INTERVAL_MS,
+            tick_jitter_ratio: TICK_JITTER_RATIO,
             snap_gc_timeout: DEFAULT_SNAP_GC_TIMEOUT_SECS,
+            snap_gc_timeout_per_mb: DEFAULT_SNAP_GC_TIMEOUT_PER_MB_SECS,
+            max_snap_gc_timeout: DEFAULT_MAX_SNAP_GC_TIMEOUT_SECS,
+            concurrent_snap_limit: DEFAULT_CONCURRENT_SNAP_LIMIT,
+            concurrent_apply_snap_limit: DEFAULT_CONCURRENT_APPLY_SNAP_LIMIT,
+            snap_sync: true,
             messages_per_tick: DEFAULT_MESSAGES_PER_TICK,
+            max_ready_regions_per_tick: MAX_READY_REGIONS_PER_TICK,
+            max_admin_results_per_ready: MAX_ADMIN_RESULTS_PER_READY,
+            delete_batch_size: DEFAULT_DELETE_BATCH_SIZE,
+            region_compact_check_interval: REGION_COMPACT_CHECK_TICK_INTERVAL,
+            region_compact_tombstones_threshold: REGION_COMPACT_TOMBSTONES_THRESHOLD,
+            consistency_check_interval: 0,
+            region_max_pending_proposals: REGION_MAX_PENDING_PROPOSALS,
+            store_busy_pending_tasks: STORE_BUSY_PENDING_TASKS,
+            store_busy_pending_ready_regions: STORE_BUSY_PENDING_READY_REGIONS,
+            store_busy_backoff_ms: STORE_BUSY_BACKOFF_MS,
+            panic_on_apply_regression: false,
+            sync_log: false,
+            apply_sync_writes: Arc::new(AtomicUsize::new(0)),
+            apply_sync_interval_ms: 0,
+            slow_log_threshold_ms: SLOW_LOG_THRESHOLD_MS,
+            rebuild_region_index: false,
+            read_only: false,
+            required_cfs: DEFAULT_CFS.iter().map(|cf| cf.to_string()).collect(),
         }
     }
 }
@@ -118,6 +285,53 @@ impl Config {
                                 self.region_split_size));
         }
 
+        if self.max_snap_gc_timeout < self.snap_gc_timeout {
+            return Err(box_err!("max snap gc timeout {} must >= snap gc timeout {}",
+                                self.max_snap_gc_timeout,
+                                self.snap_gc_timeout));
+        }
+
+        if self.delete_batch_size == 0 {
+            return Err(box_err!("delete batch size must > 0"));
+        }
+
+        if self.region_max_pending_proposals == 0 {
+            return Err(box_err!("region max pending proposals must > 0"));
+        }
+
+        if self.max_ready_regions_per_tick == 0 {
+            return Err(box_err!("max ready regions per tick must > 0"));
+        }
+
+        if self.pd_heartbeat_sub_ticks == 0 {
+            return Err(box_err!("pd heartbeat sub ticks must > 0"));
+        }
+
+        if self.tick_jitter_ratio < 0.0 || self.tick_jitter_ratio >= 1.0 {
+            return Err(box_err!("tick jitter ratio must be in [0, 1), not {}",
+                                self.tick_jitter_ratio));
+        }
+
+        if self.split_check_concurrency == 0 {
+            return Err(box_err!("split check concurrency must > 0"));
+        }
+
+        if self.concurrent_snap_limit == 0 {
+            return Err(box_err!("concurrent snap limit must > 0"));
+        }
+
+        if self.concurrent_apply_snap_limit == 0 {
+            return Err(box_err!("concurrent apply snap limit must > 0"));
+        }
+
+        if self.max_admin_results_per_ready == 0 {
+            return Err(box_err!("max admin results per ready must > 0"));
+        }
+
+        if self.store_busy_pending_tasks == 0 || self.store_busy_pending_ready_regions == 0 {
+            return Err(box_err!("store busy pending tasks and pending ready regions must > 0"));
+        }
+
         Ok(())
     }
 }
@@ -45,6 +45,34 @@ impl Snapshot {
     pub fn cf_names(&self) -> Vec<&str> {
         self.db.cf_names()
     }
+
+    /// Get the values of `keys` from this snapshot. The keys are still read
+    /// one by one, but all reads share the same read options pinned to this
+    /// snapshot.
+    pub fn multi_get_value(&self, keys: &[&[u8]]) -> Result<Vec<Option<DBVector>>> {
+        let mut opt = ReadOptions::new();
+        unsafe {
+            opt.set_snapshot(&self.snap);
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(try!(self.db.get_opt(key, &opt)));
+        }
+        Ok(values)
+    }
+
+    pub fn multi_get_value_cf(&self, cf: &str, keys: &[&[u8]]) -> Result<Vec<Option<DBVector>>> {
+        let handle = try!(rocksdb::get_cf_handle(&self.db, cf));
+        let mut opt = ReadOptions::new();
+        unsafe {
+            opt.set_snapshot(&self.snap);
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(try!(self.db.get_cf_opt(*handle, key, &opt)));
+        }
+        Ok(values)
+    }
 }
 
 impl Drop for Snapshot {
@@ -125,6 +153,13 @@ pub trait Iterable {
         iter.seek(key.into());
         Ok(iter.kv())
     }
+
+    // like `seek`, only on a specific column family.
+    fn seek_cf(&self, cf: &str, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut iter = try!(self.new_iterator_cf(cf));
+        iter.seek(key.into());
+        Ok(iter.kv())
+    }
 }
 
 fn scan_impl<F>(mut it: DBIterator, start_key: &[u8], end_key: &[u8], f: &mut F) -> Result<()>
@@ -377,4 +412,25 @@ mod tests {
 
         assert_eq!(data.len(), 2);
     }
+
+    #[test]
+    fn test_seek_cf() {
+        let path = TempDir::new("var").unwrap();
+        let engine = Arc::new(rocksdb::new_engine(path.path().to_str().unwrap(), &["cf"]).unwrap());
+        let handle = engine.cf_handle("cf").unwrap();
+
+        engine.put(b"a1", b"v1").unwrap();
+        engine.put_cf(*handle, b"b1", b"v2").unwrap();
+
+        fn check<T: Iterable>(seeker: &T) {
+            assert!(seeker.seek(b"b").unwrap().is_none());
+            let pair = seeker.seek_cf("cf", b"b").unwrap().unwrap();
+            assert_eq!(pair, (b"b1".to_vec(), b"v2".to_vec()));
+            assert!(seeker.seek_cf("cf", b"b2").unwrap().is_none());
+            assert!(seeker.seek_cf("foo", b"b").is_err());
+        }
+
+        check(engine.as_ref());
+        check(&Snapshot::new(engine.clone()));
+    }
 }
@@ -47,6 +47,15 @@ pub const REGION_META_PREFIX: u8 = 0x03;
 pub const REGION_META_PREFIX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
 pub const REGION_META_MIN_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
 pub const REGION_META_MAX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX + 1];
+// Ids of the live (not tombstone) regions, so the store can load them on start
+// without iterating all region meta, tombstone ones included.
+pub const REGION_INDEX_PREFIX: u8 = 0x04;
+pub const REGION_INDEX_PREFIX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_INDEX_PREFIX];
+pub const REGION_INDEX_MIN_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_INDEX_PREFIX];
+pub const REGION_INDEX_MAX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_INDEX_PREFIX + 1];
+// Written after the region index is built, a store without it must build
+// the index from the region meta first.
+pub const REGION_INDEX_BUILT_KEY: &'static [u8] = &[LOCAL_PREFIX, 0x05];
 
 // Following are the suffix after the local prefix.
 // For region id
@@ -147,6 +156,27 @@ pub fn region_state_key(region_id: u64) -> Vec<u8> {
     make_region_meta_key(region_id, REGION_STATE_SUFFIX)
 }
 
+pub fn region_index_key(region_id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(REGION_INDEX_PREFIX_KEY.len() + mem::size_of::<u64>());
+    key.extend_from_slice(REGION_INDEX_PREFIX_KEY);
+    // no need to check error here, can't panic;
+    key.write_u64::<BigEndian>(region_id).unwrap();
+    key
+}
+
+/// Get the region id from the region index key generated by `region_index_key`.
+pub fn decode_region_index_key(key: &[u8]) -> Result<u64> {
+    if REGION_INDEX_PREFIX_KEY.len() + mem::size_of::<u64>() != key.len() {
+        return Err(box_err!("invalid region index key length for key {}", escape(key)));
+    }
+
+    if !key.starts_with(REGION_INDEX_PREFIX_KEY) {
+        return Err(box_err!("invalid region index prefix for key {}", escape(key)));
+    }
+
+    Ok(BigEndian::read_u64(&key[REGION_INDEX_PREFIX_KEY.len()..]))
+}
+
 pub fn validate_data_key(key: &[u8]) -> Result<()> {
     if !key.starts_with(DATA_PREFIX_KEY) {
         return Err(box_err!("invalid data key {}, must start with {}",
@@ -164,9 +194,22 @@ pub fn data_key(key: &[u8]) -> Vec<u8> {
     v
 }
 
+/// Get the origin key from the data key generated by `data_key`.
+pub fn try_origin_key(key: &[u8]) -> Result<&[u8]> {
+    try!(validate_data_key(key));
+    Ok(&key[DATA_PREFIX_KEY.len()..])
+}
+
+/// Same as `try_origin_key`, but the key must be a valid data key.
+///
+/// # Panics
+///
+/// Panics if the key is empty or doesn't start with `DATA_PREFIX`.
 pub fn origin_key(key: &[u8]) -> &[u8] {
-    validate_data_key(key).expect("");
-    &key[DATA_PREFIX_KEY.len()..]
+    match try_origin_key(key) {
+        Ok(k) => k,
+        Err(e) => panic!("{:?}", e),
+    }
 }
 
 /// Get the `start_key` of current region in encoded form.
@@ -183,10 +226,55 @@ pub fn enc_end_key(region: &Region) -> Vec<u8> {
     }
 }
 
+/// Get the start and end keys of the region without the data prefix by
+/// decoding `enc_start_key` and `enc_end_key`. An empty end key means the
+/// region reaches the end of the keyspace.
+pub fn region_key_bounds(region: &Region) -> (Vec<u8>, Vec<u8>) {
+    let start_key = origin_key(&enc_start_key(region)).to_vec();
+    let enc_end_key = enc_end_key(region);
+    let end_key = if enc_end_key.as_slice() == DATA_MAX_KEY {
+        vec![]
+    } else {
+        origin_key(&enc_end_key).to_vec()
+    };
+    (start_key, end_key)
+}
+
+/// The key of a region in a store's region ranges, ordered by region end key.
+///
+/// A region with an empty end key reaches the end of the keyspace, it's
+/// represented by `Max` so that it sorts after all other regions.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EndKey {
+    Key(Vec<u8>),
+    Max,
+}
+
+impl EndKey {
+    pub fn from_region(region: &Region) -> EndKey {
+        if region.get_end_key().is_empty() {
+            EndKey::Max
+        } else {
+            EndKey::Key(data_key(region.get_end_key()))
+        }
+    }
+
+    /// Get the position of the region's start key, the first region whose end
+    /// key is after it is the only one that may contain the start key.
+    pub fn from_start_key(region: &Region) -> EndKey {
+        EndKey::Key(enc_start_key(region))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+    use std::collections::Bound::{Excluded, Unbounded};
+    use kvproto::metapb::Region;
+    use rand::{self, Rng};
+    use raftstore::store::util;
 
     #[test]
     fn test_region_id_key() {
@@ -256,6 +344,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_region_index_key() {
+        for id in vec![1, 1024, u64::max_value()] {
+            let key = region_index_key(id);
+            assert!(key.as_slice() > REGION_INDEX_MIN_KEY);
+            assert!(key.as_slice() < REGION_INDEX_MAX_KEY);
+            assert_eq!(decode_region_index_key(&key).unwrap(), id);
+        }
+        assert!(REGION_INDEX_BUILT_KEY >= REGION_INDEX_MAX_KEY);
+
+        assert!(decode_region_index_key(&region_state_key(1)).is_err());
+        assert!(decode_region_index_key(REGION_INDEX_PREFIX_KEY).is_err());
+    }
+
     #[test]
     fn test_raft_log_key() {
         for region_id in 1..10 {
@@ -270,5 +372,111 @@ mod tests {
     fn test_data_key() {
         validate_data_key(&data_key(b"abc")).unwrap();
         validate_data_key(b"abc").unwrap_err();
+
+        // keys look like the internal prefixes.
+        let cases: Vec<&[u8]> = vec![b"",
+                                     b"z",
+                                     b"zz",
+                                     DATA_MAX_KEY,
+                                     LOCAL_MIN_KEY,
+                                     STORE_IDENT_KEY,
+                                     REGION_META_PREFIX_KEY,
+                                     b"\xff\xff"];
+        for key in cases {
+            let data = data_key(key);
+            validate_data_key(&data).unwrap();
+            assert_eq!(origin_key(&data), key);
+            assert_eq!(try_origin_key(&data).unwrap(), key);
+        }
+
+        let invalid_keys: Vec<&[u8]> = vec![EMPTY_KEY, LOCAL_MIN_KEY, STORE_IDENT_KEY, b"abc"];
+        for key in invalid_keys {
+            try_origin_key(key).unwrap_err();
+        }
+        assert_eq!(try_origin_key(DATA_PREFIX_KEY).unwrap(), EMPTY_KEY);
+    }
+
+    #[test]
+    fn test_region_key_bounds() {
+        let mut region = Region::new();
+        region.set_start_key(b"a".to_vec());
+        assert_eq!(region_key_bounds(&region), (b"a".to_vec(), vec![]));
+
+        // split the region at k.
+        let mut right = region.clone();
+        right.set_start_key(b"k".to_vec());
+        region.set_end_key(b"k".to_vec());
+        assert_eq!(region_key_bounds(&region), (b"a".to_vec(), b"k".to_vec()));
+        assert_eq!(region_key_bounds(&right), (b"k".to_vec(), vec![]));
+
+        // keys which look like the encoded max key are not mistaken for it.
+        region.set_end_key(DATA_MAX_KEY.to_vec());
+        assert_eq!(region_key_bounds(&region), (b"a".to_vec(), DATA_MAX_KEY.to_vec()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_origin_key_empty() {
+        origin_key(EMPTY_KEY);
+    }
+
+    #[test]
+    fn test_data_key_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let len = rng.gen_range(0, 32);
+            let key: Vec<u8> = rng.gen_iter().take(len).collect();
+            let data = data_key(&key);
+            assert_eq!(data.len(), key.len() + DATA_PREFIX_KEY.len());
+            assert_eq!(origin_key(&data), key.as_slice());
+            // the order of keys is kept.
+            let other: Vec<u8> = rng.gen_iter().take(len).collect();
+            assert_eq!(data.cmp(&data_key(&other)), key.cmp(&other));
+        }
+    }
+
+    fn new_region(id: u64, start: &[u8], end: &[u8]) -> Region {
+        let mut region = Region::new();
+        region.set_id(id);
+        region.set_start_key(start.to_vec());
+        region.set_end_key(end.to_vec());
+        region
+    }
+
+    #[test]
+    fn test_end_key_order() {
+        let regions = vec![new_region(1, b"", b"a"),
+                           new_region(2, b"a", b"\xff\xff"),
+                           new_region(3, b"\xff\xff", b"")];
+        let mut ranges = BTreeMap::new();
+        for r in &regions {
+            assert!(ranges.insert(EndKey::from_region(r), r.get_id()).is_none());
+        }
+        // the region reaching the end of the keyspace sorts last.
+        let ids: Vec<_> = ranges.values().cloned().collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert!(EndKey::Key(data_key(b"\xff\xff\xff")) < EndKey::Max);
+        assert!(EndKey::Key(DATA_MAX_KEY.to_vec()) < EndKey::Max);
+
+        // the first region whose end key is after the start key of a new region
+        // is the only one that may overlap with it.
+        let cases = vec![(new_region(4, b"", b""), Some(1), true),
+                         (new_region(4, b"a", b"b"), Some(2), true),
+                         (new_region(4, b"\xff\xff", b""), Some(3), true),
+                         (new_region(4, b"\xff\xff\xff", b""), Some(3), true),
+                         (new_region(4, b"\xff", b"\xff\xff"), Some(2), true)];
+        for (region, exp_id, exp_overlapped) in cases {
+            let id = ranges.range(Excluded(&EndKey::from_start_key(&region)), Unbounded::<&EndKey>)
+                .next()
+                .map(|(_, &id)| id);
+            assert_eq!(id, exp_id, "{:?}", region);
+            let exist = &regions[id.unwrap() as usize - 1];
+            assert_eq!(util::is_region_overlapped(exist, &region), exp_overlapped);
+        }
+
+        // removing the last region leaves nothing after its start key.
+        assert_eq!(ranges.remove(&EndKey::from_region(&regions[2])), Some(3));
+        let start = EndKey::from_start_key(&regions[2]);
+        assert!(ranges.range(Excluded(&start), Unbounded::<&EndKey>).next().is_none());
     }
 }
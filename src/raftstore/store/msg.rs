@@ -24,6 +24,7 @@ use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
 use kvproto::metapb::RegionEpoch;
 use raft::SnapshotStatus;
 use util::event::Event;
+use util::escape;
 
 pub type Callback = Box<FnBox(RaftCmdResponse) -> Result<()> + Send>;
 
@@ -35,10 +36,18 @@ pub enum Tick {
     PdHeartbeat,
     PdStoreHeartbeat,
     SnapGc,
+    CompactCheck,
+    ConsistencyCheck,
+    ApplySync,
 }
 
 pub enum Msg {
     Quit,
+    // Transfer all the leaders to other stores before quitting, the event
+    // loop quits anyway after the timeout.
+    PrepareShutdown {
+        timeout: Duration,
+    },
 
     // For notify.
     RaftMessage(RaftMessage),
@@ -46,6 +55,13 @@ pub enum Msg {
         request: RaftCmdRequest,
         callback: Callback,
     },
+    // Transfer leader, but only call the callback after the leadership
+    // actually moves to the target peer or the timeout elapses.
+    TransferLeaderAndWait {
+        request: RaftCmdRequest,
+        timeout: Duration,
+        callback: Callback,
+    },
 
     // For split check
     SplitCheckResult {
@@ -65,6 +81,9 @@ pub enum Msg {
         to_peer_id: u64,
     },
 
+    // Wake up the event loop to handle the raft ready left by the last tick.
+    RaftReady,
+
     // For snapshot stats.
     SnapshotStats,
     SnapApplyRes {
@@ -75,14 +94,25 @@ pub enum Msg {
         region_id: u64,
         snap: Option<Snapshot>,
     },
+
+    // Compact the data in [start_key, end_key) in the background, an empty
+    // end_key means the end of all the data.
+    CompactRange {
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+    },
 }
 
 impl fmt::Debug for Msg {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Msg::Quit => write!(fmt, "Quit"),
+            Msg::PrepareShutdown { ref timeout } => write!(fmt, "Prepare shutdown {:?}", timeout),
             Msg::RaftMessage(_) => write!(fmt, "Raft Message"),
             Msg::RaftCmd { .. } => write!(fmt, "Raft Command"),
+            Msg::TransferLeaderAndWait { ref timeout, .. } => {
+                write!(fmt, "Transfer leader and wait {:?}", timeout)
+            }
             Msg::SplitCheckResult { .. } => write!(fmt, "Split Check Result"),
             Msg::ReportSnapshot { ref region_id, ref to_peer_id, ref status } => {
                 write!(fmt,
@@ -97,6 +127,7 @@ impl fmt::Debug for Msg {
                        to_peer_id,
                        region_id)
             }
+            Msg::RaftReady => write!(fmt, "Raft Ready"),
             Msg::SnapshotStats => write!(fmt, "Snapshot stats"),
             Msg::SnapApplyRes { region_id, is_success } => {
                 write!(fmt,
@@ -110,6 +141,12 @@ impl fmt::Debug for Msg {
                        region_id,
                        snap.is_some())
             }
+            Msg::CompactRange { ref start_key, ref end_key } => {
+                write!(fmt,
+                       "CompactRange [start_key: {}, end_key: {}]",
+                       escape(start_key),
+                       escape(end_key))
+            }
         }
     }
 }
@@ -12,13 +12,19 @@
 // limitations under the License.
 
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::vec::Vec;
 use std::default::Default;
+use std::cell::RefCell;
+use std::{mem, result};
+use std::time::{Duration, Instant};
 
-use rocksdb::{DB, WriteBatch, Writable};
+use rocksdb::{DB, WriteBatch, Writable, WriteOptions};
 use protobuf::{self, Message};
 use uuid::Uuid;
+use byteorder::{BigEndian, WriteBytesExt};
+use crc::crc32::{self, Digest, Hasher32};
 
 use kvproto::metapb;
 use kvproto::raftpb::{self, ConfChangeType, Snapshot as RaftSnapshot};
@@ -26,13 +32,14 @@ use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, ChangePeerRequest, Cm
                           AdminCmdType, Request, Response, AdminRequest, AdminResponse,
                           TransferLeaderRequest, TransferLeaderResponse};
 use kvproto::raft_serverpb::{RaftMessage, RaftApplyState, RaftTruncatedState, PeerState,
-                             RegionLocalState};
+                             RegionLocalState, RaftLocalState};
 use raft::{self, RawNode, StateRole, SnapshotStatus, Ready, ProgressState};
 use raftstore::{Result, Error};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::coprocessor::split_observer::SplitObserver;
 use util::{escape, HandyRwLock, SlowTimer, rocksdb};
-use pd::PdClient;
+use pd::{PdClient, RegionFlow};
+use storage::engine::DEFAULT_CFNAME;
 use super::store::Store;
 use super::peer_storage::{PeerStorage, ApplySnapResult, write_initial_state};
 use super::util;
@@ -48,6 +55,24 @@ pub struct PendingCmd {
     pub uuid: Uuid,
     pub term: u64,
     pub cb: Callback,
+    // when the command is proposed, used to observe how long it waits to be applied.
+    pub propose_time: Instant,
+}
+
+// A transfer leader command whose callback is only called after the
+// leadership is observed to move to the target peer, or the deadline passes.
+struct PendingTransferLeader {
+    cmd: PendingCmd,
+    peer_id: u64,
+    deadline: Instant,
+}
+
+// A conf change proposed while another one is still pending. Raft replaces it
+// with an empty entry at `index`, so it is re-proposed once that entry is applied.
+struct QueuedConfChange {
+    index: u64,
+    cmd: PendingCmd,
+    req: RaftCmdRequest,
 }
 
 #[derive(Debug)]
@@ -64,6 +89,10 @@ pub enum ExecResult {
         left: metapb::Region,
         right: metapb::Region,
     },
+    ComputeHash {
+        index: u64,
+        hash: Vec<u8>,
+    },
 }
 
 // When we apply commands in handing ready, we should also need a way to
@@ -82,6 +111,7 @@ pub struct ReadyResult {
 struct PendingCmdQueue {
     normals: VecDeque<PendingCmd>,
     conf_change: Option<PendingCmd>,
+    queued_conf_change: Option<QueuedConfChange>,
     uuids: HashSet<Uuid>,
 }
 
@@ -125,6 +155,19 @@ impl PendingCmdQueue {
         self.uuids.insert(cmd.uuid);
         self.conf_change = Some(cmd);
     }
+
+    fn take_queued_conf_change(&mut self) -> Option<QueuedConfChange> {
+        let queued = self.queued_conf_change.take();
+        if let Some(ref queued) = queued {
+            self.uuids.remove(&queued.cmd.uuid);
+        }
+        queued
+    }
+
+    fn queue_conf_change(&mut self, queued: QueuedConfChange) {
+        self.uuids.insert(queued.cmd.uuid);
+        self.queued_conf_change = Some(queued);
+    }
 }
 
 /// Call the callback of `cmd` that the region is removed.
@@ -147,10 +190,29 @@ pub struct Peer {
     region_id: u64,
     pub raft_group: RawNode<PeerStorage>,
     pending_cmds: PendingCmdQueue,
+    pending_transfer_leader: Option<PendingTransferLeader>,
+    max_pending_proposals: usize,
+    panic_on_apply_regression: bool,
+    sync_log: bool,
+    apply_sync_writes: Arc<AtomicUsize>,
+    // If set, applied writes are written with WAL, which is synced at most
+    // once per interval.
+    apply_sync_interval: Option<Duration>,
+    last_apply_sync: Instant,
+    // applied writes are written with WAL but not synced yet.
+    apply_unsynced: bool,
+    slow_log_threshold_ms: u64,
     peer_cache: Arc<RwLock<HashMap<u64, metapb::Peer>>>,
     coprocessor_host: CoprocessorHost,
     /// an inaccurate difference in region size since last reset.
     pub size_diff_hint: u64,
+    /// an inaccurate count of tombstones left by deleted keys since last compaction.
+    pub delete_keys_hint: u64,
+    /// read and write flow since last pd heartbeat.
+    pub flow: RegionFlow,
+    // the applied index and the data hash of the last compute hash command,
+    // verified against the hash of the leader later.
+    consistency_state: Option<(u64, Vec<u8>)>,
     // if we remove ourself in ChangePeer remove, we should set this flag, then
     // any following committed logs in same Ready should be applied failed.
     pending_remove: bool,
@@ -205,19 +267,28 @@ impl Peer {
         let sched = store.snap_scheduler();
         let tag = format!("[region {}] {}", region.get_id(), peer_id);
 
-        let ps = try!(PeerStorage::new(store.engine(), &region, sched, tag.clone()));
+        let mut ps = try!(PeerStorage::new(store.engine(), &region, sched, tag.clone()));
+        ps.set_entry_cache_limit(cfg.raft_entry_cache_size);
 
         let applied_index = ps.applied_index();
 
+        let (min_election_tick, max_election_tick) = if cfg.raft_randomize_election {
+            (0, 0)
+        } else {
+            (cfg.raft_election_timeout_ticks, cfg.raft_election_timeout_ticks + 1)
+        };
         let raft_cfg = raft::Config {
             id: peer_id,
             peers: vec![],
             election_tick: cfg.raft_election_timeout_ticks,
+            min_election_tick: min_election_tick,
+            max_election_tick: max_election_tick,
             heartbeat_tick: cfg.raft_heartbeat_ticks,
             max_size_per_msg: cfg.raft_max_size_per_msg,
             max_inflight_msgs: cfg.raft_max_inflight_msgs,
             applied: applied_index,
-            check_quorum: true,
+            check_quorum: cfg.raft_check_quorum,
+            pre_vote: cfg.raft_pre_vote,
             tag: tag.clone(),
         };
 
@@ -229,9 +300,25 @@ impl Peer {
             region_id: region.get_id(),
             raft_group: raft_group,
             pending_cmds: Default::default(),
+            pending_transfer_leader: None,
+            max_pending_proposals: cfg.region_max_pending_proposals,
+            panic_on_apply_regression: cfg.panic_on_apply_regression,
+            sync_log: cfg.sync_log,
+            apply_sync_writes: cfg.apply_sync_writes.clone(),
+            apply_sync_interval: if cfg.apply_sync_interval_ms == 0 {
+                None
+            } else {
+                Some(Duration::from_millis(cfg.apply_sync_interval_ms))
+            },
+            last_apply_sync: Instant::now(),
+            apply_unsynced: false,
+            slow_log_threshold_ms: cfg.slow_log_threshold_ms,
             peer_cache: store.peer_cache(),
             coprocessor_host: CoprocessorHost::new(),
             size_diff_hint: 0,
+            delete_keys_hint: 0,
+            flow: RegionFlow::default(),
+            consistency_state: None,
             pending_remove: false,
             tag: tag,
         };
@@ -246,10 +333,25 @@ impl Peer {
         Ok(peer)
     }
 
-    pub fn destroy(&mut self) -> Result<()> {
+    pub fn destroy(&mut self, delete_batch_size: usize) -> Result<()> {
+        let t = SlowTimer::from_millis(self.slow_log_threshold_ms);
+        try!(self.clear(delete_batch_size, true));
+        slow_log!(t, "{} destroy itself", self.tag);
+        Ok(())
+    }
+
+    /// Delete all the data and meta of the peer but the hard state, so it can
+    /// be recreated as an uninitialized peer and receive a new snapshot.
+    pub fn clear_data(&mut self, delete_batch_size: usize) -> Result<()> {
+        let t = SlowTimer::from_millis(self.slow_log_threshold_ms);
+        try!(self.clear(delete_batch_size, false));
+        slow_log!(t, "{} clear its data", self.tag);
+        Ok(())
+    }
+
+    fn clear(&mut self, delete_batch_size: usize, tombstone: bool) -> Result<()> {
         // TODO maybe very slow
         // Delete all data in this peer.
-        let t = SlowTimer::new();
 
         // TODO: figure out a way to unit test this.
         let peer_id = self.peer_id();
@@ -259,21 +361,40 @@ impl Peer {
         if let Some(cmd) = self.pending_cmds.conf_change.take() {
             notify_region_removed(self.region_id, peer_id, cmd);
         }
+        if let Some(queued) = self.pending_cmds.queued_conf_change.take() {
+            notify_region_removed(self.region_id, peer_id, queued.cmd);
+        }
+        if let Some(pending) = self.pending_transfer_leader.take() {
+            notify_region_removed(self.region_id, peer_id, pending.cmd);
+        }
 
-        let wb = WriteBatch::new();
-        try!(self.get_store().scan_region(self.engine.as_ref(),
-                                          &mut |key, _| {
-                                              try!(wb.delete(key));
-                                              Ok(true)
-                                          }));
-        let mut local_state = RegionLocalState::new();
-        local_state.set_state(PeerState::Tombstone);
-        local_state.set_region(self.get_store().get_region().clone());
-        try!(wb.put_msg(&keys::region_state_key(self.region_id), &local_state));
+        // Delete the data in batches so a large region won't produce a huge write batch,
+        // the states are written with the last batch.
+        let engine = self.engine.clone();
+        let wb = try!(self.get_store().clear_region_in_batches(delete_batch_size, |wb| {
+            try!(engine.write(wb));
+            Ok(())
+        }));
+        try!(wb.delete(&keys::apply_state_key(self.region_id)));
+        if tombstone {
+            try!(wb.delete(&keys::raft_state_key(self.region_id)));
+            let mut local_state = RegionLocalState::new();
+            local_state.set_state(PeerState::Tombstone);
+            local_state.set_region(self.get_store().get_region().clone());
+            try!(wb.put_msg(&keys::region_state_key(self.region_id), &local_state));
+        } else {
+            // keep the term and vote, so the recreated peer can't vote twice in a term.
+            let mut hard_state = self.get_store().raft_state.get_hard_state().clone();
+            hard_state.set_commit(0);
+            let mut raft_state = RaftLocalState::new();
+            raft_state.set_hard_state(hard_state);
+            try!(wb.put_msg(&keys::raft_state_key(self.region_id), &raft_state));
+            try!(wb.delete(&keys::region_state_key(self.region_id)));
+        }
+        try!(wb.delete(&keys::region_index_key(self.region_id)));
         try!(self.engine.write(wb));
 
         self.coprocessor_host.shutdown();
-        slow_log!(t, "{} destroy itself", self.tag);
 
         Ok(())
     }
@@ -291,10 +412,23 @@ impl Peer {
         self.get_store().get_region()
     }
 
+    /// Get the start and end keys the region owns without the data prefix, an
+    /// empty end key means the region reaches the end of the keyspace.
+    pub fn region_key_bounds(&self) -> (Vec<u8>, Vec<u8>) {
+        keys::region_key_bounds(self.region())
+    }
+
     pub fn peer_id(&self) -> u64 {
         self.peer.get_id()
     }
 
+    /// Scan all user kv of the region in key order on a consistent engine
+    /// snapshot, keys are passed to `f` without the data prefix.
+    pub fn scan_region_kv(&self, f: &mut FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<()> {
+        let snap = self.get_store().raw_snapshot();
+        self.get_store().scan_region_kv(&snap, f)
+    }
+
     pub fn get_raft_status(&self) -> raft::Status {
         self.raft_group.status()
     }
@@ -367,7 +501,7 @@ impl Peer {
             ready.snapshot = RaftSnapshot::new();
         }
 
-        let t = SlowTimer::new();
+        let t = SlowTimer::from_millis(self.slow_log_threshold_ms);
 
         self.send_ready_metric(&ready);
 
@@ -385,6 +519,16 @@ impl Peer {
 
         let exec_results = try!(self.handle_raft_commit_entries(&ready.committed_entries));
 
+        // The proposals of a leader which steps down may still be committed by the
+        // new leader, so they are notified with an unknown result now rather than
+        // waiting for a newer entry to be applied. The committed ones were applied
+        // above.
+        if let Some(ref ss) = ready.ss {
+            if ss.raft_state != StateRole::Leader {
+                self.notify_pending_normals();
+            }
+        }
+
         slow_log!(t,
                   "{} handle ready, entries {}, committed entries {}, messages \
                    {}, snapshot {}, hard state changed {}",
@@ -401,6 +545,7 @@ impl Peer {
         }
 
         self.raft_group.advance(ready);
+        self.check_pending_transfer_leader();
         Ok(Some(ReadyResult {
             apply_snap_result: apply_result,
             exec_results: exec_results,
@@ -441,23 +586,34 @@ impl Peer {
             // return immediately. Note that this command may fail, we can view it just as an advice
             return cmd.cb.call_box((make_transfer_leader_response(),));
         } else if get_change_peer_cmd(&req).is_some() {
-            if self.raft_group.raft.pending_conf {
+            let pending_conf = self.raft_group.raft.pending_conf;
+            if pending_conf && self.pending_cmds.queued_conf_change.is_some() {
                 return Err(box_err!("there is a pending conf change, try later"));
             }
-            if let Some(cmd) = self.pending_cmds.take_conf_change() {
-                // if it loses leader ship before confchange is replicated, there may be
-                // a stale pending conf change before next conf change is applied. If it
-                // becomes leader again with the stale pending conf change, will enter
-                // this block, so we notify leadership may have changed.
-                self.notify_not_leader(cmd);
+            if !pending_conf {
+                if let Some(cmd) = self.pending_cmds.take_conf_change() {
+                    // if it loses leader ship before confchange is replicated, there may be
+                    // a stale pending conf change before next conf change is applied. If it
+                    // becomes leader again with the stale pending conf change, will enter
+                    // this block, so we notify leadership may have changed.
+                    self.notify_not_leader(cmd);
+                }
             }
 
-            if let Err(e) = self.propose_conf_change(req) {
+            let last_index = self.raft_group.raft.raft_log.last_index();
+            if let Err(e) = self.propose_conf_change(req.clone()) {
                 cmd_resp::bind_error(&mut err_resp, e);
                 return cmd.cb.call_box((err_resp,));
             }
 
-            self.pending_cmds.set_conf_change(cmd);
+            self.on_conf_change_proposed(cmd, req, pending_conf, last_index);
+        } else if self.pending_cmds.normals.len() >= self.max_pending_proposals {
+            metric_incr!("raftstore.propose.busy");
+            let reason = format!("{} has {} pending proposals",
+                                 self.tag,
+                                 self.pending_cmds.normals.len());
+            cmd_resp::bind_error(&mut err_resp, Error::ServerIsBusy(reason, 0));
+            return cmd.cb.call_box((err_resp,));
         } else if let Err(e) = self.propose_normal(req) {
             cmd_resp::bind_error(&mut err_resp, e);
             return cmd.cb.call_box((err_resp,));
@@ -469,6 +625,116 @@ impl Peer {
         Ok(())
     }
 
+    /// Transfer leader to the peer in `req`, but unlike `propose`, only call
+    /// the callback after the leadership is observed to move to the target, or
+    /// `timeout` elapses.
+    pub fn propose_transfer_leader(&mut self,
+                                   cmd: PendingCmd,
+                                   req: RaftCmdRequest,
+                                   mut err_resp: RaftCmdResponse,
+                                   timeout: Duration)
+                                   -> Result<()> {
+        debug!("{} propose transfer leader with uuid {:?}", self.tag, cmd.uuid);
+
+        if let Err(e) = self.check_epoch(&req) {
+            cmd_resp::bind_error(&mut err_resp, e);
+            return cmd.cb.call_box((err_resp,));
+        }
+
+        let peer = match get_transfer_leader_cmd(&req) {
+            None => {
+                cmd_resp::bind_error(&mut err_resp, box_err!("missing transfer leader request"));
+                return cmd.cb.call_box((err_resp,));
+            }
+            Some(transfer_leader) => transfer_leader.get_peer().clone(),
+        };
+
+        if peer.get_id() == self.peer_id() {
+            let mut resp = make_transfer_leader_response();
+            cmd_resp::bind_uuid(&mut resp, cmd.uuid);
+            cmd_resp::bind_term(&mut resp, self.term());
+            return cmd.cb.call_box((resp,));
+        }
+
+        if !self.is_tranfer_leader_allowed(&peer) {
+            info!("{} transfer leader message {:?} rejected directly",
+                  self.tag,
+                  req);
+            cmd_resp::bind_error(&mut err_resp,
+                                 box_err!("transfer leader to {:?} is not allowed now", peer));
+            return cmd.cb.call_box((err_resp,));
+        }
+
+        if let Some(pending) = self.pending_transfer_leader.take() {
+            // raft aborts the previous transfer when a new one begins.
+            let resp = cmd_resp::err_resp(box_err!("transfer leader to {} is superseded",
+                                                   pending.peer_id),
+                                          pending.cmd.uuid,
+                                          self.term());
+            if let Err(e) = pending.cmd.cb.call_box((resp,)) {
+                error!("{} failed to notify {}: {:?}", self.tag, pending.cmd.uuid, e);
+            }
+        }
+
+        self.transfer_leader(&peer);
+        self.pending_transfer_leader = Some(PendingTransferLeader {
+            cmd: cmd,
+            peer_id: peer.get_id(),
+            deadline: Instant::now() + timeout,
+        });
+        Ok(())
+    }
+
+    // Call the callback of the pending transfer leader command if the
+    // leadership has moved.
+    fn check_pending_transfer_leader(&mut self) {
+        let leader_id = self.leader_id();
+        let pending = match self.pending_transfer_leader.take() {
+            None => return,
+            Some(pending) => pending,
+        };
+
+        if leader_id == pending.peer_id {
+            info!("{} leadership is transferred to {}, notify {}",
+                  self.tag,
+                  leader_id,
+                  pending.cmd.uuid);
+            let mut resp = make_transfer_leader_response();
+            cmd_resp::bind_uuid(&mut resp, pending.cmd.uuid);
+            cmd_resp::bind_term(&mut resp, self.term());
+            if let Err(e) = pending.cmd.cb.call_box((resp,)) {
+                error!("{} failed to notify {}: {:?}", self.tag, pending.cmd.uuid, e);
+            }
+        } else if !self.is_leader() && leader_id != raft::INVALID_ID {
+            // leadership moved to another peer.
+            self.notify_not_leader(pending.cmd);
+        } else {
+            self.pending_transfer_leader = Some(pending);
+        }
+    }
+
+    /// Fail the pending transfer leader command if it is not finished in time.
+    pub fn check_transfer_leader_timeout(&mut self) {
+        let expired = match self.pending_transfer_leader {
+            None => return,
+            Some(ref pending) => Instant::now() >= pending.deadline,
+        };
+        if !expired {
+            return;
+        }
+
+        let pending = self.pending_transfer_leader.take().unwrap();
+        warn!("{} transfer leader to {} timeout, notify {}",
+              self.tag,
+              pending.peer_id,
+              pending.cmd.uuid);
+        let e = Error::Timeout(format!("transfer leader to {}", pending.peer_id));
+        let resp = cmd_resp::err_resp(e, pending.cmd.uuid, self.term());
+        if let Err(e) = pending.cmd.cb.call_box((resp,)) {
+            error!("{} failed to notify {}: {:?}", self.tag, pending.cmd.uuid, e);
+        }
+    }
+
     /// Call the callback of `cmd` that leadership may have been changed.
     ///
     /// Please note that, `NotLeader` here doesn't mean that currently this
@@ -486,6 +752,32 @@ impl Peer {
         }
     }
 
+    fn notify_pending_normals(&mut self) {
+        let term = self.term();
+        let mut count = 0;
+        while let Some(cmd) = self.pending_cmds.pop_normal(term) {
+            let resp = cmd_resp::err_resp(Error::ResultUnknown(self.region_id), cmd.uuid, term);
+            if let Err(e) = cmd.cb.call_box((resp,)) {
+                error!("{} failed to notify pending command {}: {:?}",
+                       self.tag,
+                       cmd.uuid,
+                       e);
+            }
+            count += 1;
+        }
+        // a queued conf change can only be re-proposed by the leader.
+        if let Some(queued) = self.pending_cmds.take_queued_conf_change() {
+            self.notify_not_leader(queued.cmd);
+            count += 1;
+        }
+        if count > 0 {
+            info!("{} is not leader any more, notify {} pending commands",
+                  self.tag,
+                  count);
+            metric_count!("raftstore.step_down.notify_pending", count as i64);
+        }
+    }
+
     fn propose_normal(&mut self, mut cmd: RaftCmdRequest) -> Result<()> {
         // TODO: validate request for unexpected changes.
         try!(self.coprocessor_host.pre_propose(&self.raft_group.get_store(), &mut cmd));
@@ -502,12 +794,34 @@ impl Peer {
         self.raft_group.transfer_leader(peer.get_id());
     }
 
+    /// Transfer leader to the follower with the most matched log, returns false
+    /// if there is no such follower.
+    pub fn transfer_leader_to_best_follower(&mut self) -> bool {
+        let peer_id = self.peer_id();
+        let target = {
+            let status = self.raft_group.status();
+            status.progress
+                .iter()
+                .filter(|&(&id, progress)| id != peer_id && !progress.is_learner)
+                .max_by_key(|&(_, progress)| progress.matched)
+                .map(|(&id, _)| id)
+        };
+        match target.and_then(|id| self.get_peer_from_cache(id)) {
+            Some(peer) => {
+                self.transfer_leader(&peer);
+                true
+            }
+            None => false,
+        }
+    }
+
     fn is_tranfer_leader_allowed(&self, peer: &metapb::Peer) -> bool {
         let peer_id = peer.get_id();
         let status = self.raft_group.status();
 
-        if !status.progress.contains_key(&peer_id) {
-            return false;
+        match status.progress.get(&peer_id) {
+            Some(progress) if !progress.is_learner => {}
+            _ => return false,
         }
 
         for progress in status.progress.values() {
@@ -520,6 +834,75 @@ impl Peer {
         last_index <= status.progress[&peer_id].matched + TRANSFER_LEADER_ALLOW_LOG_LAG
     }
 
+    /// Track a proposed conf change. If another conf change was pending when it
+    /// was proposed, raft has replaced it with an empty entry, so queue it to be
+    /// re-proposed when that entry is applied.
+    fn on_conf_change_proposed(&mut self,
+                               cmd: PendingCmd,
+                               req: RaftCmdRequest,
+                               dropped: bool,
+                               prev_last_index: u64) {
+        if !dropped {
+            self.pending_cmds.set_conf_change(cmd);
+            return;
+        }
+        let index = self.raft_group.raft.raft_log.last_index();
+        if index == prev_last_index {
+            // raft dropped the proposal without appending anything.
+            self.notify_not_leader(cmd);
+            return;
+        }
+        info!("{} conf change {} is dropped at index {}, queue it",
+              self.tag,
+              cmd.uuid,
+              index);
+        self.pending_cmds.queue_conf_change(QueuedConfChange {
+            index: index,
+            cmd: cmd,
+            req: req,
+        });
+    }
+
+    /// Re-propose the queued conf change if the empty entry at `index` is the
+    /// one raft put in its place.
+    fn repropose_conf_change(&mut self, index: u64) {
+        match self.pending_cmds.queued_conf_change {
+            Some(ref queued) if queued.index == index => {}
+            _ => return,
+        }
+        let QueuedConfChange { cmd, mut req, .. } = self.pending_cmds
+            .take_queued_conf_change()
+            .unwrap();
+        if !self.is_leader() {
+            self.notify_not_leader(cmd);
+            return;
+        }
+
+        // the request was checked against the epoch before the previous conf change
+        // was applied, so propose it with the current one.
+        req.mut_header().set_region_epoch(self.region().get_region_epoch().clone());
+        if let Err(e) = self.check_epoch(&req) {
+            let resp = cmd_resp::err_resp(e, cmd.uuid, self.term());
+            if let Err(e) = cmd.cb.call_box((resp,)) {
+                error!("{} callback err {:?}", self.tag, e);
+            }
+            return;
+        }
+
+        metric_incr!("raftstore.propose.conf_change_repropose");
+        info!("{} re-propose conf change {}", self.tag, cmd.uuid);
+        let pending_conf = self.raft_group.raft.pending_conf;
+        let last_index = self.raft_group.raft.raft_log.last_index();
+        if let Err(e) = self.propose_conf_change(req.clone()) {
+            let resp = cmd_resp::err_resp(e, cmd.uuid, self.term());
+            if let Err(e) = cmd.cb.call_box((resp,)) {
+                error!("{} callback err {:?}", self.tag, e);
+            }
+            return;
+        }
+        self.on_conf_change_proposed(cmd, req, pending_conf, last_index);
+    }
+
     fn propose_conf_change(&mut self, cmd: RaftCmdRequest) -> Result<()> {
         metric_incr!("raftstore.propose.conf_change");
         let data = try!(cmd.write_to_bytes());
@@ -538,11 +921,13 @@ impl Peer {
         self.raft_group.propose_conf_change(cc).map_err(From::from)
     }
 
-    fn check_epoch(&self, req: &RaftCmdRequest) -> Result<()> {
+    pub fn check_epoch(&self, req: &RaftCmdRequest) -> Result<()> {
         let (mut check_ver, mut check_conf_ver) = (false, false);
         if req.has_admin_request() {
             match req.get_admin_request().get_cmd_type() {
                 AdminCmdType::CompactLog |
+                AdminCmdType::ComputeHash |
+                AdminCmdType::VerifyHash |
                 AdminCmdType::InvalidAdmin => {}
                 AdminCmdType::Split => check_ver = true,
                 AdminCmdType::ChangePeer => check_conf_ver = true,
@@ -579,7 +964,8 @@ impl Peer {
                                                   sent {:?}",
                                                  self.region_id,
                                                  latest_epoch,
-                                                 from_epoch)));
+                                                 from_epoch),
+                                         vec![latest_region.clone()]));
         }
 
         Ok(())
@@ -671,20 +1057,31 @@ impl Peer {
         // If we send multiple ConfChange commands, only first one will be proposed correctly,
         // others will be saved as a normal entry with no data, so we must re-propose these
         // commands again.
-        let t = SlowTimer::new();
+        let t = SlowTimer::from_millis(self.slow_log_threshold_ms);
         let mut results = vec![];
         let committed_count = committed_entries.len();
+        let mut batch = ApplyBatch::new(self.get_store().apply_state.clone());
         for entry in committed_entries {
-            let res = try!(match entry.get_entry_type() {
-                raftpb::EntryType::EntryNormal => self.handle_raft_entry_normal(entry),
-                raftpb::EntryType::EntryConfChange => self.handle_raft_entry_conf_change(entry),
-            });
+            let res = match entry.get_entry_type() {
+                raftpb::EntryType::EntryNormal => self.handle_raft_entry_normal(&mut batch, entry),
+                raftpb::EntryType::EntryConfChange => {
+                    self.handle_raft_entry_conf_change(&mut batch, entry)
+                }
+            };
 
-            if let Some(res) = res {
-                results.push(res);
+            match res {
+                Ok(Some(res)) => results.push(res),
+                Ok(None) => {}
+                Err(e) => {
+                    // the error of the batch has been reported to the callbacks.
+                    let _ = self.flush_apply_batch(&mut batch);
+                    return Err(e);
+                }
             }
         }
+        let _ = self.flush_apply_batch(&mut batch);
 
+        metric_time!("raftstore.apply_batch", t.elapsed());
         slow_log!(t,
                   "{} handle {} committed entries",
                   self.tag,
@@ -692,25 +1089,29 @@ impl Peer {
         Ok(results)
     }
 
-    fn handle_raft_entry_normal(&mut self, entry: &raftpb::Entry) -> Result<Option<ExecResult>> {
+    fn handle_raft_entry_normal(&mut self,
+                                batch: &mut ApplyBatch,
+                                entry: &raftpb::Entry)
+                                -> Result<Option<ExecResult>> {
         let index = entry.get_index();
         let term = entry.get_term();
         let data = entry.get_data();
 
         if data.is_empty() {
             // when a peer become leader, it will send an empty entry.
-            let wb = WriteBatch::new();
             let mut state = self.get_store().apply_state.clone();
             state.set_applied_index(index);
-            try!(wb.put_msg(&keys::apply_state_key(self.region_id), &state));
-            try!(self.engine.write_without_wal(wb));
+            try!(batch.wb.put_msg(&keys::apply_state_key(self.region_id), &state));
+            batch.last_index = index;
             self.mut_store().apply_state = state;
+            // or it is a conf change dropped by raft, which needs to be proposed again.
+            self.repropose_conf_change(index);
             return Ok(None);
         }
 
         let cmd = try!(protobuf::parse_from_bytes::<RaftCmdRequest>(data));
         // no need to return error here.
-        self.process_raft_cmd(index, term, cmd).or_else(|e| {
+        self.process_raft_cmd(batch, index, term, cmd).or_else(|e| {
             error!("{} process raft command at index {} err: {:?}",
                    self.tag,
                    index,
@@ -720,6 +1121,7 @@ impl Peer {
     }
 
     fn handle_raft_entry_conf_change(&mut self,
+                                     batch: &mut ApplyBatch,
                                      entry: &raftpb::Entry)
                                      -> Result<Option<ExecResult>> {
         let index = entry.get_index();
@@ -727,7 +1129,7 @@ impl Peer {
         let mut conf_change =
             try!(protobuf::parse_from_bytes::<raftpb::ConfChange>(entry.get_data()));
         let cmd = try!(protobuf::parse_from_bytes::<RaftCmdRequest>(conf_change.get_context()));
-        let res = match self.process_raft_cmd(index, term, cmd) {
+        let res = match self.process_raft_cmd(batch, index, term, cmd) {
             a @ Ok(Some(_)) => a,
             e => {
                 error!("{} process raft command at index {} err: {:?}",
@@ -746,11 +1148,15 @@ impl Peer {
         res
     }
 
-    fn find_cb(&mut self, uuid: Uuid, term: u64, cmd: &RaftCmdRequest) -> Option<Callback> {
+    fn find_pending_cmd(&mut self,
+                        uuid: Uuid,
+                        term: u64,
+                        cmd: &RaftCmdRequest)
+                        -> Option<PendingCmd> {
         if get_change_peer_cmd(cmd).is_some() {
             if let Some(cmd) = self.pending_cmds.take_conf_change() {
                 if cmd.uuid == uuid {
-                    return Some(cmd.cb);
+                    return Some(cmd);
                 } else {
                     self.notify_not_leader(cmd);
                 }
@@ -759,7 +1165,7 @@ impl Peer {
         }
         while let Some(head) = self.pending_cmds.pop_normal(term) {
             if head.uuid == uuid {
-                return Some(head.cb);
+                return Some(head);
             }
             // because of the lack of original RaftCmdRequest, we skip calling
             // coprocessor here.
@@ -770,6 +1176,7 @@ impl Peer {
     }
 
     fn process_raft_cmd(&mut self,
+                        batch: &mut ApplyBatch,
                         index: u64,
                         term: u64,
                         cmd: RaftCmdRequest)
@@ -779,8 +1186,8 @@ impl Peer {
         }
 
         let uuid = util::get_uuid_from_req(&cmd).unwrap();
-        let cb = self.find_cb(uuid, term, &cmd);
-        let (mut resp, exec_result) = self.apply_raft_cmd(index, &cmd).unwrap_or_else(|e| {
+        let pending_cmd = self.find_pending_cmd(uuid, term, &cmd);
+        let (mut resp, exec_result) = self.apply_raft_cmd(batch, index, &cmd).unwrap_or_else(|e| {
             error!("{} apply raft command err {:?}", self.tag, e);
             (cmd_resp::new_error(e), None)
         });
@@ -790,29 +1197,132 @@ impl Peer {
                uuid,
                resp.get_header());
 
-        if cb.is_none() {
+        if pending_cmd.is_none() {
             return Ok(exec_result);
         }
 
-        let cb = cb.unwrap();
+        let PendingCmd { cb, propose_time, .. } = pending_cmd.unwrap();
+        metric_time!("raftstore.propose_to_apply", propose_time.elapsed());
         self.coprocessor_host.post_apply(self.raft_group.get_store(), &cmd, &mut resp);
         // TODO: if we have exec_result, maybe we should return this callback too. Outer
         // store will call it after handing exec result.
         // Bind uuid here.
         cmd_resp::bind_uuid(&mut resp, uuid);
         cmd_resp::bind_term(&mut resp, self.term());
-        if let Err(e) = cb.call_box((resp,)) {
-            error!("{} callback err {:?}", self.tag, e);
-        }
+        // the callback is called after the batch is written.
+        batch.cbs.push((uuid, cb, resp));
 
         Ok(exec_result)
     }
 
+    /// Write the modifies of the batch to the engine, then call the callbacks
+    /// of the applied commands. If the write fails or an apply state can't be
+    /// saved, the apply state is rolled back and all the callbacks are called
+    /// with the error.
+    fn flush_apply_batch(&mut self, batch: &mut ApplyBatch) -> Result<()> {
+        let res = if let Some(e) = batch.err.take() {
+            // the batch misses the apply state of some commands, drop it.
+            batch.wb = WriteBatch::new();
+            Err(e)
+        } else if batch.wb.count() == 0 {
+            Ok(())
+        } else {
+            let wb = mem::replace(&mut batch.wb, WriteBatch::new());
+            let engine = self.engine.clone();
+            let wal = self.apply_sync_interval.is_some();
+            let sync = batch.sync || self.is_apply_sync_due();
+            let sync_writes = self.apply_sync_writes.clone();
+            let write = |wb: WriteBatch, sync: bool| {
+                if sync {
+                    metric_incr!("raftstore.apply_sync_write");
+                    sync_writes.fetch_add(1, Ordering::Relaxed);
+                    let mut opts = WriteOptions::new();
+                    opts.set_sync(true);
+                    return engine.write_opt(wb, &opts);
+                }
+                if wal {
+                    engine.write(wb)
+                } else {
+                    engine.write_without_wal(wb)
+                }
+            };
+            let res = write_apply_batch(self.region_id, batch.last_index, wb, sync, write);
+            if res.is_ok() {
+                self.on_apply_written(sync);
+            }
+            res
+        };
+        batch.sync = false;
+
+        match res {
+            Ok(_) => batch.apply_state = self.get_store().apply_state.clone(),
+            Err(ref e) => {
+                error!("{} commit batch failed err {:?}", self.tag, e);
+                self.mut_store().apply_state = batch.apply_state.clone();
+            }
+        }
+
+        let term = self.term();
+        for (uuid, cb, mut resp) in batch.cbs.drain(..) {
+            if let Err(ref e) = res {
+                resp = cmd_resp::new_error(box_err!("{:?}", e));
+                cmd_resp::bind_uuid(&mut resp, uuid);
+                cmd_resp::bind_term(&mut resp, term);
+            }
+            if let Err(e) = cb.call_box((resp,)) {
+                error!("{} callback err {:?}", self.tag, e);
+            }
+        }
+
+        res
+    }
+
     pub fn term(&self) -> u64 {
         self.raft_group.raft.term
     }
 
+    fn is_apply_sync_due(&self) -> bool {
+        match self.apply_sync_interval {
+            Some(interval) => self.last_apply_sync.elapsed() >= interval,
+            None => false,
+        }
+    }
+
+    fn on_apply_written(&mut self, synced: bool) {
+        if synced {
+            if self.apply_sync_interval.is_some() {
+                metric_incr!("raftstore.apply_sync");
+            }
+            self.last_apply_sync = Instant::now();
+            self.apply_unsynced = false;
+        } else if self.apply_sync_interval.is_some() {
+            self.apply_unsynced = true;
+        }
+    }
+
+    /// Sync the applied writes left in the WAL if the interval elapses, so
+    /// they are durable even if no more commands are applied.
+    pub fn sync_apply(&mut self) -> Result<()> {
+        if !self.apply_unsynced || !self.is_apply_sync_due() {
+            return Ok(());
+        }
+        // The snapshot worker owns the apply state when applying a snapshot.
+        if self.pending_remove || self.is_applying_snap() {
+            return Ok(());
+        }
+        // Rewrite the apply state with sync, the WAL before it is synced too.
+        let wb = WriteBatch::new();
+        try!(wb.put_msg(&keys::apply_state_key(self.region_id),
+                        &self.get_store().apply_state));
+        let mut opts = WriteOptions::new();
+        opts.set_sync(true);
+        box_try!(self.engine.write_opt(wb, &opts));
+        self.on_apply_written(true);
+        Ok(())
+    }
+
     fn apply_raft_cmd(&mut self,
+                      batch: &mut ApplyBatch,
                       index: u64,
                       req: &RaftCmdRequest)
                       -> Result<(RaftCmdResponse, Option<ExecResult>)> {
@@ -825,50 +1335,76 @@ impl Peer {
             return Ok((resp, None));
         }
 
-        let last_applied_index = self.get_store().applied_index();
-        if last_applied_index >= index {
-            return Err(box_err!("applied index moved backwards, {} >= {}",
-                                last_applied_index,
-                                index));
+        try!(check_apply_index(self.region_id,
+                               self.peer_id(),
+                               self.get_store().applied_index(),
+                               index,
+                               self.panic_on_apply_regression));
+
+        // Commands reading the engine must see the writes of the previous ones,
+        // and admin commands change the storage after written, so the pending
+        // writes are flushed first.
+        let write_only = util::is_write_only(req);
+        if !write_only {
+            let _ = self.flush_apply_batch(batch);
         }
 
-        let engine = self.engine.clone();
-        let mut ctx = ExecContext {
-            snap: Snapshot::new(engine),
-            apply_state: self.get_store().apply_state.clone(),
-            wb: WriteBatch::new(),
-            req: req,
+        let (mut resp, exec_result, saved) = {
+            let engine = self.engine.clone();
+            let mut ctx = ExecContext {
+                snap: Snapshot::new(engine),
+                index: index,
+                apply_state: self.get_store().apply_state.clone(),
+                wb: &batch.wb,
+                req: req,
+                written: if write_only {
+                    None
+                } else {
+                    Some(RefCell::new(HashMap::new()))
+                },
+            };
+            let (resp, exec_result) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
+                error!("{} execute raft command err: {:?}", self.tag, e);
+                (cmd_resp::new_error(e), None)
+            });
+
+            ctx.apply_state.set_applied_index(index);
+            let saved = ctx.save(self.region_id);
+            self.mut_store().apply_state = ctx.apply_state;
+            (resp, exec_result, saved)
         };
-        let (mut resp, exec_result) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
-            error!("{} execute raft command err: {:?}", self.tag, e);
-            (cmd_resp::new_error(e), None)
-        });
+        if let Err(e) = saved {
+            if batch.err.is_none() {
+                batch.err = Some(e);
+            }
+        }
+        batch.last_index = index;
+        if need_sync_apply(self.sync_log, &exec_result) {
+            batch.sync = true;
+        }
 
-        ctx.apply_state.set_applied_index(index);
-        ctx.save(self.region_id).expect("save state must not fail");
+        if write_only {
+            return Ok((resp, exec_result));
+        }
 
         // Commit write and change storage fields atomically.
-        let mut storage = self.mut_store();
-        match storage.engine.write_without_wal(ctx.wb) {
+        match self.flush_apply_batch(batch) {
             Ok(_) => {
-                storage.apply_state = ctx.apply_state;
-
                 if let Some(ref exec_result) = exec_result {
+                    let mut storage = self.mut_store();
                     match *exec_result {
                         ExecResult::ChangePeer { ref region, .. } => {
                             storage.region = region.clone();
                         }
-                        ExecResult::CompactLog { .. } => {}
+                        ExecResult::CompactLog { .. } |
+                        ExecResult::ComputeHash { .. } => {}
                         ExecResult::SplitRegion { ref left, .. } => {
                             storage.region = left.clone();
                         }
                     }
                 };
             }
-            Err(e) => {
-                error!("{} commit batch failed err {:?}", storage.tag, e);
-                resp = cmd_resp::message_error(e);
-            }
+            Err(e) => resp = cmd_resp::new_error(e),
         };
 
         Ok((resp, exec_result))
@@ -899,20 +1435,155 @@ fn get_change_peer_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerRequest> {
     Some(req.get_change_peer())
 }
 
+// Hash all the key value pairs of the region in all column families.
+fn compute_region_hash(snap: &Snapshot, region: &metapb::Region) -> Result<Vec<u8>> {
+    let mut digest = Digest::new(crc32::IEEE);
+    let (start_key, end_key) = (keys::enc_start_key(region), keys::enc_end_key(region));
+    for cf in snap.cf_names() {
+        digest.write(cf.as_bytes());
+        try!(snap.scan_cf(cf,
+                          &start_key,
+                          &end_key,
+                          &mut |key, value| {
+            digest.write(key);
+            digest.write(value);
+            Ok(true)
+        }));
+    }
+    let mut hash = Vec::with_capacity(4);
+    hash.write_u32::<BigEndian>(digest.sum32()).unwrap();
+    Ok(hash)
+}
+
+/// The modifies of the committed entries in one ready, they are written to
+/// the engine at once unless a command needs to read them.
+struct ApplyBatch {
+    wb: WriteBatch,
+    // the apply state of the last write, restored when the batch fails to write.
+    apply_state: RaftApplyState,
+    last_index: u64,
+    // the batch contains a command that must be written durably.
+    sync: bool,
+    // the first error saving the apply state, the batch fails when flushed.
+    err: Option<Error>,
+    cbs: Vec<(Uuid, Callback, RaftCmdResponse)>,
+}
+
+impl ApplyBatch {
+    fn new(apply_state: RaftApplyState) -> ApplyBatch {
+        ApplyBatch {
+            wb: WriteBatch::new(),
+            last_index: apply_state.get_applied_index(),
+            apply_state: apply_state,
+            sync: false,
+            err: None,
+            cbs: vec![],
+        }
+    }
+}
+
 struct ExecContext<'a> {
     pub snap: Snapshot,
+    // the index of the entry being applied.
+    pub index: u64,
     pub apply_state: RaftApplyState,
-    pub wb: WriteBatch,
+    pub wb: &'a WriteBatch,
     pub req: &'a RaftCmdRequest,
+    // the values written by the requests of the command, keyed by column family
+    // and key, `None` for a deleted key. Only kept if the command reads keys.
+    pub written: Option<RefCell<HashMap<(String, Vec<u8>), Option<Vec<u8>>>>>,
 }
 
 impl<'a> ExecContext<'a> {
+    // Read the key from the snapshot taken before the command is executed, but
+    // see the values written by the previous requests of the same command.
+    fn get_value(&self, cf: Option<&str>, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(ref written) = self.written {
+            let written_key = (cf.unwrap_or(DEFAULT_CFNAME).to_owned(), key.to_vec());
+            if let Some(v) = written.borrow().get(&written_key) {
+                return Ok(v.clone());
+            }
+        }
+        let v = match cf {
+            Some(cf) => try!(self.snap.get_value_cf(cf, key)),
+            None => try!(self.snap.get_value(key)),
+        };
+        Ok(v.map(|v| v.to_vec()))
+    }
+
+    fn record_write(&self, cf: Option<&str>, key: &[u8], value: Option<&[u8]>) {
+        if let Some(ref written) = self.written {
+            let written_key = (cf.unwrap_or(DEFAULT_CFNAME).to_owned(), key.to_vec());
+            written.borrow_mut().insert(written_key, value.map(|v| v.to_vec()));
+        }
+    }
+
     fn save(&self, region_id: u64) -> Result<()> {
-        try!(self.wb.put_msg(&keys::apply_state_key(region_id), &self.apply_state));
+        if let Err(e) = self.wb.put_msg(&keys::apply_state_key(region_id), &self.apply_state) {
+            return Err(box_err!("[region {}] failed to save apply state at index {}, {} \
+                                 modifies in batch: {:?}",
+                                region_id,
+                                self.apply_state.get_applied_index(),
+                                self.wb.count(),
+                                e));
+        }
         Ok(())
     }
 }
 
+// Check the entry at `index` is after the last applied one, otherwise the apply
+// state may be corrupted, so a distinct metric is recorded.
+fn check_apply_index(region_id: u64,
+                     peer_id: u64,
+                     last_applied_index: u64,
+                     index: u64,
+                     panic_on_regression: bool)
+                     -> Result<()> {
+    if last_applied_index < index {
+        return Ok(());
+    }
+    metric_incr!("raftstore.apply_index_regression");
+    let msg = format!("[region {}] {} applied index moved backwards, last applied {}, \
+                       applying {}",
+                      region_id,
+                      peer_id,
+                      last_applied_index,
+                      index);
+    if panic_on_regression {
+        panic!("{}", msg);
+    }
+    Err(box_err!(msg))
+}
+
+// Commands producing an admin result change the region or the raft log, they
+// are written durably if `sync_log` is set.
+fn need_sync_apply(sync_log: bool, exec_result: &Option<ExecResult>) -> bool {
+    sync_log && exec_result.is_some()
+}
+
+// Write the batch of an applied command with `write`, `sync` tells whether the
+// batch must be written with WAL and synced. Any error is wrapped with the
+// region id, the applied index and the count of modifies in the batch.
+fn write_apply_batch<F>(region_id: u64,
+                        index: u64,
+                        wb: WriteBatch,
+                        sync: bool,
+                        write: F)
+                        -> Result<()>
+    where F: FnOnce(WriteBatch, bool) -> result::Result<(), String>
+{
+    let modifies = wb.count();
+    if let Err(e) = write(wb, sync) {
+        return Err(box_err!("[region {}] failed to write batch at applied index {}, {} \
+                             modifies in batch: {}",
+                            region_id,
+                            index,
+                            modifies,
+                            e));
+    }
+    Ok(())
+}
+
 // Here we implement all commands.
 impl Peer {
     fn exec_raft_cmd(&mut self,
@@ -938,6 +1609,8 @@ impl Peer {
             AdminCmdType::ChangePeer => self.exec_change_peer(ctx, request),
             AdminCmdType::Split => self.exec_split(ctx, request),
             AdminCmdType::CompactLog => self.exec_compact_log(ctx, request),
+            AdminCmdType::ComputeHash => self.exec_compute_hash(ctx, request),
+            AdminCmdType::VerifyHash => self.exec_verify_hash(ctx, request),
             AdminCmdType::TransferLeader => Err(box_err!("transfer leader won't exec")),
             AdminCmdType::InvalidAdmin => Err(box_err!("unsupported admin command type")),
         });
@@ -963,14 +1636,42 @@ impl Peer {
               util::conf_change_type_str(&change_type),
               region.get_region_epoch());
 
-        // TODO: we should need more check, like peer validation, duplicated id, etc.
+        // TODO: we should need more check, like peer validation, etc.
         let exists = util::find_peer(&region, store_id).is_some();
         let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
 
         region.mut_region_epoch().set_conf_ver(conf_ver);
 
         match change_type {
-            raftpb::ConfChangeType::AddNode => {
+            raftpb::ConfChangeType::AddNode if exists => {
+                // Only a learner can be added again, which promotes it to a voter.
+                metric_incr!("raftstore.promote_learner");
+                let pos = region.get_peers()
+                    .iter()
+                    .position(|p| p.get_id() == peer.get_id() && p.get_is_learner());
+                let pos = match pos {
+                    Some(pos) => pos,
+                    None => {
+                        error!("{} can't add duplicated peer {:?} to region {:?}",
+                               self.tag,
+                               peer,
+                               region);
+                        return Err(box_err!("can't add duplicated peer {:?} to region {:?}",
+                                            peer,
+                                            region));
+                    }
+                };
+                region.mut_peers()[pos].set_is_learner(false);
+                self.peer_cache.wl().insert(peer.get_id(), region.get_peers()[pos].clone());
+
+                metric_incr!("raftstore.promote_learner.success");
+                warn!("{} promote learner {:?} in region {:?}",
+                      self.tag,
+                      peer,
+                      self.region());
+            }
+            raftpb::ConfChangeType::AddNode |
+            raftpb::ConfChangeType::AddLearnerNode => {
                 metric_incr!("raftstore.add_peer");
                 if exists {
                     error!("{} can't add duplicated peer {:?} to region {:?}",
@@ -981,11 +1682,23 @@ impl Peer {
                                         peer,
                                         region));
                 }
+                if region.get_peers().iter().any(|p| p.get_id() == peer.get_id()) {
+                    error!("{} can't add peer {:?} to region {:?}, peer id {} already exists",
+                           self.tag,
+                           peer,
+                           region,
+                           peer.get_id());
+                    return Err(box_err!("peer id {} already exists in region {:?}",
+                                        peer.get_id(),
+                                        region));
+                }
                 // TODO: Do we allow adding peer in same node?
 
+                let mut peer = peer.clone();
+                peer.set_is_learner(change_type == raftpb::ConfChangeType::AddLearnerNode);
                 // Add this peer to cache.
                 self.peer_cache.wl().insert(peer.get_id(), peer.clone());
-                region.mut_peers().push(peer.clone());
+                region.mut_peers().push(peer);
 
                 metric_incr!("raftstore.add_peer.success");
 
@@ -1097,7 +1810,8 @@ impl Peer {
         let mut new_state = RegionLocalState::new();
         new_state.set_region(new_region.clone());
         try!(ctx.wb.put_msg(&keys::region_state_key(new_region.get_id()), &new_state));
-        try!(write_initial_state(&ctx.wb, new_region.get_id()));
+        try!(ctx.wb.put(&keys::region_index_key(new_region.get_id()), &[]));
+        try!(write_initial_state(ctx.wb, new_region.get_id()));
 
         let mut resp = AdminResponse::new();
         resp.mut_split().set_left(region.clone());
@@ -1134,17 +1848,76 @@ impl Peer {
             Some(ExecResult::CompactLog { state: ctx.apply_state.get_truncated_state().clone() })))
     }
 
+    // All the replicas hash the data of the region at the same applied index,
+    // the leader proposes its hash to be verified by the others afterwards.
+    fn exec_compute_hash(&mut self,
+                         ctx: &ExecContext,
+                         _: &AdminRequest)
+                         -> Result<(AdminResponse, Option<ExecResult>)> {
+        let index = ctx.index;
+        let hash = try!(compute_region_hash(&ctx.snap, self.region()));
+        self.consistency_state = Some((index, hash.clone()));
+        Ok((AdminResponse::new(),
+            Some(ExecResult::ComputeHash {
+            index: index,
+            hash: hash,
+        })))
+    }
+
+    fn exec_verify_hash(&mut self,
+                        _: &ExecContext,
+                        req: &AdminRequest)
+                        -> Result<(AdminResponse, Option<ExecResult>)> {
+        let verify_req = req.get_verify_hash();
+        let index = verify_req.get_index();
+        let expected = verify_req.get_hash();
+        match self.consistency_state {
+            Some((computed_index, ref hash)) if computed_index == index => {
+                if hash.as_slice() != expected {
+                    metric_incr!("raftstore.consistency_check.failed");
+                    error!("{} hash at index {} is {:?}, but the leader's is {:?}, the data \
+                            may be corrupted",
+                           self.tag,
+                           index,
+                           hash,
+                           expected);
+                } else {
+                    metric_incr!("raftstore.consistency_check.succeed");
+                    debug!("{} hash at index {} is verified", self.tag, index);
+                }
+            }
+            // The compute hash command is not applied here, e.g. the data is
+            // received by a snapshot later, nothing to verify.
+            _ => {
+                info!("{} no hash computed at index {}, skip verifying", self.tag, index);
+            }
+        }
+        Ok((AdminResponse::new(), None))
+    }
+
     fn exec_write_cmd(&mut self, ctx: &ExecContext) -> Result<RaftCmdResponse> {
         let requests = ctx.req.get_requests();
         let mut responses = Vec::with_capacity(requests.len());
 
+        // The region may be split after the command is proposed, all the keys are
+        // checked against the current region first, so a command with a key moved
+        // out is rejected as a whole rather than applied partly.
+        for req in requests {
+            for key in util::request_keys(req) {
+                try!(self.check_data_key(key));
+            }
+        }
+
         for req in requests {
             let cmd_type = req.get_cmd_type();
             let mut resp = try!(match cmd_type {
                 CmdType::Get => self.do_get(ctx, req),
+                CmdType::BatchGet => self.do_batch_get(ctx, req),
                 CmdType::Seek => self.do_seek(ctx, req),
                 CmdType::Put => self.do_put(ctx, req),
                 CmdType::Delete => self.do_delete(ctx, req),
+                CmdType::Append => self.do_append(ctx, req),
+                CmdType::CAS => self.do_cas(ctx, req),
                 CmdType::Snap => self.do_snap(ctx, req),
                 CmdType::Invalid => Err(box_err!("invalid cmd type, message maybe currupted")),
             });
@@ -1178,20 +1951,64 @@ impl Peer {
         } else {
             try!(ctx.snap.get_value(&keys::data_key(key)))
         };
+        self.flow.read_keys += 1;
+        self.flow.read_bytes += key.len() as u64;
         if let Some(res) = res {
+            self.flow.read_bytes += res.len() as u64;
             resp.mut_get().set_value(res.to_vec());
         }
 
         Ok(resp)
     }
 
+    fn do_batch_get(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
+        let batch_get = req.get_batch_get();
+        let keys = batch_get.get_keys();
+        let mut values = Vec::with_capacity(keys.len());
+        let mut found = Vec::with_capacity(keys.len());
+        // all the keys are read from the same snapshot.
+        for key in keys {
+            try!(self.check_data_key(key));
+            let res = if batch_get.has_cf() {
+                try!(ctx.snap.get_value_cf(batch_get.get_cf(), &keys::data_key(key)))
+            } else {
+                try!(ctx.snap.get_value(&keys::data_key(key)))
+            };
+            self.flow.read_keys += 1;
+            self.flow.read_bytes += key.len() as u64;
+            match res {
+                Some(res) => {
+                    self.flow.read_bytes += res.len() as u64;
+                    values.push(res.to_vec());
+                    found.push(true);
+                }
+                None => {
+                    values.push(vec![]);
+                    found.push(false);
+                }
+            }
+        }
+
+        let mut resp = Response::new();
+        resp.mut_batch_get().set_values(protobuf::RepeatedField::from_vec(values));
+        resp.mut_batch_get().set_found(found);
+        Ok(resp)
+    }
+
     fn do_seek(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
         let key = req.get_seek().get_key();
         try!(self.check_data_key(key));
 
         let mut resp = Response::new();
-        let res = try!(ctx.snap.seek(&keys::data_key(key)));
+        let res = if req.get_seek().has_cf() {
+            let cf = req.get_seek().get_cf();
+            try!(ctx.snap.seek_cf(cf, &keys::data_key(key)))
+        } else {
+            try!(ctx.snap.seek(&keys::data_key(key)))
+        };
         if let Some((k, v)) = res {
+            self.flow.read_keys += 1;
+            self.flow.read_bytes += (keys::origin_key(&k).len() + v.len()) as u64;
             resp.mut_seek().set_key(keys::origin_key(&k).to_vec());
             resp.mut_seek().set_value(v);
         }
@@ -1203,8 +2020,26 @@ impl Peer {
         let (key, value) = (req.get_put().get_key(), req.get_put().get_value());
         try!(self.check_data_key(key));
 
-        let resp = Response::new();
+        self.flow.written_keys += 1;
+        self.flow.written_bytes += (key.len() + value.len()) as u64;
+
+        let mut resp = Response::new();
         let key = keys::data_key(key);
+        let cf = if req.get_put().has_cf() {
+            Some(req.get_put().get_cf())
+        } else {
+            None
+        };
+        if req.get_put().get_return_prev() {
+            let prev = try!(ctx.get_value(cf, &key));
+            self.flow.read_keys += 1;
+            self.flow.read_bytes += key.len() as u64;
+            if let Some(prev) = prev {
+                self.flow.read_bytes += prev.len() as u64;
+                resp.mut_put().set_prev_value(prev);
+            }
+        }
+        ctx.record_write(cf, &key, Some(value));
         if let Some(diff) = self.size_diff_hint.checked_add(key.len() as u64) {
             self.size_diff_hint = diff;
         }
@@ -1213,13 +2048,108 @@ impl Peer {
         }
         self.size_diff_hint += key.len() as u64;
         self.size_diff_hint += value.len() as u64;
-        if req.get_put().has_cf() {
-            let cf = req.get_put().get_cf();
-            let handle = try!(rocksdb::get_cf_handle(&self.engine, cf));
-            try!(ctx.wb.put_cf(*handle, &key, value));
+        let cf = match cf {
+            Some(cf) => {
+                let handle = try!(rocksdb::get_cf_handle(&self.engine, cf));
+                try!(ctx.wb.put_cf(*handle, &key, value));
+                cf
+            }
+            None => {
+                try!(ctx.wb.put(&key, value));
+                DEFAULT_CFNAME
+            }
+        };
+        metric_count!(&format!("raftstore.apply.write_bytes.{}", cf),
+                      (key.len() + value.len()) as i64);
+        Ok(resp)
+    }
+
+    // The current value is read through the values written by the previous
+    // requests of the command, so appending to the same key twice in one
+    // command keeps both appends.
+    fn do_append(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
+        let (key, value) = (req.get_append().get_key(), req.get_append().get_value());
+        try!(self.check_data_key(key));
+
+        let key = keys::data_key(key);
+        let cf = if req.get_append().has_cf() {
+            Some(req.get_append().get_cf())
+        } else {
+            None
+        };
+        let mut new_value = try!(ctx.get_value(cf, &key)).unwrap_or_else(Vec::new);
+        new_value.extend_from_slice(value);
+        ctx.record_write(cf, &key, Some(&new_value));
+
+        self.flow.read_keys += 1;
+        self.flow.read_bytes += key.len() as u64;
+        self.flow.written_keys += 1;
+        self.flow.written_bytes += (key.len() + value.len()) as u64;
+        self.size_diff_hint += value.len() as u64;
+
+        match cf {
+            Some(cf) => {
+                let handle = try!(rocksdb::get_cf_handle(&self.engine, cf));
+                try!(ctx.wb.put_cf(*handle, &key, &new_value));
+            }
+            None => try!(ctx.wb.put(&key, &new_value)),
+        }
+
+        let mut resp = Response::new();
+        resp.mut_append().set_length(new_value.len() as u64);
+        Ok(resp)
+    }
+
+    // Like append, the current value sees the values written by the previous
+    // requests of the command. A request without the expected value only succeeds
+    // if the key doesn't exist.
+    fn do_cas(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
+        let cas = req.get_cas();
+        try!(self.check_data_key(cas.get_key()));
+
+        let key = keys::data_key(cas.get_key());
+        let cf = if cas.has_cf() {
+            Some(cas.get_cf())
         } else {
-            try!(ctx.wb.put(&key, value));
+            None
+        };
+        let current = try!(ctx.get_value(cf, &key));
+        self.flow.read_keys += 1;
+        self.flow.read_bytes += key.len() as u64;
+
+        let matched = match current {
+            Some(ref v) => cas.has_expected() && &**v == cas.get_expected(),
+            None => !cas.has_expected(),
+        };
+        let mut resp = Response::new();
+        resp.mut_cas().set_succeeded(matched);
+        if !matched {
+            if let Some(v) = current {
+                self.flow.read_bytes += v.len() as u64;
+                resp.mut_cas().set_current(v);
+            }
+            return Ok(resp);
         }
+
+        let value = cas.get_value();
+        ctx.record_write(cf, &key, Some(value));
+        self.flow.written_keys += 1;
+        self.flow.written_bytes += (key.len() + value.len()) as u64;
+        self.size_diff_hint += value.len() as u64;
+
+        let cf = match cf {
+            Some(cf) => {
+                let handle = try!(rocksdb::get_cf_handle(&self.engine, cf));
+                try!(ctx.wb.put_cf(*handle, &key, value));
+                cf
+            }
+            None => {
+                try!(ctx.wb.put(&key, value));
+                DEFAULT_CFNAME
+            }
+        };
+        metric_count!(&format!("raftstore.apply.write_bytes.{}", cf),
+                      (key.len() + value.len()) as i64);
         Ok(resp)
     }
 
@@ -1227,6 +2157,9 @@ impl Peer {
         let key = req.get_delete().get_key();
         try!(self.check_data_key(key));
 
+        self.flow.written_keys += 1;
+        self.flow.written_bytes += key.len() as u64;
+
         let key = keys::data_key(key);
         // since size_diff_hint is not accurate, so we just skip calculate the value size.
         let klen = key.len() as u64;
@@ -1235,21 +2168,38 @@ impl Peer {
         } else {
             self.size_diff_hint = 0;
         }
+        self.delete_keys_hint += 1;
         let resp = Response::new();
-        if req.get_delete().has_cf() {
-            let cf = req.get_delete().get_cf();
-            let handle = try!(rocksdb::get_cf_handle(&self.engine, cf));
-            try!(ctx.wb.delete_cf(*handle, &key));
+        let cf = if req.get_delete().has_cf() {
+            Some(req.get_delete().get_cf())
         } else {
-            try!(ctx.wb.delete(&key));
-        }
+            None
+        };
+        ctx.record_write(cf, &key, None);
+        let cf = match cf {
+            Some(cf) => {
+                let handle = try!(rocksdb::get_cf_handle(&self.engine, cf));
+                try!(ctx.wb.delete_cf(*handle, &key));
+                cf
+            }
+            None => {
+                try!(ctx.wb.delete(&key));
+                DEFAULT_CFNAME
+            }
+        };
+        metric_count!(&format!("raftstore.apply.write_bytes.{}", cf), key.len() as i64);
 
         Ok(resp)
     }
 
-    fn do_snap(&mut self, _: &ExecContext, _: &Request) -> Result<Response> {
+    fn do_snap(&mut self, ctx: &ExecContext, _: &Request) -> Result<Response> {
         let mut resp = Response::new();
-        resp.mut_snap().set_region(self.get_store().get_region().clone());
+        let region = self.get_store().get_region();
+        resp.mut_snap().set_region(region.clone());
+        // the client can compare these with a later response to find out
+        // whether the region has changed since the snapshot is taken.
+        resp.mut_snap().set_region_epoch(region.get_region_epoch().clone());
+        resp.mut_snap().set_applied_index(ctx.apply_state.get_applied_index());
         Ok(resp)
     }
 }
@@ -1262,3 +2212,91 @@ fn make_transfer_leader_response() -> RaftCmdResponse {
     resp.set_admin_response(response);
     resp
 }
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{WriteBatch, Writable};
+
+    use super::*;
+    use util::metric;
+
+    #[test]
+    fn test_write_apply_batch() {
+        let wb = WriteBatch::new();
+        wb.put(b"k1", b"v1").unwrap();
+        wb.delete(b"k2").unwrap();
+        write_apply_batch(1, 5, wb, false, |_, _| Ok(())).unwrap();
+
+        // inject a fault into the write.
+        let wb = WriteBatch::new();
+        wb.put(b"k1", b"v1").unwrap();
+        wb.delete(b"k2").unwrap();
+        let err = write_apply_batch(10, 25, wb, false, |_, _| Err("injected fault".to_owned()))
+            .unwrap_err();
+        let msg = format!("{:?}", err);
+        assert!(msg.contains("region 10"), "{}", msg);
+        assert!(msg.contains("index 25"), "{}", msg);
+        assert!(msg.contains("2 modifies"), "{}", msg);
+        assert!(msg.contains("injected fault"), "{}", msg);
+    }
+
+    #[test]
+    fn test_sync_apply() {
+        let mut region = metapb::Region::new();
+        region.set_id(1);
+        let new_results = || {
+            vec![(Some(ExecResult::ChangePeer {
+                      change_type: ConfChangeType::AddNode,
+                      peer: metapb::Peer::new(),
+                      region: region.clone(),
+                  }),
+                  true),
+                 (Some(ExecResult::SplitRegion {
+                      left: region.clone(),
+                      right: region.clone(),
+                  }),
+                  true),
+                 (None, false)]
+        };
+
+        for &sync_log in &[true, false] {
+            for (exec_result, is_admin) in new_results() {
+                let sync = need_sync_apply(sync_log, &exec_result);
+                assert_eq!(sync, sync_log && is_admin);
+
+                // the write engine sees the sync flag of the batch.
+                let wb = WriteBatch::new();
+                wb.put(b"k1", b"v1").unwrap();
+                let mut synced = None;
+                write_apply_batch(1, 5, wb, sync, |_, s| {
+                        synced = Some(s);
+                        Ok(())
+                    })
+                    .unwrap();
+                assert_eq!(synced, Some(sync));
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_apply_index() {
+        metric::test::init();
+        let regressions = metric::test::counter("raftstore.apply_index_regression");
+        check_apply_index(1, 2, 10, 11, true).unwrap();
+
+        for &index in &[10, 5] {
+            let err = check_apply_index(1, 2, 10, index, false).unwrap_err();
+            let msg = format!("{:?}", err);
+            assert!(msg.contains("[region 1] 2"), "{}", msg);
+            assert!(msg.contains("last applied 10"), "{}", msg);
+            assert!(msg.contains(&format!("applying {}", index)), "{}", msg);
+        }
+        assert!(metric::test::counter("raftstore.apply_index_regression") >= regressions + 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "applied index moved backwards")]
+    fn test_check_apply_index_panic() {
+        check_apply_index(1, 2, 10, 10, true).unwrap();
+    }
+}
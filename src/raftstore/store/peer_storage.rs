@@ -17,6 +17,8 @@ use std::cell::RefCell;
 use std::error;
 use std::time::Instant;
 use std::mem;
+use std::cmp;
+use std::collections::VecDeque;
 
 use rocksdb::{DB, WriteBatch, Writable};
 use protobuf::Message;
@@ -52,6 +54,172 @@ pub enum SnapState {
     Failed,
 }
 
+/// The latest entries of the raft log, kept in memory so that replicating
+/// them doesn't read the engine. At most `limit` bytes of entries are kept.
+struct EntryCache {
+    cache: VecDeque<Entry>,
+    // the total size of the cached entries in bytes.
+    size: u64,
+    limit: u64,
+}
+
+impl EntryCache {
+    fn new() -> EntryCache {
+        EntryCache {
+            cache: VecDeque::new(),
+            size: 0,
+            limit: 0,
+        }
+    }
+
+    fn pop_front(&mut self) {
+        if let Some(e) = self.cache.pop_front() {
+            self.size -= e.compute_size() as u64;
+        }
+    }
+
+    fn pop_back(&mut self) {
+        if let Some(e) = self.cache.pop_back() {
+            self.size -= e.compute_size() as u64;
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.limit {
+            self.pop_front();
+        }
+    }
+
+    fn first_index(&self) -> Option<u64> {
+        self.cache.front().map(|e| e.get_index())
+    }
+
+    fn get(&self, idx: u64) -> Option<&Entry> {
+        match self.first_index() {
+            Some(first) if idx >= first => self.cache.get((idx - first) as usize),
+            _ => None,
+        }
+    }
+
+    /// Get entries in [low, high), None if `low` is not cached.
+    fn entries(&self, low: u64, high: u64, max_size: u64) -> Option<Vec<Entry>> {
+        let first = match self.first_index() {
+            Some(first) if low >= first => first,
+            _ => return None,
+        };
+        let mut ents = Vec::with_capacity((high - low) as usize);
+        let mut total_size: u64 = 0;
+        let start = (low - first) as usize;
+        for e in self.cache.iter().skip(start).take((high - low) as usize) {
+            total_size += e.compute_size() as u64;
+            if total_size > max_size && !ents.is_empty() {
+                break;
+            }
+            ents.push(e.clone());
+        }
+        Some(ents)
+    }
+
+    fn append(&mut self, entries: &[Entry]) {
+        if self.limit == 0 || entries.is_empty() {
+            return;
+        }
+        let first_index = entries[0].get_index();
+        match self.cache.back().map(|e| e.get_index()) {
+            // the new entries don't follow the cached ones, drop the cache.
+            Some(last) if last + 1 < first_index => self.clear(),
+            // the conflicting entries are replaced.
+            Some(_) => {
+                while self.cache.back().map_or(false, |e| e.get_index() >= first_index) {
+                    self.pop_back();
+                }
+            }
+            None => {}
+        }
+        for e in entries {
+            self.size += e.compute_size() as u64;
+            self.cache.push_back(e.clone());
+        }
+        self.evict();
+    }
+
+    fn clear(&mut self) {
+        self.cache.clear();
+        self.size = 0;
+    }
+
+    /// Drop the entries before `idx`.
+    fn compact_to(&mut self, idx: u64) {
+        while self.first_index().map_or(false, |i| i < idx) {
+            self.pop_front();
+        }
+    }
+
+    fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+        self.evict();
+    }
+}
+
+/// The estimated size in bytes of the raft log in [first_index, last_index].
+/// It's tracked on append and compaction instead of scanning the engine, the
+/// removed entries are assumed to be of the average size.
+struct RaftLogSize {
+    first_index: u64,
+    last_index: u64,
+    size: u64,
+    // whether the size exceeded the warning threshold at the last check.
+    oversized: bool,
+}
+
+impl RaftLogSize {
+    fn new(first_index: u64, last_index: u64, size: u64) -> RaftLogSize {
+        RaftLogSize {
+            first_index: first_index,
+            last_index: last_index,
+            size: size,
+            oversized: false,
+        }
+    }
+
+    // The estimated size of the entries in [low, high).
+    fn estimate(&self, low: u64, high: u64) -> u64 {
+        let low = cmp::max(low, self.first_index);
+        let high = cmp::min(high, self.last_index + 1);
+        if low >= high {
+            return 0;
+        }
+        self.size * (high - low) / (self.last_index + 1 - self.first_index)
+    }
+
+    // Entries in [first_index, last_index] of `size` bytes replace all the
+    // entries from first_index.
+    fn append(&mut self, first_index: u64, last_index: u64, size: u64) {
+        let removed = self.estimate(first_index, self.last_index + 1);
+        self.size = self.size - removed + size;
+        if self.first_index > self.last_index || first_index < self.first_index {
+            self.first_index = first_index;
+        }
+        self.last_index = last_index;
+    }
+
+    fn compact_to(&mut self, idx: u64) {
+        if idx <= self.first_index {
+            return;
+        }
+        self.size -= self.estimate(self.first_index, idx);
+        self.first_index = idx;
+        self.last_index = cmp::max(self.last_index, idx - 1);
+    }
+
+    // All the entries up to `last_index` are replaced by a snapshot.
+    fn reset(&mut self, last_index: u64) {
+        self.first_index = last_index + 1;
+        self.last_index = last_index;
+        self.size = 0;
+    }
+}
+
 pub struct PeerStorage {
     pub engine: Arc<DB>,
 
@@ -62,6 +230,8 @@ pub struct PeerStorage {
     snap_state: RefCell<SnapState>,
     snap_sched: Scheduler<SnapTask>,
     snap_tried_cnt: AtomicUsize,
+    cache: EntryCache,
+    log_size: RaftLogSize,
 
     pub tag: String,
 }
@@ -147,6 +317,12 @@ impl PeerStorage {
             }
         };
 
+        // Only scan the log once when the peer is created, the size is tracked
+        // incrementally since then.
+        let first_index = apply_state.get_truncated_state().get_index() + 1;
+        let last_index = raft_state.get_last_index();
+        let size = try!(scan_raft_log_size(&engine, region.get_id(), first_index, last_index));
+
         Ok(PeerStorage {
             engine: engine,
             region: region.clone(),
@@ -155,6 +331,8 @@ impl PeerStorage {
             snap_state: RefCell::new(SnapState::Relax),
             snap_sched: snap_sched,
             snap_tried_cnt: AtomicUsize::new(0),
+            cache: EntryCache::new(),
+            log_size: RaftLogSize::new(first_index, last_index, size),
             tag: tag,
         })
     }
@@ -180,7 +358,11 @@ impl PeerStorage {
         }
 
         for p in self.region.get_peers() {
-            conf_state.mut_nodes().push(p.get_id());
+            if p.get_is_learner() {
+                conf_state.mut_learners().push(p.get_id());
+            } else {
+                conf_state.mut_nodes().push(p.get_id());
+            }
         }
 
         Ok(RaftState {
@@ -202,8 +384,35 @@ impl PeerStorage {
         Ok(())
     }
 
+    /// Keep at most `limit` bytes of the latest entries in memory, 0 disables
+    /// the cache.
+    pub fn set_entry_cache_limit(&mut self, limit: u64) {
+        self.cache.set_limit(limit);
+    }
+
+    /// The number of entries kept in memory.
+    pub fn entry_cache_len(&self) -> usize {
+        self.cache.cache.len()
+    }
+
+    /// The size in bytes of the entries kept in memory.
+    pub fn entry_cache_size(&self) -> u64 {
+        self.cache.size
+    }
+
+    /// Drop the cached entries before `idx`, which are compacted, and exclude
+    /// them from the raft log size.
+    pub fn compact_to(&mut self, idx: u64) {
+        self.cache.compact_to(idx);
+        self.log_size.compact_to(idx);
+    }
+
     pub fn entries(&self, low: u64, high: u64, max_size: u64) -> raft::Result<Vec<Entry>> {
         try!(self.check_range(low, high));
+        if let Some(ents) = self.cache.entries(low, high, max_size) {
+            return Ok(ents);
+        }
+
         let mut ents = Vec::with_capacity((high - low) as usize);
         let mut total_size: u64 = 0;
         let mut next_index = low;
@@ -249,6 +458,9 @@ impl PeerStorage {
             return Ok(self.truncated_term());
         }
         try!(self.check_range(idx, idx + 1));
+        if let Some(e) = self.cache.get(idx) {
+            return Ok(e.get_term());
+        }
         let key = keys::raft_log_key(self.get_region_id(), idx);
         match try!(self.engine.get_msg::<Entry>(&key)) {
             Some(entry) => Ok(entry.get_term()),
@@ -256,6 +468,27 @@ impl PeerStorage {
         }
     }
 
+    /// The estimated size in bytes of the uncompacted raft log, that is all
+    /// entries in [first_index, last_index].
+    pub fn raft_log_size(&self) -> u64 {
+        self.log_size.size
+    }
+
+    /// Check whether the raft log size has just crossed `threshold`, a warning
+    /// is logged only when it goes above the threshold.
+    pub fn check_raft_log_size(&mut self, threshold: u64) -> bool {
+        let oversized = self.log_size.size > threshold;
+        let crossed = oversized && !self.log_size.oversized;
+        self.log_size.oversized = oversized;
+        if crossed {
+            warn!("{} raft log size {} exceeds threshold {}, maybe log gc is stuck",
+                  self.tag,
+                  self.log_size.size,
+                  threshold);
+        }
+        crossed
+    }
+
     #[inline]
     pub fn first_index(&self) -> u64 {
         self.apply_state.get_truncated_state().get_index() + 1
@@ -331,16 +564,18 @@ impl PeerStorage {
     // Append the given entries to the raft log using previous last index or self.last_index.
     // Return the new last index for later update. After we commit in engine, we can set last_index
     // to the return one.
-    pub fn append(&self, ctx: &mut InvokeContext, entries: &[Entry]) -> Result<u64> {
+    pub fn append(&mut self, ctx: &mut InvokeContext, entries: &[Entry]) -> Result<u64> {
         debug!("{} append {} entries", self.tag, entries.len());
         let prev_last_index = ctx.raft_state.get_last_index();
         if entries.len() == 0 {
             return Ok(prev_last_index);
         }
 
+        let mut size = 0;
         for entry in entries {
-            try!(ctx.wb.put_msg(&keys::raft_log_key(self.get_region_id(), entry.get_index()),
-                                entry));
+            let key = keys::raft_log_key(self.get_region_id(), entry.get_index());
+            size += (key.len() + entry.compute_size() as usize) as u64;
+            try!(ctx.wb.put_msg(&key, entry));
         }
 
         let last_index = entries[entries.len() - 1].get_index();
@@ -351,6 +586,8 @@ impl PeerStorage {
         }
 
         ctx.raft_state.set_last_index(last_index);
+        self.cache.append(entries);
+        self.log_size.append(entries[0].get_index(), last_index, size);
 
         Ok(last_index)
     }
@@ -388,6 +625,7 @@ impl PeerStorage {
         region_state.set_state(PeerState::Applying);
         region_state.set_region(region.clone());
         try!(ctx.wb.put_msg(&keys::region_state_key(region_id), &region_state));
+        try!(ctx.wb.put(&keys::region_index_key(region_id), &[]));
 
         let last_index = snap.get_metadata().get_index();
 
@@ -480,6 +718,60 @@ impl PeerStorage {
         Ok(())
     }
 
+    /// scan all user kv of the region in key order.
+    ///
+    /// Note: unlike `scan_region`, keys are passed to `f` as origin keys.
+    pub fn scan_region_kv<T>(&self,
+                             db: &T,
+                             f: &mut FnMut(&[u8], &[u8]) -> Result<bool>)
+                             -> Result<()>
+        where T: Iterable
+    {
+        self.scan_region(db,
+                         &mut |key, value| {
+                             if !key.starts_with(keys::DATA_PREFIX_KEY) {
+                                 return Ok(true);
+                             }
+                             f(keys::origin_key(key), value)
+                         })
+    }
+
+    /// Delete all region related kv in batches of at most `batch_size` keys,
+    /// `write` is called for every full batch.
+    ///
+    /// The raft state, apply state and region state keys are kept untouched, so
+    /// a crash in the middle still leaves a loadable region. The last, partial
+    /// batch is returned for the caller to finish with the final state changes.
+    pub fn clear_region_in_batches<F>(&self,
+                                      batch_size: usize,
+                                      mut write: F)
+                                      -> Result<WriteBatch>
+        where F: FnMut(WriteBatch) -> Result<()>
+    {
+        let region_id = self.get_region_id();
+        let kept_keys = [keys::raft_state_key(region_id),
+                         keys::apply_state_key(region_id),
+                         keys::region_state_key(region_id)];
+
+        let mut wb = WriteBatch::new();
+        let mut count = 0;
+        try!(self.scan_region(self.engine.as_ref(),
+                              &mut |key, _| {
+            if kept_keys.iter().any(|k| k.as_slice() == key) {
+                return Ok(true);
+            }
+            try!(wb.delete(key));
+            count += 1;
+            if count >= batch_size {
+                try!(write(mem::replace(&mut wb, WriteBatch::new())));
+                count = 0;
+            }
+            Ok(true)
+        }));
+
+        Ok(wb)
+    }
+
     pub fn get_region_id(&self) -> u64 {
         self.region.get_id()
     }
@@ -491,6 +783,9 @@ impl PeerStorage {
         if !raft::is_empty_snap(&ready.snapshot) {
             let res = try!(self.apply_snapshot(&mut ctx, &ready.snapshot));
             apply_snap_res = Some(res);
+            // the cached entries are replaced by the snapshot.
+            self.cache.clear();
+            self.log_size.reset(ready.snapshot.get_metadata().get_index());
         }
         if !ready.entries.is_empty() {
             try!(self.append(&mut ctx, &ready.entries));
@@ -521,7 +816,11 @@ impl PeerStorage {
         // If we apply snapshot ok, we should update some infos like applied index too.
         if let Some(res) = apply_snap_res {
             self.set_snap_state(SnapState::Applying);
-            let task = SnapTask::Apply { region_id: region_id };
+            let task = SnapTask::Apply {
+                region_id: region_id,
+                term: self.truncated_term(),
+                index: self.truncated_index(),
+            };
             // TODO: gracefully remove region instead.
             self.snap_sched.schedule(task).expect("snap apply job should not fail");
             self.region = res.region.clone();
@@ -609,7 +908,11 @@ pub fn do_snapshot(mgr: SnapManager, snap: &DbSnapshot, region_id: u64) -> raft:
 
     let mut conf_state = ConfState::new();
     for p in state.get_region().get_peers() {
-        conf_state.mut_nodes().push(p.get_id());
+        if p.get_is_learner() {
+            conf_state.mut_learners().push(p.get_id());
+        } else {
+            conf_state.mut_nodes().push(p.get_id());
+        }
     }
 
     snapshot.mut_metadata().set_conf_state(conf_state);
@@ -643,6 +946,27 @@ pub fn do_snapshot(mgr: SnapManager, snap: &DbSnapshot, region_id: u64) -> raft:
     Ok(snapshot)
 }
 
+// Scan the size in bytes of the raft log in [first_index, last_index].
+fn scan_raft_log_size(engine: &DB,
+                      region_id: u64,
+                      first_index: u64,
+                      last_index: u64)
+                      -> Result<u64> {
+    let mut size = 0;
+    if last_index < first_index {
+        return Ok(size);
+    }
+    let start_key = keys::raft_log_key(region_id, first_index);
+    let end_key = keys::raft_log_key(region_id, last_index + 1);
+    try!(engine.scan(&start_key,
+                     &end_key,
+                     &mut |key, value| {
+                         size += (key.len() + value.len()) as u64;
+                         Ok(true)
+                     }));
+    Ok(size)
+}
+
 // When we bootstrap the region or handling split new region, we must
 // call this to initialize region local state first.
 pub fn write_initial_state<T: Mutable>(w: &T, region_id: u64) -> Result<()> {
@@ -697,7 +1021,7 @@ mod test {
     use std::fs::File;
     use rocksdb::*;
     use kvproto::raftpb::{Entry, ConfState};
-    use kvproto::raft_serverpb::RaftSnapshotData;
+    use kvproto::raft_serverpb::{RaftSnapshotData, RegionLocalState, PeerState};
     use raft::{StorageError, Error as RaftError};
     use tempdir::*;
     use protobuf;
@@ -709,7 +1033,7 @@ mod test {
     use util::worker::{Worker, Scheduler};
     use util::HandyRwLock;
 
-    use super::InvokeContext;
+    use super::{InvokeContext, scan_raft_log_size};
 
     impl MsgSender for Sender<Msg> {
         fn send(&self, msg: Msg) -> raftstore::Result<()> {
@@ -754,6 +1078,140 @@ mod test {
         m.compute_size()
     }
 
+    #[test]
+    fn test_storage_raft_log_size() {
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let td = TempDir::new("tikv-store-test").unwrap();
+        let worker = Worker::new("snap_manager");
+        let sched = worker.scheduler();
+        let mut store = new_storage_from_ents(sched, &td, &ents);
+        let scan_size = |store: &PeerStorage| {
+            scan_raft_log_size(&store.engine,
+                               store.get_region_id(),
+                               store.first_index(),
+                               store.last_index())
+                .unwrap()
+        };
+        let size = store.raft_log_size();
+        assert!(size > 0);
+        assert_eq!(size, scan_size(&store));
+        assert!(!store.check_raft_log_size(size));
+
+        let mut ctx = InvokeContext::new(&store);
+        let mut big_entry = new_entry(6, 5);
+        big_entry.set_data(vec![0; 1024]);
+        store.append(&mut ctx, &[big_entry]).unwrap();
+        store.engine.write(ctx.wb).unwrap();
+        store.raft_state = ctx.raft_state;
+
+        let new_size = store.raft_log_size();
+        assert_eq!(new_size, scan_size(&store));
+        assert!(new_size > size + 1024);
+        // only warn when the threshold is crossed.
+        assert!(store.check_raft_log_size(size));
+        assert!(!store.check_raft_log_size(size));
+
+        // overwrite the big entry with a small one.
+        let mut ctx = InvokeContext::new(&store);
+        store.append(&mut ctx, &[new_entry(6, 6)]).unwrap();
+        store.engine.write(ctx.wb).unwrap();
+        store.raft_state = ctx.raft_state;
+        assert!(store.raft_log_size() < size + 1024);
+        assert!(!store.check_raft_log_size(size + 1024));
+
+        // compacted entries are excluded.
+        let size = store.raft_log_size();
+        store.compact_to(6);
+        assert!(store.raft_log_size() < size);
+    }
+
+    #[test]
+    fn test_storage_clear_region_in_batches() {
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let td = TempDir::new("tikv-store-test").unwrap();
+        let worker = Worker::new("snap_manager");
+        let sched = worker.scheduler();
+        let store = new_storage_from_ents(sched, &td, &ents);
+        let region_id = store.get_region_id();
+        for i in 0..100 {
+            let key = keys::data_key(format!("k{:03}", i).as_bytes());
+            store.engine.put(&key, b"v").unwrap();
+        }
+
+        let mut writes = 0;
+        let wb = store.clear_region_in_batches(10, |wb| {
+                store.engine.write(wb).unwrap();
+                writes += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert!(writes > 1, "expect multiple writes, got {}", writes);
+        // the states are still there before the final write.
+        assert!(store.engine.get_value(&keys::raft_state_key(region_id)).unwrap().is_some());
+        let state_key = keys::region_state_key(region_id);
+        let state: RegionLocalState = store.engine.get_msg(&state_key).unwrap().unwrap();
+        assert_eq!(state.get_state(), PeerState::Normal);
+
+        let mut state = RegionLocalState::new();
+        state.set_state(PeerState::Tombstone);
+        state.set_region(store.get_region().clone());
+        wb.delete(&keys::raft_state_key(region_id)).unwrap();
+        wb.delete(&keys::apply_state_key(region_id)).unwrap();
+        wb.put_msg(&state_key, &state).unwrap();
+        store.engine.write(wb).unwrap();
+
+        let mut count = 0;
+        store.scan_region(store.engine.as_ref(),
+                         &mut |key, _| {
+                             assert_eq!(key, state_key.as_slice());
+                             count += 1;
+                             Ok(true)
+                         })
+            .unwrap();
+        assert_eq!(count, 1);
+        let state: RegionLocalState = store.engine.get_msg(&state_key).unwrap().unwrap();
+        assert_eq!(state.get_state(), PeerState::Tombstone);
+    }
+
+    #[test]
+    fn test_storage_scan_region_kv() {
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let td = TempDir::new("tikv-store-test").unwrap();
+        let worker = Worker::new("snap_manager");
+        let sched = worker.scheduler();
+        let store = new_storage_from_ents(sched, &td, &ents);
+        let mut expected = vec![];
+        for i in 0..20 {
+            let key = format!("k{:02}", i).into_bytes();
+            store.engine.put(&keys::data_key(&key), &key).unwrap();
+            expected.push(key);
+        }
+
+        let snap = store.raw_snapshot();
+        // writes after the snapshot is taken should not be seen.
+        store.engine.put(&keys::data_key(b"k99"), b"k99").unwrap();
+
+        let mut exported = vec![];
+        store.scan_region_kv(&snap,
+                            &mut |key, value| {
+                                assert_eq!(key, value);
+                                exported.push(key.to_vec());
+                                Ok(true)
+                            })
+            .unwrap();
+        assert_eq!(exported, expected);
+
+        // stop scanning when `f` returns false.
+        let mut count = 0;
+        store.scan_region_kv(&snap,
+                            &mut |_, _| {
+                                count += 1;
+                                Ok(count < 5)
+                            })
+            .unwrap();
+        assert_eq!(count, 5);
+    }
+
     #[test]
     fn test_storage_term() {
         let ents = vec![
@@ -931,6 +1389,70 @@ mod test {
         }
     }
 
+    fn append_ents(store: &mut PeerStorage, ents: &[Entry]) {
+        let mut ctx = InvokeContext::new(store);
+        store.append(&mut ctx, ents).unwrap();
+        store.engine.write(ctx.wb).unwrap();
+        store.raft_state = ctx.raft_state;
+    }
+
+    #[test]
+    fn test_storage_entry_cache() {
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let td = TempDir::new("tikv-store-test").unwrap();
+        let worker = Worker::new("snap_manager");
+        let sched = worker.scheduler();
+        let mut store = new_storage_from_ents(sched, &td, &ents);
+        // all the new entries are of the same size, leave room for 3 of them.
+        let entry_size = new_entry(6, 6).compute_size() as u64;
+        let limit = 3 * entry_size + entry_size / 2;
+        store.set_entry_cache_limit(limit);
+        let max_u64 = u64::max_value();
+
+        let new_ents: Vec<_> = (6..11).map(|i| new_entry(i, 6)).collect();
+        append_ents(&mut store, &new_ents);
+        assert_eq!(store.entry_cache_len(), 3);
+        assert_eq!(store.entry_cache_size(), 3 * entry_size);
+        assert!(store.entry_cache_size() <= limit);
+
+        // the evicted entries are read from the engine.
+        let mut expect = vec![new_entry(4, 4), new_entry(5, 5)];
+        expect.extend(new_ents.iter().cloned());
+        assert_eq!(store.entries(4, 11, max_u64).unwrap(), expect);
+        assert_eq!(store.term(6).unwrap(), 6);
+
+        // the cached ones are not.
+        store.engine.delete(&keys::raft_log_key(1, 9)).unwrap();
+        assert_eq!(store.entries(8, 11, max_u64).unwrap(), &new_ents[2..]);
+        assert_eq!(store.entries(8, 11, 0).unwrap(), &new_ents[2..3]);
+        assert_eq!(store.term(9).unwrap(), 6);
+
+        // the conflicting entries are replaced.
+        append_ents(&mut store, &[new_entry(10, 7), new_entry(11, 7)]);
+        assert_eq!(store.entry_cache_len(), 3);
+        assert!(store.entry_cache_size() <= limit);
+        assert_eq!(store.entries(9, 12, max_u64).unwrap(),
+                   vec![new_entry(9, 6), new_entry(10, 7), new_entry(11, 7)]);
+
+        // an entry bigger than the limit evicts all the others and isn't cached.
+        let mut big_entry = new_entry(12, 7);
+        big_entry.set_data(vec![0; limit as usize]);
+        append_ents(&mut store, &[big_entry.clone()]);
+        assert_eq!(store.entry_cache_len(), 0);
+        assert_eq!(store.entry_cache_size(), 0);
+        assert_eq!(store.entries(12, 13, max_u64).unwrap(), vec![big_entry]);
+        append_ents(&mut store, &[new_entry(12, 8)]);
+
+        store.compact_to(12);
+        assert_eq!(store.entry_cache_len(), 1);
+        assert_eq!(store.entry_cache_size(), entry_size);
+        store.set_entry_cache_limit(0);
+        assert_eq!(store.entry_cache_len(), 0);
+        assert_eq!(store.entry_cache_size(), 0);
+        append_ents(&mut store, &[new_entry(13, 8)]);
+        assert_eq!(store.entry_cache_len(), 0);
+    }
+
     #[test]
     fn test_storage_apply_snapshot() {
         let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
@@ -973,4 +1495,67 @@ mod test {
         assert_eq!(ctx.apply_state.get_truncated_state().get_term(), 5);
         assert_eq!(s2.first_index(), s2.applied_index() + 1);
     }
+
+    #[test]
+    fn test_storage_skip_stale_snapshot() {
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let td1 = TempDir::new("tikv-store-test").unwrap();
+        let snap_dir = TempDir::new("snap").unwrap();
+        let mgr = new_snap_mgr(snap_dir.path().to_str().unwrap(), None);
+        let mut worker = Worker::new("snap_manager");
+        let sched = worker.scheduler();
+        let s1 = new_storage_from_ents(sched.clone(), &td1, &ents);
+        s1.engine.put(&keys::data_key(b"k1"), b"v1").unwrap();
+        let (tx, rx) = channel();
+        let runner = SnapRunner::new(s1.engine.clone(), tx, mgr.clone());
+        worker.start(runner).unwrap();
+        assert!(s1.snapshot().is_err());
+        let snap1 = match rx.recv().unwrap() {
+            Msg::SnapGenRes { snap, .. } => snap.unwrap(),
+            m => panic!("unexpected message: {:?}", m),
+        };
+
+        let key = SnapKey::from_snap(&snap1).unwrap();
+        let source_snap = mgr.rl().get_snap_file(&key, true).unwrap();
+        let mut dst_snap = mgr.rl().get_snap_file(&key, false).unwrap();
+        let mut f = File::open(source_snap.path()).unwrap();
+        dst_snap.encode_u64(0).unwrap();
+        io::copy(&mut f, &mut dst_snap).unwrap();
+        dst_snap.save().unwrap();
+
+        let td2 = TempDir::new("tikv-store-test").unwrap();
+        let mut worker2 = Worker::new("snap_apply");
+        let s2 = new_storage(worker2.scheduler(), &td2);
+        let (tx2, rx2) = channel();
+        let runner = SnapRunner::new(s2.engine.clone(), tx2, mgr.clone());
+        worker2.start(runner).unwrap();
+        let mut ctx = InvokeContext::new(&s2);
+        s2.apply_snapshot(&mut ctx, &snap1).unwrap();
+        // The peer moves past the snapshot before the snapshot is applied.
+        ctx.apply_state.set_applied_index(7);
+        ctx.apply_state.mut_truncated_state().set_index(6);
+        ctx.apply_state.mut_truncated_state().set_term(6);
+        ctx.save_apply(s2.get_region_id()).unwrap();
+        s2.engine.write(ctx.wb).unwrap();
+
+        let task = SnapTask::Apply {
+            region_id: s2.get_region_id(),
+            term: 5,
+            index: 5,
+        };
+        worker2.schedule(task).unwrap();
+        match rx2.recv().unwrap() {
+            Msg::SnapApplyRes { is_success, .. } => assert!(!is_success),
+            m => panic!("unexpected message: {:?}", m),
+        }
+        // The stale snapshot is reported as failed and must not be written.
+        assert!(s2.engine.get_value(&keys::data_key(b"k1")).unwrap().is_none());
+        let state: RegionLocalState = s2.engine
+            .get_msg(&keys::region_state_key(s2.get_region_id()))
+            .unwrap()
+            .unwrap();
+        // the store cleans up the region when it gets the failure.
+        assert_eq!(state.get_state(), PeerState::Applying);
+        assert!(dst_snap.exists());
+    }
 }
@@ -1,10 +1,13 @@
 use std::io::{self, Write, ErrorKind, Seek, SeekFrom, Read};
 use std::fmt::{self, Formatter, Display};
 use std::fs::{self, File, OpenOptions, Metadata};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std::sync::{Arc, RwLock};
 use std::path::{Path, PathBuf};
+use std::usize;
+#[cfg(test)]
+use std::cell::RefCell;
 
 use crc::crc32::{self, Digest, Hasher32};
 use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
@@ -59,21 +62,100 @@ const SNAP_GEN_PREFIX: &'static str = "gen";
 /// Name prefix for the received snapshot file.
 const SNAP_REV_PREFIX: &'static str = "rev";
 
+/// Name suffix for the progress of a partially received snapshot file.
+const SNAP_META_SUFFIX: &'static str = ".meta";
+
+#[cfg(test)]
+thread_local!(static FS_EVENTS: RefCell<Vec<String>> = RefCell::new(vec![]));
+
+/// Record the file system operations on snapshot files, so tests can check
+/// their order.
+#[cfg(test)]
+fn record_fs_event(op: &str, path: &Path) {
+    FS_EVENTS.with(|e| e.borrow_mut().push(format!("{} {}", op, path.display())));
+}
+
+#[cfg(not(test))]
+fn record_fs_event(_: &str, _: &Path) {}
+
+fn sync_dir(path: &Path) -> io::Result<()> {
+    try!(try!(File::open(path)).sync_all());
+    record_fs_event("sync", path);
+    Ok(())
+}
+
 /// A structure represents the snapshot file.
 ///
 /// All changes to the file will be written to `tmp_file` first, and use
 /// `save` method to make them persistent. When saving a crc32 checksum
 /// will be appended to the file end automatically.
+///
+/// A resumable file records every completed chunk of the temporary file in
+/// a meta file, so a failed receiving can continue from the last completed
+/// chunk instead of starting over.
 pub struct SnapFile {
     file: PathBuf,
     digest: Digest,
     // File is the file obj represent the tmpfile, string is the actual path to
     // tmpfile.
     tmp_file: Option<(File, String)>,
+    // 0 means the file is not resumable.
+    chunk_size: u64,
+    file_size: u64,
+    // bytes written to the tmpfile.
+    written: u64,
+    // whether to sync the file and its directory when saving.
+    sync: bool,
 }
 
 impl SnapFile {
     fn new<T: Into<PathBuf>>(snap_dir: T, is_sending: bool, key: &SnapKey) -> io::Result<SnapFile> {
+        let mut f = try!(SnapFile::open(snap_dir, is_sending, key));
+        try!(f.init());
+        Ok(f)
+    }
+
+    /// Create a resumable receiving file, which continues from the completed
+    /// chunks of a previous receiving if there are any.
+    fn new_resumable<T: Into<PathBuf>>(snap_dir: T,
+                                       key: &SnapKey,
+                                       file_size: u64,
+                                       chunk_size: u64)
+                                       -> io::Result<SnapFile> {
+        let mut f = try!(SnapFile::open(snap_dir, false, key));
+        f.chunk_size = chunk_size;
+        f.file_size = file_size;
+        if f.exists() {
+            return Ok(f);
+        }
+
+        let offset = f.partial_offset(file_size);
+        let tmp_path = f.tmp_path();
+        let mut tmp_f = try!(OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&tmp_path));
+        // drop the uncompleted chunk and rebuild the checksum of the rest.
+        try!(tmp_f.set_len(offset));
+        let mut buffer = vec![0; 4096];
+        loop {
+            let read = try!(tmp_f.read(&mut buffer));
+            if read == 0 {
+                break;
+            }
+            f.digest.write(&buffer[..read]);
+        }
+        f.written = offset;
+        f.tmp_file = Some((tmp_f, tmp_path));
+        Ok(f)
+    }
+
+    /// Create the file object without touching the disk.
+    fn open<T: Into<PathBuf>>(snap_dir: T,
+                              is_sending: bool,
+                              key: &SnapKey)
+                              -> io::Result<SnapFile> {
         let mut file_path = snap_dir.into();
         if !file_path.exists() {
             try!(fs::create_dir_all(file_path.as_path()));
@@ -86,13 +168,15 @@ impl SnapFile {
         let file_name = format!("{}_{}.snap", prefix, key);
         file_path.push(&file_name);
 
-        let mut f = SnapFile {
+        Ok(SnapFile {
             file: file_path,
             digest: Digest::new(crc32::IEEE),
             tmp_file: None,
-        };
-        try!(f.init());
-        Ok(f)
+            chunk_size: 0,
+            file_size: 0,
+            written: 0,
+            sync: false,
+        })
     }
 
     pub fn init(&mut self) -> io::Result<()> {
@@ -100,12 +184,75 @@ impl SnapFile {
             return Ok(());
         }
 
-        let tmp_path = format!("{}.tmp", self.path().display());
+        let tmp_path = self.tmp_path();
         let tmp_f = try!(OpenOptions::new().write(true).create_new(true).open(&tmp_path));
         self.tmp_file = Some((tmp_f, tmp_path));
         Ok(())
     }
 
+    fn tmp_path(&self) -> String {
+        format!("{}.tmp", self.path().display())
+    }
+
+    fn meta_path(&self) -> String {
+        format!("{}{}", self.tmp_path(), SNAP_META_SUFFIX)
+    }
+
+    /// Get the offset the receiving of a `file_size` bytes file can be
+    /// resumed from, 0 if there is no usable progress.
+    fn partial_offset(&self, file_size: u64) -> u64 {
+        let res = File::open(self.meta_path()).and_then(|mut f| {
+            let size = try!(f.read_u64::<BigEndian>());
+            let completed = try!(f.read_u64::<BigEndian>());
+            let tmp_len = try!(fs::metadata(self.tmp_path())).len();
+            Ok((size, completed, tmp_len))
+        });
+        match res {
+            Ok((size, completed, tmp_len)) if size == file_size && completed <= tmp_len => {
+                completed
+            }
+            _ => 0,
+        }
+    }
+
+    /// Get the metadata of the temporary file left by a failed receiving.
+    pub fn partial_meta(&self) -> io::Result<Metadata> {
+        fs::metadata(self.tmp_path())
+    }
+
+    /// Delete the temporary file left by a failed receiving.
+    pub fn delete_partial(&self) {
+        if self.tmp_file.is_some() {
+            return;
+        }
+        for path in &[self.tmp_path(), self.meta_path()] {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != ErrorKind::NotFound {
+                    error!("failed to delete {}: {:?}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Persist the completed chunks so the receiving can be resumed from them.
+    fn record_progress(&mut self) -> io::Result<()> {
+        let completed = self.written / self.chunk_size * self.chunk_size;
+        if completed >= self.file_size {
+            // the last chunk is finished by `save`.
+            return Ok(());
+        }
+        try!(self.tmp_file.as_mut().unwrap().0.sync_data());
+        let mut meta = try!(File::create(self.meta_path()));
+        try!(meta.write_u64::<BigEndian>(self.file_size));
+        try!(meta.write_u64::<BigEndian>(completed));
+        meta.sync_data()
+    }
+
+    /// Bytes written to the temporary file, including the resumed ones.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
     pub fn meta(&self) -> io::Result<Metadata> {
         self.file.metadata()
     }
@@ -154,6 +301,12 @@ impl SnapFile {
         fs::remove_file(self.path())
     }
 
+    /// Sync the file and its directory when saving, so a saved file
+    /// survives a crash.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
     /// Use the content in temporary files replace the target file.
     ///
     /// Please note that this method can only be called once.
@@ -162,7 +315,18 @@ impl SnapFile {
         if let Some((mut f, path)) = self.tmp_file.take() {
             try!(f.write_u32::<BigEndian>(self.digest.sum32()));
             try!(f.flush());
-            try!(fs::rename(path, self.file.as_path()));
+            if self.sync {
+                try!(f.sync_all());
+                record_fs_event("sync", Path::new(&path));
+            }
+            try!(fs::rename(&path, self.file.as_path()));
+            record_fs_event("rename", self.file.as_path());
+            if self.sync {
+                try!(sync_dir(self.file.parent().unwrap()));
+            }
+            if self.chunk_size > 0 {
+                self.delete_partial();
+            }
         }
         Ok(())
     }
@@ -179,6 +343,11 @@ impl Write for SnapFile {
         }
         let written = try!(self.tmp_file.as_mut().unwrap().0.write(buf));
         self.digest.write(&buf[..written]);
+        let last_written = self.written;
+        self.written += written as u64;
+        if self.chunk_size > 0 && self.written / self.chunk_size > last_written / self.chunk_size {
+            try!(self.record_progress());
+        }
         Ok(written)
     }
 
@@ -193,6 +362,10 @@ impl Write for SnapFile {
 impl Drop for SnapFile {
     fn drop(&mut self) {
         if let Some((_, path)) = self.tmp_file.take() {
+            if self.chunk_size > 0 {
+                // keep the completed chunks for resuming.
+                return;
+            }
             debug!("deleting {}", path);
             if let Err(e) = fs::remove_file(&path) {
                 warn!("failed to delete temporary file {}: {:?}", path, e);
@@ -209,10 +382,22 @@ pub enum SnapEntry {
     Applying = 4,
 }
 
+impl SnapEntry {
+    #[inline]
+    fn is_sending(&self) -> bool {
+        match *self {
+            SnapEntry::Generating | SnapEntry::Sending => true,
+            SnapEntry::Receiving | SnapEntry::Applying => false,
+        }
+    }
+}
+
 /// `SnapStats` is for snapshot statistics.
 pub struct SnapStats {
     pub sending_count: usize,
     pub receiving_count: usize,
+    // snapshots waiting for a free slot of `concurrent_limit`.
+    pub queued_count: usize,
 }
 
 /// `SnapManagerCore` trace all current processing snapshots.
@@ -220,6 +405,12 @@ pub struct SnapManagerCore {
     // directory to store snapfile.
     base: String,
     registry: HashMap<SnapKey, Vec<SnapEntry>>,
+    // max number of snapshots transferring in each direction.
+    concurrent_limit: usize,
+    // (key, is_sending) of the snapshots rejected by `try_register`.
+    queued: HashSet<(SnapKey, bool)>,
+    // whether to sync the snapshot files when saving them.
+    sync: bool,
     ch: Option<SendCh>,
 }
 
@@ -228,10 +419,21 @@ impl SnapManagerCore {
         SnapManagerCore {
             base: path.into(),
             registry: map![],
+            concurrent_limit: usize::MAX,
+            queued: HashSet::new(),
+            sync: false,
             ch: ch,
         }
     }
 
+    pub fn set_concurrent_limit(&mut self, limit: usize) {
+        self.concurrent_limit = limit;
+    }
+
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
     pub fn init(&self) -> io::Result<()> {
         let path = Path::new(&self.base);
         if !path.exists() {
@@ -265,6 +467,9 @@ impl SnapManagerCore {
                     None => return None,
                     Some(n) => n,
                 };
+                if name.ends_with(SNAP_META_SUFFIX) {
+                    return None;
+                }
                 let is_sending = name.starts_with(SNAP_GEN_PREFIX);
                 let numbers: Vec<u64> = name.split('.')
                     .next()
@@ -290,7 +495,36 @@ impl SnapManagerCore {
 
     #[inline]
     pub fn get_snap_file(&self, key: &SnapKey, is_sending: bool) -> io::Result<SnapFile> {
-        SnapFile::new(&self.base, is_sending, key)
+        let mut f = try!(SnapFile::new(&self.base, is_sending, key));
+        f.set_sync(self.sync);
+        Ok(f)
+    }
+
+    /// Get the snap file without creating the temporary file, which is
+    /// enough for checking or deleting it.
+    #[inline]
+    pub fn open_snap_file(&self, key: &SnapKey, is_sending: bool) -> io::Result<SnapFile> {
+        SnapFile::open(&self.base, is_sending, key)
+    }
+
+    #[inline]
+    pub fn get_resumable_snap_file(&self,
+                                   key: &SnapKey,
+                                   file_size: u64,
+                                   chunk_size: u64)
+                                   -> io::Result<SnapFile> {
+        let mut f = try!(SnapFile::new_resumable(&self.base, key, file_size, chunk_size));
+        f.set_sync(self.sync);
+        Ok(f)
+    }
+
+    /// Get the offset the receiving snapshot file of `key` can be resumed from.
+    pub fn resume_offset(&self, key: &SnapKey, file_size: u64) -> io::Result<u64> {
+        let f = try!(SnapFile::open(&self.base, false, key));
+        if f.exists() {
+            return Ok(file_size);
+        }
+        Ok(f.partial_offset(file_size))
     }
 
     pub fn register(&mut self, key: SnapKey, entry: SnapEntry) {
@@ -311,6 +545,42 @@ impl SnapManagerCore {
         self.notify_stats();
     }
 
+    /// Register the entry only if the snapshots being transferred in the same
+    /// direction don't reach the concurrent limit, otherwise the snapshot is
+    /// queued and false is returned, the caller should retry it later.
+    pub fn try_register(&mut self, key: SnapKey, entry: SnapEntry) -> bool {
+        let is_sending = entry.is_sending();
+        let registered = self.registry
+            .get(&key)
+            .map_or(false, |v| v.iter().any(|e| e.is_sending() == is_sending));
+        if !registered {
+            let stats = self.stats();
+            let count = if is_sending {
+                stats.sending_count
+            } else {
+                stats.receiving_count
+            };
+            if count >= self.concurrent_limit {
+                debug!("{} snapshots are in progress, queue {} {:?}", count, key, entry);
+                if self.queued.insert((key, is_sending)) {
+                    self.notify_stats();
+                }
+                return false;
+            }
+        }
+        self.queued.remove(&(key.clone(), is_sending));
+        self.register(key, entry);
+        true
+    }
+
+    /// Remove the queued snapshot, it's called when the snapshot won't be
+    /// retried anymore.
+    pub fn cancel_queued(&mut self, key: &SnapKey, is_sending: bool) {
+        if self.queued.remove(&(key.clone(), is_sending)) {
+            self.notify_stats();
+        }
+    }
+
     pub fn deregister(&mut self, key: &SnapKey, entry: &SnapEntry) {
         debug!("deregister [key: {}, entry: {:?}]", key, entry);
         let mut need_clean = false;
@@ -345,9 +615,10 @@ impl SnapManagerCore {
         for v in self.registry.values() {
             let (mut is_sending, mut is_receiving) = (false, false);
             for s in v {
-                match *s {
-                    SnapEntry::Sending | SnapEntry::Generating => is_sending = true,
-                    SnapEntry::Receiving | SnapEntry::Applying => is_receiving = true,
+                if s.is_sending() {
+                    is_sending = true;
+                } else {
+                    is_receiving = true;
                 }
             }
             if is_sending {
@@ -361,6 +632,7 @@ impl SnapManagerCore {
         SnapStats {
             sending_count: sending_cnt,
             receiving_count: receiving_cnt,
+            queued_count: self.queued.len(),
         }
     }
 }
@@ -370,3 +642,142 @@ pub type SnapManager = Arc<RwLock<SnapManagerCore>>;
 pub fn new_snap_mgr<T: Into<String>>(path: T, ch: Option<SendCh>) -> SnapManager {
     Arc::new(RwLock::new(SnapManagerCore::new(path, ch)))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use super::FS_EVENTS;
+
+    fn read_file(path: &Path) -> Vec<u8> {
+        let mut content = vec![];
+        File::open(path).unwrap().read_to_end(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn test_resume_receiving() {
+        let temp_dir = TempDir::new("test-resume-receiving").unwrap();
+        let mgr = new_snap_mgr(temp_dir.path().to_str().unwrap(), None);
+        let key = SnapKey::new(1, 1, 1);
+        let data: Vec<u8> = (0..100).collect();
+        let (file_size, chunk_size) = (data.len() as u64, 16);
+
+        assert_eq!(mgr.rl().resume_offset(&key, file_size).unwrap(), 0);
+        let mut f = mgr.rl().get_resumable_snap_file(&key, file_size, chunk_size).unwrap();
+        assert_eq!(f.written(), 0);
+        // the transfer breaks in the middle of the third chunk.
+        f.write_all(&data[..40]).unwrap();
+        drop(f);
+
+        assert_eq!(mgr.rl().resume_offset(&key, file_size).unwrap(), 32);
+        // the progress of another snapshot with the same key is ignored.
+        assert_eq!(mgr.rl().resume_offset(&key, file_size + 1).unwrap(), 0);
+
+        let mut f = mgr.rl().get_resumable_snap_file(&key, file_size, chunk_size).unwrap();
+        assert_eq!(f.written(), 32);
+        f.write_all(&data[32..]).unwrap();
+        f.save().unwrap();
+        f.validate().unwrap();
+        assert_eq!(mgr.rl().resume_offset(&key, file_size).unwrap(), file_size);
+        assert!(f.partial_meta().is_err());
+        let resumed = read_file(f.path());
+
+        // the resumed file is the same as the one received at once.
+        let key = SnapKey::new(1, 1, 2);
+        let mut f = mgr.rl().get_resumable_snap_file(&key, file_size, chunk_size).unwrap();
+        f.write_all(&data).unwrap();
+        f.save().unwrap();
+        assert_eq!(resumed, read_file(f.path()));
+    }
+
+    #[test]
+    fn test_concurrent_limit() {
+        let temp_dir = TempDir::new("test-concurrent-limit").unwrap();
+        let mgr = new_snap_mgr(temp_dir.path().to_str().unwrap(), None);
+        mgr.wl().set_concurrent_limit(2);
+
+        let keys: Vec<_> = (1..5).map(|i| SnapKey::new(i, 1, 1)).collect();
+        assert!(mgr.wl().try_register(keys[0].clone(), SnapEntry::Sending));
+        assert!(mgr.wl().try_register(keys[1].clone(), SnapEntry::Generating));
+        // the excess is queued, not started.
+        assert!(!mgr.wl().try_register(keys[2].clone(), SnapEntry::Sending));
+        assert!(!mgr.wl().try_register(keys[3].clone(), SnapEntry::Sending));
+        assert!(!mgr.rl().has_registered(&keys[2]));
+        let stats = mgr.rl().stats();
+        assert_eq!((stats.sending_count, stats.queued_count), (2, 2));
+
+        // a generated snapshot can be sent without taking another slot.
+        assert!(mgr.wl().try_register(keys[1].clone(), SnapEntry::Sending));
+        // the receiving snapshots are limited separately.
+        assert!(mgr.wl().try_register(keys[2].clone(), SnapEntry::Receiving));
+
+        // the queued snapshot is started when it's retried after a slot is freed.
+        mgr.wl().deregister(&keys[0], &SnapEntry::Sending);
+        assert!(mgr.wl().try_register(keys[2].clone(), SnapEntry::Sending));
+        assert!(mgr.rl().has_registered(&keys[2]));
+        let stats = mgr.rl().stats();
+        assert_eq!((stats.sending_count, stats.receiving_count), (2, 1));
+        assert_eq!(stats.queued_count, 1);
+
+        mgr.wl().cancel_queued(&keys[3], true);
+        assert_eq!(mgr.rl().stats().queued_count, 0);
+    }
+
+    #[test]
+    fn test_delete_partial() {
+        let temp_dir = TempDir::new("test-delete-partial").unwrap();
+        let mgr = new_snap_mgr(temp_dir.path().to_str().unwrap(), None);
+        let key = SnapKey::new(1, 1, 1);
+        let mut f = mgr.rl().get_resumable_snap_file(&key, 100, 16).unwrap();
+        f.write_all(&[0; 20]).unwrap();
+        drop(f);
+
+        // the meta file is not listed as a snapshot.
+        let snaps = mgr.rl().list_snap().unwrap();
+        assert_eq!(snaps, vec![(key.clone(), false)]);
+
+        let f = mgr.rl().open_snap_file(&key, false).unwrap();
+        assert!(f.partial_meta().is_ok());
+        f.delete_partial();
+        assert!(f.partial_meta().is_err());
+        assert_eq!(mgr.rl().resume_offset(&key, 100).unwrap(), 0);
+        assert!(mgr.rl().list_snap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_before_listed() {
+        let temp_dir = TempDir::new("test-sync-before-listed").unwrap();
+        let mgr = new_snap_mgr(temp_dir.path().to_str().unwrap(), None);
+        mgr.wl().set_sync(true);
+        let key = SnapKey::new(1, 1, 1);
+        let mut f = mgr.rl().get_snap_file(&key, true).unwrap();
+        f.write_all(b"data").unwrap();
+        FS_EVENTS.with(|e| e.borrow_mut().clear());
+        f.save().unwrap();
+
+        // the file is synced before it's renamed to the name `list_snap`
+        // recognizes, and the rename is synced before `save` returns.
+        let events = FS_EVENTS.with(|e| e.borrow().clone());
+        assert_eq!(events,
+                   vec![format!("sync {}.tmp", f.path().display()),
+                        format!("rename {}", f.path().display()),
+                        format!("sync {}", temp_dir.path().display())]);
+        assert_eq!(mgr.rl().list_snap().unwrap(), vec![(key, true)]);
+        f.validate().unwrap();
+
+        mgr.wl().set_sync(false);
+        let key = SnapKey::new(1, 1, 2);
+        let mut f = mgr.rl().get_snap_file(&key, true).unwrap();
+        f.write_all(b"data").unwrap();
+        FS_EVENTS.with(|e| e.borrow_mut().clear());
+        f.save().unwrap();
+        let events = FS_EVENTS.with(|e| e.borrow().clone());
+        assert_eq!(events, vec![format!("rename {}", f.path().display())]);
+    }
+}
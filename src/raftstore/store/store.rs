@@ -12,28 +12,32 @@
 // limitations under the License.
 
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::option::Option;
-use std::collections::{HashMap, HashSet, BTreeMap};
+use std::collections::{HashMap, HashSet, BTreeMap, VecDeque};
 use std::boxed::Box;
 use std::collections::Bound::{Excluded, Unbounded};
-use std::time::Duration;
-use std::{cmp, u64};
+use std::time::{Duration, Instant};
+use std::{cmp, mem, u64};
+use std::fmt::Display;
 
-use rocksdb::DB;
+use rocksdb::{DB, Writable, WriteBatch};
 use mio::{self, EventLoop, EventLoopBuilder, Sender};
 use protobuf;
 use uuid::Uuid;
+use rand::{self, Rng};
 
 use kvproto::raft_serverpb::{RaftMessage, RaftSnapshotData, RaftTruncatedState, RegionLocalState,
                              PeerState};
 use kvproto::raftpb::{ConfChangeType, Snapshot, MessageType};
 use kvproto::pdpb::StoreStats;
-use util::{HandyRwLock, SlowTimer};
-use pd::PdClient;
+use util::{rocksdb, HandyRwLock, SlowTimer, duration_to_ms};
+use pd::{PdClient, RegionFlow};
 use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, StatusCmdType, StatusResponse,
-                          RaftCmdRequest, RaftCmdResponse};
+                          RaftCmdRequest, RaftCmdResponse, PeerProgress, RegionInfo,
+                          ProgressState as PbProgressState};
 use protobuf::Message;
-use raft::SnapshotStatus;
+use raft::{SnapshotStatus, ProgressState};
 use raftstore::{Result, Error};
 use kvproto::metapb;
 use util::worker::{Worker, Scheduler};
@@ -41,7 +45,7 @@ use util::get_disk_stat;
 use super::worker::{SplitCheckRunner, SplitCheckTask, SnapTask, SnapRunner, CompactTask,
                     CompactRunner, PdRunner, PdTask};
 use super::{util, SendCh, Msg, Tick, SnapManager};
-use super::keys::{self, enc_start_key, enc_end_key};
+use super::keys::{self, enc_start_key, enc_end_key, EndKey};
 use super::engine::{Iterable, Peekable};
 use super::config::Config;
 use super::peer::{Peer, PendingCmd, ReadyResult, ExecResult};
@@ -50,8 +54,6 @@ use super::msg::Callback;
 use super::cmd_resp::{bind_uuid, bind_term, bind_error};
 use super::transport::Transport;
 
-type Key = Vec<u8>;
-
 const ROCKSDB_TOTAL_SST_FILE_SIZE_PROPERTY: &'static str = "rocksdb.total-sst-files-size";
 
 pub struct Store<T: Transport, C: PdClient + 'static> {
@@ -63,10 +65,14 @@ pub struct Store<T: Transport, C: PdClient + 'static> {
     // region_id -> peers
     region_peers: HashMap<u64, Peer>,
     pending_raft_groups: HashSet<u64>,
+    // region_id -> exec results deferred to the following raft ready passes.
+    pending_exec_results: HashMap<u64, VecDeque<ExecResult>>,
     // region end key -> region id
-    region_ranges: BTreeMap<Key, u64>,
+    region_ranges: BTreeMap<EndKey, u64>,
 
     split_check_worker: Worker<SplitCheckTask>,
+    // split checks scheduled and not finished yet.
+    running_split_checks: Arc<AtomicUsize>,
     snap_worker: Worker<SnapTask>,
     compact_worker: Worker<CompactTask>,
     pd_worker: Worker<PdTask>,
@@ -77,6 +83,17 @@ pub struct Store<T: Transport, C: PdClient + 'static> {
     peer_cache: Arc<RwLock<HashMap<u64, metapb::Peer>>>,
 
     snap_mgr: SnapManager,
+
+    // Set when preparing to shutdown, the store quits after all the leaders
+    // are transferred or the deadline is reached.
+    shutdown_deadline: Option<Instant>,
+    // The last region visited by the pd heartbeat tick.
+    pd_heartbeat_cursor: u64,
+    // The last region checked by the consistency check tick.
+    consistency_check_cursor: u64,
+    // Set when all the regions are loaded and the ticks and workers are started,
+    // cleared when preparing to shutdown.
+    ready: bool,
 }
 
 pub fn create_event_loop<T, C>(cfg: &Config) -> Result<EventLoop<Store<T, C>>>
@@ -107,6 +124,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let sendch = SendCh::new(sender);
 
         let peer_cache = HashMap::new();
+        mgr.wl().set_concurrent_limit(cfg.concurrent_snap_limit);
+        mgr.wl().set_sync(cfg.snap_sync);
 
         Ok(Store {
             cfg: cfg,
@@ -115,7 +134,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             sendch: sendch,
             region_peers: HashMap::new(),
             pending_raft_groups: HashSet::new(),
+            pending_exec_results: HashMap::new(),
             split_check_worker: Worker::new("split check worker"),
+            running_split_checks: Arc::new(AtomicUsize::new(0)),
             snap_worker: Worker::new("snapshot worker"),
             compact_worker: Worker::new("compact worker"),
             pd_worker: Worker::new("pd worker"),
@@ -124,30 +145,29 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             pd_client: pd_client,
             peer_cache: Arc::new(RwLock::new(peer_cache)),
             snap_mgr: mgr,
+            shutdown_deadline: None,
+            pd_heartbeat_cursor: 0,
+            consistency_check_cursor: 0,
+            ready: false,
         })
     }
 
     // Do something before store runs.
     fn prepare(&mut self) -> Result<()> {
-        // Scan region meta to get saved regions.
-        let start_key = keys::REGION_META_MIN_KEY;
-        let end_key = keys::REGION_META_MAX_KEY;
+        box_try!(rocksdb::check_cfs(&self.engine, &self.cfg.required_cfs));
+
+        let t = Instant::now();
         let engine = self.engine.clone();
-        try!(engine.scan(start_key,
-                         end_key,
-                         &mut |key, value| {
-            let (region_id, suffix) = try!(keys::decode_region_meta_key(key));
-            if suffix != keys::REGION_STATE_SUFFIX {
-                return Ok(true);
-            }
+        let index_built = try!(engine.get_value(keys::REGION_INDEX_BUILT_KEY)).is_some();
+        let states = if !index_built || self.cfg.rebuild_region_index {
+            info!("store {} rebuilds region index from region meta", self.store_id());
+            try!(rebuild_region_index(&engine))
+        } else {
+            try!(load_indexed_regions(&engine))
+        };
 
-            let local_state = try!(protobuf::parse_from_bytes::<RegionLocalState>(value));
-            if local_state.get_state() == PeerState::Tombstone {
-                debug!("region {:?} is tombstone in store {}",
-                       local_state.get_region(),
-                       self.store_id());
-                return Ok(true);
-            }
+        let count = states.len();
+        for (region_id, local_state) in states {
             let region = local_state.get_region();
             let mut peer = try!(Peer::create(self, region));
 
@@ -156,16 +176,24 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                       local_state.get_region(),
                       self.store_id());
                 peer.mut_store().set_snap_state(SnapState::Applying);
-                box_try!(self.snap_worker.schedule(SnapTask::Apply { region_id: region_id }));
+                let task = SnapTask::Apply {
+                    region_id: region_id,
+                    term: peer.get_store().truncated_term(),
+                    index: peer.get_store().truncated_index(),
+                };
+                box_try!(self.snap_worker.schedule(task));
             }
 
-            self.region_ranges.insert(enc_end_key(region), region_id);
+            self.region_ranges.insert(EndKey::from_region(region), region_id);
             // No need to check duplicated here, because we use region id as the key
             // in DB.
             self.region_peers.insert(region_id, peer);
-            Ok(true)
-        }));
+        }
 
+        info!("store {} loads {} regions takes {:?}",
+              self.store_id(),
+              count,
+              t.elapsed());
         Ok(())
     }
 
@@ -180,10 +208,15 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_pd_heartbeat_tick(event_loop);
         self.register_pd_store_heartbeat_tick(event_loop);
         self.register_snap_mgr_gc_tick(event_loop);
+        self.register_compact_check_tick(event_loop);
+        self.register_consistency_check_tick(event_loop);
+        self.register_apply_sync_tick(event_loop);
 
         let split_check_runner = SplitCheckRunner::new(self.sendch.clone(),
                                                        self.cfg.region_max_size,
-                                                       self.cfg.region_split_size);
+                                                       self.cfg.region_split_size,
+                                                       self.cfg.split_check_concurrency,
+                                                       self.running_split_checks.clone());
         box_try!(self.split_check_worker.start(split_check_runner));
 
         let runner = SnapRunner::new(self.engine.clone(),
@@ -191,11 +224,15 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                                      self.snap_mgr.clone());
         box_try!(self.snap_worker.start(runner));
 
-        box_try!(self.compact_worker.start(CompactRunner));
+        box_try!(self.compact_worker.start(CompactRunner::new(self.engine.clone())));
 
         let pd_runner = PdRunner::new(self.pd_client.clone(), self.sendch.clone());
         box_try!(self.pd_worker.start(pd_runner));
 
+        self.ready = true;
+        info!("store {} is ready with {} regions",
+              self.store_id(),
+              self.region_peers.len());
         try!(event_loop.run(self));
         Ok(())
     }
@@ -238,16 +275,66 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_raft_base_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        let read_only = self.cfg.read_only;
         for (&region_id, peer) in &mut self.region_peers {
             if !peer.get_store().is_applying_snap() {
                 peer.raft_group.tick();
                 self.pending_raft_groups.insert(region_id);
             }
+            peer.check_transfer_leader_timeout();
+            // PD adds the peers of a read-only store as learners, which never
+            // campaign, but a voter that was placed here before should hand over
+            // its leadership.
+            if read_only && peer.is_leader() && peer.raft_group.raft.lead_transferee.is_none() {
+                peer.transfer_leader_to_best_follower();
+            }
+        }
+
+        if self.check_shutdown(event_loop) {
+            return;
         }
 
         self.register_raft_base_tick(event_loop);
     }
 
+    fn on_prepare_shutdown(&mut self, event_loop: &mut EventLoop<Self>, timeout: Duration) {
+        info!("store {} prepare to shutdown in {:?}", self.store_id(), timeout);
+        self.shutdown_deadline = Some(Instant::now() + timeout);
+        self.ready = false;
+        self.check_shutdown(event_loop);
+    }
+
+    // Transfer the leaders out of this store if it's preparing to shutdown, returns
+    // true if the event loop is shutdown.
+    fn check_shutdown(&mut self, event_loop: &mut EventLoop<Self>) -> bool {
+        let deadline = match self.shutdown_deadline {
+            None => return false,
+            Some(deadline) => deadline,
+        };
+
+        let mut leader_count = 0;
+        for peer in self.region_peers.values_mut() {
+            if !peer.is_leader() {
+                continue;
+            }
+            leader_count += 1;
+            // A failed transfer is aborted after an election timeout, then retry.
+            if peer.raft_group.raft.lead_transferee.is_none() {
+                peer.transfer_leader_to_best_follower();
+            }
+        }
+
+        if leader_count > 0 && Instant::now() < deadline {
+            return false;
+        }
+
+        info!("store {} shutdown with {} leaders left",
+              self.store_id(),
+              leader_count);
+        event_loop.shutdown();
+        true
+    }
+
     // Clippy doesn't allow hash_map contains_key followed by insert, and suggests
     // using entry().or_insert() instead, but we can't use this because creating peer
     // may fail, so we allow map_entry.
@@ -288,15 +375,15 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             self.region_peers.insert(region_id, peer);
         }
 
-        if try!(self.is_snapshot_overlapped(&msg)) {
+        if try!(self.is_snapshot_overlapped(&msg)) || self.is_snapshot_limited(&msg) {
             return Ok(());
         }
 
         self.insert_peer_cache(msg.take_from_peer());
         self.insert_peer_cache(msg.take_to_peer());
 
+        let timer = SlowTimer::from_millis(self.cfg.slow_log_threshold_ms);
         let peer = self.region_peers.get_mut(&region_id).unwrap();
-        let timer = SlowTimer::new();
         try!(peer.raft_group.step(msg.take_message()));
         slow_log!(timer, "{} raft step", peer.tag);
 
@@ -338,7 +425,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     fn is_msg_stale(&self, msg: &RaftMessage) -> Result<bool> {
         let region_id = msg.get_region_id();
         let from_epoch = msg.get_region_epoch();
-        let is_vote_msg = msg.get_message().get_msg_type() == MessageType::MsgRequestVote;
+        // An isolated peer with pre-vote enabled sends MsgRequestPreVote before
+        // MsgRequestVote, so both must be treated as votes from a possibly stale peer.
+        let is_vote_msg = match msg.get_message().get_msg_type() {
+            MessageType::MsgRequestVote | MessageType::MsgRequestPreVote => true,
+            _ => false,
+        };
         let from_store_id = msg.get_from_peer().get_store_id();
 
         // Let's consider following cases with three nodes [1, 2, 3] and 1 is leader:
@@ -455,6 +547,24 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         }
     }
 
+    // The snapshot is dropped if too many snapshots are waiting to be applied,
+    // the leader will send it again later.
+    fn is_snapshot_limited(&self, msg: &RaftMessage) -> bool {
+        if !msg.get_message().has_snapshot() {
+            return false;
+        }
+        let applying_count = self.region_peers.values().filter(|p| p.is_applying_snap()).count();
+        if applying_count < self.cfg.concurrent_apply_snap_limit {
+            return false;
+        }
+        info!("[region {}] {} snapshots are being applied, drop snapshot from {:?}",
+              msg.get_region_id(),
+              applying_count,
+              msg.get_from_peer());
+        metric_incr!("raftstore.snapshot.limited");
+        true
+    }
+
     fn is_snapshot_overlapped(&self, msg: &RaftMessage) -> Result<bool> {
         let region_id = msg.get_region_id();
 
@@ -466,14 +576,30 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             try!(snap_data.merge_from_bytes(snap.get_data()));
             let snap_region = snap_data.get_region();
             if let Some((_, &exist_region_id)) = self.region_ranges
-                .range(Excluded(&enc_start_key(snap_region)), Unbounded::<&Key>)
+                .range(Excluded(&EndKey::from_start_key(snap_region)), Unbounded::<&EndKey>)
                 .next() {
+                // The first region whose end key is after the snapshot start key
+                // is the only candidate in region_ranges.
                 let exist_region = self.region_peers[&exist_region_id].region();
-                if enc_start_key(exist_region) < enc_end_key(snap_region) {
+                if util::is_region_overlapped(exist_region, snap_region) {
                     warn!("region overlapped {:?}, {:?}", exist_region, snap_region);
                     return Ok(true);
                 }
             }
+
+            // Regions which are applying snapshot are not in region_ranges yet.
+            for peer in self.region_peers.values() {
+                if !peer.is_applying_snap() {
+                    continue;
+                }
+                let exist_region = peer.region();
+                if util::is_region_overlapped(exist_region, snap_region) {
+                    warn!("region overlapped with applying region {:?}, {:?}",
+                          exist_region,
+                          snap_region);
+                    return Ok(true);
+                }
+            }
         }
 
         Ok(false)
@@ -484,9 +610,18 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_raft_ready(&mut self) -> Result<()> {
-        let t = SlowTimer::new();
-        let ids: Vec<u64> = self.pending_raft_groups.drain().collect();
+        let t = SlowTimer::from_millis(self.cfg.slow_log_threshold_ms);
+        let ids = take_ready_regions(&mut self.pending_raft_groups,
+                                     self.cfg.max_ready_regions_per_tick);
         let pending_count = ids.len();
+        if !self.pending_raft_groups.is_empty() {
+            // Leave the rest to the next iteration so that timers and messages
+            // are not starved, the event loop may be idle, so wake it up.
+            metric_incr!("raftstore.raft_ready.yield");
+            if let Err(e) = self.sendch.send(Msg::RaftReady) {
+                error!("failed to notify raft ready: {:?}", e);
+            }
+        }
 
         for region_id in ids {
             let mut ready_result = None;
@@ -509,6 +644,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     return Err(e);
                 }
             }
+            self.on_pending_exec_results(region_id);
         }
 
         slow_log!(t, "on {} regions raft ready", pending_count);
@@ -523,10 +659,13 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let mut p = self.region_peers.remove(&region_id).unwrap();
         // We can't destroy a peer which is applying snapshot.
         assert!(!p.is_applying_snap());
+        if let Some(results) = self.pending_exec_results.remove(&region_id) {
+            warn!("[region {}] drop {} pending exec results", region_id, results.len());
+        }
 
         let is_initialized = p.is_initialized();
-        let end_key = enc_end_key(p.region());
-        if let Err(e) = p.destroy() {
+        let end_key = EndKey::from_region(p.region());
+        if let Err(e) = p.destroy(self.cfg.delete_batch_size) {
             // should panic here?
             error!("[region {}] destroy peer {:?} in store {} err {:?}",
                    region_id,
@@ -555,7 +694,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 info!("{} notify pd with change peer region {:?}",
                       p.tag,
                       p.region());
-                self.heartbeat_pd(p);
+                self.heartbeat_pd(p, RegionFlow::default());
             }
         }
 
@@ -567,7 +706,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_ready_compact_log(&mut self, region_id: u64, state: RaftTruncatedState) {
-        let peer = self.region_peers.get(&region_id).unwrap();
+        let peer = self.region_peers.get_mut(&region_id).unwrap();
+        peer.mut_store().compact_to(state.get_index() + 1);
         let task = CompactTask::new(peer.get_store(), state.get_index() + 1);
         if let Err(e) = self.compact_worker.schedule(task) {
             error!("[region {}] failed to schedule compact task: {}",
@@ -617,12 +757,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 // Insert new regions and validation
                 info!("insert new regions left: {:?}, right:{:?}", left, right);
                 if self.region_ranges
-                    .insert(enc_end_key(&left), left.get_id())
+                    .insert(EndKey::from_region(&left), left.get_id())
                     .is_some() {
                     panic!("region should not exist, {:?}", left);
                 }
                 if self.region_ranges
-                    .insert(enc_end_key(&right), new_region_id)
+                    .insert(EndKey::from_region(&right), new_region_id)
                     .is_none() {
                     panic!("region should exist, {:?}", right);
                 }
@@ -639,8 +779,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         info!("notify pd with split left {:?}, right {:?}",
               left_region,
               right_region);
-        self.heartbeat_pd(left);
-        self.heartbeat_pd(right);
+        // The flow is only reported in the regular heartbeat.
+        self.heartbeat_pd(left, RegionFlow::default());
+        self.heartbeat_pd(right, RegionFlow::default());
 
         // Now pd only uses ReportSplit for history operation show,
         // so we send it independently here.
@@ -669,25 +810,64 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                   prev_region,
                   region);
             // we have already initialized the peer, so it must exist in region_ranges.
-            if self.region_ranges.remove(&enc_end_key(&prev_region)).is_none() {
+            if self.region_ranges.remove(&EndKey::from_region(&prev_region)).is_none() {
                 panic!("[region {}] region should exist {:?}",
                        region_id,
                        prev_region);
             }
         }
 
-        self.region_ranges.insert(enc_end_key(&region), region.get_id());
+        // The data is applied in snap worker asynchronously, the region will be
+        // inserted into region_ranges after receiving the SnapApplyRes.
     }
 
     fn on_ready_result(&mut self, region_id: u64, ready_result: ReadyResult) -> Result<()> {
         if let Some(apply_result) = ready_result.apply_snap_result {
+            // The deferred results are before the snapshot, finish them first.
+            if let Some(results) = self.pending_exec_results.remove(&region_id) {
+                self.on_exec_results(region_id, results.into_iter().collect());
+            }
             self.on_ready_apply_snapshot(apply_result);
         }
 
-        let t = SlowTimer::new();
-        let result_count = ready_result.exec_results.len();
+        if !ready_result.exec_results.is_empty() {
+            self.pending_exec_results
+                .entry(region_id)
+                .or_insert_with(VecDeque::new)
+                .extend(ready_result.exec_results);
+        }
+
+        Ok(())
+    }
+
+    /// Handle the pending exec results of the region in order. Splits and conf
+    /// changes are heavy, at most `max_admin_results_per_ready` of them are
+    /// handled in one pass, so other regions' ready are not delayed too much.
+    fn on_pending_exec_results(&mut self, region_id: u64) {
+        let (results, has_more) = match self.pending_exec_results.get_mut(&region_id) {
+            None => return,
+            Some(pending) => {
+                let results = take_exec_results(pending, self.cfg.max_admin_results_per_ready);
+                (results, !pending.is_empty())
+            }
+        };
+        if has_more {
+            metric_incr!("raftstore.exec_results.yield");
+            self.pending_raft_groups.insert(region_id);
+            if let Err(e) = self.sendch.send(Msg::RaftReady) {
+                error!("failed to notify raft ready: {:?}", e);
+            }
+        } else {
+            self.pending_exec_results.remove(&region_id);
+        }
+        self.on_exec_results(region_id, results);
+    }
+
+    fn on_exec_results(&mut self, region_id: u64, exec_results: Vec<ExecResult>) {
+        let t = SlowTimer::from_millis(self.cfg.slow_log_threshold_ms);
+        let result_count = exec_results.len();
         // handle executing committed log results
-        for result in ready_result.exec_results {
+        for result in exec_results {
             match result {
                 ExecResult::ChangePeer { change_type, peer, .. } => {
                     self.on_ready_change_peer(region_id, change_type, peer)
@@ -696,17 +876,24 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 ExecResult::SplitRegion { left, right } => {
                     self.on_ready_split_region(region_id, left, right)
                 }
+                ExecResult::ComputeHash { index, hash } => {
+                    self.on_ready_compute_hash(region_id, index, hash)
+                }
             }
         }
         slow_log!(t,
                   "[region {}] on ready {} results",
                   region_id,
                   result_count);
-
-        Ok(())
     }
 
-    fn propose_raft_command(&mut self, msg: RaftCmdRequest, cb: Callback) -> Result<()> {
+    // If `transfer_timeout` is set, the command must be a transfer leader request,
+    // and the callback will be called after the leadership moves or the timeout elapses.
+    fn propose_raft_command(&mut self,
+                            msg: RaftCmdRequest,
+                            cb: Callback,
+                            transfer_timeout: Option<Duration>)
+                            -> Result<()> {
         let mut resp = RaftCmdResponse::new();
         let uuid: Uuid = match util::get_uuid_from_req(&msg) {
             None => {
@@ -728,28 +915,55 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             return cb.call_box((resp,));
         }
 
+        if self.cfg.read_only && util::has_write_cmd(&msg) {
+            bind_error(&mut resp,
+                       box_err!("store {} is read-only, writes are rejected", self.store_id()));
+            return cb.call_box((resp,));
+        }
+
+        // Reads and admin commands are still served by an overloaded store.
+        let busy_reason = if util::has_write_cmd(&msg) {
+            self.busy_reason()
+        } else {
+            None
+        };
+
         let region_id = msg.get_header().get_region_id();
-        let mut peer = match self.region_peers.get_mut(&region_id) {
-            None => {
-                bind_error(&mut resp, Error::RegionNotFound(region_id));
+        if !self.region_peers.contains_key(&region_id) {
+            bind_error(&mut resp, self.region_not_found(region_id));
+            return cb.call_box((resp,));
+        }
+        let term = {
+            let peer = &self.region_peers[&region_id];
+            let term = peer.term();
+            bind_term(&mut resp, term);
+
+            if !peer.is_leader() {
+                bind_error(&mut resp,
+                           Error::NotLeader(region_id, peer.get_peer_from_cache(peer.leader_id())));
                 return cb.call_box((resp,));
             }
-            Some(peer) => peer,
-        };
 
-        let term = peer.term();
-        bind_term(&mut resp, term);
+            let peer_id = msg.get_header().get_peer().get_id();
+            if peer.peer_id() != peer_id {
+                bind_error(&mut resp,
+                           box_err!("mismatch peer id {} != {}", peer.peer_id(), peer_id));
+                return cb.call_box((resp,));
+            }
+            term
+        };
 
-        if !peer.is_leader() {
-            bind_error(&mut resp,
-                       Error::NotLeader(region_id, peer.get_peer_from_cache(peer.leader_id())));
+        // The epoch is checked here too, so the regions split from the target
+        // region can be attached to the error.
+        if let Err(e) = self.region_peers[&region_id].check_epoch(&msg) {
+            bind_error(&mut resp, self.attach_split_regions(e));
             return cb.call_box((resp,));
         }
 
-        let peer_id = msg.get_header().get_peer().get_id();
-        if peer.peer_id() != peer_id {
+        if let Some(reason) = busy_reason {
+            metric_incr!("raftstore.propose.store_busy");
             bind_error(&mut resp,
-                       box_err!("mismatch peer id {} != {}", peer.peer_id(), peer_id));
+                       Error::ServerIsBusy(reason, self.cfg.store_busy_backoff_ms));
             return cb.call_box((resp,));
         }
 
@@ -767,8 +981,13 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             uuid: uuid,
             term: term,
             cb: cb,
+            propose_time: Instant::now(),
         };
-        try!(peer.propose(pending_cmd, msg, resp));
+        let mut peer = self.region_peers.get_mut(&region_id).unwrap();
+        match transfer_timeout {
+            Some(timeout) => try!(peer.propose_transfer_leader(pending_cmd, msg, resp, timeout)),
+            None => try!(peer.propose(pending_cmd, msg, resp)),
+        }
 
         self.pending_raft_groups.insert(region_id);
 
@@ -778,6 +997,43 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         Ok(())
     }
 
+    // Add the region right after the regions of a stale epoch error, which is
+    // split from the target region if their keys are adjacent.
+    fn attach_split_regions(&self, e: Error) -> Error {
+        let (msg, mut regions) = match e {
+            Error::StaleEpoch(msg, regions) => (msg, regions),
+            e => return e,
+        };
+        let sibling = match regions.last() {
+            Some(region) if !region.get_end_key().is_empty() => {
+                self.region_ranges
+                    .range(Excluded(&EndKey::from_region(region)), Unbounded::<&EndKey>)
+                    .next()
+                    .map(|(_, id)| self.region_peers[id].region())
+                    .and_then(|r| if r.get_start_key() == region.get_end_key() {
+                        Some(r.clone())
+                    } else {
+                        None
+                    })
+            }
+            _ => None,
+        };
+        if let Some(region) = sibling {
+            regions.push(region);
+        }
+        Error::StaleEpoch(msg, regions)
+    }
+
+    // Get the reason if the store is overloaded.
+    fn busy_reason(&self) -> Option<String> {
+        // The split check worker hands the tasks to its pool at once, so the
+        // checks not finished yet are counted instead of its queue.
+        let split_checks = self.running_split_checks.load(Ordering::SeqCst);
+        let workers = [(self.split_check_worker.name(), split_checks),
+                       (self.snap_worker.name(), self.snap_worker.pending_tasks())];
+        store_busy_reason(&self.cfg, &workers, self.pending_raft_groups.len())
+    }
+
     fn register_raft_gc_log_tick(&self, event_loop: &mut EventLoop<Self>) {
         if let Err(e) = register_timer(event_loop,
                                        Tick::RaftLogGc,
@@ -791,6 +1047,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
     fn on_raft_gc_log_tick(&mut self, event_loop: &mut EventLoop<Self>) {
         for (&region_id, peer) in &mut self.region_peers {
+            metric_gauge!("raftstore.region_raft_log_bytes",
+                          peer.get_store().raft_log_size());
+            peer.mut_store().check_raft_log_size(self.cfg.raft_log_size_warn_threshold);
+
             if !peer.is_leader() {
                 continue;
             }
@@ -851,14 +1111,15 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_split_region_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
-        // To avoid frequent scan, we only add new scan tasks if all previous tasks
-        // have finished.
+        // To bound the IO, at most split_check_concurrency regions are scanned at
+        // the same time, the others are left to the following ticks.
         // TODO: check whether a gc progress has been started.
-        if self.split_check_worker.is_busy() {
-            self.register_split_region_check_tick(event_loop);
-            return;
-        }
+        let running = self.running_split_checks.load(Ordering::SeqCst);
+        let mut quota = self.cfg.split_check_concurrency.saturating_sub(running);
         for (_, peer) in &mut self.region_peers {
+            if quota == 0 {
+                break;
+            }
             if !peer.is_leader() {
                 continue;
             }
@@ -871,15 +1132,138 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                   peer.size_diff_hint,
                   self.cfg.region_check_size_diff);
             let task = SplitCheckTask::new(peer.get_store());
+            self.running_split_checks.fetch_add(1, Ordering::SeqCst);
             if let Err(e) = self.split_check_worker.schedule(task) {
                 error!("failed to schedule split check: {}", e);
+                self.running_split_checks.fetch_sub(1, Ordering::SeqCst);
+                continue;
             }
             peer.size_diff_hint = 0;
+            quota -= 1;
         }
 
         self.register_split_region_check_tick(event_loop);
     }
 
+    fn register_compact_check_tick(&self, event_loop: &mut EventLoop<Self>) {
+        let interval = jitter_interval(&mut rand::thread_rng(),
+                                       self.cfg.region_compact_check_interval,
+                                       self.cfg.tick_jitter_ratio);
+        if let Err(e) = register_timer(event_loop, Tick::CompactCheck, interval) {
+            error!("register compact check tick err: {:?}", e);
+        };
+    }
+
+    fn on_compact_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        // Don't pile up compactions if the previous ones have not finished yet.
+        if self.compact_worker.is_busy() {
+            self.register_compact_check_tick(event_loop);
+            return;
+        }
+        for (_, peer) in &mut self.region_peers {
+            if peer.delete_keys_hint < self.cfg.region_compact_tombstones_threshold {
+                continue;
+            }
+            info!("{} region's deleted keys {} >= {}, need to compact the range",
+                  peer.tag,
+                  peer.delete_keys_hint,
+                  self.cfg.region_compact_tombstones_threshold);
+            let task = CompactTask::CompactRange {
+                start: enc_start_key(peer.region()),
+                end: enc_end_key(peer.region()),
+            };
+            if let Err(e) = self.compact_worker.schedule(task) {
+                error!("{} failed to schedule compact range task: {}", peer.tag, e);
+                continue;
+            }
+            peer.delete_keys_hint = 0;
+        }
+
+        self.register_compact_check_tick(event_loop);
+    }
+
+    fn register_consistency_check_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if self.cfg.consistency_check_interval == 0 {
+            return;
+        }
+        if let Err(e) = register_timer(event_loop,
+                                       Tick::ConsistencyCheck,
+                                       self.cfg.consistency_check_interval) {
+            error!("register consistency check tick err: {:?}", e);
+        };
+    }
+
+    fn on_consistency_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        let leader_ids = self.region_peers
+            .iter()
+            .filter(|&(_, p)| p.is_leader())
+            .map(|(&id, _)| id)
+            .collect();
+        if let Some(region_id) = next_region_to_check(leader_ids,
+                                                      &mut self.consistency_check_cursor) {
+            let peer = &self.region_peers[&region_id];
+            info!("{} schedule consistency check", peer.tag);
+            let request = new_compute_hash_request(region_id, peer.peer.clone());
+            let cb = Box::new(move |_: RaftCmdResponse| -> Result<()> { Ok(()) });
+            if let Err(e) = self.sendch.send(Msg::RaftCmd {
+                request: request,
+                callback: cb,
+            }) {
+                error!("{} send compute hash err {:?}", peer.tag, e);
+            }
+        }
+
+        self.register_consistency_check_tick(event_loop);
+    }
+
+    fn register_apply_sync_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if self.cfg.apply_sync_interval_ms == 0 {
+            return;
+        }
+        if let Err(e) = register_timer(event_loop,
+                                       Tick::ApplySync,
+                                       self.cfg.apply_sync_interval_ms) {
+            error!("register apply sync tick err: {:?}", e);
+        };
+    }
+
+    fn on_apply_sync_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        for peer in self.region_peers.values_mut() {
+            if let Err(e) = peer.sync_apply() {
+                error!("{} failed to sync applied writes: {:?}", peer.tag, e);
+            }
+        }
+
+        self.register_apply_sync_tick(event_loop);
+    }
+
+    fn on_ready_compute_hash(&mut self, region_id: u64, index: u64, hash: Vec<u8>) {
+        let peer = match self.region_peers.get(&region_id) {
+            None => return,
+            Some(peer) => peer,
+        };
+        // Only the leader proposes its hash, the others verify theirs against it.
+        if !peer.is_leader() {
+            return;
+        }
+        let request = new_verify_hash_request(region_id, peer.peer.clone(), index, hash);
+        let cb = Box::new(move |_: RaftCmdResponse| -> Result<()> { Ok(()) });
+        if let Err(e) = self.sendch.send(Msg::RaftCmd {
+            request: request,
+            callback: cb,
+        }) {
+            error!("{} send verify hash at index {} err {:?}", peer.tag, index, e);
+        }
+    }
+
+    fn on_compact_range(&mut self, start_key: &[u8], end_key: &[u8]) {
+        let task = new_compact_range_task(start_key, end_key);
+        info!("schedule {}", task);
+        if let Err(e) = self.compact_worker.schedule(task) {
+            error!("failed to schedule compact range task: {}", e);
+        }
+    }
+
     fn on_split_check_result(&mut self,
                              region_id: u64,
                              epoch: metapb::RegionEpoch,
@@ -908,6 +1292,17 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             return;
         }
 
+        let max_regions = self.cfg.max_regions_per_store;
+        if max_regions > 0 && self.region_peers.len() >= max_regions {
+            warn!("{} store {} already has {} regions, skip splitting at {:?}",
+                  peer.tag,
+                  self.store_id(),
+                  self.region_peers.len(),
+                  split_key);
+            metric_incr!("raftstore.split.skip_max_regions");
+            return;
+        }
+
         let key = keys::origin_key(&split_key);
         let task = PdTask::AskSplit {
             region: region.clone(),
@@ -923,10 +1318,11 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         }
     }
 
-    fn heartbeat_pd(&self, peer: &Peer) {
+    fn heartbeat_pd(&self, peer: &Peer, flow: RegionFlow) {
         let task = PdTask::Heartbeat {
             region: peer.region().clone(),
             peer: peer.peer.clone(),
+            flow: flow,
         };
         if let Err(e) = self.pd_worker.schedule(task) {
             error!("{} failed to notify pd: {}", peer.tag, e);
@@ -934,15 +1330,27 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_pd_heartbeat_tick(&mut self, event_loop: &mut EventLoop<Self>) {
-        let mut leader_count = 0;
-        for peer in self.region_peers.values() {
+        let region_ids = self.region_peers.keys().cloned().collect();
+        let region_ids = next_heartbeat_regions(region_ids,
+                                                &mut self.pd_heartbeat_cursor,
+                                                self.cfg.pd_heartbeat_sub_ticks);
+        let mut heartbeats = vec![];
+        for region_id in region_ids {
+            let peer = self.region_peers.get_mut(&region_id).unwrap();
+            // Followers also apply commands, reset their flow too so that
+            // a new leader doesn't report the flow before its election.
+            let flow = mem::replace(&mut peer.flow, RegionFlow::default());
             if peer.is_leader() {
-                leader_count += 1;
-                self.heartbeat_pd(peer);
+                heartbeats.push((region_id, flow));
             }
         }
 
-        metric_gauge!("raftstore.leader_count", leader_count);
+        for (region_id, flow) in heartbeats {
+            self.heartbeat_pd(&self.region_peers[&region_id], flow);
+        }
+
+        let leader_count = self.region_peers.values().filter(|p| p.is_leader()).count();
+        metric_gauge!("raftstore.leader_count", leader_count as u64);
         metric_gauge!("raftstore.region_count", self.region_peers.len() as u64);
 
         self.register_pd_heartbeat_tick(event_loop);
@@ -950,9 +1358,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
 
     fn register_pd_heartbeat_tick(&self, event_loop: &mut EventLoop<Self>) {
-        if let Err(e) = register_timer(event_loop,
-                                       Tick::PdHeartbeat,
-                                       self.cfg.pd_heartbeat_tick_interval) {
+        let interval = self.cfg.pd_heartbeat_tick_interval / self.cfg.pd_heartbeat_sub_ticks;
+        if let Err(e) = register_timer(event_loop, Tick::PdHeartbeat, cmp::max(interval, 1)) {
             error!("register pd heartbeat tick err: {:?}", e);
         };
     }
@@ -992,10 +1399,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         stats.set_store_id(self.store_id());
         stats.set_available(available);
         stats.set_region_count(self.region_peers.len() as u32);
+        stats.set_read_only(self.cfg.read_only);
 
         let snap_stats = self.snap_mgr.rl().stats();
         stats.set_sending_snap_count(snap_stats.sending_count as u32);
         stats.set_receiving_snap_count(snap_stats.receiving_count as u32);
+        stats.set_queued_snap_count(snap_stats.queued_count as u32);
 
         metric_gauge!("raftstore.capacity", capacity);
         metric_gauge!("raftstore.available", available);
@@ -1003,6 +1412,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                       snap_stats.sending_count as u64);
         metric_gauge!("raftstore.snapshot.receiving",
                       snap_stats.receiving_count as u64);
+        metric_gauge!("raftstore.snapshot.queued",
+                      snap_stats.queued_count as u64);
+        report_worker_metrics("split_check", &self.split_check_worker);
+        report_worker_metrics("snap", &self.snap_worker);
+        report_worker_metrics("compact", &self.compact_worker);
+        report_worker_metrics("pd", &self.pd_worker);
 
         if let Err(e) = self.pd_worker.schedule(PdTask::StoreHeartbeat { stats: stats }) {
             error!("failed to notify pd: {}", e);
@@ -1044,7 +1459,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 };
             }
 
-            let f = try!(self.snap_mgr.rl().get_snap_file(&key, is_sending));
+            let f = try!(self.snap_mgr.rl().open_snap_file(&key, is_sending));
             if is_sending {
                 if key.term < compacted_term || key.idx < compacted_idx {
                     debug!("snap file {} has been compacted, delete.", key);
@@ -1052,7 +1467,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 } else if let Ok(meta) = f.meta() {
                     let modified = box_try!(meta.modified());
                     if let Ok(elapsed) = modified.elapsed() {
-                        if elapsed > Duration::from_secs(self.cfg.snap_gc_timeout) {
+                        if elapsed > snap_gc_timeout(&self.cfg, meta.len()) {
                             debug!("snap file {} has been expired, delete.", key);
                             f.delete();
                         }
@@ -1061,7 +1476,23 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             } else if key.term <= compacted_term &&
                (key.idx < compacted_idx || key.idx == compacted_idx && !is_applying_snap) {
                 debug!("snap file {} has been applied, delete.", key);
-                f.delete();
+                if f.exists() {
+                    f.delete();
+                }
+                f.delete_partial();
+            } else if let Ok(meta) = f.partial_meta() {
+                // A partially received file is kept for resuming until it expires.
+                let modified = box_try!(meta.modified());
+                if let Ok(elapsed) = modified.elapsed() {
+                    if elapsed > snap_gc_timeout(&self.cfg, meta.len()) {
+                        debug!("partial snap file {} has been expired, delete.", key);
+                        f.delete_partial();
+                    }
+                }
+            }
+            if !f.exists() && f.partial_meta().is_err() {
+                // The file is deleted, so the queued snapshot won't be retried.
+                self.snap_mgr.wl().cancel_queued(&key, is_sending);
             }
         }
         Ok(())
@@ -1083,9 +1514,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn register_snap_mgr_gc_tick(&self, event_loop: &mut EventLoop<Self>) {
-        if let Err(e) = register_timer(event_loop,
-                                       Tick::SnapGc,
-                                       self.cfg.snap_mgr_gc_tick_interval) {
+        let interval = jitter_interval(&mut rand::thread_rng(),
+                                       self.cfg.snap_mgr_gc_tick_interval,
+                                       self.cfg.tick_jitter_ratio);
+        if let Err(e) = register_timer(event_loop, Tick::SnapGc, interval) {
             error!("register snap mgr gc tick err: {:?}", e);
         }
     }
@@ -1140,15 +1572,56 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     }
 
     fn on_snap_apply_res(&mut self, region_id: u64, is_success: bool) {
-        let peer = self.region_peers.get_mut(&region_id).unwrap();
-        let mut storage = peer.mut_store();
-        assert!(storage.is_snap_state(SnapState::Applying),
-                "snap state should not change during applying");
-        if !is_success {
-            // TODO: cleanup region and treat it as tombstone.
-            panic!("applying snapshot to {} failed", region_id);
+        let region = match self.region_peers.get_mut(&region_id) {
+            None => {
+                warn!("[region {}] receive snap apply result for a missing region, skip",
+                      region_id);
+                return;
+            }
+            Some(peer) => {
+                if !peer.is_applying_snap() {
+                    warn!("{} is not applying snapshot, skip apply result", peer.tag);
+                    return;
+                }
+                peer.mut_store().set_snap_state(SnapState::Relax);
+                peer.region().clone()
+            }
+        };
+
+        if is_success {
+            self.region_ranges.insert(EndKey::from_region(&region), region_id);
+            return;
+        }
+
+        self.on_snap_apply_failed(region_id, region);
+    }
+
+    // The region data may be partially written, remove the peer and clear its
+    // data without leaving a tombstone, so the peer is recreated uninitialized
+    // and the leader sends it a new snapshot.
+    fn on_snap_apply_failed(&mut self, region_id: u64, region: metapb::Region) {
+        error!("[region {}] failed to apply snapshot, clear the region {:?}",
+               region_id,
+               region);
+        metric_incr!("raftstore.apply_snap.failed");
+
+        let mut p = self.region_peers.remove(&region_id).unwrap();
+        // The region may be in region_ranges if it was applying when the store started.
+        let end_key = EndKey::from_region(&region);
+        if self.region_ranges.get(&end_key) == Some(&region_id) {
+            self.region_ranges.remove(&end_key);
+        }
+        // No tombstone is left, so the next message of the region recreates the
+        // peer as uninitialized and the leader sends it a new snapshot.
+        if let Err(e) = p.clear_data(self.cfg.delete_batch_size) {
+            // The region state is still applying, so it will be applied again
+            // after restart.
+            error!("[region {}] clear peer {:?} in store {} err {:?}",
+                   region_id,
+                   p.peer,
+                   self.store_id(),
+                   e);
         }
-        storage.set_snap_state(SnapState::Relax);
     }
 }
 
@@ -1163,6 +1636,16 @@ fn register_timer<T: Transport, C: PdClient>(event_loop: &mut EventLoop<Store<T,
         .map_err(|e| box_err!("register timer err: {:?}", e))
 }
 
+/// Picks a random interval in [interval * (1 - ratio), interval * (1 + ratio)], so
+/// the disk heavy ticks of the stores started together don't fire in lockstep.
+fn jitter_interval<R: Rng>(rng: &mut R, interval: u64, ratio: f64) -> u64 {
+    let delta = (interval as f64 * ratio) as u64;
+    if delta == 0 {
+        return interval;
+    }
+    rng.gen_range(interval - delta, interval + delta + 1)
+}
+
 fn new_compact_log_request(region_id: u64,
                            peer: metapb::Peer,
                            compact_index: u64)
@@ -1179,12 +1662,42 @@ fn new_compact_log_request(region_id: u64,
     request
 }
 
+fn new_compute_hash_request(region_id: u64, peer: metapb::Peer) -> RaftCmdRequest {
+    let mut request = RaftCmdRequest::new();
+    request.mut_header().set_region_id(region_id);
+    request.mut_header().set_peer(peer);
+    request.mut_header().set_uuid(Uuid::new_v4().as_bytes().to_vec());
+
+    let mut admin = AdminRequest::new();
+    admin.set_cmd_type(AdminCmdType::ComputeHash);
+    request.set_admin_request(admin);
+    request
+}
+
+fn new_verify_hash_request(region_id: u64,
+                           peer: metapb::Peer,
+                           index: u64,
+                           hash: Vec<u8>)
+                           -> RaftCmdRequest {
+    let mut request = RaftCmdRequest::new();
+    request.mut_header().set_region_id(region_id);
+    request.mut_header().set_peer(peer);
+    request.mut_header().set_uuid(Uuid::new_v4().as_bytes().to_vec());
+
+    let mut admin = AdminRequest::new();
+    admin.set_cmd_type(AdminCmdType::VerifyHash);
+    admin.mut_verify_hash().set_index(index);
+    admin.mut_verify_hash().set_hash(hash);
+    request.set_admin_request(admin);
+    request
+}
+
 impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
     type Timeout = Tick;
     type Message = Msg;
 
     fn notify(&mut self, event_loop: &mut EventLoop<Self>, msg: Msg) {
-        let t = SlowTimer::new();
+        let t = SlowTimer::from_millis(self.cfg.slow_log_threshold_ms);
         let msg_str = format!("{:?}", msg);
         match msg {
             Msg::RaftMessage(data) => {
@@ -1193,14 +1706,20 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
                 }
             }
             Msg::RaftCmd { request, callback } => {
-                if let Err(e) = self.propose_raft_command(request, callback) {
+                if let Err(e) = self.propose_raft_command(request, callback, None) {
                     error!("propose raft command err: {:?}", e);
                 }
             }
+            Msg::TransferLeaderAndWait { request, timeout, callback } => {
+                if let Err(e) = self.propose_raft_command(request, callback, Some(timeout)) {
+                    error!("propose transfer leader err: {:?}", e);
+                }
+            }
             Msg::Quit => {
                 info!("receive quit message");
                 event_loop.shutdown();
             }
+            Msg::PrepareShutdown { timeout } => self.on_prepare_shutdown(event_loop, timeout),
             Msg::SplitCheckResult { region_id, epoch, split_key } => {
                 info!("[region {}] split check complete.", region_id);
                 self.on_split_check_result(region_id, epoch, split_key);
@@ -1211,6 +1730,8 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Msg::ReportUnreachable { region_id, to_peer_id } => {
                 self.on_unreachable(region_id, to_peer_id);
             }
+            // The pending raft ready will be handled in `tick`.
+            Msg::RaftReady => {}
             Msg::SnapshotStats => self.store_heartbeat_pd(),
             Msg::SnapApplyRes { region_id, is_success } => {
                 self.on_snap_apply_res(region_id, is_success);
@@ -1218,12 +1739,15 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Msg::SnapGenRes { region_id, snap } => {
                 self.on_snap_gen_res(region_id, snap);
             }
+            Msg::CompactRange { start_key, end_key } => {
+                self.on_compact_range(&start_key, &end_key);
+            }
         }
         slow_log!(t, "handle {:?}", msg_str);
     }
 
     fn timeout(&mut self, event_loop: &mut EventLoop<Self>, timeout: Tick) {
-        let t = SlowTimer::new();
+        let t = SlowTimer::from_millis(self.cfg.slow_log_threshold_ms);
         match timeout {
             Tick::Raft => self.on_raft_base_tick(event_loop),
             Tick::RaftLogGc => self.on_raft_gc_log_tick(event_loop),
@@ -1231,6 +1755,9 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Tick::PdHeartbeat => self.on_pd_heartbeat_tick(event_loop),
             Tick::PdStoreHeartbeat => self.on_pd_store_heartbeat_tick(event_loop),
             Tick::SnapGc => self.on_snap_mgr_gc(event_loop),
+            Tick::CompactCheck => self.on_compact_check_tick(event_loop),
+            Tick::ConsistencyCheck => self.on_consistency_check_tick(event_loop),
+            Tick::ApplySync => self.on_apply_sync_tick(event_loop),
         }
         slow_log!(t, "handle timeout {:?}", timeout);
     }
@@ -1263,9 +1790,25 @@ impl<T: Transport, C: PdClient> Store<T, C> {
     /// load the target peer of request as mutable borrow.
     fn mut_target_peer(&mut self, request: &RaftCmdRequest) -> Result<&mut Peer> {
         let region_id = request.get_header().get_region_id();
-        match self.region_peers.get_mut(&region_id) {
-            None => Err(Error::RegionNotFound(region_id)),
-            Some(peer) => Ok(peer),
+        if !self.region_peers.contains_key(&region_id) {
+            return Err(self.region_not_found(region_id));
+        }
+        Ok(self.region_peers.get_mut(&region_id).unwrap())
+    }
+
+    /// Builds the error for a region without a peer in this store, consulting
+    /// the tombstone state key like `is_msg_stale` does.
+    fn region_not_found(&self, region_id: u64) -> Error {
+        let state_key = keys::region_state_key(region_id);
+        match self.engine.get_msg::<RegionLocalState>(&state_key) {
+            Ok(Some(ref local_state)) if local_state.get_state() == PeerState::Tombstone => {
+                Error::RegionTombstone(region_id)
+            }
+            Ok(_) => Error::RegionNotFound(region_id),
+            Err(e) => {
+                error!("[region {}] failed to load local state: {:?}", region_id, e);
+                Error::RegionNotFound(region_id)
+            }
         }
     }
 
@@ -1280,6 +1823,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let mut response = try!(match cmd_type {
             StatusCmdType::RegionLeader => self.execute_region_leader(request),
             StatusCmdType::RegionDetail => self.execute_region_detail(request),
+            StatusCmdType::RegionCount => self.execute_region_count(),
+            StatusCmdType::RegionProgress => self.execute_region_progress(request),
+            StatusCmdType::StoreRegions => self.execute_store_regions(),
+            StatusCmdType::StoreReady => self.execute_store_ready(),
             StatusCmdType::InvalidStatus => Err(box_err!("invalid status command!")),
         });
         response.set_cmd_type(cmd_type);
@@ -1318,4 +1865,545 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         Ok(resp)
     }
+
+    // Count the regions in this store, only the in-memory state is read.
+    fn execute_region_count(&self) -> Result<StatusResponse> {
+        let (mut leader_count, mut applying_count) = (0, 0);
+        for peer in self.region_peers.values() {
+            if peer.is_leader() {
+                leader_count += 1;
+            }
+            if peer.is_applying_snap() {
+                applying_count += 1;
+            }
+        }
+        let total = self.region_peers.len() as u64;
+
+        let mut resp = StatusResponse::new();
+        {
+            let region_count = resp.mut_region_count();
+            region_count.set_leader_count(leader_count);
+            region_count.set_follower_count(total - leader_count);
+            region_count.set_applying_count(applying_count);
+            region_count.set_normal_count(total - applying_count);
+        }
+        Ok(resp)
+    }
+
+    // List the regions in this store ordered by end key, only the in-memory
+    // state is read.
+    fn execute_store_regions(&self) -> Result<StatusResponse> {
+        let mut resp = StatusResponse::new();
+        for region_id in self.region_ranges.values() {
+            let peer = match self.region_peers.get(region_id) {
+                Some(peer) => peer,
+                None => {
+                    error!("[region {}] is in region ranges but has no peer", region_id);
+                    continue;
+                }
+            };
+            let region = peer.region();
+            let mut info = RegionInfo::new();
+            info.set_region_id(*region_id);
+            info.set_start_key(region.get_start_key().to_vec());
+            info.set_end_key(region.get_end_key().to_vec());
+            info.set_leader_id(peer.leader_id());
+            info.set_peer_count(region.get_peers().len() as u64);
+            resp.mut_store_regions().mut_regions().push(info);
+        }
+        Ok(resp)
+    }
+
+    // A store which is still starting can't handle the command at all, so it's
+    // only reported not ready after it starts to shutdown.
+    fn execute_store_ready(&self) -> Result<StatusResponse> {
+        let mut resp = StatusResponse::new();
+        resp.mut_store_ready().set_ready(self.ready);
+        resp.mut_store_ready().set_region_count(self.region_peers.len() as u64);
+        Ok(resp)
+    }
+
+    // Only the leader tracks the progress of the peers, it's read from memory.
+    fn execute_region_progress(&mut self, request: RaftCmdRequest) -> Result<StatusResponse> {
+        let peer = try!(self.mut_target_peer(&request));
+        if !peer.is_leader() {
+            return Err(Error::NotLeader(peer.region().get_id(),
+                                        peer.get_peer_from_cache(peer.leader_id())));
+        }
+
+        let status = peer.get_raft_status();
+        let mut ids: Vec<_> = status.progress.keys().cloned().collect();
+        ids.sort();
+        let mut resp = StatusResponse::new();
+        for id in ids {
+            let pr = &status.progress[&id];
+            let mut progress = PeerProgress::new();
+            progress.set_peer_id(id);
+            progress.set_matched(pr.matched);
+            progress.set_next(pr.next_idx);
+            progress.set_state(match pr.state {
+                ProgressState::Probe => PbProgressState::Probe,
+                ProgressState::Replicate => PbProgressState::Replicate,
+                ProgressState::Snapshot => PbProgressState::Snapshot,
+            });
+            resp.mut_region_progress().mut_progresses().push(progress);
+        }
+        Ok(resp)
+    }
+}
+
+// Get the gc timeout of a sending snapshot of `size` bytes.
+fn snap_gc_timeout(cfg: &Config, size: u64) -> Duration {
+    let extra = (size / (1024 * 1024)).saturating_mul(cfg.snap_gc_timeout_per_mb);
+    let timeout = cfg.snap_gc_timeout.saturating_add(extra);
+    Duration::from_secs(cmp::min(timeout, cfg.max_snap_gc_timeout))
+}
+
+// Get the reason if the store is overloaded by the pending tasks of its
+// `workers` or the regions waiting for raft ready.
+fn store_busy_reason(cfg: &Config,
+                     workers: &[(&str, usize)],
+                     pending_ready_regions: usize)
+                     -> Option<String> {
+    for &(name, pending) in workers {
+        if pending >= cfg.store_busy_pending_tasks {
+            return Some(format!("{} has {} pending tasks", name, pending));
+        }
+    }
+    if pending_ready_regions >= cfg.store_busy_pending_ready_regions {
+        return Some(format!("{} regions are waiting for raft ready", pending_ready_regions));
+    }
+    None
+}
+
+/// Build the task compacting the data in [start_key, end_key), an empty
+/// `end_key` means the end of all the data.
+fn new_compact_range_task(start_key: &[u8], end_key: &[u8]) -> CompactTask {
+    let end = if end_key.is_empty() {
+        keys::DATA_MAX_KEY.to_vec()
+    } else {
+        keys::data_key(end_key)
+    };
+    CompactTask::CompactRange {
+        start: keys::data_key(start_key),
+        end: end,
+    }
+}
+
+/// Take the regions the pd heartbeat sub-tick visits, the ones after `cursor`
+/// by region id, wrapping around. Every region is visited once in `sub_ticks`
+/// sub-ticks.
+fn next_heartbeat_regions(mut region_ids: Vec<u64>, cursor: &mut u64, sub_ticks: u64) -> Vec<u64> {
+    if region_ids.is_empty() {
+        return region_ids;
+    }
+    region_ids.sort();
+    let count = region_ids.len() as u64;
+    let batch_size = ((count + sub_ticks - 1) / sub_ticks) as usize;
+    let start = region_ids.iter().position(|&id| id > *cursor).unwrap_or(0);
+    let ids: Vec<u64> = region_ids.iter().cycle().skip(start).take(batch_size).cloned().collect();
+    *cursor = *ids.last().unwrap();
+    ids
+}
+
+// Returns the first region after `cursor`, or the first one if all the regions
+// after it have been checked, and moves the cursor to it.
+fn next_region_to_check(mut region_ids: Vec<u64>, cursor: &mut u64) -> Option<u64> {
+    region_ids.sort();
+    let next = region_ids.iter().find(|&&id| id > *cursor).or_else(|| region_ids.first()).cloned();
+    if let Some(id) = next {
+        *cursor = id;
+    }
+    next
+}
+
+fn report_worker_metrics<T: Display + Send + 'static>(name: &str, worker: &Worker<T>) {
+    metric_gauge!(&format!("raftstore.worker.{}.pending", name),
+                  worker.pending_tasks() as u64);
+    if let Some(d) = worker.last_task_duration() {
+        metric_gauge!(&format!("raftstore.worker.{}.last_task_ms", name),
+                      duration_to_ms(d));
+    }
+}
+
+// Splits and conf changes change the region.
+fn is_admin_result(result: &ExecResult) -> bool {
+    match *result {
+        ExecResult::CompactLog { .. } |
+        ExecResult::ComputeHash { .. } => false,
+        ExecResult::ChangePeer { .. } |
+        ExecResult::SplitRegion { .. } => true,
+    }
+}
+
+/// Take the pending exec results in order until `max_admin` splits or conf
+/// changes are taken.
+fn take_exec_results(pending: &mut VecDeque<ExecResult>, max_admin: usize) -> Vec<ExecResult> {
+    let mut results = vec![];
+    let mut admin_cnt = 0;
+    while let Some(result) = pending.pop_front() {
+        if is_admin_result(&result) {
+            if admin_cnt == max_admin {
+                pending.push_front(result);
+                break;
+            }
+            admin_cnt += 1;
+        }
+        results.push(result);
+    }
+    results
+}
+
+/// Scan all the region meta to get the live regions, and rewrite the region index
+/// with them, the stale index keys are removed.
+fn rebuild_region_index(engine: &DB) -> Result<Vec<(u64, RegionLocalState)>> {
+    let wb = WriteBatch::new();
+    try!(engine.scan(keys::REGION_INDEX_MIN_KEY,
+                     keys::REGION_INDEX_MAX_KEY,
+                     &mut |key, _| {
+                         try!(wb.delete(key));
+                         Ok(true)
+                     }));
+
+    let mut states = vec![];
+    try!(engine.scan(keys::REGION_META_MIN_KEY,
+                     keys::REGION_META_MAX_KEY,
+                     &mut |key, value| {
+        let (region_id, suffix) = try!(keys::decode_region_meta_key(key));
+        if suffix != keys::REGION_STATE_SUFFIX {
+            return Ok(true);
+        }
+
+        let local_state = try!(protobuf::parse_from_bytes::<RegionLocalState>(value));
+        if local_state.get_state() == PeerState::Tombstone {
+            debug!("region {:?} is tombstone", local_state.get_region());
+            return Ok(true);
+        }
+        try!(wb.put(&keys::region_index_key(region_id), &[]));
+        states.push((region_id, local_state));
+        Ok(true)
+    }));
+
+    try!(wb.put(keys::REGION_INDEX_BUILT_KEY, &[]));
+    try!(engine.write(wb));
+    Ok(states)
+}
+
+/// Load the live regions recorded in the region index, the tombstone regions
+/// are not touched.
+fn load_indexed_regions(engine: &DB) -> Result<Vec<(u64, RegionLocalState)>> {
+    let mut region_ids = vec![];
+    try!(engine.scan(keys::REGION_INDEX_MIN_KEY,
+                     keys::REGION_INDEX_MAX_KEY,
+                     &mut |key, _| {
+                         region_ids.push(try!(keys::decode_region_index_key(key)));
+                         Ok(true)
+                     }));
+
+    let mut states = Vec::with_capacity(region_ids.len());
+    for region_id in region_ids {
+        let state_key = keys::region_state_key(region_id);
+        let local_state: RegionLocalState = match try!(engine.get_msg(&state_key)) {
+            Some(s) => s,
+            None => {
+                return Err(box_err!("region {} is indexed but has no state, \
+                                     rebuild the region index to repair it",
+                                    region_id))
+            }
+        };
+        if local_state.get_state() == PeerState::Tombstone {
+            return Err(box_err!("region {} is indexed but is tombstone, \
+                                 rebuild the region index to repair it",
+                                region_id));
+        }
+        states.push((region_id, local_state));
+    }
+    Ok(states)
+}
+
+// Take at most `limit` region ids from `pending`.
+fn take_ready_regions(pending: &mut HashSet<u64>, limit: usize) -> Vec<u64> {
+    if pending.len() <= limit {
+        return pending.drain().collect();
+    }
+    let ids: Vec<u64> = pending.iter().take(limit).cloned().collect();
+    for id in &ids {
+        pending.remove(id);
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+    use std::time::Duration;
+
+    use std::fmt;
+
+    use rocksdb::{DB, Writable, WriteBatch};
+    use tempdir::TempDir;
+    use kvproto::metapb;
+    use kvproto::raft_serverpb::{RaftTruncatedState, RegionLocalState, PeerState};
+    use kvproto::raftpb::ConfChangeType;
+
+    use super::{take_ready_regions, take_exec_results, snap_gc_timeout, store_busy_reason,
+                rebuild_region_index, load_indexed_regions, next_heartbeat_regions,
+                new_compact_range_task, jitter_interval};
+    use raftstore::store::{Config, keys, Mutable, Peekable};
+    use raftstore::store::peer::ExecResult;
+    use raftstore::store::worker::CompactTask;
+    use util::worker::Worker;
+    use util::rocksdb;
+    use rand;
+
+    #[test]
+    fn test_take_ready_regions() {
+        let mut pending: HashSet<u64> = (1..1001).collect();
+        let mut taken = HashSet::new();
+        // the handler yields after 128 regions, the rest stay pending.
+        for i in 1..8 {
+            let ids = take_ready_regions(&mut pending, 128);
+            assert_eq!(ids.len(), 128);
+            assert_eq!(pending.len(), 1000 - 128 * i);
+            for id in ids {
+                assert!(!pending.contains(&id));
+                assert!(taken.insert(id));
+            }
+        }
+        let ids = take_ready_regions(&mut pending, 128);
+        assert_eq!(ids.len(), 1000 - 128 * 7);
+        assert!(pending.is_empty());
+        taken.extend(ids);
+        assert_eq!(taken.len(), 1000);
+
+        assert!(take_ready_regions(&mut pending, 128).is_empty());
+    }
+
+    fn compact_log(index: u64) -> ExecResult {
+        let mut state = RaftTruncatedState::new();
+        state.set_index(index);
+        ExecResult::CompactLog { state: state }
+    }
+
+    fn split_region(id: u64) -> ExecResult {
+        let mut left = metapb::Region::new();
+        left.set_id(id);
+        ExecResult::SplitRegion {
+            left: left,
+            right: metapb::Region::new(),
+        }
+    }
+
+    fn change_peer(id: u64) -> ExecResult {
+        let mut peer = metapb::Peer::new();
+        peer.set_id(id);
+        ExecResult::ChangePeer {
+            change_type: ConfChangeType::AddNode,
+            peer: peer,
+            region: metapb::Region::new(),
+        }
+    }
+
+    fn result_key(result: &ExecResult) -> (&'static str, u64) {
+        match *result {
+            ExecResult::CompactLog { ref state } => ("compact", state.get_index()),
+            ExecResult::SplitRegion { ref left, .. } => ("split", left.get_id()),
+            ExecResult::ChangePeer { ref peer, .. } => ("change_peer", peer.get_id()),
+            ExecResult::ComputeHash { index, .. } => ("compute_hash", index),
+        }
+    }
+
+    #[test]
+    fn test_take_exec_results() {
+        let mut pending = VecDeque::new();
+        pending.push_back(compact_log(1));
+        pending.push_back(split_region(2));
+        pending.push_back(compact_log(3));
+        pending.push_back(change_peer(4));
+        pending.push_back(split_region(5));
+
+        // every pass handles at most one split or conf change, the compact
+        // log results in between are handled along with them.
+        let expects = vec![
+            vec![("compact", 1), ("split", 2), ("compact", 3)],
+            vec![("change_peer", 4)],
+            vec![("split", 5)],
+        ];
+        for expect in expects {
+            let results = take_exec_results(&mut pending, 1);
+            let keys: Vec<_> = results.iter().map(result_key).collect();
+            assert_eq!(keys, expect);
+        }
+        assert!(pending.is_empty());
+        assert!(take_exec_results(&mut pending, 1).is_empty());
+
+        pending.push_back(split_region(6));
+        pending.push_back(change_peer(7));
+        pending.push_back(compact_log(8));
+        let results = take_exec_results(&mut pending, 2);
+        assert_eq!(results.len(), 3);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_snap_gc_timeout() {
+        let mut cfg = Config::new();
+        cfg.snap_gc_timeout = 10;
+        cfg.snap_gc_timeout_per_mb = 2;
+        cfg.max_snap_gc_timeout = 100;
+
+        let mb = 1024 * 1024;
+        let cases = vec![(0, 10), (mb - 1, 10), (mb, 12), (10 * mb + 1, 30), (45 * mb, 100),
+                         (1024 * mb, 100), (u64::max_value(), 100)];
+        for (size, exp) in cases {
+            assert_eq!(snap_gc_timeout(&cfg, size), Duration::from_secs(exp), "{}", size);
+        }
+    }
+
+    struct BlockedTask;
+
+    impl fmt::Display for BlockedTask {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "blocked task")
+        }
+    }
+
+    #[test]
+    fn test_store_busy_reason() {
+        let mut cfg = Config::new();
+        cfg.store_busy_pending_tasks = 3;
+        cfg.store_busy_pending_ready_regions = 10;
+
+        // the worker is never started, so all the scheduled tasks stay pending.
+        let worker: Worker<BlockedTask> = Worker::new("split check worker");
+        let busy = |w: &Worker<BlockedTask>, ready| {
+            store_busy_reason(&cfg, &[("snap worker", 0), (w.name(), w.pending_tasks())], ready)
+        };
+        assert_eq!(busy(&worker, 0), None);
+        for _ in 0..2 {
+            worker.schedule(BlockedTask).unwrap();
+        }
+        assert_eq!(busy(&worker, 9), None);
+        worker.schedule(BlockedTask).unwrap();
+        assert_eq!(busy(&worker, 0),
+                   Some("split check worker has 3 pending tasks".to_owned()));
+        let idle: Worker<BlockedTask> = Worker::new("idle worker");
+        assert_eq!(busy(&idle, 10),
+                   Some("10 regions are waiting for raft ready".to_owned()));
+    }
+
+    fn write_region_state(engine: &DB, region_id: u64, state: PeerState) {
+        let mut local_state = RegionLocalState::new();
+        local_state.set_state(state);
+        local_state.mut_region().set_id(region_id);
+        let wb = WriteBatch::new();
+        wb.put_msg(&keys::region_state_key(region_id), &local_state).unwrap();
+        if state == PeerState::Tombstone {
+            wb.delete(&keys::region_index_key(region_id)).unwrap();
+        } else {
+            wb.put(&keys::region_index_key(region_id), &[]).unwrap();
+        }
+        engine.write(wb).unwrap();
+    }
+
+    fn region_ids(states: &[(u64, RegionLocalState)]) -> Vec<u64> {
+        states.iter().map(|&(id, _)| id).collect()
+    }
+
+    #[test]
+    fn test_region_index() {
+        let path = TempDir::new("test-region-index").unwrap();
+        let engine = rocksdb::new_engine(path.path().to_str().unwrap(), &[]).unwrap();
+
+        // Most regions are created then tombstoned, like after many merges
+        // or migrations.
+        let live = vec![3, 500, 1001, 2048, 4000];
+        for id in 1..4001 {
+            write_region_state(&engine, id, PeerState::Normal);
+            if !live.contains(&id) {
+                write_region_state(&engine, id, PeerState::Tombstone);
+            }
+        }
+        write_region_state(&engine, 2048, PeerState::Applying);
+
+        let states = rebuild_region_index(&engine).unwrap();
+        assert_eq!(region_ids(&states), live);
+        assert!(engine.get_value(keys::REGION_INDEX_BUILT_KEY).unwrap().is_some());
+
+        let states = load_indexed_regions(&engine).unwrap();
+        assert_eq!(region_ids(&states), live);
+        assert_eq!(states[3].1.get_state(), PeerState::Applying);
+
+        // A stale index key is reported, and fixed by rebuilding the index.
+        engine.put(&keys::region_index_key(1), &[]).unwrap();
+        assert!(load_indexed_regions(&engine).is_err());
+        engine.put(&keys::region_index_key(5000), &[]).unwrap();
+        assert!(load_indexed_regions(&engine).is_err());
+        assert_eq!(region_ids(&rebuild_region_index(&engine).unwrap()), live);
+        assert_eq!(region_ids(&load_indexed_regions(&engine).unwrap()), live);
+    }
+
+    #[test]
+    fn test_next_heartbeat_regions() {
+        let region_ids: Vec<u64> = (1..11).collect();
+        let mut cursor = 0;
+        // all the regions are visited in one tick without sub-ticks.
+        assert_eq!(next_heartbeat_regions(region_ids.clone(), &mut cursor, 1),
+                   region_ids);
+        assert_eq!(next_heartbeat_regions(region_ids.clone(), &mut cursor, 1),
+                   region_ids);
+
+        // 10 regions are spread over 4 sub-ticks, 3 at most a time.
+        let mut cursor = 0;
+        let mut visited = HashSet::new();
+        for _ in 0..4 {
+            let mut ids = region_ids.clone();
+            ids.reverse();
+            let batch = next_heartbeat_regions(ids, &mut cursor, 4);
+            assert_eq!(batch.len(), 3);
+            visited.extend(batch);
+        }
+        assert_eq!(visited.len(), region_ids.len());
+        // it wraps around.
+        assert_eq!(cursor, 2);
+        assert_eq!(next_heartbeat_regions(region_ids.clone(), &mut cursor, 4),
+                   vec![3, 4, 5]);
+
+        // the cursor region may be gone.
+        let mut cursor = 4;
+        assert_eq!(next_heartbeat_regions(vec![2, 7, 9], &mut cursor, 2), vec![7, 9]);
+        assert_eq!(next_heartbeat_regions(vec![2, 7, 9], &mut cursor, 2), vec![2, 7]);
+        assert!(next_heartbeat_regions(vec![], &mut cursor, 2).is_empty());
+    }
+
+    #[test]
+    fn test_jitter_interval() {
+        let mut rng = rand::thread_rng();
+        assert_eq!(jitter_interval(&mut rng, 1000, 0.0), 1000);
+        // the delta is too small to jitter.
+        assert_eq!(jitter_interval(&mut rng, 5, 0.1), 5);
+
+        let intervals: HashSet<_> =
+            (0..100).map(|_| jitter_interval(&mut rng, 60000, 0.1)).collect();
+        assert!(intervals.len() > 1);
+        for &i in &intervals {
+            assert!(i >= 54000 && i <= 66000, "{}", i);
+        }
+    }
+
+    #[test]
+    fn test_new_compact_range_task() {
+        let cases: &[(&[u8], &[u8], &[u8], &[u8])] = &[(b"a", b"b", b"za", b"zb"),
+                                                       (b"", b"b", b"z", b"zb"),
+                                                       (b"a", b"", b"za", b"{")];
+        for &(start_key, end_key, exp_start, exp_end) in cases {
+            match new_compact_range_task(start_key, end_key) {
+                CompactTask::CompactRange { start, end } => {
+                    assert_eq!(start, exp_start);
+                    assert_eq!(end, exp_end);
+                }
+                t => panic!("unexpected task {}", t),
+            }
+        }
+    }
 }
@@ -17,7 +17,7 @@ use uuid::Uuid;
 
 use kvproto::metapb;
 use kvproto::raftpb::{self, ConfChangeType};
-use kvproto::raft_cmdpb::RaftCmdRequest;
+use kvproto::raft_cmdpb::{RaftCmdRequest, Request, CmdType};
 use raftstore::{Result, Error};
 
 pub fn find_peer(region: &metapb::Region, store_id: u64) -> Option<&metapb::Peer> {
@@ -62,22 +62,129 @@ pub fn check_key_in_region(key: &[u8], region: &metapb::Region) -> Result<()> {
 pub fn conf_change_type_str(conf_type: &raftpb::ConfChangeType) -> String {
     match *conf_type {
         ConfChangeType::AddNode => "AddNode".to_owned(),
+        ConfChangeType::AddLearnerNode => "AddLearnerNode".to_owned(),
         ConfChangeType::RemoveNode => "RemoveNode".to_owned(),
     }
 }
 
+/// How a command accesses the data of a region.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CmdKind {
+    // only reads the data.
+    Read,
+    // puts or deletes keys without reading them.
+    Write,
+    // reads keys and writes them according to what is read.
+    ReadWrite,
+    Invalid,
+}
+
+pub fn cmd_kind(cmd_type: CmdType) -> CmdKind {
+    match cmd_type {
+        CmdType::Get | CmdType::BatchGet | CmdType::Seek | CmdType::Snap => CmdKind::Read,
+        CmdType::Put | CmdType::Delete => CmdKind::Write,
+        CmdType::Append | CmdType::CAS => CmdKind::ReadWrite,
+        CmdType::Invalid => CmdKind::Invalid,
+    }
+}
+
+// Like `cmd_kind`, but a put returning the previous value reads the key too.
+pub fn request_kind(req: &Request) -> CmdKind {
+    if req.get_cmd_type() == CmdType::Put && req.get_put().get_return_prev() {
+        return CmdKind::ReadWrite;
+    }
+    cmd_kind(req.get_cmd_type())
+}
+
+// Returns true if the command modifies the data of the region.
+pub fn has_write_cmd(req: &RaftCmdRequest) -> bool {
+    !req.has_admin_request() &&
+    req.get_requests().iter().any(|r| match request_kind(r) {
+        CmdKind::Write | CmdKind::ReadWrite => true,
+        CmdKind::Read | CmdKind::Invalid => false,
+    })
+}
+
+// Returns true if the command only puts or deletes keys, so it can be applied
+// together with the following commands in one write batch.
+pub fn is_write_only(req: &RaftCmdRequest) -> bool {
+    !req.has_admin_request() &&
+    req.get_requests().iter().all(|r| request_kind(r) == CmdKind::Write)
+}
+
+// Returns the keys the command accesses.
+pub fn request_keys(req: &Request) -> Vec<&[u8]> {
+    match req.get_cmd_type() {
+        CmdType::Get => vec![req.get_get().get_key()],
+        CmdType::BatchGet => req.get_batch_get().get_keys().iter().map(|k| k.as_slice()).collect(),
+        CmdType::Seek => vec![req.get_seek().get_key()],
+        CmdType::Put => vec![req.get_put().get_key()],
+        CmdType::Delete => vec![req.get_delete().get_key()],
+        CmdType::Append => vec![req.get_append().get_key()],
+        CmdType::CAS => vec![req.get_cas().get_key()],
+        CmdType::Snap | CmdType::Invalid => vec![],
+    }
+}
+
 // check whether epoch is staler than check_epoch.
 pub fn is_epoch_stale(epoch: &metapb::RegionEpoch, check_epoch: &metapb::RegionEpoch) -> bool {
     epoch.get_version() < check_epoch.get_version() ||
     epoch.get_conf_ver() < check_epoch.get_conf_ver()
 }
 
+// check whether the key ranges of two regions overlap, an empty end key
+// means +infinity.
+pub fn is_region_overlapped(a: &metapb::Region, b: &metapb::Region) -> bool {
+    // a starts before b ends ...
+    (b.get_end_key().is_empty() || a.get_start_key() < b.get_end_key()) &&
+    // ... and b starts before a ends.
+    (a.get_end_key().is_empty() || b.get_start_key() < a.get_end_key())
+}
+
 #[cfg(test)]
 mod tests {
     use kvproto::metapb;
 
     use super::*;
 
+    #[test]
+    fn test_write_cmd() {
+        let new_cmd = |cmd_types: &[CmdType]| {
+            let mut req = RaftCmdRequest::new();
+            for &cmd_type in cmd_types {
+                let mut r = Request::new();
+                r.set_cmd_type(cmd_type);
+                req.mut_requests().push(r);
+            }
+            req
+        };
+
+        let req = new_cmd(&[CmdType::Get, CmdType::Snap]);
+        assert!(!has_write_cmd(&req));
+        assert!(!is_write_only(&req));
+
+        let req = new_cmd(&[CmdType::Put, CmdType::Delete]);
+        assert!(has_write_cmd(&req));
+        assert!(is_write_only(&req));
+
+        for &cmd_type in &[CmdType::Append, CmdType::CAS] {
+            let req = new_cmd(&[CmdType::Put, cmd_type]);
+            assert!(has_write_cmd(&req), "{:?}", cmd_type);
+            assert!(!is_write_only(&req), "{:?}", cmd_type);
+        }
+
+        // a put returning the previous value reads the key.
+        let mut req = new_cmd(&[CmdType::Put]);
+        req.mut_requests()[0].mut_put().set_return_prev(true);
+        assert!(has_write_cmd(&req));
+        assert!(!is_write_only(&req));
+
+        let mut req = new_cmd(&[]);
+        req.mut_admin_request();
+        assert!(!has_write_cmd(&req));
+        assert!(!is_write_only(&req));
+    }
+
     #[test]
     fn test_peer() {
         let mut region = metapb::Region::new();
@@ -92,4 +199,33 @@ mod tests {
         assert!(find_peer(&region, 1).is_none());
 
     }
+
+    fn new_region(start_key: &[u8], end_key: &[u8]) -> metapb::Region {
+        let mut region = metapb::Region::new();
+        region.set_start_key(start_key.to_vec());
+        region.set_end_key(end_key.to_vec());
+        region
+    }
+
+    #[test]
+    fn test_region_overlapped() {
+        let cases: Vec<(&[u8], &[u8], &[u8], &[u8], bool)> = vec![
+            (b"", b"", b"", b"", true),
+            (b"", b"", b"a", b"b", true),
+            (b"a", b"", b"", b"a", false),
+            (b"a", b"", b"", b"b", true),
+            (b"b", b"", b"a", b"b", false),
+            (b"b", b"", b"a", b"c", true),
+            (b"b", b"", b"c", b"", true),
+            (b"a", b"b", b"b", b"", false),
+            (b"a", b"b", b"c", b"d", false),
+            (b"a", b"c", b"b", b"d", true),
+            (b"a", b"d", b"b", b"c", true),
+        ];
+        for (s1, e1, s2, e2, exp) in cases {
+            let (r1, r2) = (new_region(s1, e1), new_region(s2, e2));
+            assert_eq!(is_region_overlapped(&r1, &r2), exp, "{:?} {:?}", r1, r2);
+            assert_eq!(is_region_overlapped(&r2, &r1), exp, "{:?} {:?}", r2, r1);
+        }
+    }
 }
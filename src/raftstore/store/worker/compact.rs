@@ -14,6 +14,7 @@
 use raftstore::store::{PeerStorage, keys};
 use raftstore::store::engine::Iterable;
 use util::worker::Runnable;
+use util::escape;
 
 use rocksdb::{DB, WriteBatch, Writable};
 use std::sync::Arc;
@@ -21,16 +22,16 @@ use std::fmt::{self, Formatter, Display};
 use std::error;
 
 /// Compact task.
-pub struct Task {
-    engine: Arc<DB>,
-    region_id: u64,
-    compact_idx: u64,
+pub enum Task {
+    /// Delete the raft logs of the region before `compact_idx`.
+    CompactRaftLog { region_id: u64, compact_idx: u64 },
+    /// Compact the data in [start, end) to clean up tombstones.
+    CompactRange { start: Vec<u8>, end: Vec<u8> },
 }
 
 impl Task {
     pub fn new(ps: &PeerStorage, compact_idx: u64) -> Task {
-        Task {
-            engine: ps.get_engine().clone(),
+        Task::CompactRaftLog {
             region_id: ps.get_region_id(),
             compact_idx: compact_idx,
         }
@@ -39,10 +40,20 @@ impl Task {
 
 impl Display for Task {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f,
-               "Compact Task [region: {}, to: {}]",
-               self.region_id,
-               self.compact_idx)
+        match *self {
+            Task::CompactRaftLog { region_id, compact_idx } => {
+                write!(f,
+                       "Compact Raft Log Task [region: {}, to: {}]",
+                       region_id,
+                       compact_idx)
+            }
+            Task::CompactRange { ref start, ref end } => {
+                write!(f,
+                       "Compact Range Task [start: {}, end: {}]",
+                       escape(start),
+                       escape(end))
+            }
+        }
     }
 }
 
@@ -58,42 +69,56 @@ quick_error! {
     }
 }
 
-pub struct Runner;
+pub struct Runner {
+    engine: Arc<DB>,
+}
 
 impl Runner {
+    pub fn new(engine: Arc<DB>) -> Runner {
+        Runner { engine: engine }
+    }
+
     /// Do the compact job and return the count of log compacted.
-    fn compact(&mut self, task: Task) -> Result<u64, Error> {
-        let start_key = keys::raft_log_key(task.region_id, 0);
-        let mut first_idx = task.compact_idx;
-        if let Some((k, _)) = box_try!(task.engine.seek(&start_key)) {
+    fn compact_raft_log(&mut self, region_id: u64, compact_idx: u64) -> Result<u64, Error> {
+        let start_key = keys::raft_log_key(region_id, 0);
+        let mut first_idx = compact_idx;
+        if let Some((k, _)) = box_try!(self.engine.seek(&start_key)) {
             first_idx = box_try!(keys::raft_log_index(&k));
         }
-        if first_idx >= task.compact_idx {
+        if first_idx >= compact_idx {
             info!("no need to compact");
             return Ok(0);
         }
         let wb = WriteBatch::new();
-        for idx in first_idx..task.compact_idx {
-            let key = keys::raft_log_key(task.region_id, idx);
+        for idx in first_idx..compact_idx {
+            let key = keys::raft_log_key(region_id, idx);
             box_try!(wb.delete(&key));
         }
         // It is safe to disable WAL here. If crashed, we can still
         // compact the log after restart.
-        box_try!(task.engine.write_without_wal(wb));
-        Ok(task.compact_idx - first_idx)
+        box_try!(self.engine.write_without_wal(wb));
+        Ok(compact_idx - first_idx)
     }
 }
 
 impl Runnable<Task> for Runner {
     fn run(&mut self, task: Task) {
         debug!("executing task {}", task);
-        let region_id = task.region_id;
-        match self.compact(task) {
-            Err(e) => error!("failed to compact: {:?}", e),
-            Ok(n) => {
-                info!("{} log entries have been compacted for region {}",
-                      n,
-                      region_id)
+        match task {
+            Task::CompactRaftLog { region_id, compact_idx } => {
+                match self.compact_raft_log(region_id, compact_idx) {
+                    Err(e) => error!("failed to compact: {:?}", e),
+                    Ok(n) => {
+                        info!("{} log entries have been compacted for region {}",
+                              n,
+                              region_id)
+                    }
+                }
+            }
+            Task::CompactRange { start, end } => {
+                self.engine.compact_range(Some(&start), Some(&end));
+                metric_incr!("raftstore.compact_range.count");
+                info!("range [{}, {}) has been compacted", escape(&start), escape(&end));
             }
         }
     }
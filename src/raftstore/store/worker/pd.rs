@@ -23,7 +23,7 @@ use kvproto::pdpb;
 
 use util::worker::Runnable;
 use util::escape;
-use pd::PdClient;
+use pd::{PdClient, RegionFlow};
 use raftstore::store::{SendCh, Msg};
 use raftstore::Result;
 
@@ -37,6 +37,7 @@ pub enum Task {
     Heartbeat {
         region: metapb::Region,
         peer: metapb::Peer,
+        flow: RegionFlow,
     },
     StoreHeartbeat {
         stats: pdpb::StoreStats,
@@ -57,11 +58,12 @@ impl Display for Task {
                        region.get_id(),
                        escape(&split_key))
             }
-            Task::Heartbeat { ref region, ref peer } => {
+            Task::Heartbeat { ref region, ref peer, ref flow } => {
                 write!(f,
-                       "heartbeat for region {:?}, leader {}",
+                       "heartbeat for region {:?}, leader {}, flow {:?}",
                        region,
-                       peer.get_id())
+                       peer.get_id(),
+                       flow)
             }
             Task::StoreHeartbeat { ref stats } => write!(f, "store heartbeat stats: {:?}", stats),
             Task::ReportSplit { ref left, ref right } => {
@@ -129,10 +131,14 @@ impl<T: PdClient> Runner<T> {
         }
     }
 
-    fn handle_heartbeat(&self, region: metapb::Region, peer: metapb::Peer) {
+    fn handle_heartbeat(&self, region: metapb::Region, peer: metapb::Peer, flow: RegionFlow) {
         metric_incr!("pd.heartbeat");
+        metric_count!("pd.heartbeat.written_bytes", flow.written_bytes as i64);
+        metric_count!("pd.heartbeat.written_keys", flow.written_keys as i64);
+        metric_count!("pd.heartbeat.read_bytes", flow.read_bytes as i64);
+        metric_count!("pd.heartbeat.read_keys", flow.read_keys as i64);
         // Now we use put region protocol for heartbeat.
-        match self.pd_client.region_heartbeat(region.clone(), peer.clone()) {
+        match self.pd_client.region_heartbeat(region.clone(), peer.clone(), flow) {
             Ok(mut resp) => {
                 metric_incr!("pd.heartbeat.success");
                 if resp.has_change_peer() {
@@ -181,7 +187,7 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
             Task::AskSplit { region, split_key, peer } => {
                 self.handle_ask_split(region, split_key, peer)
             }
-            Task::Heartbeat { region, peer } => self.handle_heartbeat(region, peer),
+            Task::Heartbeat { region, peer, flow } => self.handle_heartbeat(region, peer, flow),
             Task::StoreHeartbeat { stats } => self.handle_store_heartbeat(stats),
             Task::ReportSplit { left, right } => self.handle_report_split(left, right),
         };
@@ -20,7 +20,7 @@ use std::time::Instant;
 use std::str;
 
 use rocksdb::{DB, Writable, WriteBatch};
-use kvproto::raft_serverpb::{RaftApplyState, RegionLocalState, PeerState};
+use kvproto::raft_serverpb::{RaftLocalState, RaftApplyState, RegionLocalState, PeerState};
 
 use util::worker::Runnable;
 use util::codec::bytes::CompactBytesDecoder;
@@ -39,6 +39,8 @@ pub enum Task {
     },
     Apply {
         region_id: u64,
+        term: u64,
+        index: u64,
     },
 }
 
@@ -46,7 +48,9 @@ impl Display for Task {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             Task::Gen { region_id, .. } => write!(f, "Snap gen for {}", region_id),
-            Task::Apply { region_id, .. } => write!(f, "Snap apply for {}", region_id),
+            Task::Apply { region_id, index, .. } => {
+                write!(f, "Snap apply for {} at {}", region_id, index)
+            }
         }
     }
 }
@@ -121,15 +125,43 @@ impl<T: MsgSender> Runner<T> {
         metric_time!("raftstore.generate_snap.cost", ts.elapsed());
     }
 
-    fn apply_snap(&self, region_id: u64) -> Result<(), Error> {
-        info!("begin apply snap data for {}", region_id);
+    /// Check whether the snapshot at `idx` is outdated by the current state of
+    /// the peer, which may have moved on while the task was waiting in queue.
+    fn is_stale(&self, region_id: u64, idx: u64) -> Result<bool, Error> {
+        let state_key = keys::raft_state_key(region_id);
+        let raft_state: RaftLocalState = match box_try!(self.db.get_msg(&state_key)) {
+            Some(state) => state,
+            None => return Err(box_err!("failed to get raftstate from {}", escape(&state_key))),
+        };
         let state_key = keys::apply_state_key(region_id);
         let apply_state: RaftApplyState = match box_try!(self.db.get_msg(&state_key)) {
             Some(state) => state,
-            None => return Err(box_err!("failed to get raftstate from {}", escape(&state_key))),
+            None => return Err(box_err!("failed to get applystate from {}", escape(&state_key))),
         };
-        let term = apply_state.get_truncated_state().get_term();
-        let idx = apply_state.get_truncated_state().get_index();
+        let truncated_idx = apply_state.get_truncated_state().get_index();
+        if truncated_idx > idx || apply_state.get_applied_index() > idx ||
+           raft_state.get_last_index() < idx {
+            info!("[region {}] snapshot at {} is stale, truncated index {}, applied index {}, \
+                   last index {}",
+                  region_id,
+                  idx,
+                  truncated_idx,
+                  apply_state.get_applied_index(),
+                  raft_state.get_last_index());
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn apply_snap(&self, region_id: u64, term: u64, idx: u64) -> Result<(), Error> {
+        info!("begin apply snap data for {} at {}", region_id, idx);
+        if try!(self.is_stale(region_id, idx)) {
+            // The region data was cleared when the snapshot was accepted, so a
+            // stale snapshot must go through the failure path rather than leave
+            // the region empty.
+            metric_incr!("raftstore.apply_snap.stale");
+            return Err(box_err!("snapshot of region {} at {} is stale", region_id, idx));
+        }
         let snap_key = SnapKey::new(region_id, term, idx);
         let snap_file = box_try!(self.mgr.rl().get_snap_file(&snap_key, false));
         self.mgr.wl().register(snap_key.clone(), SnapEntry::Applying);
@@ -139,7 +171,11 @@ impl<T: MsgSender> Runner<T> {
         if !snap_file.exists() {
             return Err(box_err!("missing snap file {}", snap_file.path().display()));
         }
-        box_try!(snap_file.validate());
+        if let Err(e) = snap_file.validate() {
+            // The file is broken, delete it so that it can be received again.
+            snap_file.delete();
+            return Err(box_err!("invalid snap file {}: {:?}", snap_file.path().display(), e));
+        }
         let mut reader = box_try!(File::open(snap_file.path()));
 
         let timer = Instant::now();
@@ -183,11 +219,11 @@ impl<T: MsgSender> Runner<T> {
         Ok(())
     }
 
-    fn handle_apply(&self, region_id: u64) {
+    fn handle_apply(&self, region_id: u64, term: u64, idx: u64) {
         metric_incr!("raftstore.apply_snap");
         let ts = Instant::now();
         let mut is_success = true;
-        if let Err(e) = self.apply_snap(region_id) {
+        if let Err(e) = self.apply_snap(region_id, term, idx) {
             is_success = false;
             error!("failed to apply snap: {:?}!!!", e);
         }
@@ -209,7 +245,7 @@ impl<T: MsgSender> Runnable<Task> for Runner<T> {
     fn run(&mut self, task: Task) {
         match task {
             Task::Gen { region_id } => self.handle_gen(region_id),
-            Task::Apply { region_id } => self.handle_apply(region_id),
+            Task::Apply { region_id, term, index } => self.handle_apply(region_id, term, index),
         }
     }
 }
@@ -12,15 +12,19 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fmt::{self, Formatter, Display};
 use std::time::Instant;
 
 use rocksdb::DB;
+use threadpool::ThreadPool;
 
 use kvproto::metapb::RegionEpoch;
 use raftstore::store::{PeerStorage, keys, SendCh, Msg};
 use raftstore::store::engine::Iterable;
+use storage::Key;
 use util::escape;
+use util::codec::table;
 use util::worker::Runnable;
 
 /// Split checking task.
@@ -50,24 +54,65 @@ impl Display for Task {
     }
 }
 
-pub struct Runner {
+#[derive(Clone)]
+struct Checker {
     ch: SendCh,
     region_max_size: u64,
     split_size: u64,
+    // regions being scanned now.
+    scanning: Arc<AtomicUsize>,
+}
+
+/// Runs the split checks on a pool of `concurrency` threads, so at most that
+/// many regions are scanned at the same time.
+pub struct Runner {
+    checker: Checker,
+    pool: ThreadPool,
+    // tasks scheduled and not finished yet, the counter is shared with the
+    // scheduler which adds to it when scheduling a task.
+    running: Arc<AtomicUsize>,
 }
 
 impl Runner {
-    pub fn new(ch: SendCh, region_max_size: u64, split_size: u64) -> Runner {
+    pub fn new(ch: SendCh,
+               region_max_size: u64,
+               split_size: u64,
+               concurrency: usize,
+               running: Arc<AtomicUsize>)
+               -> Runner {
         Runner {
-            ch: ch,
-            region_max_size: region_max_size,
-            split_size: split_size,
+            checker: Checker {
+                ch: ch,
+                region_max_size: region_max_size,
+                split_size: split_size,
+                scanning: Arc::new(AtomicUsize::new(0)),
+            },
+            pool: ThreadPool::new_with_name(thd_name!("split-check-pool"), concurrency),
+            running: running,
         }
     }
 }
 
 impl Runnable<Task> for Runner {
     fn run(&mut self, task: Task) {
+        let checker = self.checker.clone();
+        let running = self.running.clone();
+        self.pool.execute(move || {
+            checker.check(task);
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+impl Checker {
+    fn check(&self, task: Task) {
+        let scanning = self.scanning.fetch_add(1, Ordering::SeqCst) + 1;
+        metric_gauge!("raftstore.check_split.scanning", scanning as u64);
+        self.check_region(task);
+        self.scanning.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn check_region(&self, task: Task) {
         debug!("executing task {} {}",
                escape(&task.start_key),
                escape(&task.end_key));
@@ -98,6 +143,12 @@ impl Runnable<Task> for Runner {
             debug!("no need to send for {} < {}", size, self.region_max_size);
             return;
         }
+        if let Some(row_key) = align_to_row(&split_key) {
+            // the row may start before the region, then it's split already.
+            if row_key > task.start_key {
+                split_key = row_key;
+            }
+        }
         let res = self.ch.send(new_split_check_result(task.region_id, task.epoch, split_key));
         if let Err(e) = res {
             warn!("failed to send check result of {}: {}", task.region_id, e);
@@ -106,6 +157,19 @@ impl Runnable<Task> for Runner {
     }
 }
 
+/// Round the data key down to the start of the table row it belongs to, so
+/// all the columns and versions of a row stay in one region. Returns None if
+/// the key is not a table record key.
+fn align_to_row(key: &[u8]) -> Option<Vec<u8>> {
+    let raw = match Key::from_encoded(keys::origin_key(key).to_vec()).raw() {
+        Ok(raw) => raw,
+        Err(_) => return None,
+    };
+    table::truncate_as_row_key(&raw)
+        .ok()
+        .map(|row_key| keys::data_key(Key::from_raw(row_key).encoded()))
+}
+
 fn new_split_check_result(region_id: u64, epoch: RegionEpoch, split_key: Vec<u8>) -> Msg {
     Msg::SplitCheckResult {
         region_id: region_id,
@@ -113,3 +177,100 @@ fn new_split_check_result(region_id: u64, epoch: RegionEpoch, split_key: Vec<u8>
         split_key: split_key,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use mio::{EventLoop, Handler};
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use kvproto::metapb::RegionEpoch;
+    use super::{Task, Runner, align_to_row};
+    use raftstore::store::{keys, SendCh, Msg};
+    use storage::Key;
+    use util::codec::table;
+    use util::metric;
+    use util::rocksdb;
+    use util::worker::Worker;
+
+    struct TestHandler;
+
+    impl Handler for TestHandler {
+        type Timeout = ();
+        type Message = Msg;
+    }
+
+    fn data_key(raw: &[u8], ts: u64) -> Vec<u8> {
+        keys::data_key(Key::from_raw(raw).append_ts(ts).encoded())
+    }
+
+    #[test]
+    fn test_align_to_row() {
+        let mut data_keys = vec![];
+        for handle in 1..4 {
+            for col in 1..4 {
+                let raw = table::encode_column_key(1, handle, col);
+                data_keys.push((handle, data_key(&raw, 5)));
+                data_keys.push((handle, data_key(&raw, 10)));
+            }
+        }
+        for (i, &(handle, ref key)) in data_keys.iter().enumerate() {
+            let row_key = align_to_row(key).unwrap();
+            assert!(row_key <= *key);
+            // the row is never split, earlier rows are before the split key.
+            for &(h, ref k) in &data_keys {
+                assert_eq!(*k >= row_key, h >= handle, "{} {}", i, h);
+            }
+        }
+
+        let index_key = table::encode_index_seek_key(1, 2, b"abc");
+        assert_eq!(align_to_row(&data_key(&index_key, 5)), None);
+        assert_eq!(align_to_row(&data_key(b"k1", 5)), None);
+    }
+
+    #[test]
+    fn test_split_check_concurrency() {
+        metric::test::init();
+        let path = TempDir::new("test-split-check").unwrap();
+        let engine = Arc::new(rocksdb::new_engine(path.path().to_str().unwrap(), &[]).unwrap());
+        for i in 0..100 {
+            let key = keys::data_key(format!("k{:03}", i).as_bytes());
+            engine.put(&key, &[0; 1024]).unwrap();
+        }
+
+        let event_loop = EventLoop::<TestHandler>::new().unwrap();
+        let ch = SendCh::new(event_loop.channel());
+        let running = Arc::new(AtomicUsize::new(0));
+        let mut worker = Worker::new("test split check worker");
+        let runner = Runner::new(ch, 64 * 1024, 32 * 1024, 2, running.clone());
+        worker.start(runner).unwrap();
+        for region_id in 1..21 {
+            let task = Task {
+                region_id: region_id,
+                epoch: RegionEpoch::new(),
+                start_key: keys::DATA_MIN_KEY.to_vec(),
+                end_key: keys::DATA_MAX_KEY.to_vec(),
+                engine: engine.clone(),
+            };
+            running.fetch_add(1, Ordering::SeqCst);
+            worker.schedule(task).unwrap();
+        }
+        for _ in 0..500 {
+            if running.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+
+        let scanning = metric::test::gauges("raftstore.check_split.scanning");
+        assert!(scanning.len() >= 20, "{:?}", scanning);
+        assert!(scanning.iter().all(|&n| n <= 2), "{:?}", scanning);
+        worker.stop().unwrap().join().unwrap();
+    }
+}
@@ -21,6 +21,10 @@ const DEFAULT_NOTIFY_CAPACITY: usize = 4096;
 const DEFAULT_MESSAGES_PER_TICK: usize = 256;
 const DEFAULT_SEND_BUFFER_SIZE: usize = 128 * 1024;
 const DEFAULT_RECV_BUFFER_SIZE: usize = 128 * 1024;
+const DEFAULT_SNAP_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_END_POINT_MAX_SCAN_KEYS: usize = 1024 * 1024;
+const DEFAULT_END_POINT_CONCURRENCY: usize = 8;
+const DEFAULT_END_POINT_MAX_TASKS_PER_WORKER: usize = 256;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -36,6 +40,20 @@ pub struct Config {
     pub messages_per_tick: usize,
     pub send_buffer_size: usize,
     pub recv_buffer_size: usize,
+    // A snapshot is received in chunks of this size, a failed transfer is
+    // resumed from the last completed chunk.
+    pub snap_chunk_size: usize,
+    // A coprocessor request stops scanning after this many keys, and returns
+    // the partial result with the last scanned key to continue from.
+    pub end_point_max_scan_keys: usize,
+    // Number of threads handling coprocessor requests.
+    pub end_point_concurrency: usize,
+    // A coprocessor request is rejected as busy when every thread already has
+    // this many requests running or queued.
+    pub end_point_max_tasks_per_worker: usize,
+    // If true, the batch commands whose keys are not sorted or unique are
+    // rejected instead of being executed.
+    pub strict_batch_keys: bool,
     pub store_cfg: StoreConfig,
 }
 
@@ -49,6 +67,11 @@ impl Default for Config {
             messages_per_tick: DEFAULT_MESSAGES_PER_TICK,
             send_buffer_size: DEFAULT_SEND_BUFFER_SIZE,
             recv_buffer_size: DEFAULT_RECV_BUFFER_SIZE,
+            snap_chunk_size: DEFAULT_SNAP_CHUNK_SIZE,
+            end_point_max_scan_keys: DEFAULT_END_POINT_MAX_SCAN_KEYS,
+            end_point_concurrency: DEFAULT_END_POINT_CONCURRENCY,
+            end_point_max_tasks_per_worker: DEFAULT_END_POINT_MAX_TASKS_PER_WORKER,
+            strict_batch_keys: false,
             store_cfg: StoreConfig::default(),
         }
     }
@@ -62,6 +85,22 @@ impl Config {
     pub fn validate(&self) -> Result<()> {
         try!(self.store_cfg.validate());
 
+        if self.snap_chunk_size == 0 {
+            return Err(box_err!("snap chunk size must be greater than 0"));
+        }
+
+        if self.end_point_max_scan_keys == 0 {
+            return Err(box_err!("end point max scan keys must be greater than 0"));
+        }
+
+        if self.end_point_concurrency == 0 {
+            return Err(box_err!("end point concurrency must be greater than 0"));
+        }
+
+        if self.end_point_max_tasks_per_worker == 0 {
+            return Err(box_err!("end point max tasks per worker must be greater than 0"));
+        }
+
         Ok(())
     }
 }
@@ -16,6 +16,7 @@ use std::cmp;
 use mio::{Token, EventLoop, EventSet, PollOpt};
 use mio::tcp::TcpStream;
 use bytes::{MutBuf, MutByteBuf};
+use byteorder::{BigEndian, WriteBytesExt};
 use protobuf::Message as PbMessage;
 
 use kvproto::msgpb::Message;
@@ -25,9 +26,12 @@ use super::server::Server;
 use util::codec::rpc;
 use super::transport::RaftStoreRouter;
 use super::resolve::StoreAddrResolver;
-use super::snap::Task as SnapTask;
+use super::snap::{Task as SnapTask, SNAP_RESUME_FLAG};
+use super::coprocessor::CancelHandle;
+use raftstore::store::{SnapKey, SnapManager};
 use util::worker::Scheduler;
 use util::buf::{TryRead, create_mem_buf, SendBuffer};
+use util::HandyRwLock;
 
 
 #[derive(PartialEq)]
@@ -37,7 +41,6 @@ enum ConnType {
     Snapshot,
 }
 
-const SNAPSHOT_PAYLOAD_BUF: usize = 4 * 1024 * 1024;
 const DEFAULT_SEND_BUFFER_SIZE: usize = 8 * 1024;
 
 pub struct Conn {
@@ -60,8 +63,13 @@ pub struct Conn {
     file_size: usize,
     read_size: usize,
     snap_scheduler: Scheduler<SnapTask>,
+    snap_mgr: SnapManager,
+    snap_chunk_size: usize,
 
     send_buffer: SendBuffer,
+
+    // cancels the coprocessor requests read from the connection when it's closed.
+    pub cop_cancel: CancelHandle,
 }
 
 fn try_read_data<T: TryRead, B: MutBuf>(r: &mut T, buf: &mut B) -> Result<()> {
@@ -83,7 +91,9 @@ impl Conn {
     pub fn new(sock: TcpStream,
                token: Token,
                store_id: Option<u64>,
-               snap_scheduler: Scheduler<SnapTask>)
+               snap_scheduler: Scheduler<SnapTask>,
+               snap_mgr: SnapManager,
+               snap_chunk_size: usize)
                -> Conn {
         Conn {
             sock: sock,
@@ -96,15 +106,20 @@ impl Conn {
             payload: None,
             last_msg_id: 0,
             snap_scheduler: snap_scheduler,
+            snap_mgr: snap_mgr,
+            snap_chunk_size: snap_chunk_size,
             store_id: store_id,
             // send buffer can be grown automatically, first using
             // DEFAULT_SEND_BUFFER_SIZE is ok. Maybe we should need
             // max size to shrink later.
             send_buffer: SendBuffer::new(DEFAULT_SEND_BUFFER_SIZE),
+            cop_cancel: CancelHandle::new(),
         }
     }
 
     pub fn close(&mut self) {
+        // nobody reads the responses of the coprocessor requests any more.
+        self.cop_cancel.cancel();
         if self.conn_type == ConnType::Snapshot {
             if let Err(e) = self.snap_scheduler.schedule(SnapTask::Discard(self.token)) {
                 error!("failed to cleanup snapshot: {:?}", e);
@@ -149,13 +164,31 @@ impl Conn {
         if data.is_snapshot() {
             self.conn_type = ConnType::Snapshot;
 
-            let mut snap_data = RaftSnapshotData::new();
-            try!(snap_data.merge_from_bytes(
-                data.msg.get_raft().get_message().get_snapshot().get_data()));
-            self.file_size = snap_data.get_file_size() as usize;
-            self.payload = Some(create_mem_buf(cmp::min(SNAPSHOT_PAYLOAD_BUF, self.file_size)));
+            let key = {
+                let snap = data.msg.get_raft().get_message().get_snapshot();
+                let mut snap_data = RaftSnapshotData::new();
+                try!(snap_data.merge_from_bytes(snap.get_data()));
+                self.file_size = snap_data.get_file_size() as usize;
+                try!(SnapKey::from_snap(snap))
+            };
+            // Tell the sender where to start, chunks completed by a failed
+            // transfer are not sent again. Old senders don't ask for it and
+            // send the whole file right after the message.
+            let resume = data.msg_id & SNAP_RESUME_FLAG != 0;
+            let offset = if resume {
+                let offset = try!(self.snap_mgr.rl().resume_offset(&key, self.file_size as u64));
+                try!(self.send_snap_offset(event_loop, offset));
+                offset
+            } else {
+                0
+            };
+            self.read_size = offset as usize;
+            if self.read_size < self.file_size {
+                let size = cmp::min(self.snap_chunk_size, self.file_size - self.read_size);
+                self.payload = Some(create_mem_buf(size));
+            }
 
-            let register_task = SnapTask::Register(self.token, data.msg.take_raft());
+            let register_task = SnapTask::Register(self.token, data.msg.take_raft(), resume);
             box_try!(self.snap_scheduler.schedule(register_task));
 
             return self.read_snapshot(event_loop);
@@ -281,7 +314,29 @@ impl Conn {
               S: StoreAddrResolver
     {
         msg.encode_to(&mut self.send_buffer).unwrap();
+        self.register_writable(event_loop)
+    }
 
+    fn send_snap_offset<T, S>(&mut self,
+                              event_loop: &mut EventLoop<Server<T, S>>,
+                              offset: u64)
+                              -> Result<()>
+        where T: RaftStoreRouter,
+              S: StoreAddrResolver
+    {
+        self.send_buffer.write_u64::<BigEndian>(offset).unwrap();
+        // try to send it at once, the connection may be closed soon after.
+        try!(self.send_buffer.send_to(&mut self.sock));
+        if self.send_buffer.is_empty() {
+            return Ok(());
+        }
+        self.register_writable(event_loop)
+    }
+
+    fn register_writable<T, S>(&mut self, event_loop: &mut EventLoop<Server<T, S>>) -> Result<()>
+        where T: RaftStoreRouter,
+              S: StoreAddrResolver
+    {
         if !self.interest.is_writable() {
             // re-register writable if we have not,
             // if registered, we can only remove this flag when
@@ -1,21 +1,41 @@
 use std::cmp::Ordering;
 use tipb::expression::{Expr, ExprType};
 
-use util::codec::Datum;
-use util::xeval::evaluator;
-
-use super::Result;
-
-
-pub fn build_aggr_func(expr: &Expr) -> Result<Box<AggrFunc>> {
+use util::codec::{self, Datum};
+use util::codec::mysql::DIV_FRAC_INCR;
+use util::xeval::{self, evaluator};
+
+use super::{Error, Result};
+
+/// Build the aggregate function for `expr`.
+///
+/// If `final_avg` is true, `Avg` pushes the final average instead of count and sum.
+/// If `first_non_null` is true, `First` skips the null values.
+pub fn build_aggr_func(expr: &Expr,
+                       final_avg: bool,
+                       first_non_null: bool)
+                       -> Result<Box<AggrFunc>> {
     match expr.get_tp() {
         ExprType::Count => Ok(box 0),
-        ExprType::First => Ok(box None),
+        ExprType::First => {
+            Ok(box First {
+                res: None,
+                skip_null: first_non_null,
+            })
+        }
         ExprType::Sum => Ok(box Sum { res: None }),
         ExprType::Avg => {
             Ok(box Avg {
                 sum: Sum { res: None },
                 cnt: 0,
+                final_avg: final_avg,
+            })
+        }
+        ExprType::Variance => {
+            Ok(box Variance {
+                sum: Sum { res: None },
+                square_sum: Sum { res: None },
+                cnt: 0,
             })
         }
         ExprType::Max => Ok(box Extremum::new(Ordering::Less)),
@@ -51,22 +71,30 @@ impl AggrFunc for Count {
     }
 }
 
-type First = Option<Datum>;
+struct First {
+    res: Option<Datum>,
+    // take the first non-null value rather than the first value.
+    skip_null: bool,
+}
 
 impl AggrFunc for First {
     fn update(&mut self, mut args: Vec<Datum>) -> Result<()> {
-        if self.is_some() {
+        if self.res.is_some() {
             return Ok(());
         }
         if args.len() != 1 {
             return Err(box_err!("Wrong number of args for AggFuncFirstRow: {}", args.len()));
         }
-        *self = args.pop();
+        let arg = args.pop().unwrap();
+        if self.skip_null && arg == Datum::Null {
+            return Ok(());
+        }
+        self.res = Some(arg);
         Ok(())
     }
 
     fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
-        collector.push(self.take().unwrap());
+        collector.push(self.res.take().unwrap_or(Datum::Null));
         Ok(())
     }
 }
@@ -88,7 +116,16 @@ impl Sum {
             return Ok(false);
         }
         let res = match self.res.take() {
-            Some(b) => box_try!(evaluator::eval_arith(a, b, Datum::checked_add)),
+            Some(b) => {
+                match evaluator::eval_arith(a, b, Datum::checked_add) {
+                    Ok(res) => res,
+                    // report it rather than returning a wrong sum.
+                    Err(xeval::Error::Codec(codec::Error::Overflow(reason))) => {
+                        return Err(Error::AggrOverflow(reason))
+                    }
+                    Err(e) => return Err(box_err!(e)),
+                }
+            }
             None => a,
         };
         self.res = Some(res);
@@ -117,6 +154,8 @@ impl AggrFunc for Sum {
 struct Avg {
     sum: Sum,
     cnt: u64,
+    // push sum / cnt rather than cnt and sum.
+    final_avg: bool,
 }
 
 impl AggrFunc for Avg {
@@ -127,9 +166,54 @@ impl AggrFunc for Avg {
         Ok(())
     }
 
+    fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
+        if !self.final_avg {
+            collector.push(Datum::U64(self.cnt));
+            return self.sum.calc(collector);
+        }
+        let sum = match self.sum.res.take() {
+            None | Some(Datum::Null) => {
+                collector.push(Datum::Null);
+                return Ok(());
+            }
+            Some(sum) => box_try!(sum.into_dec()),
+        };
+        match sum.checked_div(&self.cnt.into(), DIV_FRAC_INCR) {
+            Some(avg) => collector.push(Datum::Dec(avg)),
+            None => collector.push(Datum::Null),
+        }
+        Ok(())
+    }
+}
+
+/// `Variance` pushes count, sum and sum of squares, the final variance
+/// is computed by the client.
+struct Variance {
+    sum: Sum,
+    square_sum: Sum,
+    cnt: u64,
+}
+
+impl AggrFunc for Variance {
+    fn update(&mut self, args: Vec<Datum>) -> Result<()> {
+        let square = match args.first() {
+            None | Some(&Datum::Null) => Datum::Null,
+            Some(a) => {
+                let d = box_try!(box_try!(a.clone().into_arith()).into_dec());
+                Datum::Dec(d.clone() * d)
+            }
+        };
+        if try!(self.sum.add_asssign(args)) {
+            self.cnt += 1;
+            try!(self.square_sum.add_asssign(vec![square]));
+        }
+        Ok(())
+    }
+
     fn calc(&mut self, collector: &mut Vec<Datum>) -> Result<()> {
         collector.push(Datum::U64(self.cnt));
-        self.sum.calc(collector)
+        try!(self.sum.calc(collector));
+        self.square_sum.calc(collector)
     }
 }
 
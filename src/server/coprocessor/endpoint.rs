@@ -12,16 +12,18 @@
 // limitations under the License.
 
 use std::sync::Arc;
-use std::usize;
-use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{cmp, mem, usize};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std::time::Instant;
 use std::boxed::FnBox;
 use std::rc::Rc;
+use std::cell::{Cell, RefCell};
 use std::fmt::{self, Display, Formatter};
 
 use tipb::select::{self, SelectRequest, SelectResponse, Row};
-use tipb::schema::ColumnInfo;
+use tipb::schema::{ColumnInfo, IndexInfo};
 use tipb::expression::{Expr, ExprType};
 use protobuf::{Message as PbMsg, RepeatedField};
 use byteorder::{BigEndian, ReadBytesExt};
@@ -30,6 +32,7 @@ use threadpool::ThreadPool;
 use storage::{Engine, SnapshotStore};
 use kvproto::msgpb::{MessageType, Message};
 use kvproto::coprocessor::{Request, Response, KeyRange};
+use kvproto::errorpb;
 use storage::{Snapshot, Key};
 use util::codec::table::TableDecoder;
 use util::codec::number::NumberDecoder;
@@ -38,7 +41,8 @@ use util::xeval::Evaluator;
 use util::{escape, duration_to_ms};
 use util::worker::BatchRunnable;
 use util::SlowTimer;
-use server::OnResponse;
+use util::event::Event;
+use server::{OnResponse, Config};
 
 use super::{Error, Result};
 use super::aggregate::{self, AggrFunc};
@@ -48,21 +52,30 @@ pub const REQ_TYPE_INDEX: i64 = 102;
 
 const DEFAULT_ERROR_CODE: i32 = 1;
 
-// TODO: make this number configurable.
-const DEFAULT_POOL_SIZE: usize = 8;
-
 pub const SINGLE_GROUP: &'static [u8] = b"SingleGroup";
 
+// Max count of index entries fetched by one reverse scan.
+const INDEX_REVERSE_SCAN_BATCH_SIZE: usize = 256;
+
+// Check whether the request is cancelled every this many scanned keys.
+const CHECK_CANCELLED_INTERVAL: usize = 256;
+
 pub struct Host {
     snap_endpoint: Arc<TiDbEndPoint>,
     pool: ThreadPool,
+    // requests handed to the pool and not finished yet.
+    running_reqs: Arc<AtomicUsize>,
+    max_running_reqs: usize,
 }
 
 impl Host {
-    pub fn new(engine: Arc<Box<Engine>>) -> Host {
+    pub fn new(engine: Arc<Box<Engine>>, cfg: &Config) -> Host {
         Host {
-            snap_endpoint: Arc::new(TiDbEndPoint::new(engine)),
-            pool: ThreadPool::new_with_name(thd_name!("endpoint-pool"), DEFAULT_POOL_SIZE),
+            snap_endpoint: Arc::new(TiDbEndPoint::new(engine, cfg.end_point_max_scan_keys)),
+            pool: ThreadPool::new_with_name(thd_name!("endpoint-pool"),
+                                            cfg.end_point_concurrency),
+            running_reqs: Arc::new(AtomicUsize::new(0)),
+            max_running_reqs: cfg.end_point_concurrency * cfg.end_point_max_tasks_per_worker,
         }
     }
 }
@@ -70,6 +83,7 @@ impl Host {
 pub struct RequestTask {
     req: Request,
     on_resp: OnResponse,
+    cancelled: Event<()>,
 }
 
 impl RequestTask {
@@ -77,8 +91,48 @@ impl RequestTask {
         RequestTask {
             req: req,
             on_resp: on_resp,
+            cancelled: Event::new(),
         }
     }
+
+    /// Create a task cancelled together with all the other tasks sharing
+    /// `handle`, like the requests of one connection.
+    pub fn with_cancel_handle(req: Request,
+                              on_resp: OnResponse,
+                              handle: &CancelHandle)
+                              -> RequestTask {
+        RequestTask {
+            req: req,
+            on_resp: on_resp,
+            cancelled: handle.cancelled.clone(),
+        }
+    }
+
+    /// Get a handle that can still cancel the task after it's scheduled.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle { cancelled: self.cancelled.clone() }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.set(());
+    }
+}
+
+/// `CancelHandle` cancels a scheduled `RequestTask`. The scan of a cancelled
+/// task is aborted and a "request cancelled" error is responded.
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Event<()>,
+}
+
+impl CancelHandle {
+    pub fn new() -> CancelHandle {
+        CancelHandle { cancelled: Event::new() }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.set(());
+    }
 }
 
 impl Display for RequestTask {
@@ -107,13 +161,43 @@ impl BatchRunnable<RequestTask> for Host {
             let mut group = grouped_reqs.entry(key).or_insert_with(|| vec![]);
             group.push(req);
         }
-        for (_, reqs) in grouped_reqs {
+        for (_, mut reqs) in grouped_reqs {
+            // only this thread adds to `running_reqs`, so it can't grow
+            // between the check and the add.
+            let running = self.running_reqs.load(Ordering::SeqCst);
+            let admitted = cmp::min(reqs.len(), self.max_running_reqs.saturating_sub(running));
+            for t in reqs.drain(admitted..) {
+                metric_incr!("copr.busy");
+                on_error(busy_error(), box move |r| {
+                    let mut resp_msg = Message::new();
+                    resp_msg.set_msg_type(MessageType::CopResp);
+                    resp_msg.set_cop_resp(r);
+                    t.on_resp.call_box((resp_msg,));
+                });
+            }
+            if reqs.is_empty() {
+                continue;
+            }
+
+            self.running_reqs.fetch_add(reqs.len(), Ordering::SeqCst);
+            let running_reqs = self.running_reqs.clone();
             let end_point = self.snap_endpoint.clone();
-            self.pool.execute(move || end_point.handle_requests(reqs));
+            self.pool.execute(move || {
+                let count = reqs.len();
+                end_point.handle_requests(reqs);
+                running_reqs.fetch_sub(count, Ordering::SeqCst);
+            });
         }
     }
 }
 
+fn busy_error() -> Error {
+    let mut err = errorpb::Error::new();
+    err.set_message("endpoint is busy".to_owned());
+    err.mut_server_is_busy().set_reason("too many pending coprocessor requests".to_owned());
+    Error::Region(err)
+}
+
 type ResponseHandler = Box<FnBox(Response) -> ()>;
 
 fn on_error(e: Error, cb: ResponseHandler) {
@@ -121,6 +205,7 @@ fn on_error(e: Error, cb: ResponseHandler) {
     match e {
         Error::Region(e) => resp.set_region_error(e),
         Error::Locked(info) => resp.set_locked(info),
+        Error::AggrOverflow(_) |
         Error::Other(_) => resp.set_other_error(format!("{}", e)),
     }
     cb(resp)
@@ -128,11 +213,15 @@ fn on_error(e: Error, cb: ResponseHandler) {
 
 pub struct TiDbEndPoint {
     engine: Arc<Box<Engine>>,
+    max_scan_keys: usize,
 }
 
 impl TiDbEndPoint {
-    pub fn new(engine: Arc<Box<Engine>>) -> TiDbEndPoint {
-        TiDbEndPoint { engine: engine }
+    pub fn new(engine: Arc<Box<Engine>>, max_scan_keys: usize) -> TiDbEndPoint {
+        TiDbEndPoint {
+            engine: engine,
+            max_scan_keys: max_scan_keys,
+        }
     }
 }
 
@@ -159,12 +248,16 @@ impl TiDbEndPoint {
         for t in reqs {
             let timer = SlowTimer::new();
             let tp = t.req.get_tp();
-            self.handle_request(snap.as_ref(), t.req, t.on_resp);
+            self.handle_request(snap.as_ref(), t.req, t.on_resp, t.cancelled);
             metric_time!(&format!("copr.request.{}", tp), timer.elapsed());
         }
     }
 
-    fn handle_request(&self, snap: &Snapshot, req: Request, on_resp: OnResponse) {
+    fn handle_request(&self,
+                      snap: &Snapshot,
+                      req: Request,
+                      on_resp: OnResponse,
+                      cancelled: Event<()>) {
         let cb = box move |r| {
             let mut resp_msg = Message::new();
             resp_msg.set_msg_type(MessageType::CopResp);
@@ -178,7 +271,7 @@ impl TiDbEndPoint {
                     on_error(box_err!(e), cb);
                     return;
                 }
-                match self.handle_select(snap, req, sel) {
+                match self.handle_select(snap, req, sel, cancelled) {
                     Ok(r) => cb(r),
                     Err(e) => on_error(e, cb),
                 }
@@ -190,12 +283,15 @@ impl TiDbEndPoint {
     pub fn handle_select(&self,
                          snap: &Snapshot,
                          mut req: Request,
-                         sel: SelectRequest)
+                         sel: SelectRequest,
+                         cancelled: Event<()>)
                          -> Result<Response> {
         let snap = SnapshotStore::new(snap, sel.get_start_ts());
-        let mut ctx = try!(SelectContext::new(sel, snap));
-        let mut range = req.take_ranges().into_vec();
-        let desc = ctx.core.sel.get_order_by().first().map_or(false, |o| o.get_desc());
+        let mut ctx = try!(SelectContext::new(sel, snap, cancelled, self.max_scan_keys));
+        let mut range: Vec<_> = req.take_ranges().into_iter().map(to_half_open).collect();
+        // Ordering by columns doesn't depend on the scan order.
+        let desc = !ctx.core.topn &&
+                   ctx.core.sel.get_order_by().first().map_or(false, |o| o.get_desc());
         debug!("scanning range: {:?}", range);
         if desc {
             range.reverse();
@@ -206,7 +302,13 @@ impl TiDbEndPoint {
             usize::MAX
         };
         let sel_ts = Instant::now();
-        let res = if req.get_tp() == REQ_TYPE_SELECT {
+        let res = if req.get_tp() == REQ_TYPE_SELECT && is_count_only(&ctx.core.sel) {
+            metric_incr!("copr.select.count_only");
+            ctx.count_rows_from_sel(range)
+        } else if req.get_tp() == REQ_TYPE_SELECT && ctx.core.topn {
+            metric_incr!("copr.select.topn");
+            ctx.get_topn_rows_from_sel(range)
+        } else if req.get_tp() == REQ_TYPE_SELECT {
             ctx.get_rows_from_sel(range, limit, desc)
         } else {
             ctx.get_rows_from_idx(range, limit, desc)
@@ -214,11 +316,17 @@ impl TiDbEndPoint {
         metric_time!(&format!("copr.select.{}", req.get_tp()), sel_ts.elapsed());
         let resp_ts = Instant::now();
         let mut resp = Response::new();
+        if let Some(key) = ctx.last_key.borrow_mut().take() {
+            // The scan stopped at the limit, the client continues after the last key.
+            metric_incr!("copr.scan_limited");
+            resp.set_more(true);
+            resp.set_last_key(key);
+        }
         let mut sel_resp = SelectResponse::new();
         match res {
             Ok(rows) => sel_resp.set_rows(RepeatedField::from_vec(rows)),
             Err(e) => {
-                if let Error::Other(_) = e {
+                if let Error::Other(_) | Error::AggrOverflow(_) = e {
                     // should we handle locked here too?
                     sel_resp.set_error(to_pb_error(&e));
                     // TODO add detail error
@@ -266,11 +374,45 @@ fn prefix_next(key: &[u8]) -> Vec<u8> {
     }
 }
 
+/// `to_half_open` turns the range into `[start, end)`, which the scans work on.
+/// An exclusive start skips, and an inclusive end takes, all the keys prefixed
+/// by the bound, e.g. all the index entries of the bound value.
+fn to_half_open(mut range: KeyRange) -> KeyRange {
+    if range.get_start_exclusive() {
+        let start = prefix_next(range.get_start());
+        range.set_start(start);
+        range.set_start_exclusive(false);
+    }
+    if range.get_end_inclusive() {
+        let end = prefix_next(range.get_end());
+        range.set_end(end);
+        range.set_end_inclusive(false);
+    }
+    range
+}
+
 /// `is_point` checks if the key range represents a point.
 fn is_point(range: &KeyRange) -> bool {
     range.get_end() == &*prefix_next(range.get_start())
 }
 
+/// `is_count_only` checks if the select only counts the rows matching the optional
+/// filter, like `SELECT COUNT(*) FROM t WHERE ...`, so the rows needn't be buffered.
+fn is_count_only(sel: &SelectRequest) -> bool {
+    if !sel.has_table_info() || !sel.get_group_by().is_empty() || sel.get_distinct() {
+        return false;
+    }
+    let aggrs = sel.get_aggregates();
+    aggrs.len() == 1 && aggrs[0].get_tp() == ExprType::Count &&
+    aggrs[0].get_children().iter().all(|e| {
+        match e.get_tp() {
+            ExprType::Int64 | ExprType::Uint64 | ExprType::Float32 | ExprType::Float64 |
+            ExprType::String | ExprType::Bytes => true,
+            _ => false,
+        }
+    })
+}
+
 #[inline]
 fn get_pk(col: &ColumnInfo, h: i64) -> Datum {
     if mysql::has_unsigned_flag(col.get_flag() as u64) {
@@ -310,20 +452,131 @@ fn inflate_with_col<'a, T>(eval: &mut Evaluator,
     Ok(())
 }
 
+/// A row buffered by a top n select, along with the values of the order by
+/// items evaluated against it.
+struct SortRow {
+    keys: Vec<Datum>,
+    row: Row,
+    desc: Rc<Vec<bool>>,
+    // the first error comparing the rows of the heap.
+    err: Rc<RefCell<Option<Error>>>,
+}
+
+impl Ord for SortRow {
+    fn cmp(&self, right: &SortRow) -> cmp::Ordering {
+        match cmp_sort_keys(&self.keys, &right.keys, &self.desc) {
+            Ok(o) => o,
+            Err(e) => {
+                let mut err = self.err.borrow_mut();
+                if err.is_none() {
+                    *err = Some(e);
+                }
+                cmp::Ordering::Equal
+            }
+        }
+    }
+}
+
+impl PartialOrd for SortRow {
+    fn partial_cmp(&self, right: &SortRow) -> Option<cmp::Ordering> {
+        Some(self.cmp(right))
+    }
+}
+
+impl PartialEq for SortRow {
+    fn eq(&self, right: &SortRow) -> bool {
+        self.cmp(right) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for SortRow {}
+
+/// Keeps the first `limit` rows in the order of the order by items. The last
+/// of them is on the top of the heap, it's replaced by any smaller row.
+struct TopNHeap {
+    rows: BinaryHeap<SortRow>,
+    limit: usize,
+    desc: Rc<Vec<bool>>,
+    err: Rc<RefCell<Option<Error>>>,
+}
+
+impl TopNHeap {
+    fn new(limit: usize, desc: Vec<bool>) -> TopNHeap {
+        TopNHeap {
+            rows: BinaryHeap::new(),
+            limit: limit,
+            desc: Rc::new(desc),
+            err: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    fn try_add_row(&mut self, keys: Vec<Datum>, row: Row) -> Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let row = SortRow {
+            keys: keys,
+            row: row,
+            desc: self.desc.clone(),
+            err: self.err.clone(),
+        };
+        if self.rows.len() < self.limit {
+            self.rows.push(row);
+        } else if row < *self.rows.peek().unwrap() {
+            self.rows.pop();
+            self.rows.push(row);
+        }
+        self.take_err()
+    }
+
+    fn take_err(&self) -> Result<()> {
+        match self.err.borrow_mut().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn into_sorted_rows(self) -> Result<Vec<Row>> {
+        let rows = self.rows.into_sorted_vec();
+        try!(self.take_err());
+        Ok(rows.into_iter().map(|r| r.row).collect())
+    }
+}
+
+/// Compares the sort keys of two rows lexicographically, every key is compared
+/// in the direction of its order by item.
+fn cmp_sort_keys(lhs: &[Datum], rhs: &[Datum], desc: &[bool]) -> Result<cmp::Ordering> {
+    for ((l, r), &desc) in lhs.iter().zip(rhs).zip(desc) {
+        let o = box_try!(l.cmp(r));
+        if o != cmp::Ordering::Equal {
+            return Ok(if desc { o.reverse() } else { o });
+        }
+    }
+    Ok(cmp::Ordering::Equal)
+}
+
 pub struct SelectContextCore {
     sel: SelectRequest,
     eval: Evaluator,
     cols: HashSet<i64>,
     cond_cols: HashMap<i64, ColumnInfo>,
+    // columns referred by the order by items of a top n select.
+    order_cols: HashMap<i64, ColumnInfo>,
     aggr: bool,
+    // If true, the rows are ordered by the order by items instead of the handle.
+    topn: bool,
+    topn_heap: TopNHeap,
     gks: Vec<Rc<Vec<u8>>>,
     gk_aggrs: HashMap<Rc<Vec<u8>>, Vec<Box<AggrFunc>>>,
+    // group keys of the rows already emitted by a distinct select.
+    distinct_gks: HashSet<Vec<u8>>,
 }
 
 impl SelectContextCore {
     fn new(sel: SelectRequest) -> Result<SelectContextCore> {
         let cols;
         let mut cond_cols;
+        let mut order_cols = HashMap::new();
 
         {
             let select_cols = if sel.has_table_info() {
@@ -337,17 +590,42 @@ impl SelectContextCore {
                 .collect();
             cond_cols = HashMap::new();
             try!(collect_col_in_expr(&mut cond_cols, select_cols, sel.get_field_where()));
+            for item in sel.get_order_by().iter().filter(|item| item.has_expr()) {
+                try!(collect_col_in_expr(&mut order_cols, select_cols, item.get_expr()));
+            }
         }
 
 
+        // A distinct select without aggregates emits the first row of every group.
+        let aggr = !sel.get_aggregates().is_empty() ||
+                   (!sel.get_group_by().is_empty() && !sel.get_distinct());
+        // An order by item without expression orders by the handle, which is
+        // just the scan order.
+        let topn = sel.has_table_info() && !aggr && !sel.get_distinct() &&
+                   sel.get_order_by().iter().any(|item| item.has_expr());
+        let limit = if sel.has_limit() {
+            sel.get_limit() as usize
+        } else {
+            usize::MAX
+        };
+        let desc = sel.get_order_by()
+            .iter()
+            .filter(|item| item.has_expr())
+            .map(|item| item.get_desc())
+            .collect();
+
         Ok(SelectContextCore {
-            aggr: !sel.get_aggregates().is_empty() || !sel.get_group_by().is_empty(),
+            aggr: aggr,
+            topn: topn,
             sel: sel,
             eval: Default::default(),
             cols: cols,
             cond_cols: cond_cols,
+            order_cols: order_cols,
+            topn_heap: TopNHeap::new(limit, desc),
             gks: vec![],
             gk_aggrs: map![],
+            distinct_gks: HashSet::new(),
         })
     }
 
@@ -364,12 +642,71 @@ impl SelectContextCore {
 
         if self.aggr {
             try!(self.aggregate(h, &row_data));
+        } else if self.sel.get_distinct() {
+            try!(self.distinct(h, row_data, dest));
+        } else if self.topn {
+            try!(self.collect_topn_row(h, row_data));
         } else {
             dest.push(try!(self.get_row(h, row_data)))
         }
         Ok(())
     }
 
+    /// Check whether the row matches the filter, the row is not decoded at all
+    /// if there is no filter.
+    fn is_matched(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        if !self.sel.has_field_where() {
+            return Ok(true);
+        }
+        let h = box_try!(table::decode_handle(key));
+        let row_data = box_try!(table::cut_row(value, &self.cols));
+        self.eval.row.clear();
+        self.should_skip(h, &row_data).map(|skip| !skip)
+    }
+
+    fn distinct(&mut self,
+                h: i64,
+                values: HashMap<i64, &[u8]>,
+                dest: &mut Vec<Row>)
+                -> Result<()> {
+        let gk = if self.sel.get_group_by().is_empty() {
+            None
+        } else {
+            try!(inflate_with_col(&mut self.eval,
+                                  &values,
+                                  self.sel.get_table_info().get_columns(),
+                                  h));
+            Some(try!(self.get_group_key()))
+        };
+        let row = try!(self.get_row(h, values));
+        // Without group by items, the whole row is used as the group key.
+        let gk = gk.unwrap_or_else(|| row.get_data().to_vec());
+        if self.distinct_gks.insert(gk) {
+            dest.push(row);
+        }
+        Ok(())
+    }
+
+    fn collect_topn_row(&mut self, h: i64, values: HashMap<i64, &[u8]>) -> Result<()> {
+        try!(inflate_with_col(&mut self.eval, &values, self.order_cols.values(), h));
+        let mut keys = Vec::with_capacity(self.sel.get_order_by().len());
+        for item in self.sel.get_order_by() {
+            // Ordering by the handle among column order by items makes no
+            // difference to the result, so it's skipped.
+            if item.has_expr() {
+                keys.push(box_try!(self.eval.eval(item.get_expr())));
+            }
+        }
+        let row = try!(self.get_row(h, values));
+        self.topn_heap.try_add_row(keys, row)
+    }
+
+    /// Returns the buffered rows in the order of the order by items.
+    fn topn_rows(&mut self) -> Result<Vec<Row>> {
+        let heap = mem::replace(&mut self.topn_heap, TopNHeap::new(0, vec![]));
+        heap.into_sorted_rows()
+    }
+
     fn should_skip(&mut self, h: i64, values: &HashMap<i64, &[u8]>) -> Result<bool> {
         if !self.sel.has_field_where() {
             return Ok(false);
@@ -401,6 +738,8 @@ impl SelectContextCore {
         Ok(row)
     }
 
+    /// Evaluates every group by item against the current row. An item can be
+    /// any expression, the columns it refers to must be inflated already.
     fn get_group_key(&mut self) -> Result<Vec<u8>> {
         let items = self.sel.get_group_by();
         if items.is_empty() {
@@ -433,8 +772,11 @@ impl SelectContextCore {
             }
             Entry::Vacant(e) => {
                 let mut aggrs = Vec::with_capacity(aggr_exprs.len());
+                let final_avg = self.sel.get_final_avg();
+                let first_non_null = self.sel.get_first_non_null();
                 for expr in aggr_exprs {
-                    let mut aggr = try!(aggregate::build_aggr_func(expr));
+                    let mut aggr =
+                        try!(aggregate::build_aggr_func(expr, final_avg, first_non_null));
                     let args = box_try!(self.eval.batch_eval(expr.get_children()));
                     try!(aggr.update(args));
                     aggrs.push(aggr);
@@ -452,6 +794,7 @@ impl SelectContextCore {
     /// Aggs: count(c1), sum(c2), avg(c3)
     /// Rows: groupKey1, count1, value2, count3, value3
     ///       groupKey2, count1, value2, count3, value3
+    /// If the request asks for the final average, avg(c3) is a single datum instead.
     fn aggr_rows(&mut self) -> Result<Vec<Row>> {
         let mut rows = Vec::with_capacity(self.gk_aggrs.len());
         // Each aggregate partial result will be converted to two datum.
@@ -499,16 +842,54 @@ fn collect_col_in_expr(cols: &mut HashMap<i64, ColumnInfo>,
 pub struct SelectContext<'a> {
     snap: SnapshotStore<'a>,
     core: SelectContextCore,
+    cancelled: Event<()>,
+    scanned: Cell<usize>,
+    max_scan_keys: usize,
+    // The last scanned key if the scan stops at `max_scan_keys`.
+    last_key: RefCell<Option<Vec<u8>>>,
 }
 
 impl<'a> SelectContext<'a> {
-    fn new(sel: SelectRequest, snap: SnapshotStore<'a>) -> Result<SelectContext<'a>> {
+    fn new(sel: SelectRequest,
+           snap: SnapshotStore<'a>,
+           cancelled: Event<()>,
+           max_scan_keys: usize)
+           -> Result<SelectContext<'a>> {
         Ok(SelectContext {
             core: try!(SelectContextCore::new(sel)),
             snap: snap,
+            cancelled: cancelled,
+            scanned: Cell::new(0),
+            max_scan_keys: max_scan_keys,
+            last_key: RefCell::new(None),
         })
     }
 
+    /// Check whether the scan should stop after `key` for reaching `max_scan_keys`,
+    /// the key is recorded for the client to continue from.
+    fn reach_scan_limit(&self, key: &[u8]) -> bool {
+        if self.scanned.get() < self.max_scan_keys {
+            return false;
+        }
+        *self.last_key.borrow_mut() = Some(key.to_vec());
+        true
+    }
+
+    fn is_scan_limited(&self) -> bool {
+        self.last_key.borrow().is_some()
+    }
+
+    /// Count a scanned key, and abort the scan if the request is cancelled.
+    fn check_cancelled(&self) -> Result<()> {
+        let scanned = self.scanned.get();
+        self.scanned.set(scanned + 1);
+        if scanned % CHECK_CANCELLED_INTERVAL == 0 && self.cancelled.is_set() {
+            metric_incr!("copr.cancelled");
+            return Err(box_err!("request cancelled after scanning {} keys", scanned));
+        }
+        Ok(())
+    }
+
     fn get_rows_from_sel(&mut self,
                          ranges: Vec<KeyRange>,
                          limit: usize,
@@ -516,7 +897,7 @@ impl<'a> SelectContext<'a> {
                          -> Result<Vec<Row>> {
         let mut rows = vec![];
         for ran in ranges {
-            if rows.len() >= limit {
+            if rows.len() >= limit || self.is_scan_limited() {
                 break;
             }
             let timer = Instant::now();
@@ -552,6 +933,7 @@ impl<'a> SelectContext<'a> {
             };
             let mut scanner = try!(self.snap.scanner());
             while limit > rows.len() {
+                try!(self.check_cancelled());
                 let kv = if desc {
                     try!(scanner.reverse_seek(Key::from_raw(&seek_key)))
                 } else {
@@ -569,6 +951,9 @@ impl<'a> SelectContext<'a> {
                     break;
                 }
                 try!(self.core.handle_row(&key, &value, &mut rows));
+                if self.reach_scan_limit(&key) {
+                    break;
+                }
                 seek_key = if desc {
                     box_try!(table::truncate_as_row_key(&key)).to_vec()
                 } else {
@@ -583,6 +968,65 @@ impl<'a> SelectContext<'a> {
         }
     }
 
+    /// Scan all the ranges and return the first rows in the order of the order by
+    /// items, at most the limit of the select are kept in memory.
+    fn get_topn_rows_from_sel(&mut self, ranges: Vec<KeyRange>) -> Result<Vec<Row>> {
+        try!(self.get_rows_from_sel(ranges, usize::MAX, false));
+        self.core.topn_rows()
+    }
+
+    /// Count the rows matching the filter in all the ranges, the result is a single
+    /// row laid out like the partial result of the `COUNT` aggregate.
+    fn count_rows_from_sel(&mut self, ranges: Vec<KeyRange>) -> Result<Vec<Row>> {
+        let mut count = 0;
+        for ran in ranges {
+            if self.is_scan_limited() {
+                break;
+            }
+            count += try!(self.count_rows_from_range(ran));
+        }
+        let row_data = [Datum::Bytes(SINGLE_GROUP.to_vec()), Datum::U64(count)];
+        let mut row = Row::new();
+        row.set_data(box_try!(datum::encode_value(&row_data)));
+        Ok(vec![row])
+    }
+
+    fn count_rows_from_range(&mut self, range: KeyRange) -> Result<u64> {
+        if is_point(&range) {
+            let value = match try!(self.snap.get(&Key::from_raw(range.get_start()))) {
+                None => return Ok(0),
+                Some(v) => v,
+            };
+            let matched = try!(self.core.is_matched(range.get_start(), &value));
+            return Ok(matched as u64);
+        }
+        let mut count = 0;
+        let mut seek_key = range.get_start().to_vec();
+        let mut scanner = try!(self.snap.scanner());
+        loop {
+            try!(self.check_cancelled());
+            let (key, value) = match try!(scanner.seek(Key::from_raw(&seek_key))) {
+                Some((key, value)) => (box_try!(key.raw()), value),
+                None => break,
+            };
+            if range.get_end() <= &key {
+                debug!("key: {} out of range [{}, {})",
+                       escape(&key),
+                       escape(range.get_start()),
+                       escape(range.get_end()));
+                break;
+            }
+            if try!(self.core.is_matched(&key, &value)) {
+                count += 1;
+            }
+            if self.reach_scan_limit(&key) {
+                break;
+            }
+            seek_key = prefix_next(&key);
+        }
+        Ok(count)
+    }
+
     fn get_rows_from_idx(&self,
                          ranges: Vec<KeyRange>,
                          limit: usize,
@@ -590,7 +1034,7 @@ impl<'a> SelectContext<'a> {
                          -> Result<Vec<Row>> {
         let mut rows = vec![];
         for r in ranges {
-            if rows.len() >= limit {
+            if rows.len() >= limit || self.is_scan_limited() {
                 break;
             }
             let part = try!(self.get_idx_row_from_range(r, limit, desc));
@@ -600,50 +1044,84 @@ impl<'a> SelectContext<'a> {
     }
 
     fn get_idx_row_from_range(&self, r: KeyRange, limit: usize, desc: bool) -> Result<Vec<Row>> {
+        if desc {
+            return self.get_idx_row_from_range_desc(r, limit);
+        }
         let mut rows = vec![];
         let info = self.core.sel.get_index_info();
-        let mut seek_key = if desc {
-            r.get_end().to_vec()
-        } else {
-            r.get_start().to_vec()
-        };
+        let mut seek_key = r.get_start().to_vec();
         let mut scanner = try!(self.snap.scanner());
         while rows.len() < limit {
-            let nk = if desc {
-                try!(scanner.reverse_seek(Key::from_raw(&seek_key)))
-            } else {
-                try!(scanner.seek(Key::from_raw(&seek_key)))
-            };
-            let (key, val) = match nk {
+            try!(self.check_cancelled());
+            let (key, val) = match try!(scanner.seek(Key::from_raw(&seek_key))) {
                 Some((key, val)) => (box_try!(key.raw()), val),
                 None => break,
             };
-            if r.get_start() > &key || r.get_end() <= &key {
+            if r.get_end() <= &key {
                 debug!("key: {} out of range [{}, {})",
                        escape(&key),
                        escape(r.get_start()),
                        escape(r.get_end()));
                 break;
             }
-            let mut datums = box_try!(table::decode_index_key(&key));
-            let handle = if datums.len() > info.get_columns().len() {
-                datums.pop().unwrap()
-            } else {
-                let h = box_try!(val.as_slice().read_i64::<BigEndian>());
-                Datum::I64(h)
-            };
-            let data = box_try!(datum::encode_value(&datums));
-            let handle_data = box_try!(datum::encode_value(&[handle]));
-            let mut row = Row::new();
-            row.set_handle(handle_data);
-            row.set_data(data);
-            rows.push(row);
-            seek_key = if desc {
-                key
-            } else {
-                prefix_next(&key)
-            };
+            rows.push(try!(decode_index_row(info, &key, &val)));
+            if self.reach_scan_limit(&key) {
+                break;
+            }
+            seek_key = prefix_next(&key);
         }
         Ok(rows)
     }
+
+    /// Iterate the index in descending index key order.
+    fn get_idx_row_from_range_desc(&self, r: KeyRange, limit: usize) -> Result<Vec<Row>> {
+        let mut rows = vec![];
+        let info = self.core.sel.get_index_info();
+        let mut seek_key = Key::from_raw(r.get_end());
+        let mut scanner = try!(self.snap.scanner());
+        while rows.len() < limit {
+            let batch_size = cmp::min(limit - rows.len(), INDEX_REVERSE_SCAN_BATCH_SIZE);
+            let kvs = try!(scanner.reverse_scan(seek_key, batch_size));
+            let finished = kvs.len() < batch_size;
+            let mut last_key = None;
+            for kv in kvs {
+                try!(self.check_cancelled());
+                let (key, val) = try!(kv);
+                if r.get_start() > &key {
+                    debug!("key: {} out of range [{}, {})",
+                           escape(&key),
+                           escape(r.get_start()),
+                           escape(r.get_end()));
+                    return Ok(rows);
+                }
+                rows.push(try!(decode_index_row(info, &key, &val)));
+                if self.reach_scan_limit(&key) {
+                    return Ok(rows);
+                }
+                last_key = Some(key);
+            }
+            match last_key {
+                Some(key) if !finished => seek_key = Key::from_raw(&key),
+                _ => break,
+            }
+        }
+        Ok(rows)
+    }
+}
+
+fn decode_index_row(info: &IndexInfo, key: &[u8], val: &[u8]) -> Result<Row> {
+    let mut datums = box_try!(table::decode_index_key(key));
+    let handle = if datums.len() > info.get_columns().len() {
+        datums.pop().unwrap()
+    } else {
+        let mut val = val;
+        let h = box_try!(val.read_i64::<BigEndian>());
+        Datum::I64(h)
+    };
+    let data = box_try!(datum::encode_value(&datums));
+    let handle_data = box_try!(datum::encode_value(&[handle]));
+    let mut row = Row::new();
+    row.set_handle(handle_data);
+    row.set_data(data);
+    Ok(row)
 }
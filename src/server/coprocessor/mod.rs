@@ -21,6 +21,10 @@ quick_error! {
             description("key is locked")
             display("locked {:?}", l)
         }
+        AggrOverflow(reason: String) {
+            description("aggregation overflow")
+            display("aggregation overflow: {}", reason)
+        }
         Other(err: Box<error::Error + Send + Sync>) {
             from()
             cause(err.as_ref())
@@ -56,5 +60,5 @@ impl From<txn::Error> for Error {
     }
 }
 
-pub use self::endpoint::{Host as EndPointHost, RequestTask, SelectContext, SINGLE_GROUP,
-                         REQ_TYPE_SELECT, REQ_TYPE_INDEX};
+pub use self::endpoint::{Host as EndPointHost, RequestTask, CancelHandle, SelectContext,
+                         SINGLE_GROUP, REQ_TYPE_SELECT, REQ_TYPE_INDEX};
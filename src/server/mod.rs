@@ -14,6 +14,8 @@
 use std::thread;
 use std::time::Duration;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fmt::{self, Formatter, Display};
 use std::boxed::{Box, FnBox};
 use std::io::Write;
@@ -45,6 +47,8 @@ pub use self::resolve::{StoreAddrResolver, PdStoreAddrResolver, MockStoreAddrRes
 pub type OnResponse = Box<FnBox(msgpb::Message) + Send>;
 
 const MAX_SEND_RETRY_CNT: i32 = 20;
+// The notify channel is nearly full when this percent of its capacity is used.
+const NOTIFY_NEAR_FULL_PERCENT: usize = 80;
 
 // send_msg wraps Sender and retries some times if queue is full.
 pub fn send_msg<M: Send>(ch: &mio::Sender<M>, mut msg: M) -> Result<()> {
@@ -163,40 +167,78 @@ pub enum Msg {
 #[derive(Debug)]
 pub struct SendCh {
     ch: mio::Sender<Msg>,
+    // messages sent but not handled by the event loop yet, shared by all the
+    // clones.
+    pending: Arc<AtomicUsize>,
+    capacity: usize,
 }
 
 impl Clone for SendCh {
     fn clone(&self) -> SendCh {
-        SendCh { ch: self.ch.clone() }
+        SendCh {
+            ch: self.ch.clone(),
+            pending: self.pending.clone(),
+            capacity: self.capacity,
+        }
     }
 }
 
 impl SendCh {
-    pub fn new(ch: mio::Sender<Msg>) -> SendCh {
-        SendCh { ch: ch }
+    /// `capacity` must be the notify capacity of the event loop.
+    pub fn new(ch: mio::Sender<Msg>, capacity: usize) -> SendCh {
+        SendCh {
+            ch: ch,
+            pending: Arc::new(AtomicUsize::new(0)),
+            capacity: capacity,
+        }
     }
 
     pub fn send(&self, msg: Msg) -> Result<()> {
-        try!(send_msg(&self.ch, msg));
+        // Count it before sending, the event loop may handle it at once.
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if let Err(e) = send_msg(&self.ch, msg) {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            return Err(e);
+        }
         Ok(())
     }
+
+    /// Must be called by the event loop for every message it handles.
+    pub fn on_received(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// The number of messages waiting to be handled by the event loop.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub fn is_near_full(&self) -> bool {
+        self.pending() * 100 >= self.capacity * NOTIFY_NEAR_FULL_PERCENT
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::thread;
 
-    use mio::{EventLoop, Handler};
+    use mio::{EventLoop, EventLoopBuilder, Handler};
+    use kvproto::msgpb::Message;
+    use kvproto::raft_serverpb::RaftMessage;
 
     use super::*;
+    use raftstore::store::Transport;
 
-    struct SenderHandler;
+    struct SenderHandler {
+        ch: SendCh,
+    }
 
     impl Handler for SenderHandler {
         type Timeout = ();
         type Message = Msg;
 
         fn notify(&mut self, event_loop: &mut EventLoop<SenderHandler>, msg: Msg) {
+            self.ch.on_received();
             if let Msg::Quit = msg {
                 event_loop.shutdown()
             }
@@ -206,13 +248,46 @@ mod tests {
     #[test]
     fn test_sender() {
         let mut event_loop = EventLoop::new().unwrap();
-        let ch = SendCh::new(event_loop.channel());
+        let ch = SendCh::new(event_loop.channel(), 4096);
+        let mut handler = SenderHandler { ch: ch.clone() };
         let h = thread::spawn(move || {
-            event_loop.run(&mut SenderHandler).unwrap();
+            event_loop.run(&mut handler).unwrap();
         });
 
         ch.send(Msg::Quit).unwrap();
 
         h.join().unwrap();
     }
+
+    #[test]
+    fn test_sender_near_full() {
+        let mut builder = EventLoopBuilder::new();
+        builder.notify_capacity(10);
+        let mut event_loop = builder.build().unwrap();
+        let ch = SendCh::new(event_loop.channel(), 10);
+        let trans = ServerTransport::new(ch.clone());
+
+        let msg = || {
+            Msg::SendStore {
+                store_id: 1,
+                data: ConnData::new(0, Message::new()),
+            }
+        };
+        for _ in 0..7 {
+            ch.send(msg()).unwrap();
+        }
+        assert!(!ch.is_near_full());
+        trans.send(RaftMessage::new()).unwrap();
+        assert_eq!(ch.pending(), 8);
+        assert!(ch.is_near_full());
+        // The transport refuses to queue more instead of filling the channel up.
+        assert!(trans.send(RaftMessage::new()).is_err());
+        assert_eq!(ch.pending(), 8);
+
+        ch.send(Msg::Quit).unwrap();
+        let mut handler = SenderHandler { ch: ch.clone() };
+        event_loop.run(&mut handler).unwrap();
+        assert_eq!(ch.pending(), 0);
+        assert!(!ch.is_near_full());
+    }
 }
@@ -16,6 +16,7 @@ use std::sync::{Arc, RwLock};
 
 use mio::EventLoop;
 use rocksdb::DB;
+use util::rocksdb;
 
 use pd::{INVALID_ID, PdClient, Error as PdError};
 use kvproto::raft_serverpb::StoreIdent;
@@ -87,6 +88,8 @@ impl<C> Node<C>
                     -> Result<()>
         where T: Transport + 'static
     {
+        // Fail before bootstrapping anything into a misconfigured engine.
+        box_try!(rocksdb::check_cfs(&engine, &self.store_cfg.required_cfs));
         let bootstrapped = try!(self.pd_client
             .is_cluster_bootstrapped());
         let mut store_id = try!(self.check_store(&engine));
@@ -97,7 +97,10 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
     // address in Node before creating the Server, so we first
     // create the listener outer, get the real listening address for
     // Node and then pass it here.
+    // `sendch` must be created from the channel of `event_loop`, it's shared
+    // with the transport so they agree on the queued messages.
     pub fn new(event_loop: &mut EventLoop<Self>,
+               sendch: SendCh,
                cfg: &Config,
                listener: TcpListener,
                storage: Storage,
@@ -110,7 +113,7 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
                                  EventSet::readable(),
                                  PollOpt::edge()));
 
-        let sendch = SendCh::new(event_loop.channel());
+        storage.set_strict_batch_keys(cfg.strict_batch_keys);
         let store_handler = StoreHandler::new(storage);
         let end_point_worker = Worker::new("end-point-worker");
         let snap_worker = Worker::new("snap-handler");
@@ -135,11 +138,14 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
     }
 
     pub fn run(&mut self, event_loop: &mut EventLoop<Self>) -> Result<()> {
-        let end_point = EndPointHost::new(self.store.engine());
+        let end_point = EndPointHost::new(self.store.engine(), &self.cfg);
         box_try!(self.end_point_worker.start_batch(end_point, DEFAULT_COPROCESSOR_BATCH));
 
         let ch = self.get_sendch();
-        let snap_runner = SnapHandler::new(self.snap_mgr.clone(), self.raft_router.clone(), ch);
+        let snap_runner = SnapHandler::new(self.snap_mgr.clone(),
+                                           self.raft_router.clone(),
+                                           ch,
+                                           self.cfg.snap_chunk_size);
         box_try!(self.snap_worker.start(snap_runner));
 
         try!(event_loop.run(self));
@@ -202,7 +208,12 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
                                  EventSet::readable() | EventSet::hup(),
                                  PollOpt::edge()));
 
-        let conn = Conn::new(sock, new_token, store_id, self.snap_worker.scheduler());
+        let conn = Conn::new(sock,
+                             new_token,
+                             store_id,
+                             self.snap_worker.scheduler(),
+                             self.snap_mgr.clone(),
+                             self.cfg.snap_chunk_size);
         self.conns.insert(new_token, conn);
         debug!("register conn {:?}", new_token);
 
@@ -252,8 +263,12 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
             }
             MessageType::CopReq => {
                 let on_resp = self.make_response_cb(token, msg_id);
-                box_try!(self.end_point_worker
-                    .schedule(RequestTask::new(msg.take_cop_req(), on_resp)));
+                let req = msg.take_cop_req();
+                let task = match self.conns.get(&token) {
+                    Some(conn) => RequestTask::with_cancel_handle(req, on_resp, &conn.cop_cancel),
+                    None => RequestTask::new(req, on_resp),
+                };
+                box_try!(self.end_point_worker.schedule(task));
                 Ok(())
             }
             _ => {
@@ -544,6 +559,7 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Handler for Server<T, S> {
     }
 
     fn notify(&mut self, event_loop: &mut EventLoop<Self>, msg: Msg) {
+        self.sendch.on_received();
         match msg {
             Msg::Quit => event_loop.shutdown(),
             Msg::WriteData { token, data } => self.write_data(event_loop, token, data),
@@ -623,7 +639,7 @@ mod tests {
     use mio::tcp::TcpListener;
 
     use super::*;
-    use super::super::{Msg, ConnData, Result, Config};
+    use super::super::{Msg, SendCh, ConnData, Result, Config};
     use super::super::transport::RaftStoreRouter;
     use super::super::resolve::{StoreAddrResolver, Callback as ResolveCallback};
     use storage::{Storage, Dsn};
@@ -679,9 +695,11 @@ mod tests {
 
         let cfg = Config::new();
         let mut event_loop = create_event_loop(&cfg).unwrap();
+        let sendch = SendCh::new(event_loop.channel(), cfg.notify_capacity);
         let (tx, rx) = mpsc::channel();
         let mut server =
             Server::new(&mut event_loop,
+                        sendch,
                         &cfg,
                         listener,
                         Storage::new(Dsn::RocksDBPath(TEMP_DIR)).unwrap(),
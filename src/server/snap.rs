@@ -15,14 +15,16 @@ use std::fmt::{self, Formatter, Display};
 use std::io;
 use std::fs::File;
 use std::net::{SocketAddr, TcpStream};
-use std::io::{Read, Write};
+use std::io::{Read, Write, Seek, SeekFrom};
 use std::collections::HashMap;
 use std::boxed::FnBox;
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Instant, Duration};
 use threadpool::ThreadPool;
 use mio::Token;
 use bytes::{Buf, ByteBuf};
+use byteorder::{BigEndian, ReadBytesExt};
+use protobuf::Message;
 
 use super::{Result, ConnData, SendCh, Msg};
 use super::transport::RaftStoreRouter;
@@ -31,21 +33,32 @@ use util::worker::Runnable;
 use util::codec::rpc;
 use util::HandyRwLock;
 
-use kvproto::raft_serverpb::RaftMessage;
+use kvproto::raft_serverpb::{RaftMessage, RaftSnapshotData};
 
 pub type Callback = Box<FnBox(Result<()>) + Send>;
 
 const DEFAULT_SENDER_POOL_SIZE: usize = 3;
+// A receiver which supports resuming replies the offset in this time.
+const RESUME_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+const SNAP_IO_TIMEOUT_SECS: u64 = 60;
+// A receiver which didn't reply the offset may have just been slow or
+// restarting, resuming is tried again after this time.
+const LEGACY_ADDR_EXPIRE_SECS: u64 = 10 * 60;
+
+/// Set in the message id of a snapshot message by a sender which can resume,
+/// the receiver replies the offset to start from only if it's set.
+pub const SNAP_RESUME_FLAG: u64 = 1 << 63;
 
 /// `Task` that `Runner` can handle.
 ///
-/// `Register` register a pending snapshot file with token;
+/// `Register` register a pending snapshot file with token, and whether the
+/// sender resumes from the chunks received before;
 /// `Write` write data to snapshot file;
 /// `Close` save the snapshot file;
 /// `Discard` discard all the unsaved changes made to snapshot file;
 /// `SendTo` send the snapshot file to specified address.
 pub enum Task {
-    Register(Token, RaftMessage),
+    Register(Token, RaftMessage, bool),
     Write(Token, ByteBuf),
     Close(Token),
     Discard(Token),
@@ -59,7 +72,9 @@ pub enum Task {
 impl Display for Task {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
-            Task::Register(token, ref meta) => write!(f, "Register {:?} token: {:?}", meta, token),
+            Task::Register(token, ref meta, _) => {
+                write!(f, "Register {:?} token: {:?}", meta, token)
+            }
             Task::Write(token, _) => write!(f, "Write snap for {:?}", token),
             Task::Close(token) => write!(f, "Close file {:?}", token),
             Task::Discard(token) => write!(f, "Discard file {:?}", token),
@@ -70,20 +85,78 @@ impl Display for Task {
     }
 }
 
+/// Send the content of `r` starting from `offset`, returns the sent size.
+fn send_from<R: Read + Seek, W: Write>(r: &mut R, w: &mut W, offset: u64) -> io::Result<u64> {
+    let len = try!(r.seek(SeekFrom::End(0)));
+    if offset > len {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("offset {} exceeds file size {}", offset, len)));
+    }
+    try!(r.seek(SeekFrom::Start(offset)));
+    io::copy(r, w)
+}
+
+/// Read the offset replied by a receiver which supports resuming. A receiver
+/// which doesn't reply is probably an old one, it's remembered for a while so
+/// the retries send the whole file without asking.
+fn read_resume_offset(conn: &mut TcpStream,
+                      addr: SocketAddr,
+                      legacy_addrs: &RwLock<HashMap<SocketAddr, Instant>>)
+                      -> Result<u64> {
+    try!(conn.set_read_timeout(Some(Duration::from_secs(RESUME_HANDSHAKE_TIMEOUT_SECS))));
+    match conn.read_u64::<BigEndian>() {
+        Ok(offset) => {
+            try!(conn.set_read_timeout(Some(Duration::from_secs(SNAP_IO_TIMEOUT_SECS))));
+            Ok(offset)
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                      e.kind() == io::ErrorKind::TimedOut ||
+                      e.kind() == io::ErrorKind::UnexpectedEof => {
+            legacy_addrs.wl().insert(addr, Instant::now());
+            Err(box_err!("{} doesn't reply the resume offset: {:?}, send the whole snapshot later",
+                         addr,
+                         e))
+        }
+        Err(e) => Err(From::from(e)),
+    }
+}
+
+/// Check whether the receiver was found not to support resuming, the mark
+/// expires after `expire`.
+fn is_legacy_addr(legacy_addrs: &RwLock<HashMap<SocketAddr, Instant>>,
+                  addr: &SocketAddr,
+                  expire: Duration)
+                  -> bool {
+    match legacy_addrs.rl().get(addr) {
+        None => return false,
+        Some(t) if t.elapsed() < expire => return true,
+        Some(_) => {}
+    }
+    legacy_addrs.wl().remove(addr);
+    false
+}
+
 /// Send the snapshot to specified address.
 ///
-/// It will first send the normal raft snapshot message and then send the snapshot file.
-fn send_snap(mgr: SnapManager, addr: SocketAddr, data: ConnData) -> Result<()> {
+/// It will first send the normal raft snapshot message, then send the snapshot file
+/// from the offset replied by the receiver, which skips the chunks it already has.
+/// Receivers in `legacy_addrs` don't support resuming, the whole file is sent to
+/// them without waiting for the offset.
+fn send_snap(mgr: SnapManager,
+             addr: SocketAddr,
+             data: ConnData,
+             legacy_addrs: Arc<RwLock<HashMap<SocketAddr, Instant>>>)
+             -> Result<()> {
     assert!(data.is_snapshot());
     let timer = Instant::now();
     let snap = data.msg.get_raft().get_message().get_snapshot();
     let key = try!(SnapKey::from_snap(&snap));
-    mgr.wl().register(key.clone(), SnapEntry::Sending);
+    if !mgr.wl().try_register(key.clone(), SnapEntry::Sending) {
+        // The snapshot will be retried after the failure is reported to raft.
+        return Err(box_err!("too many snapshots are being sent, queue {}", key));
+    }
     let snap_file = box_try!(mgr.rl().get_snap_file(&key, true));
-    defer!({
-        snap_file.delete();
-        mgr.wl().deregister(&key, &SnapEntry::Sending);
-    });
+    defer!(mgr.wl().deregister(&key, &SnapEntry::Sending));
     if !snap_file.exists() {
         return Err(box_err!("missing snap file: {:?}", snap_file.path()));
     }
@@ -92,12 +165,37 @@ fn send_snap(mgr: SnapManager, addr: SocketAddr, data: ConnData) -> Result<()> {
     let mut f = try!(File::open(snap_file.path()));
     let mut conn = try!(TcpStream::connect(&addr));
     try!(conn.set_nodelay(true));
+    try!(conn.set_read_timeout(Some(Duration::from_secs(SNAP_IO_TIMEOUT_SECS))));
+    try!(conn.set_write_timeout(Some(Duration::from_secs(SNAP_IO_TIMEOUT_SECS))));
 
-    let res = rpc::encode_msg(&mut conn, data.msg_id, &data.msg)
-        .and_then(|_| io::copy(&mut f, &mut conn).map_err(From::from))
+    let expire = Duration::from_secs(LEGACY_ADDR_EXPIRE_SECS);
+    let resume = !is_legacy_addr(&legacy_addrs, &addr, expire);
+    let msg_id = if resume {
+        data.msg_id | SNAP_RESUME_FLAG
+    } else {
+        data.msg_id
+    };
+    let res = rpc::encode_msg(&mut conn, msg_id, &data.msg)
+        .map_err(From::from)
+        .and_then(|_| if resume {
+            read_resume_offset(&mut conn, addr, &legacy_addrs)
+        } else {
+            Ok(0)
+        })
+        .and_then(|offset| {
+            if offset > 0 {
+                info!("resume sending snapshot {} from offset {}", key, offset);
+            }
+            send_from(&mut f, &mut conn, offset).map_err(From::from)
+        })
         .and_then(|_| conn.read(&mut [0]).map_err(From::from))
         .map(|_| ())
         .map_err(From::from);
+    if res.is_ok() {
+        snap_file.delete();
+    }
+    // Otherwise the file is kept, so the retry of the same snapshot can reuse it
+    // and resume from where the receiver stopped.
     if let Ok(meta) = snap_file.meta() {
         debug!("sending snapshot[path: {}, size: {}] takes {:?}",
                snap_file.path().display(),
@@ -108,22 +206,39 @@ fn send_snap(mgr: SnapManager, addr: SocketAddr, data: ConnData) -> Result<()> {
     res
 }
 
+fn snap_file_size(msg: &RaftMessage) -> io::Result<u64> {
+    let mut snap_data = RaftSnapshotData::new();
+    if let Err(e) = snap_data.merge_from_bytes(msg.get_message().get_snapshot().get_data()) {
+        return Err(io::Error::new(io::ErrorKind::Other, e));
+    }
+    Ok(snap_data.get_file_size())
+}
+
 pub struct Runner<R: RaftStoreRouter + 'static> {
     snap_mgr: SnapManager,
     files: HashMap<Token, (SnapFile, RaftMessage)>,
     pool: ThreadPool,
     ch: SendCh,
     raft_router: Arc<RwLock<R>>,
+    chunk_size: usize,
+    // receivers which don't support resuming, and when they are found.
+    legacy_addrs: Arc<RwLock<HashMap<SocketAddr, Instant>>>,
 }
 
 impl<R: RaftStoreRouter + 'static> Runner<R> {
-    pub fn new(snap_mgr: SnapManager, r: Arc<RwLock<R>>, ch: SendCh) -> Runner<R> {
+    pub fn new(snap_mgr: SnapManager,
+               r: Arc<RwLock<R>>,
+               ch: SendCh,
+               chunk_size: usize)
+               -> Runner<R> {
         Runner {
             snap_mgr: snap_mgr,
             files: map![],
             pool: ThreadPool::new_with_name(thd_name!("snap sender"), DEFAULT_SENDER_POOL_SIZE),
             raft_router: r,
             ch: ch,
+            chunk_size: chunk_size,
+            legacy_addrs: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -137,10 +252,21 @@ impl<R: RaftStoreRouter + 'static> Runner<R> {
 impl<R: RaftStoreRouter + 'static> Runnable<Task> for Runner<R> {
     fn run(&mut self, task: Task) {
         match task {
-            Task::Register(token, meta) => {
+            Task::Register(token, meta, resume) => {
                 let mgr = self.snap_mgr.clone();
+                let chunk_size = self.chunk_size as u64;
                 match SnapKey::from_snap(meta.get_message().get_snapshot())
-                    .and_then(|key| mgr.rl().get_snap_file(&key, false).map(|r| (r, key))) {
+                    .and_then(|key| {
+                        let file_size = try!(snap_file_size(&meta));
+                        if !resume && !mgr.rl().has_registered(&key) {
+                            // the sender sends the whole file, drop the chunks
+                            // received before.
+                            try!(mgr.rl().open_snap_file(&key, false)).delete_partial();
+                        }
+                        mgr.rl()
+                            .get_resumable_snap_file(&key, file_size, chunk_size)
+                            .map(|r| (r, key))
+                    }) {
                     Ok((f, k)) => {
                         if f.exists() {
                             info!("file {} already exists, skip receiving.",
@@ -151,8 +277,22 @@ impl<R: RaftStoreRouter + 'static> Runnable<Task> for Runner<R> {
                             self.close(token);
                             return;
                         }
+                        if mgr.rl().has_registered(&k) {
+                            error!("snap {} is being received, skip token {:?}", k, token);
+                            self.close(token);
+                            return;
+                        }
+                        if f.written() > 0 {
+                            info!("resume receiving snap {} from offset {}", k, f.written());
+                        }
+                        if !mgr.wl().try_register(k.clone(), SnapEntry::Receiving) {
+                            // The sender retries it later, and resumes from the
+                            // chunks received so far.
+                            info!("too many snapshots are being received, queue {}", k);
+                            self.close(token);
+                            return;
+                        }
                         debug!("begin to receive snap {:?}", meta);
-                        mgr.wl().register(k.clone(), SnapEntry::Receiving);
                         self.files.insert(token, (f, meta));
                     }
                     Err(e) => error!("failed to create snap file for {:?}: {:?}", token, e),
@@ -198,8 +338,9 @@ impl<R: RaftStoreRouter + 'static> Runnable<Task> for Runner<R> {
             }
             Task::SendTo { addr, data, cb } => {
                 let mgr = self.snap_mgr.clone();
+                let legacy_addrs = self.legacy_addrs.clone();
                 self.pool.execute(move || {
-                    let res = send_snap(mgr, addr, data);
+                    let res = send_snap(mgr, addr, data, legacy_addrs);
                     if res.is_err() {
                         error!("failed to send snap to {}: {:?}", addr, res);
                     }
@@ -209,3 +350,58 @@ impl<R: RaftStoreRouter + 'static> Runnable<Task> for Runner<R> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::RwLock;
+    use std::thread;
+    use std::time::Duration;
+
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::{send_from, read_resume_offset, is_legacy_addr};
+
+    #[test]
+    fn test_send_from() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut r = Cursor::new(data.clone());
+        for &offset in &[0, 32, 100] {
+            let mut w = vec![];
+            let sent = send_from(&mut r, &mut w, offset).unwrap();
+            assert_eq!(sent, data.len() as u64 - offset);
+            assert_eq!(w, &data[offset as usize..]);
+        }
+        assert!(send_from(&mut r, &mut vec![], 101).is_err());
+    }
+
+    #[test]
+    fn test_read_resume_offset() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let legacy_addrs = RwLock::new(HashMap::new());
+        let handle = thread::spawn(move || {
+            // a new receiver replies the offset.
+            let (mut conn, _) = listener.accept().unwrap();
+            conn.write_u64::<BigEndian>(1024).unwrap();
+            // an old receiver never replies, and closes the connection.
+            let _ = listener.accept().unwrap();
+        });
+
+        let mut conn = TcpStream::connect(&addr).unwrap();
+        assert_eq!(read_resume_offset(&mut conn, addr, &legacy_addrs).unwrap(), 1024);
+        assert!(legacy_addrs.read().unwrap().is_empty());
+
+        let mut conn = TcpStream::connect(&addr).unwrap();
+        handle.join().unwrap();
+        assert!(read_resume_offset(&mut conn, addr, &legacy_addrs).is_err());
+        assert!(legacy_addrs.read().unwrap().contains_key(&addr));
+
+        // the mark expires, so resuming is tried again.
+        assert!(is_legacy_addr(&legacy_addrs, &addr, Duration::from_secs(60)));
+        assert!(!is_legacy_addr(&legacy_addrs, &addr, Duration::from_secs(0)));
+        assert!(legacy_addrs.read().unwrap().is_empty());
+    }
+}
@@ -110,6 +110,16 @@ impl ServerTransport {
 impl Transport for ServerTransport {
     fn send(&self, msg: RaftMessage) -> RaftStoreResult<()> {
         let to_store_id = msg.get_to_peer().get_store_id();
+        // Refuse to queue more when the server falls behind, so the peer
+        // reports the store unreachable and raft slows down replicating to
+        // it, instead of the messages being dropped when the channel is full.
+        if self.ch.is_near_full() {
+            metric_incr!("server.transport.near_full");
+            return Err(box_err!("notify channel is nearly full with {} messages, drop \
+                                 message to store {}",
+                                self.ch.pending(),
+                                to_store_id));
+        }
 
         let mut req = Message::new();
         req.set_msg_type(MessageType::Raft);
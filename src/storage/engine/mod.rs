@@ -91,8 +91,20 @@ pub trait Engine: Send + Sync + Debug {
 pub trait Snapshot: Send {
     fn get(&self, key: &Key) -> Result<Option<Value>>;
     fn get_cf(&self, cf: CfName, key: &Key) -> Result<Option<Value>>;
+
+    /// Get the values of `keys`, results are in the same order as `keys`.
+    fn multi_get(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        keys.iter().map(|k| self.get(k)).collect()
+    }
+
+    fn multi_get_cf(&self, cf: CfName, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        keys.iter().map(|k| self.get_cf(cf, k)).collect()
+    }
+
     #[allow(needless_lifetimes)]
     fn iter<'a>(&'a self) -> Result<Box<Cursor + 'a>>;
+    #[allow(needless_lifetimes)]
+    fn iter_cf<'a>(&'a self, cf: CfName) -> Result<Box<Cursor + 'a>>;
 }
 
 pub trait Cursor {
@@ -170,14 +182,31 @@ pub trait Cursor {
     /// This method assume the current position of cursor is
     /// around `key`, otherwise you should use `reverse_seek` instead.
     fn near_reverse_seek(&mut self, key: &Key) -> Result<bool> {
-        if !try!(self.near_seek(key)) && !self.seek_to_last() {
-            return Ok(false);
+        if !self.valid() {
+            return self.reverse_seek(key);
         }
-
-        while self.key() >= key.encoded().as_slice() && self.prev() {
+        let mut cnt = 0;
+        if self.key() >= key.encoded().as_slice() {
+            while self.key() >= key.encoded().as_slice() && self.prev() {
+                cnt += 1;
+                if cnt >= SEEK_BOUND {
+                    return self.reverse_seek(key);
+                }
+            }
+            return Ok(self.valid());
         }
 
-        Ok(self.valid())
+        // move to the first key >= `key`, the one before it is the target.
+        while self.key() < key.encoded().as_slice() && self.next() {
+            cnt += 1;
+            if cnt >= SEEK_BOUND {
+                return self.reverse_seek(key);
+            }
+        }
+        if !self.valid() {
+            return Ok(self.seek_to_last());
+        }
+        Ok(self.prev())
     }
 }
 
@@ -247,7 +276,10 @@ mod tests {
         test_batch(e.as_ref());
         test_seek(e.as_ref());
         test_near_seek(e.as_ref());
+        test_near_seek_bound(e.as_ref());
+        test_near_reverse_seek_bound(e.as_ref());
         test_cf(e.as_ref());
+        test_seek_to_first_last(e.as_ref());
         test_empty_write(e.as_ref());
     }
 
@@ -380,6 +412,63 @@ mod tests {
         must_delete(engine, b"z");
     }
 
+    fn test_near_seek_bound(engine: &Engine) {
+        let keys: Vec<Vec<u8>> = (0..SEEK_BOUND * 3)
+            .map(|i| format!("k{:04}", i).into_bytes())
+            .collect();
+        for k in &keys {
+            must_put(engine, k, b"v");
+        }
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut cursor = snapshot.iter().unwrap();
+        let mut seek_cursor = snapshot.iter().unwrap();
+        // hit-ahead: target is only a few steps ahead of current position.
+        // far-jump: target is beyond SEEK_BOUND, fall back to a full seek.
+        // back-jump: target is before current position.
+        for &i in &[0, 3, 4, SEEK_BOUND * 2 + 5, SEEK_BOUND - 1, 1] {
+            let key = make_key(&keys[i]);
+            assert!(cursor.near_seek(&key).unwrap());
+            assert!(seek_cursor.seek(&key).unwrap());
+            assert_eq!(cursor.key(), seek_cursor.key());
+            assert_eq!(cursor.key(), &*bytes::encode_bytes(&keys[i]));
+        }
+        for k in &keys {
+            must_delete(engine, k);
+        }
+    }
+
+    fn test_near_reverse_seek_bound(engine: &Engine) {
+        let keys: Vec<Vec<u8>> = (0..SEEK_BOUND * 3)
+            .map(|i| format!("k{:04}", i).into_bytes())
+            .collect();
+        for k in &keys {
+            must_put(engine, k, b"v");
+        }
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut cursor = snapshot.iter().unwrap();
+        let mut seek_cursor = snapshot.iter().unwrap();
+        // hit-behind: target is only a few steps behind current position.
+        // far-jump: target is beyond SEEK_BOUND, fall back to a full reverse seek.
+        // forward-jump: target is after current position.
+        for &i in &[SEEK_BOUND * 3 - 1, SEEK_BOUND * 3 - 4, SEEK_BOUND * 3 - 5, 5, 8,
+                    SEEK_BOUND * 2, 6] {
+            let key = make_key(&keys[i]);
+            assert!(cursor.near_reverse_seek(&key).unwrap());
+            assert!(seek_cursor.reverse_seek(&key).unwrap());
+            assert_eq!(cursor.key(), seek_cursor.key());
+            assert_eq!(cursor.key(), &*bytes::encode_bytes(&keys[i - 1]));
+        }
+        // there is nothing before the first key, everything is before a key
+        // after the last one.
+        assert!(!cursor.near_reverse_seek(&make_key(&keys[0])).unwrap());
+        let key = make_key(b"l");
+        assert!(cursor.near_reverse_seek(&key).unwrap());
+        assert_eq!(cursor.key(), &*bytes::encode_bytes(keys.last().unwrap()));
+        for k in &keys {
+            must_delete(engine, k);
+        }
+    }
+
     fn test_cf(engine: &Engine) {
         assert_none_cf(engine, "cf", b"key");
         must_put_cf(engine, "cf", b"key", b"value");
@@ -388,6 +477,47 @@ mod tests {
         assert_none_cf(engine, "cf", b"key");
     }
 
+    fn assert_seek_to_first_last(cursor: &mut Cursor,
+                                 first: (&[u8], &[u8]),
+                                 last: (&[u8], &[u8])) {
+        assert!(cursor.seek_to_first());
+        assert_eq!(cursor.key(), &*bytes::encode_bytes(first.0));
+        assert_eq!(cursor.value(), first.1);
+        assert!(!cursor.prev());
+        assert!(cursor.seek_to_last());
+        assert_eq!(cursor.key(), &*bytes::encode_bytes(last.0));
+        assert_eq!(cursor.value(), last.1);
+        assert!(!cursor.next());
+    }
+
+    fn test_seek_to_first_last(engine: &Engine) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        assert!(!snapshot.iter_cf("cf").unwrap().seek_to_first());
+        assert!(!snapshot.iter_cf("cf").unwrap().seek_to_last());
+
+        // default cf still holds `x` written by `test_get_put`.
+        let keys: &[&[u8]] = &[b"m", b"a", b"zz"];
+        for k in keys {
+            must_put(engine, k, b"v");
+        }
+        let cf_keys: &[&[u8]] = &[b"a", b"b\xff", b"b"];
+        for (i, k) in cf_keys.iter().enumerate() {
+            must_put_cf(engine, "cf", k, format!("{}", i).as_bytes());
+        }
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut cursor = snapshot.iter().unwrap();
+        assert_seek_to_first_last(cursor.as_mut(), (b"a", b"v"), (b"zz", b"v"));
+        let mut cursor = snapshot.iter_cf("cf").unwrap();
+        assert_seek_to_first_last(cursor.as_mut(), (b"a", b"0"), (b"b\xff", b"1"));
+
+        for k in keys {
+            must_delete(engine, k);
+        }
+        for k in cf_keys {
+            muest_delete_cf(engine, "cf", k);
+        }
+    }
+
     fn test_empty_write(engine: &Engine) {
         engine.write(&Context::new(), vec![]).unwrap();
     }
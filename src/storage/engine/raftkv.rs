@@ -258,10 +258,28 @@ impl Snapshot for RegionSnapshot {
         Ok(v.map(|v| v.to_vec()))
     }
 
+    fn multi_get(&self, keys: &[Key]) -> engine::Result<Vec<Option<Value>>> {
+        let keys: Vec<_> = keys.iter().map(|k| k.encoded().as_slice()).collect();
+        let vs = box_try!(self.multi_get_value(&keys));
+        Ok(vs.into_iter().map(|v| v.map(|v| v.to_vec())).collect())
+    }
+
+    fn multi_get_cf(&self, cf: CfName, keys: &[Key]) -> engine::Result<Vec<Option<Value>>> {
+        let keys: Vec<_> = keys.iter().map(|k| k.encoded().as_slice()).collect();
+        let vs = box_try!(self.multi_get_value_cf(cf, &keys));
+        Ok(vs.into_iter().map(|v| v.map(|v| v.to_vec())).collect())
+    }
+
     #[allow(needless_lifetimes)]
     fn iter<'b>(&'b self) -> engine::Result<Box<Cursor + 'b>> {
         Ok(box RegionSnapshot::iter(self))
     }
+
+    #[allow(needless_lifetimes)]
+    fn iter_cf<'b>(&'b self, cf: CfName) -> engine::Result<Box<Cursor + 'b>> {
+        let iter = box_try!(RegionSnapshot::iter_cf(self, cf));
+        Ok(box iter)
+    }
 }
 
 impl<'a> Cursor for RegionIterator<'a> {
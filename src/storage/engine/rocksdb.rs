@@ -148,11 +148,32 @@ impl Snapshot for RocksSnapshot {
         Ok(v.map(|v| v.to_vec()))
     }
 
+    fn multi_get(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        trace!("RocksSnapshot: multi_get {} keys", keys.len());
+        let keys: Vec<_> = keys.iter().map(|k| k.encoded().as_slice()).collect();
+        let vs = box_try!(self.multi_get_value(&keys));
+        Ok(vs.into_iter().map(|v| v.map(|v| v.to_vec())).collect())
+    }
+
+    fn multi_get_cf(&self, cf: CfName, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        trace!("RocksSnapshot: multi_get_cf {} {} keys", cf, keys.len());
+        let keys: Vec<_> = keys.iter().map(|k| k.encoded().as_slice()).collect();
+        let vs = box_try!(self.multi_get_value_cf(cf, &keys));
+        Ok(vs.into_iter().map(|v| v.map(|v| v.to_vec())).collect())
+    }
+
     #[allow(needless_lifetimes)]
     fn iter<'b>(&'b self) -> Result<Box<Cursor + 'b>> {
         trace!("RocksSnapshot: create iterator");
         Ok(box self.new_iterator())
     }
+
+    #[allow(needless_lifetimes)]
+    fn iter_cf<'b>(&'b self, cf: CfName) -> Result<Box<Cursor + 'b>> {
+        trace!("RocksSnapshot: create cf iterator {}", cf);
+        let iter = box_try!(self.new_iterator_cf(cf));
+        Ok(box iter)
+    }
 }
 
 impl<'a> Cursor for DBIterator<'a> {
@@ -201,6 +201,13 @@ impl Storage {
         Ok(())
     }
 
+    /// Rejects the batch commands whose keys are not sorted or unique.
+    pub fn set_strict_batch_keys(&self, strict: bool) {
+        if let Some(ref sched) = self.sched {
+            sched.set_strict_batch_keys(strict);
+        }
+    }
+
     pub fn get_engine(&self) -> Arc<Box<Engine>> {
         self.engine.clone()
     }
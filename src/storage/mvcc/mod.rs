@@ -15,7 +15,7 @@ mod meta;
 mod txn;
 
 pub use self::meta::FIRST_META_INDEX;
-pub use self::txn::{MvccTxn, MvccSnapshot, MvccCursor};
+pub use self::txn::{MvccTxn, MvccSnapshot, MvccCursor, MvccValue, SHORT_VALUE_MAX_LEN};
 use util::escape;
 
 quick_error! {
@@ -52,5 +52,13 @@ quick_error! {
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// The lock which blocks reading a key, see `Error::KeyIsLocked`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockInfo {
+    pub key: Vec<u8>,
+    pub primary: Vec<u8>,
+    pub ts: u64,
+}
+
 // Make sure meta version in tests could never catch up with key version(timestamp).
 pub const TEST_TS_BASE: u64 = 1000000;
@@ -12,13 +12,18 @@
 // limitations under the License.
 
 use std::fmt;
+use std::collections::{HashMap, HashSet};
 use protobuf::core::Message;
-use storage::{Key, Value, Mutation};
-use storage::engine::{Engine, Snapshot, Modify, Cursor, DEFAULT_CFNAME};
+use storage::{Key, Value, Mutation, CfName};
+use storage::engine::{Engine, Snapshot, Modify, Cursor, Callback, DEFAULT_CFNAME};
 use kvproto::mvccpb::{MetaLock, MetaLockType, MetaItem};
 use kvproto::kvrpcpb::Context;
 use super::meta::{Meta, FIRST_META_INDEX};
-use super::{Error, Result};
+use super::{Error, Result, LockInfo};
+
+/// Put values shorter than this are inlined into the lock and the meta item
+/// instead of being written under a separate data key.
+pub const SHORT_VALUE_MAX_LEN: usize = 64;
 
 fn meta_lock_type(mutation: &Mutation) -> MetaLockType {
     match *mutation {
@@ -34,6 +39,9 @@ pub struct MvccTxn<'a> {
     ctx: &'a Context,
     start_ts: u64,
     writes: Vec<Modify>,
+    // keys read by `get_for_update`, they are locked by this transaction.
+    for_update_keys: Vec<Key>,
+    short_value_max_len: usize,
 }
 
 impl<'a> fmt::Debug for MvccTxn<'a> {
@@ -54,18 +62,43 @@ impl<'a> MvccTxn<'a> {
             ctx: ctx,
             start_ts: start_ts,
             writes: vec![],
+            for_update_keys: vec![],
+            short_value_max_len: SHORT_VALUE_MAX_LEN,
         }
     }
 
+    /// Values shorter than `len` are inlined by `prewrite`, 0 disables inlining.
+    pub fn set_short_value_max_len(&mut self, len: usize) {
+        self.short_value_max_len = len;
+    }
+
     pub fn submit(&mut self) -> Result<()> {
         if self.writes.is_empty() {
             return Ok(());
         }
+        for (cf, bytes) in cf_write_bytes(&self.writes) {
+            metric_count!(&format!("storage.mvcc.write_bytes.{}", cf), bytes as i64);
+        }
         let batch = self.writes.drain(..).collect();
         try!(self.engine.write(self.ctx, batch));
         Ok(())
     }
 
+    /// Like `submit`, but returns once the writes are scheduled, `cb` is called
+    /// after the engine finishes writing them.
+    pub fn async_submit(&mut self, cb: Callback<()>) -> Result<()> {
+        if self.writes.is_empty() {
+            cb(Ok(()));
+            return Ok(());
+        }
+        for (cf, bytes) in cf_write_bytes(&self.writes) {
+            metric_count!(&format!("storage.mvcc.write_bytes.{}", cf), bytes as i64);
+        }
+        let batch = self.writes.drain(..).collect();
+        try!(self.engine.async_write(self.ctx, batch, cb));
+        Ok(())
+    }
+
     fn write_meta(&mut self, key: &Key, meta: &mut Meta) {
         if let Some((split_meta, index)) = meta.split() {
             let modify = Modify::Put(DEFAULT_CFNAME, key.append_ts(index), split_meta.to_bytes());
@@ -77,11 +110,18 @@ impl<'a> MvccTxn<'a> {
         self.writes.push(modify);
     }
 
-    fn lock_key(&mut self, key: Key, lock_type: MetaLockType, primary: Vec<u8>) {
+    fn lock_key(&mut self,
+                key: Key,
+                lock_type: MetaLockType,
+                primary: Vec<u8>,
+                short_value: Option<Value>) {
         let mut lock = MetaLock::new();
         lock.set_field_type(lock_type);
         lock.set_primary_key(primary);
         lock.set_start_ts(self.start_ts);
+        if let Some(v) = short_value {
+            lock.set_short_value(v);
+        }
 
         let mut b = vec![];
         lock.write_to_vec(&mut b).unwrap();
@@ -96,6 +136,24 @@ impl<'a> MvccTxn<'a> {
         self.snapshot.get(key)
     }
 
+    /// Read the key and lock it like a `Mutation::Lock`, so any concurrent
+    /// transaction writing or locking the key conflicts with this one.
+    ///
+    /// The keys must be committed or rolled back with the other keys of the
+    /// transaction, see `for_update_keys`.
+    pub fn get_for_update(&mut self, key: &Key, primary: &[u8]) -> Result<Option<Value>> {
+        let value = try!(self.get(key));
+        if !self.for_update_keys.iter().any(|k| k.encoded() == key.encoded()) {
+            try!(self.prewrite(Mutation::Lock(key.clone()), primary));
+            self.for_update_keys.push(key.clone());
+        }
+        Ok(value)
+    }
+
+    pub fn for_update_keys(&self) -> &[Key] {
+        &self.for_update_keys
+    }
+
     pub fn prewrite(&mut self, mutation: Mutation, primary: &[u8]) -> Result<()> {
         let key = mutation.key();
         let meta = try!(self.snapshot.load_meta(key, FIRST_META_INDEX));
@@ -115,12 +173,18 @@ impl<'a> MvccTxn<'a> {
                 });
             }
         }
-        self.lock_key(key.clone(), meta_lock_type(&mutation), primary.to_vec());
-
+        let lock_type = meta_lock_type(&mutation);
+        let mut short_value = None;
         if let Mutation::Put((_, ref value)) = mutation {
-            let value_key = key.append_ts(self.start_ts);
-            self.writes.push(Modify::Put(DEFAULT_CFNAME, value_key, value.clone()));
+            if value.len() < self.short_value_max_len {
+                // moved into the meta item on commit.
+                short_value = Some(value.clone());
+            } else {
+                let value_key = key.append_ts(self.start_ts);
+                self.writes.push(Modify::Put(DEFAULT_CFNAME, value_key, value.clone()));
+            }
         }
+        self.lock_key(key.clone(), lock_type, primary.to_vec(), short_value);
         Ok(())
     }
 
@@ -131,9 +195,25 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
+    /// Commit all the `keys` in the write buffer of the transaction, each
+    /// distinct key's meta is loaded and written only once even if the key
+    /// appears more than once.
+    pub fn commit_batch(&mut self, keys: &[Key], commit_ts: u64) -> Result<()> {
+        let mut committed = HashSet::with_capacity(keys.len());
+        for key in keys {
+            if !committed.insert(key.encoded()) {
+                continue;
+            }
+            let mut meta = try!(self.snapshot.load_meta(key, FIRST_META_INDEX));
+            try!(self.commit_impl(key, commit_ts, &mut meta));
+            self.write_meta(key, &mut meta);
+        }
+        Ok(())
+    }
+
     fn commit_impl(&mut self, key: &Key, commit_ts: u64, meta: &mut Meta) -> Result<()> {
-        let lock_type = match try!(self.snapshot.load_lock(key)) {
-            Some(ref lock) if lock.get_start_ts() == self.start_ts => lock.get_field_type(),
+        let mut lock = match try!(self.snapshot.load_lock(key)) {
+            Some(lock) if lock.get_start_ts() == self.start_ts => lock,
             _ => {
                 return match try!(self.snapshot.get_txn_commit_ts(key, meta, self.start_ts)) {
                     // Committed by concurrent transaction.
@@ -143,10 +223,13 @@ impl<'a> MvccTxn<'a> {
                 };
             }
         };
-        if lock_type == MetaLockType::ReadWrite {
+        if lock.get_field_type() == MetaLockType::ReadWrite {
             let mut item = MetaItem::new();
             item.set_start_ts(self.start_ts);
             item.set_commit_ts(commit_ts);
+            if lock.has_short_value() {
+                item.set_short_value(lock.take_short_value());
+            }
             meta.push_item(item);
         }
         self.unlock_key(key.clone());
@@ -167,16 +250,20 @@ impl<'a> MvccTxn<'a> {
 
     pub fn rollback(&mut self, key: &Key) -> Result<()> {
         let mut meta = try!(self.snapshot.load_meta(key, FIRST_META_INDEX));
-        try!(self.rollback_impl(key, &mut meta));
-        self.write_meta(key, &mut meta);
-        Ok(())
+        // rollback never changes the meta, so it's not written again.
+        self.rollback_impl(key, &mut meta)
     }
 
     fn rollback_impl(&mut self, key: &Key, meta: &mut Meta) -> Result<()> {
         match try!(self.snapshot.load_lock(key)) {
+            // Only drop the value written by our own prewrite, the key may be
+            // locked by a concurrent transaction that must not lose its intent.
             Some(ref lock) if lock.get_start_ts() == self.start_ts => {
-                let value_key = key.append_ts(lock.get_start_ts());
-                self.writes.push(Modify::Delete(DEFAULT_CFNAME, value_key));
+                // short values and locks have no value written under the data key.
+                if lock.get_field_type() == MetaLockType::ReadWrite && !lock.has_short_value() {
+                    let value_key = key.append_ts(self.start_ts);
+                    self.writes.push(Modify::Delete(DEFAULT_CFNAME, value_key));
+                }
             }
             _ => {
                 return match try!(self.snapshot.get_txn_commit_ts(key, meta, self.start_ts)) {
@@ -200,6 +287,47 @@ impl<'a> MvccTxn<'a> {
     }
 }
 
+// Sum the bytes of the keys and values written to each column family.
+fn cf_write_bytes(writes: &[Modify]) -> HashMap<CfName, usize> {
+    let mut bytes = HashMap::new();
+    for m in writes {
+        let (cf, size) = match *m {
+            Modify::Put(cf, ref k, ref v) => (cf, k.encoded().len() + v.len()),
+            Modify::Delete(cf, ref k) => (cf, k.encoded().len()),
+        };
+        *bytes.entry(cf).or_insert(0) += size;
+    }
+    bytes
+}
+
+/// Where the value of a committed version is.
+enum ValueRef {
+    // inlined in the meta item.
+    Short(Value),
+    // written under the data key.
+    Data(Key),
+}
+
+impl ValueRef {
+    fn new(key: &Key, item: &MetaItem) -> ValueRef {
+        if item.has_short_value() {
+            ValueRef::Short(item.get_short_value().to_vec())
+        } else {
+            ValueRef::Data(key.append_ts(item.get_start_ts()))
+        }
+    }
+}
+
+/// The version of a key visible at the start timestamp of a snapshot.
+#[derive(Debug, PartialEq)]
+pub enum MvccValue {
+    Value(Value),
+    // the latest visible version is a delete.
+    Deleted,
+    // no version is visible.
+    NotFound,
+}
+
 pub struct MvccSnapshot<'a> {
     snapshot: &'a Snapshot,
     start_ts: u64,
@@ -230,6 +358,21 @@ impl<'a> MvccSnapshot<'a> {
         }
     }
 
+    /// Get the lock of `key` which blocks reading it at `start_ts`, only the
+    /// lock is checked, the value is not read.
+    pub fn get_lock(&self, key: &Key) -> Result<Option<LockInfo>> {
+        match try!(self.load_lock(key)) {
+            Some(ref lock) if lock.get_start_ts() <= self.start_ts => {
+                Ok(Some(LockInfo {
+                    key: try!(key.raw()),
+                    primary: lock.get_primary_key().to_vec(),
+                    ts: lock.get_start_ts(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn load_meta(&self, key: &Key, index: u64) -> Result<Meta> {
         let meta = match try!(self.snapshot.get(&key.append_ts(index))) {
             Some(x) => try!(Meta::parse(&x)),
@@ -254,18 +397,103 @@ impl<'a> MvccSnapshot<'a> {
         self.get_impl(key, &meta, self.start_ts)
     }
 
+    /// Like `get`, but tells a deleted key from one never written before
+    /// `start_ts`.
+    pub fn get_with_tombstone(&self, key: &Key) -> Result<MvccValue> {
+        if let Some(lock) = try!(self.get_lock(key)) {
+            return Err(Error::KeyIsLocked {
+                key: lock.key,
+                primary: lock.primary,
+                ts: lock.ts,
+            });
+        }
+        let meta = try!(self.load_meta(key, FIRST_META_INDEX));
+        match try!(self.find_value(key, &meta, self.start_ts)) {
+            Some(ValueRef::Short(v)) => Ok(MvccValue::Value(v)),
+            // A delete commits a version without writing the data key.
+            Some(ValueRef::Data(data_key)) => {
+                match try!(self.snapshot.get(&data_key)) {
+                    Some(v) => Ok(MvccValue::Value(v)),
+                    None => Ok(MvccValue::Deleted),
+                }
+            }
+            None => Ok(MvccValue::NotFound),
+        }
+    }
+
+    /// Get the values of `keys`, results are in the same order as `keys`.
+    ///
+    /// Locks, first metas and values not inlined are each fetched with one
+    /// `multi_get` instead of being loaded key by key.
+    pub fn batch_get(&self, keys: &[Key]) -> Result<Vec<Result<Option<Value>>>> {
+        let locks = try!(self.snapshot.multi_get_cf("lock", keys));
+        let meta_keys: Vec<_> = keys.iter().map(|k| k.append_ts(FIRST_META_INDEX)).collect();
+        let metas = try!(self.snapshot.multi_get(&meta_keys));
+
+        let mut results = Vec::with_capacity(keys.len());
+        // positions in results and data keys of the values to fetch.
+        let (mut idxs, mut data_keys) = (vec![], vec![]);
+        for (i, (lock, meta)) in locks.into_iter().zip(metas).enumerate() {
+            match self.resolve_version(&keys[i], lock, meta) {
+                Ok(Some(ValueRef::Short(v))) => results.push(Ok(Some(v))),
+                Ok(Some(ValueRef::Data(data_key))) => {
+                    idxs.push(i);
+                    data_keys.push(data_key);
+                    results.push(Ok(None));
+                }
+                res => results.push(res.map(|_| None)),
+            }
+        }
+
+        let values = try!(self.snapshot.multi_get(&data_keys));
+        for (i, v) in idxs.into_iter().zip(values) {
+            results[i] = Ok(v);
+        }
+        Ok(results)
+    }
+
+    /// Check the lock and find the value of the version visible at `start_ts`.
+    fn resolve_version(&self,
+                       key: &Key,
+                       lock: Option<Value>,
+                       meta: Option<Value>)
+                       -> Result<Option<ValueRef>> {
+        if let Some(x) = lock {
+            let mut lock = MetaLock::new();
+            try!(lock.merge_from_bytes(&x));
+            if lock.get_start_ts() <= self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.get_primary_key().to_vec(),
+                    ts: lock.get_start_ts(),
+                });
+            }
+        }
+        let first_meta = match meta {
+            Some(x) => try!(Meta::parse(&x)),
+            None => Meta::new(),
+        };
+        self.find_value(key, &first_meta, self.start_ts)
+    }
+
     fn get_impl(&self, key: &Key, first_meta: &Meta, ts: u64) -> Result<Option<Value>> {
+        match try!(self.find_value(key, first_meta, ts)) {
+            Some(ValueRef::Short(v)) => Ok(Some(v)),
+            Some(ValueRef::Data(data_key)) => Ok(try!(self.snapshot.get(&data_key))),
+            None => Ok(None),
+        }
+    }
+
+    fn find_value(&self, key: &Key, first_meta: &Meta, ts: u64) -> Result<Option<ValueRef>> {
         // Find the latest write below our start timestamp.
         if let Some(x) = first_meta.iter_items().find(|x| x.get_commit_ts() <= ts) {
-            let data_key = key.append_ts(x.get_start_ts());
-            return Ok(try!(self.snapshot.get(&data_key)));
+            return Ok(Some(ValueRef::new(key, x)));
         }
         let mut next = first_meta.next_index();
         while let Some(x) = next {
             let meta = try!(self.load_meta(key, x));
             if let Some(x) = meta.iter_items().find(|x| x.get_commit_ts() <= ts) {
-                let data_key = key.append_ts(x.get_start_ts());
-                return Ok(try!(self.snapshot.get(&data_key)));
+                return Ok(Some(ValueRef::new(key, x)));
             }
             next = meta.next_index();
         }
@@ -304,6 +532,8 @@ pub struct MvccCursor<'a> {
     cursor: &'a mut Cursor,
     snapshot: &'a MvccSnapshot<'a>,
     start_ts: u64,
+    // the inlined value returned by the last `get`.
+    short_value: Option<Value>,
 }
 
 impl<'a> MvccCursor<'a> {
@@ -315,6 +545,7 @@ impl<'a> MvccCursor<'a> {
             cursor: cursor,
             snapshot: snapshot,
             start_ts: start_ts,
+            short_value: None,
         }
     }
 
@@ -338,21 +569,49 @@ impl<'a> MvccCursor<'a> {
                 });
             }
         }
-        match try!(self.get_version(key)) {
-            Some(ts) => {
-                let key = key.append_ts(ts);
-                self.cursor.get(&key).map_err(From::from)
+        let start_ts = self.start_ts;
+        let item = try!(self.find_item(key, |x| x.get_commit_ts() <= start_ts));
+        self.get_item_value(key, item)
+    }
+
+    /// Get the value of the newest version committed strictly before `ts`, like
+    /// the value right before the write committed at `ts`. Locks are ignored,
+    /// they can't affect a committed history.
+    pub fn get_older_than(&mut self, key: &Key, ts: u64) -> Result<Option<&[u8]>> {
+        let item = try!(self.find_item(key, |x| x.get_commit_ts() < ts));
+        self.get_item_value(key, item)
+    }
+
+    pub fn get_version(&mut self, key: &Key) -> Result<Option<u64>> {
+        let start_ts = self.start_ts;
+        let item = try!(self.find_item(key, |x| x.get_commit_ts() <= start_ts));
+        Ok(item.map(|x| x.get_start_ts()))
+    }
+
+    fn get_item_value(&mut self, key: &Key, item: Option<MetaItem>) -> Result<Option<&[u8]>> {
+        match item {
+            Some(item) => {
+                match ValueRef::new(key, &item) {
+                    ValueRef::Short(v) => {
+                        self.short_value = Some(v);
+                        Ok(self.short_value.as_ref().map(|v| v.as_slice()))
+                    }
+                    ValueRef::Data(data_key) => self.cursor.get(&data_key).map_err(From::from),
+                }
             }
             None => Ok(None),
         }
     }
 
-    pub fn get_version(&mut self, key: &Key) -> Result<Option<u64>> {
+    /// Walk the meta chain to find the latest write matching `pred`, the items
+    /// are ordered by commit ts descending.
+    fn find_item<F>(&mut self, key: &Key, pred: F) -> Result<Option<MetaItem>>
+        where F: Fn(&MetaItem) -> bool
+    {
         let mut meta = try!(self.load_meta(key, FIRST_META_INDEX));
         loop {
-            // Find the latest write below our start timestamp.
-            if let Some(x) = meta.iter_items().find(|x| x.get_commit_ts() <= self.start_ts) {
-                return Ok(Some(x.get_start_ts()));
+            if let Some(x) = meta.iter_items().find(|x| pred(x)) {
+                return Ok(Some(x.clone()));
             }
             meta = match meta.next_index() {
                 Some(x) => try!(self.load_meta(key, x)),
@@ -366,8 +625,10 @@ impl<'a> MvccCursor<'a> {
 #[cfg(test)]
 mod tests {
     use kvproto::kvrpcpb::Context;
-    use super::MvccTxn;
+    use super::{MvccTxn, MvccSnapshot, MvccCursor, MvccValue, SHORT_VALUE_MAX_LEN,
+                cf_write_bytes};
     use storage::{make_key, Mutation, DEFAULT_CFS};
+    use util::metric;
     use storage::engine::{self, Engine, Dsn, TEMP_DIR};
     use storage::mvcc::TEST_TS_BASE;
     use storage::mvcc::meta::META_SPLIT_SIZE;
@@ -403,6 +664,49 @@ mod tests {
         must_get_err(engine.as_ref(), b"y", 100);
     }
 
+    #[test]
+    fn test_mvcc_get_with_tombstone() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+
+        // never written.
+        must_get_with_tombstone(engine.as_ref(), b"x", 1, MvccValue::NotFound);
+
+        must_prewrite_put(engine.as_ref(), b"x", b"x5", b"x", 5);
+        must_commit(engine.as_ref(), b"x", 5, 10);
+        must_prewrite_put(engine.as_ref(), b"y", &long_value, b"y", 5);
+        must_commit(engine.as_ref(), b"y", 5, 10);
+        must_get_with_tombstone(engine.as_ref(), b"x", 7, MvccValue::NotFound);
+        must_get_with_tombstone(engine.as_ref(),
+                                b"x",
+                                13,
+                                MvccValue::Value(b"x5".to_vec()));
+        must_get_with_tombstone(engine.as_ref(),
+                                b"y",
+                                13,
+                                MvccValue::Value(long_value.clone()));
+
+        // written then deleted.
+        must_prewrite_delete(engine.as_ref(), b"x", b"x", 15);
+        must_commit(engine.as_ref(), b"x", 15, 20);
+        must_prewrite_delete(engine.as_ref(), b"y", b"y", 15);
+        must_commit(engine.as_ref(), b"y", 15, 20);
+        must_get_with_tombstone(engine.as_ref(),
+                                b"x",
+                                17,
+                                MvccValue::Value(b"x5".to_vec()));
+        must_get_with_tombstone(engine.as_ref(), b"x", 23, MvccValue::Deleted);
+        must_get_with_tombstone(engine.as_ref(), b"y", 23, MvccValue::Deleted);
+
+        // written again.
+        must_prewrite_put(engine.as_ref(), b"x", b"x25", b"x", 25);
+        must_commit(engine.as_ref(), b"x", 25, 30);
+        must_get_with_tombstone(engine.as_ref(),
+                                b"x",
+                                33,
+                                MvccValue::Value(b"x25".to_vec()));
+    }
+
     #[test]
     fn test_mvcc_txn_prewrite() {
         let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
@@ -444,6 +748,51 @@ mod tests {
         must_commit_err(engine.as_ref(), b"x", 5, 6);
     }
 
+    #[test]
+    fn test_mvcc_txn_commit_batch() {
+        let batch_engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let keys: Vec<_> = (0..50).map(|i| format!("k{:02}", i).into_bytes()).collect();
+        // both short values and long values.
+        let values: Vec<_> = (0..50).map(|i| vec![b'v'; i * 2]).collect();
+        let engines: Vec<&Engine> = vec![batch_engine.as_ref(), engine.as_ref()];
+        for e in &engines {
+            for (k, v) in keys.iter().zip(&values) {
+                must_prewrite_put(*e, k, v, &keys[0], 5);
+            }
+        }
+
+        let ctx = Context::new();
+        let snapshot = batch_engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(batch_engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(5));
+        let mut batch: Vec<_> = keys.iter().map(|k| make_key(k)).collect();
+        // a duplicated key is only committed once.
+        batch.push(make_key(&keys[0]));
+        txn.commit_batch(&batch, to_fake_ts(10)).unwrap();
+        txn.submit().unwrap();
+
+        for k in &keys {
+            must_commit(engine.as_ref(), k, 5, 10);
+        }
+
+        for e in &engines {
+            for (k, v) in keys.iter().zip(&values) {
+                must_get_none(*e, k, 7);
+                must_get(*e, k, 15, v);
+                // the lock is released.
+                must_prewrite_lock(*e, k, k, 20);
+                must_rollback(*e, k, 20);
+            }
+        }
+
+        // commit again is idempotent, a key not prewritten fails the batch.
+        let snapshot = batch_engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(batch_engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(5));
+        txn.commit_batch(&batch, to_fake_ts(10)).unwrap();
+        batch.push(make_key(b"x"));
+        assert!(txn.commit_batch(&batch, to_fake_ts(10)).is_err());
+    }
+
     #[test]
     fn test_mvcc_txn_commit_then_get() {
         let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
@@ -469,6 +818,22 @@ mod tests {
         must_get_none(engine.as_ref(), b"x", 20);
     }
 
+    #[test]
+    fn test_mvcc_txn_rollback_other_lock() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+
+        must_prewrite_put(engine.as_ref(), b"x", b"x5", b"x", 5);
+        must_prewrite_put(engine.as_ref(), b"y", b"y10", b"y", 10);
+        // txn 10 rolls back x, which is locked by txn 5.
+        must_rollback(engine.as_ref(), b"x", 10);
+        must_rollback(engine.as_ref(), b"y", 10);
+        must_get_none(engine.as_ref(), b"y", 20);
+        // x keeps its lock and value.
+        must_prewrite_lock_err(engine.as_ref(), b"x", b"x", 15);
+        must_commit(engine.as_ref(), b"x", 5, 15);
+        must_get(engine.as_ref(), b"x", 20, b"x5");
+    }
+
     #[test]
     fn test_mvcc_txn_rollback_err() {
         let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
@@ -478,6 +843,87 @@ mod tests {
         must_rollback_err(engine.as_ref(), b"x", 5);
     }
 
+    #[test]
+    fn test_mvcc_txn_short_value() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN];
+
+        must_prewrite_put(engine.as_ref(), b"x", b"x5", b"x", 5);
+        must_prewrite_put(engine.as_ref(), b"y", &long_value, b"x", 5);
+        // Only the long value is written under its data key.
+        assert_data_key(engine.as_ref(), b"x", 5, false);
+        assert_data_key(engine.as_ref(), b"y", 5, true);
+        must_commit(engine.as_ref(), b"x", 5, 10);
+        must_commit(engine.as_ref(), b"y", 5, 10);
+        must_get_none(engine.as_ref(), b"x", 7);
+        must_get(engine.as_ref(), b"x", 13, b"x5");
+        must_get(engine.as_ref(), b"y", 13, &long_value);
+
+        // Overwrite the long value with a short one.
+        must_prewrite_put(engine.as_ref(), b"y", b"y15", b"y", 15);
+        must_commit_then_get(engine.as_ref(), b"y", 15, 20, 25, b"y15");
+        must_get(engine.as_ref(), b"y", 17, &long_value);
+
+        // Inlining disabled.
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(30));
+        txn.set_short_value_max_len(0);
+        txn.prewrite(Mutation::Put((make_key(b"z"), b"z30".to_vec())), b"z").unwrap();
+        txn.submit().unwrap();
+        assert_data_key(engine.as_ref(), b"z", 30, true);
+        must_commit(engine.as_ref(), b"z", 30, 35);
+        must_get(engine.as_ref(), b"z", 40, b"z30");
+    }
+
+    #[test]
+    fn test_mvcc_txn_cf_write_bytes() {
+        metric::test::init();
+        let lock_counter = "storage.mvcc.write_bytes.lock";
+        let last_lock_bytes = metric::test::counter(lock_counter);
+
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let ctx = Context::new();
+        let (mut lock_bytes, mut default_bytes) = (0, 0);
+        // a prewrite and rollback storm only churns the lock cf.
+        for ts in 1..101 {
+            for is_rollback in vec![false, true] {
+                let snapshot = engine.snapshot(&ctx).unwrap();
+                let mut txn =
+                    MvccTxn::new(engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(ts));
+                if is_rollback {
+                    txn.rollback(&make_key(b"x")).unwrap();
+                } else {
+                    txn.prewrite(Mutation::Put((make_key(b"x"), b"v".to_vec())), b"x").unwrap();
+                }
+                let bytes = cf_write_bytes(&txn.writes);
+                lock_bytes += *bytes.get("lock").unwrap();
+                default_bytes += bytes.get("default").cloned().unwrap_or(0);
+                txn.submit().unwrap();
+            }
+        }
+        assert!(lock_bytes > 100 * 2 * make_key(b"x").encoded().len(),
+                "{}",
+                lock_bytes);
+        assert_eq!(default_bytes, 0);
+        // other tests may write to the lock cf concurrently.
+        assert!(metric::test::counter(lock_counter) >= last_lock_bytes + lock_bytes as i64);
+
+        // the value of a long put is written to the default cf and dropped by rollback.
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN];
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(200));
+        txn.prewrite(Mutation::Put((make_key(b"y"), long_value.clone())), b"y").unwrap();
+        assert!(cf_write_bytes(&txn.writes)["default"] > long_value.len());
+        txn.submit().unwrap();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(200));
+        txn.rollback(&make_key(b"y")).unwrap();
+        assert!(cf_write_bytes(&txn.writes).contains_key("default"));
+        txn.submit().unwrap();
+        assert_data_key(engine.as_ref(), b"y", 200, false);
+    }
+
     #[test]
     fn test_mvcc_txn_rollback_then_get() {
         let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
@@ -501,6 +947,40 @@ mod tests {
         must_commit(engine.as_ref(), b"x", 10, 15);
     }
 
+    #[test]
+    fn test_mvcc_txn_get_for_update() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        must_prewrite_put(engine.as_ref(), b"x", b"x5", b"x", 5);
+        must_commit(engine.as_ref(), b"x", 5, 10);
+
+        let ctx = Context::new();
+        let key = make_key(b"x");
+        {
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = MvccTxn::new(engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(15));
+            assert_eq!(txn.get_for_update(&key, b"x").unwrap().unwrap(), b"x5");
+            // read again in the same txn.
+            assert_eq!(txn.get_for_update(&key, b"x").unwrap().unwrap(), b"x5");
+            assert_eq!(txn.for_update_keys().len(), 1);
+            txn.submit().unwrap();
+        }
+        {
+            // the key is locked by the txn started at 15.
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = MvccTxn::new(engine.as_ref(), snapshot.as_ref(), &ctx, to_fake_ts(16));
+            assert!(txn.get_for_update(&key, b"x").is_err());
+            assert!(txn.for_update_keys().is_empty());
+            txn.submit().unwrap();
+        }
+        must_prewrite_lock_err(engine.as_ref(), b"x", b"x", 17);
+
+        // only the first txn can commit.
+        must_commit_err(engine.as_ref(), b"x", 16, 20);
+        must_commit(engine.as_ref(), b"x", 15, 20);
+        must_get(engine.as_ref(), b"x", 25, b"x5");
+        must_prewrite_put(engine.as_ref(), b"x", b"x25", b"x", 25);
+    }
+
     fn to_fake_ts(ts: u64) -> u64 {
         TEST_TS_BASE + ts
     }
@@ -517,6 +997,63 @@ mod tests {
         must_get_none(engine.as_ref(), b"x", 5);
     }
 
+    #[test]
+    fn test_mvcc_cursor_get_older_than() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+        must_prewrite_put(engine.as_ref(), b"x", b"v1", b"x", 5);
+        must_commit(engine.as_ref(), b"x", 5, 10);
+        must_prewrite_put(engine.as_ref(), b"x", &long_value, b"x", 15);
+        must_commit(engine.as_ref(), b"x", 15, 20);
+        must_prewrite_delete(engine.as_ref(), b"x", b"x", 25);
+        must_commit(engine.as_ref(), b"x", 25, 30);
+        must_prewrite_put(engine.as_ref(), b"x", b"v3", b"x", 35);
+        must_commit(engine.as_ref(), b"x", 35, 40);
+        // a pending lock doesn't matter.
+        must_prewrite_put(engine.as_ref(), b"x", b"v4", b"x", 45);
+
+        let cases: Vec<(u64, Option<&[u8]>)> = vec![
+            (10, None),
+            (11, Some(&b"v1"[..])),
+            (20, Some(&b"v1"[..])),
+            (21, Some(&long_value[..])),
+            (30, Some(&long_value[..])),
+            (31, None),
+            (40, None),
+            (41, Some(&b"v3"[..])),
+            (50, Some(&b"v3"[..])),
+        ];
+        for (ts, expect) in cases {
+            must_get_older_than(engine.as_ref(), b"x", ts, expect);
+        }
+
+        // the versions are spread over the meta chain.
+        for i in 1u64..300 {
+            let val = format!("y{}", i);
+            must_prewrite_put(engine.as_ref(), b"y", val.as_bytes(), b"y", 5 * i);
+            must_commit(engine.as_ref(), b"y", 5 * i, 5 * i + 1)
+        }
+        must_get_older_than(engine.as_ref(), b"y", 6, None);
+        must_get_older_than(engine.as_ref(), b"y", 7, Some(b"y1"));
+        must_get_older_than(engine.as_ref(), b"y", 5 * 100 + 1, Some(b"y99"));
+        must_get_older_than(engine.as_ref(), b"y", 5 * 300, Some(b"y299"));
+    }
+
+    fn must_get_older_than(engine: &Engine, key: &[u8], ts: u64, expect: Option<&[u8]>) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mvcc_snap = MvccSnapshot::new(snapshot.as_ref(), u64::max_value());
+        let mut cursor = snapshot.iter().unwrap();
+        let mut mvcc_cursor = MvccCursor::new(cursor.as_mut(), &mvcc_snap, u64::max_value());
+        let value = mvcc_cursor.get_older_than(&make_key(key), to_fake_ts(ts)).unwrap();
+        assert_eq!(value, expect, "older than {}", ts);
+    }
+
+    fn assert_data_key(engine: &Engine, key: &[u8], start_ts: u64, exist: bool) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let data_key = make_key(key).append_ts(to_fake_ts(start_ts));
+        assert_eq!(snapshot.get(&data_key).unwrap().is_some(), exist);
+    }
+
     fn must_get(engine: &Engine, key: &[u8], ts: u64, expect: &[u8]) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -531,6 +1068,13 @@ mod tests {
         assert!(txn.get(&make_key(key)).unwrap().is_none());
     }
 
+    fn must_get_with_tombstone(engine: &Engine, key: &[u8], ts: u64, expect: MvccValue) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let snapshot = MvccSnapshot::new(snapshot.as_ref(), to_fake_ts(ts));
+        assert_eq!(snapshot.get_with_tombstone(&make_key(key)).unwrap(), expect);
+    }
+
     fn must_get_err(engine: &Engine, key: &[u8], ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
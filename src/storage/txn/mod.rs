@@ -36,6 +36,10 @@ quick_error! {
             cause(err)
             description(err.description())
         }
+        InvalidBatchKeys(key: Vec<u8>) {
+            description("batch keys are not sorted or unique")
+            display("batch keys are not sorted or unique at {}", ::util::escape(key))
+        }
     }
 }
 
@@ -33,6 +33,10 @@ impl Scheduler {
         }
     }
 
+    pub fn set_strict_batch_keys(&self, strict: bool) {
+        self.store.set_strict_batch_keys(strict);
+    }
+
     pub fn exec(&self, cmd: Command) {
         let store = self.store.clone();
         self.pool.execute(move || handle_cmd(store, cmd));
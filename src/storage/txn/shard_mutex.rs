@@ -27,13 +27,36 @@ impl ShardMutex {
         }
     }
 
+    /// Locks the shards of all the keys.
+    ///
+    /// The shards are always locked in ascending order of their indices no
+    /// matter how the keys are ordered, so two callers locking overlapping
+    /// key sets can't deadlock.
     pub fn lock<H>(&self, keys: &[H]) -> Vec<MutexGuard<()>>
         where H: Hash
+    {
+        self.shard_indices(keys).into_iter().map(|i| self.mutex[i].lock().unwrap()).collect()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns how many different shards the keys are mapped to.
+    pub fn shard_count<H>(&self, keys: &[H]) -> usize
+        where H: Hash
+    {
+        self.shard_indices(keys).len()
+    }
+
+    // Get the sorted and deduplicated shard indices of the keys.
+    fn shard_indices<H>(&self, keys: &[H]) -> Vec<usize>
+        where H: Hash
     {
         let mut indices: Vec<usize> = keys.iter().map(|x| self.shard_index(x)).collect();
         indices.sort();
         indices.dedup();
-        indices.iter().map(|&i| self.mutex[i].lock().unwrap()).collect()
+        indices
     }
 
     fn shard_index<H>(&self, key: &H) -> usize
@@ -48,8 +71,9 @@ impl ShardMutex {
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use rand;
     use super::*;
 
@@ -76,4 +100,41 @@ mod tests {
             t.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_shard_mutex_lock_order() {
+        const LOOP_COUNT: usize = 10000;
+
+        let sm = Arc::new(ShardMutex::new(16));
+        let a = b"a".to_vec();
+        // the keys must be in different shards to make a deadlock possible.
+        let b = (0..)
+            .map(|i| format!("b{}", i).into_bytes())
+            .find(|k| sm.shard_index(k) != sm.shard_index(&a))
+            .unwrap();
+        assert_eq!(sm.shard_count(&[&a, &b]), 2);
+        assert_eq!(sm.shard_indices(&[&a, &b]), sm.shard_indices(&[&b, &a]));
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let mut children = vec![];
+        for keys in vec![vec![a.clone(), b.clone()], vec![b, a]] {
+            let (sm, done) = (sm.clone(), done.clone());
+            children.push(thread::spawn(move || {
+                for _ in 0..LOOP_COUNT {
+                    let _guard = sm.lock(&keys);
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+
+        // both threads complete instead of waiting for each other forever.
+        let timer = Instant::now();
+        while done.load(Ordering::SeqCst) < children.len() {
+            assert!(timer.elapsed() < Duration::from_secs(10), "deadlock");
+            thread::sleep(Duration::from_millis(10));
+        }
+        for t in children {
+            t.join().unwrap();
+        }
+    }
 }
@@ -12,32 +12,89 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::result;
+use std::boxed::FnBox;
 use kvproto::kvrpcpb::Context;
 use storage::{Key, Value, KvPair, Mutation};
 use storage::{Engine, Snapshot, Cursor};
-use storage::mvcc::{MvccTxn, MvccSnapshot, Error as MvccError, MvccCursor};
+use storage::mvcc::{MvccTxn, MvccSnapshot, Error as MvccError, MvccCursor, LockInfo};
 use super::shard_mutex::ShardMutex;
 use super::{Error, Result};
 
+pub type Callback = Box<FnBox(Result<()>) + Send>;
+
 pub struct TxnStore {
     engine: Arc<Box<Engine>>,
     shard_mutex: ShardMutex,
+    // If true, the keys of a batch must be sorted and unique.
+    strict_batch_keys: AtomicBool,
 }
 
 const SHARD_MUTEX_SIZE: usize = 256;
 
 impl TxnStore {
     pub fn new(engine: Arc<Box<Engine>>) -> TxnStore {
+        TxnStore::with_shard_count(engine, SHARD_MUTEX_SIZE)
+    }
+
+    pub fn with_shard_count(engine: Arc<Box<Engine>>, shard_count: usize) -> TxnStore {
+        assert!(shard_count > 0, "shard count should be greater than 0");
         TxnStore {
             engine: engine,
-            shard_mutex: ShardMutex::new(SHARD_MUTEX_SIZE),
+            shard_mutex: ShardMutex::new(shard_count),
+            strict_batch_keys: AtomicBool::new(false),
         }
     }
 
-    pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
+    /// Rejects the batches whose keys are not sorted or unique, they are most
+    /// likely built by a buggy client.
+    pub fn set_strict_batch_keys(&self, strict: bool) {
+        self.strict_batch_keys.store(strict, Ordering::Relaxed);
+    }
+
+    fn check_batch_keys<'a, I>(&self, keys: I) -> Result<()>
+        where I: IntoIterator<Item = &'a Key>
+    {
+        if !self.strict_batch_keys.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        check_sorted_unique(keys)
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shard_mutex.size()
+    }
+
+    /// Returns how many shards the keys will lock, for diagnosing contention.
+    pub fn key_shard_count(&self, keys: &[&Key]) -> usize {
+        self.shard_mutex.shard_count(keys)
+    }
+
+    /// Take one snapshot of the engine and run `f` against it at `start_ts`,
+    /// so multiple reads in `f` share the same snapshot.
+    pub fn with_snapshot<F, T>(&self, ctx: Context, start_ts: u64, f: F) -> Result<T>
+        where F: FnOnce(&SnapshotStore) -> Result<T>
+    {
         let snapshot = try!(self.engine.as_ref().as_ref().snapshot(&ctx));
         let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-        snap_store.get(key)
+        f(&snap_store)
+    }
+
+    pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
+        self.with_snapshot(ctx, start_ts, |snap_store| snap_store.get(key))
+    }
+
+    /// Read the version of `key` live at `read_ts`, a lock below `read_ts`
+    /// fails the read.
+    pub fn get_at(&self, ctx: Context, key: &Key, read_ts: u64) -> Result<Option<Value>> {
+        self.with_snapshot(ctx, read_ts, |snap_store| snap_store.get_at(key, read_ts))
+    }
+
+    /// Get the lock blocking reading `key` at `start_ts`, without reading
+    /// the value.
+    pub fn is_locked(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<LockInfo>> {
+        self.with_snapshot(ctx, start_ts, |snap_store| snap_store.is_locked(key))
     }
 
     pub fn batch_get(&self,
@@ -45,9 +102,15 @@ impl TxnStore {
                      keys: &[Key],
                      start_ts: u64)
                      -> Result<Vec<Result<Option<Value>>>> {
-        let snapshot = try!(self.engine.as_ref().as_ref().snapshot(&ctx));
-        let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-        snap_store.batch_get(keys)
+        self.with_snapshot(ctx, start_ts, |snap_store| snap_store.batch_get(keys))
+    }
+
+    pub fn batch_get_with_locks(&self,
+                                ctx: Context,
+                                keys: &[Key],
+                                start_ts: u64)
+                                -> Result<Vec<result::Result<Option<Value>, LockInfo>>> {
+        self.with_snapshot(ctx, start_ts, |snap_store| snap_store.batch_get_with_locks(keys))
     }
 
     pub fn scan(&self,
@@ -56,10 +119,23 @@ impl TxnStore {
                 limit: usize,
                 start_ts: u64)
                 -> Result<Vec<Result<KvPair>>> {
-        let snapshot = try!(self.engine.as_ref().as_ref().snapshot(&ctx));
-        let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-        let mut scanner = try!(snap_store.scanner());
-        scanner.scan(key, limit)
+        self.with_snapshot(ctx, start_ts, |snap_store| {
+            let mut scanner = try!(snap_store.scanner());
+            scanner.scan(key, limit)
+        })
+    }
+
+    /// Scan at most `limit` keys starting with `prefix` in ascending order.
+    pub fn scan_prefix(&self,
+                       ctx: Context,
+                       prefix: &[u8],
+                       limit: usize,
+                       start_ts: u64)
+                       -> Result<Vec<Result<KvPair>>> {
+        self.with_snapshot(ctx, start_ts, |snap_store| {
+            let mut scanner = try!(snap_store.scanner());
+            scanner.scan_prefix(prefix, limit)
+        })
     }
 
     pub fn reverse_scan(&self,
@@ -68,10 +144,10 @@ impl TxnStore {
                         limit: usize,
                         start_ts: u64)
                         -> Result<Vec<Result<KvPair>>> {
-        let snapshot = try!(self.engine.as_ref().as_ref().snapshot(&ctx));
-        let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-        let mut scanner = try!(snap_store.scanner());
-        scanner.reverse_scan(key, limit)
+        self.with_snapshot(ctx, start_ts, |snap_store| {
+            let mut scanner = try!(snap_store.scanner());
+            scanner.reverse_scan(key, limit)
+        })
     }
 
     pub fn prewrite(&self,
@@ -80,6 +156,7 @@ impl TxnStore {
                     primary: Vec<u8>,
                     start_ts: u64)
                     -> Result<Vec<Result<()>>> {
+        try!(self.check_batch_keys(mutations.iter().map(|m| m.key())));
         let _gurad = {
             let locked_keys: Vec<&Key> = mutations.iter().map(|x| x.key()).collect();
             self.shard_mutex.lock(&locked_keys)
@@ -91,7 +168,7 @@ impl TxnStore {
 
         let mut results = vec![];
         for m in mutations {
-            match txn.prewrite(m, &primary) {
+            match txn.prewrite(m, &primary).map_err(observe_mvcc_error) {
                 Ok(_) => results.push(Ok(())),
                 e @ Err(MvccError::KeyIsLocked { .. }) => results.push(e.map_err(Error::from)),
                 Err(e) => return Err(Error::from(e)),
@@ -107,19 +184,40 @@ impl TxnStore {
                   start_ts: u64,
                   commit_ts: u64)
                   -> Result<()> {
+        try!(self.check_batch_keys(&keys));
         let _guard = self.shard_mutex.lock(&keys);
 
         let engine = self.engine.as_ref().as_ref();
         let snapshot = try!(engine.snapshot(&ctx));
         let mut txn = MvccTxn::new(engine, snapshot.as_ref(), &ctx, start_ts);
 
-        for k in keys {
-            try!(txn.commit(&k, commit_ts));
-        }
+        try!(txn.commit_batch(&keys, commit_ts).map_err(observe_mvcc_error));
         try!(txn.submit());
         Ok(())
     }
 
+    /// Like `commit`, but doesn't wait for the writes, `callback` is called once
+    /// they are durable. The keys are unlatched after the writes are scheduled,
+    /// the engine applies the writes in the order they are scheduled.
+    pub fn async_commit(&self,
+                        ctx: Context,
+                        keys: Vec<Key>,
+                        start_ts: u64,
+                        commit_ts: u64,
+                        callback: Callback)
+                        -> Result<()> {
+        try!(self.check_batch_keys(&keys));
+        let _guard = self.shard_mutex.lock(&keys);
+
+        let engine = self.engine.as_ref().as_ref();
+        let snapshot = try!(engine.snapshot(&ctx));
+        let mut txn = MvccTxn::new(engine, snapshot.as_ref(), &ctx, start_ts);
+
+        try!(txn.commit_batch(&keys, commit_ts).map_err(observe_mvcc_error));
+        try!(txn.async_submit(box move |res| callback(res.map_err(Error::from))));
+        Ok(())
+    }
+
     pub fn commit_then_get(&self,
                            ctx: Context,
                            key: Key,
@@ -134,7 +232,7 @@ impl TxnStore {
         let mut txn = MvccTxn::new(engine, snapshot.as_ref(), &ctx, lock_ts);
 
 
-        let val = try!(txn.commit_then_get(&key, commit_ts, get_ts));
+        let val = try!(txn.commit_then_get(&key, commit_ts, get_ts).map_err(observe_mvcc_error));
         try!(txn.submit());
         Ok(val)
     }
@@ -146,12 +244,13 @@ impl TxnStore {
         let snapshot = try!(engine.snapshot(&ctx));
         let mut txn = MvccTxn::new(engine, snapshot.as_ref(), &ctx, start_ts);
 
-        try!(txn.rollback(&key));
+        try!(txn.rollback(&key).map_err(observe_mvcc_error));
         try!(txn.submit());
         Ok(())
     }
 
     pub fn rollback(&self, ctx: Context, keys: Vec<Key>, start_ts: u64) -> Result<()> {
+        try!(self.check_batch_keys(&keys));
         let _guard = self.shard_mutex.lock(&keys);
 
         let engine = self.engine.as_ref().as_ref();
@@ -159,7 +258,7 @@ impl TxnStore {
         let mut txn = MvccTxn::new(engine, snapshot.as_ref(), &ctx, start_ts);
 
         for k in keys {
-            try!(txn.rollback(&k));
+            try!(txn.rollback(&k).map_err(observe_mvcc_error));
         }
         try!(txn.submit());
         Ok(())
@@ -172,12 +271,38 @@ impl TxnStore {
         let snapshot = try!(engine.snapshot(&ctx));
         let mut txn = MvccTxn::new(engine, snapshot.as_ref(), &ctx, lock_ts);
 
-        let val = try!(txn.rollback_then_get(&key));
+        let val = try!(txn.rollback_then_get(&key).map_err(observe_mvcc_error));
         try!(txn.submit());
         Ok(val)
     }
 }
 
+// Records the conflict errors before they are converted.
+fn observe_mvcc_error(e: MvccError) -> MvccError {
+    match e {
+        MvccError::WriteConflict => metric_count!("storage.txn.write_conflict", 1),
+        MvccError::KeyIsLocked { .. } => metric_count!("storage.txn.key_is_locked", 1),
+        MvccError::TxnLockNotFound => metric_count!("storage.txn.txn_lock_not_found", 1),
+        _ => {}
+    }
+    e
+}
+
+fn check_sorted_unique<'a, I>(keys: I) -> Result<()>
+    where I: IntoIterator<Item = &'a Key>
+{
+    let mut last: Option<&Key> = None;
+    for key in keys {
+        if let Some(last) = last {
+            if last.encoded() >= key.encoded() {
+                return Err(Error::InvalidBatchKeys(key.encoded().clone()));
+            }
+        }
+        last = Some(key);
+    }
+    Ok(())
+}
+
 pub struct SnapshotStore<'a> {
     snapshot: &'a Snapshot,
     start_ts: u64,
@@ -192,17 +317,50 @@ impl<'a> SnapshotStore<'a> {
     }
 
     pub fn get(&self, key: &Key) -> Result<Option<Value>> {
-        let txn = MvccSnapshot::new(self.snapshot, self.start_ts);
+        self.get_at(key, self.start_ts)
+    }
+
+    /// Like `get`, but reads as of `read_ts` instead of the store's `start_ts`.
+    pub fn get_at(&self, key: &Key, read_ts: u64) -> Result<Option<Value>> {
+        let txn = MvccSnapshot::new(self.snapshot, read_ts);
         Ok(try!(txn.get(key)))
     }
 
+    /// Get the lock blocking reading `key`, without reading the value.
+    pub fn is_locked(&self, key: &Key) -> Result<Option<LockInfo>> {
+        let txn = MvccSnapshot::new(self.snapshot, self.start_ts);
+        Ok(try!(txn.get_lock(key)))
+    }
+
     pub fn batch_get(&self, keys: &[Key]) -> Result<Vec<Result<Option<Value>>>> {
         let txn = MvccSnapshot::new(self.snapshot, self.start_ts);
-        let mut results = Vec::with_capacity(keys.len());
-        for k in keys {
-            results.push(txn.get(k).map_err(Error::from));
+        let results = try!(txn.batch_get(keys));
+        Ok(results.into_iter().map(|r| r.map_err(Error::from)).collect())
+    }
+
+    /// Like `batch_get`, but a locked key yields the lock instead of an error,
+    /// so the caller can resolve it and retry only the locked keys. Any other
+    /// error fails the whole batch.
+    pub fn batch_get_with_locks(&self,
+                                keys: &[Key])
+                                -> Result<Vec<result::Result<Option<Value>, LockInfo>>> {
+        let txn = MvccSnapshot::new(self.snapshot, self.start_ts);
+        let results = try!(txn.batch_get(keys));
+        let mut res = Vec::with_capacity(results.len());
+        for r in results {
+            match r {
+                Ok(v) => res.push(Ok(v)),
+                Err(MvccError::KeyIsLocked { key, primary, ts }) => {
+                    res.push(Err(LockInfo {
+                        key: key,
+                        primary: primary,
+                        ts: ts,
+                    }))
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
-        Ok(results)
+        Ok(res)
     }
 
     pub fn scanner(&self) -> Result<StoreScanner> {
@@ -224,7 +382,9 @@ pub struct StoreScanner<'a> {
 impl<'a> StoreScanner<'a> {
     pub fn seek(&mut self, mut key: Key) -> Result<Option<(Key, Value)>> {
         loop {
-            if !try!(self.cursor.seek(&key)) {
+            // Scans are mostly sequential, so the cursor is usually right
+            // before `key`, near_seek avoids a full seek in that case.
+            if !try!(self.cursor.near_seek(&key)) {
                 return Ok(None);
             }
             key = try!(Key::from_encoded(self.cursor.key().to_vec()).truncate_ts());
@@ -241,7 +401,8 @@ impl<'a> StoreScanner<'a> {
 
     pub fn reverse_seek(&mut self, mut key: Key) -> Result<Option<(Key, Value)>> {
         loop {
-            if !try!(self.cursor.reverse_seek(&key)) {
+            // like `seek`, the cursor is usually right after `key`.
+            if !try!(self.cursor.near_reverse_seek(&key)) {
                 return Ok(None);
             }
             key = try!(Key::from_encoded(self.cursor.key().to_vec()).truncate_ts());
@@ -286,6 +447,70 @@ impl<'a> StoreScanner<'a> {
         Ok(results)
     }
 
+    /// Scan at most `limit` keys in range [`key`, `end`), keys reaching `end`
+    /// are never returned.
+    pub fn scan_in_range(&mut self,
+                         mut key: Key,
+                         end: &Key,
+                         limit: usize)
+                         -> Result<Vec<Result<KvPair>>> {
+        let mut results = vec![];
+        while results.len() < limit {
+            match self.seek(key) {
+                Ok(Some((k, v))) => {
+                    if k.encoded() >= end.encoded() {
+                        break;
+                    }
+                    results.push(Ok((try!(k.raw()), v)));
+                    key = k;
+                }
+                Ok(None) => break,
+                Err(Error::Mvcc(e)) => {
+                    key = try!(StoreScanner::handle_mvcc_err(e, &mut results));
+                    if key.encoded() >= end.encoded() {
+                        // the locked key is out of range, drop its error.
+                        results.pop();
+                        break;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+            key = key.append_ts(u64::max_value());
+        }
+        Ok(results)
+    }
+
+    /// Scan at most `limit` keys starting with `prefix`, the scan stops at the
+    /// first key not starting with `prefix`.
+    pub fn scan_prefix(&mut self, prefix: &[u8], limit: usize) -> Result<Vec<Result<KvPair>>> {
+        let mut key = Key::from_raw(prefix);
+        let mut results = vec![];
+        while results.len() < limit {
+            match self.seek(key) {
+                Ok(Some((k, v))) => {
+                    let raw = try!(k.raw());
+                    if !raw.starts_with(prefix) {
+                        break;
+                    }
+                    results.push(Ok((raw, v)));
+                    key = k;
+                }
+                Ok(None) => break,
+                Err(Error::Mvcc(e)) => {
+                    key = try!(StoreScanner::handle_mvcc_err(e, &mut results));
+                    if !try!(key.raw()).starts_with(prefix) {
+                        // the locked key is out of the prefix, drop its error.
+                        results.pop();
+                        break;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+            key = key.append_ts(u64::max_value());
+        }
+        Ok(results)
+    }
+
     pub fn reverse_scan(&mut self, mut key: Key, limit: usize) -> Result<Vec<Result<KvPair>>> {
         let mut results = vec![];
         while results.len() < limit {
@@ -302,18 +527,74 @@ impl<'a> StoreScanner<'a> {
         Ok(results)
     }
 
-    pub fn get(&mut self, key: &Key, ts: u64) -> Result<Option<&[u8]>> {
-        self.cursor.get(&key.append_ts(ts)).map_err(From::from)
+    /// Reverse scan at most `limit` keys starting with `prefix`, the scan
+    /// stops at the first key not starting with `prefix`.
+    pub fn reverse_scan_prefix(&mut self,
+                               prefix: &[u8],
+                               limit: usize)
+                               -> Result<Vec<Result<KvPair>>> {
+        let mut key = match prefix_upper_bound(prefix) {
+            Some(k) => Key::from_raw(&k),
+            None => {
+                // every key behind the prefix starts with it, so scan from the last one.
+                if !self.cursor.seek_to_last() {
+                    return Ok(vec![]);
+                }
+                let mut k = self.cursor.key().to_vec();
+                k.push(0);
+                Key::from_encoded(k)
+            }
+        };
+        let mut results = vec![];
+        while results.len() < limit {
+            match self.reverse_seek(key) {
+                Ok(Some((k, v))) => {
+                    let raw = try!(k.raw());
+                    if !raw.starts_with(prefix) {
+                        break;
+                    }
+                    results.push(Ok((raw, v)));
+                    key = k;
+                }
+                Ok(None) => break,
+                Err(Error::Mvcc(e)) => {
+                    key = try!(StoreScanner::handle_mvcc_err(e, &mut results));
+                    if !try!(key.raw()).starts_with(prefix) {
+                        // the locked key is out of the prefix, drop its error.
+                        results.pop();
+                        break;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Returns the smallest key greater than all the keys starting with `prefix`,
+/// or None if there is no such key.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = prefix.to_vec();
+    while let Some(b) = bound.pop() {
+        if b < u8::max_value() {
+            bound.push(b + 1);
+            return Some(bound);
+        }
     }
+    None
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::mpsc::channel;
+
     use super::*;
     use kvproto::kvrpcpb::Context;
     use storage::{Mutation, Key, KvPair, make_key, DEFAULT_CFS};
     use storage::engine::{self, Dsn, TEMP_DIR};
-    use storage::mvcc::TEST_TS_BASE;
+    use storage::mvcc::{TEST_TS_BASE, LockInfo};
+    use util::metric;
 
     trait TxnStoreAssert {
         fn get_none(&self, key: &[u8], ts: u64);
@@ -530,6 +811,58 @@ mod tests {
         store.commit_then_get_ok(b"secondary", 5, 10, 12, b"s-5");
     }
 
+    #[test]
+    fn test_txn_store_conflict_metrics() {
+        metric::test::init();
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        // other tests may record these metrics concurrently, so only check increments.
+        let write_conflict = metric::test::counter("storage.txn.write_conflict");
+        store.put_ok(b"x", b"x5-10", 5, 10);
+        store.prewrite_err(vec![Mutation::Put((make_key(b"x"), b"x".to_vec()))], b"x", 8);
+        assert!(metric::test::counter("storage.txn.write_conflict") > write_conflict);
+
+        let key_is_locked = metric::test::counter("storage.txn.key_is_locked");
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"y"), b"y".to_vec()))], b"y", 20);
+        let res = store.prewrite(Context::new(),
+                                 vec![Mutation::Put((make_key(b"y"), b"y".to_vec()))],
+                                 b"y".to_vec(),
+                                 21)
+            .unwrap();
+        assert!(res[0].is_err());
+        assert!(metric::test::counter("storage.txn.key_is_locked") > key_is_locked);
+
+        let lock_not_found = metric::test::counter("storage.txn.txn_lock_not_found");
+        store.commit_err(vec![b"z"], 30, 31);
+        assert!(metric::test::counter("storage.txn.txn_lock_not_found") > lock_not_found);
+    }
+
+    #[test]
+    fn test_txn_store_shard_count() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+        assert_eq!(store.shard_count(), 256);
+
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::with_shard_count(Arc::new(engine), 1024);
+        assert_eq!(store.shard_count(), 1024);
+
+        let k = make_key(b"k");
+        assert_eq!(store.key_shard_count(&[&k, &k]), 1);
+
+        let mut independent = 0;
+        for i in 0..100 {
+            let k1 = make_key(format!("k1_{}", i).as_bytes());
+            let k2 = make_key(format!("k2_{}", i).as_bytes());
+            if store.key_shard_count(&[&k1, &k2]) == 2 {
+                independent += 1;
+            }
+        }
+        // the probability of two keys sharing a shard is 1/1024.
+        assert!(independent >= 95, "{}", independent);
+    }
+
     #[test]
     fn test_txn_store_scan() {
         let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
@@ -698,6 +1031,381 @@ mod tests {
         check_v40();
     }
 
+    #[test]
+    fn test_txn_store_scan_in_range() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"A", b"A10", 5, 10);
+        store.put_ok(b"B", b"B10", 5, 10);
+        store.put_ok(b"C", b"C10", 5, 10);
+        store.put_ok(b"E", b"E10", 5, 10);
+
+        let snapshot = store.engine.snapshot(&Context::new()).unwrap();
+        let snap_store = SnapshotStore::new(snapshot.as_ref(), 10);
+        let scan_in_range = |start: &[u8], end: &[u8], limit: usize| -> Vec<KvPair> {
+            let mut scanner = snap_store.scanner().unwrap();
+            scanner.scan_in_range(make_key(start), &make_key(end), limit)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+
+        let expect = |pairs: &[(&[u8], &[u8])]| -> Vec<KvPair> {
+            pairs.iter().map(|&(k, v)| (k.to_vec(), v.to_vec())).collect()
+        };
+
+        assert_eq!(scan_in_range(b"", b"C", 10),
+                   expect(&[(b"A", b"A10"), (b"B", b"B10")]));
+        assert_eq!(scan_in_range(b"B", b"D", 10),
+                   expect(&[(b"B", b"B10"), (b"C", b"C10")]));
+        assert_eq!(scan_in_range(b"A", b"F", 2),
+                   expect(&[(b"A", b"A10"), (b"B", b"B10")]));
+        assert_eq!(scan_in_range(b"C\x00", b"E", 10), vec![]);
+        assert_eq!(scan_in_range(b"A", b"A", 10), vec![]);
+
+        // a lock beyond the end bound should not be reported.
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"D"), b"D20".to_vec()))],
+                          b"D",
+                          8);
+        let snapshot = store.engine.snapshot(&Context::new()).unwrap();
+        let snap_store = SnapshotStore::new(snapshot.as_ref(), 10);
+        let mut scanner = snap_store.scanner().unwrap();
+        let res = scanner.scan_in_range(make_key(b"B"), &make_key(b"D"), 10).unwrap();
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|r| r.is_ok()));
+        let mut scanner = snap_store.scanner().unwrap();
+        let res = scanner.scan_in_range(make_key(b"B"), &make_key(b"E"), 10).unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res[2].is_err());
+    }
+
+    #[test]
+    fn test_txn_store_with_snapshot() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"A", b"A10", 5, 10);
+        store.put_ok(b"B", b"B10", 5, 10);
+        store.put_ok(b"A", b"A20", 15, 20);
+        store.delete_ok(b"B", 25, 30);
+
+        let keys: Vec<&[u8]> = vec![b"A", b"B", b"C"];
+        for &ts in &[5, 10, 20, 30] {
+            let values = store.with_snapshot(Context::new(), ts, |snap_store| {
+                    let mut values = vec![];
+                    for k in &keys {
+                        values.push(try!(snap_store.get(&make_key(k))));
+                    }
+                    let mut scanner = try!(snap_store.scanner());
+                    let pairs = try!(scanner.scan(make_key(b""), 10));
+                    Ok((values, pairs.len()))
+                })
+                .unwrap();
+            let expect: Vec<_> = keys.iter()
+                .map(|k| store.get(Context::new(), &make_key(k), ts).unwrap())
+                .collect();
+            assert_eq!(values.0, expect);
+            assert_eq!(values.1,
+                       store.scan(Context::new(), make_key(b""), 10, ts).unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_txn_store_is_locked() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        let key = make_key(b"A");
+        assert_eq!(store.is_locked(Context::new(), &key, 10).unwrap(), None);
+
+        store.prewrite_ok(vec![Mutation::Put((key.clone(), b"A10".to_vec()))], b"A", 5);
+        let lock = LockInfo {
+            key: b"A".to_vec(),
+            primary: b"A".to_vec(),
+            ts: 5,
+        };
+        assert_eq!(store.is_locked(Context::new(), &key, 10).unwrap(), Some(lock.clone()));
+        assert_eq!(store.is_locked(Context::new(), &key, 5).unwrap(), Some(lock));
+        // a lock after the read ts doesn't block it.
+        assert_eq!(store.is_locked(Context::new(), &key, 4).unwrap(), None);
+        assert_eq!(store.is_locked(Context::new(), &make_key(b"B"), 10).unwrap(), None);
+
+        store.commit_ok(vec![b"A"], 5, 10);
+        assert_eq!(store.is_locked(Context::new(), &key, 20).unwrap(), None);
+        store.get_ok(b"A", 20, b"A10");
+    }
+
+    #[test]
+    fn test_txn_store_async_commit() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"A"), b"A10".to_vec()))], b"A", 5);
+        let (tx, rx) = channel();
+        store.async_commit(Context::new(),
+                          vec![make_key(b"A")],
+                          5,
+                          10,
+                          box move |res| tx.send(res).unwrap())
+            .unwrap();
+        rx.recv().unwrap().unwrap();
+        store.get_ok(b"A", 20, b"A10");
+
+        // committing again is fine.
+        let (tx, rx) = channel();
+        store.async_commit(Context::new(),
+                          vec![make_key(b"A")],
+                          5,
+                          10,
+                          box move |res| tx.send(res).unwrap())
+            .unwrap();
+        rx.recv().unwrap().unwrap();
+
+        // a key not prewritten can't be committed, the error is returned directly.
+        let res = store.async_commit(Context::new(),
+                                     vec![make_key(b"B")],
+                                     5,
+                                     10,
+                                     box |_| panic!("callback should not be called"));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_txn_store_strict_batch_keys() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        let mutations = |keys: &[&[u8]]| -> Vec<Mutation> {
+            keys.iter().map(|k| Mutation::Put((make_key(k), k.to_vec()))).collect()
+        };
+        let unsorted: &[&[u8]] = &[b"B", b"A"];
+        let duplicated: &[&[u8]] = &[b"C", b"C"];
+
+        store.set_strict_batch_keys(true);
+        for keys in vec![unsorted, duplicated] {
+            match store.prewrite(Context::new(), mutations(keys), keys[0].to_vec(), 5) {
+                Err(Error::InvalidBatchKeys(_)) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+            let keys: Vec<Key> = keys.iter().map(|k| make_key(k)).collect();
+            match store.commit(Context::new(), keys.clone(), 5, 10) {
+                Err(Error::InvalidBatchKeys(_)) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+            match store.rollback(Context::new(), keys, 5) {
+                Err(Error::InvalidBatchKeys(_)) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        store.prewrite_ok(mutations(&[b"A", b"B"]), b"A", 5);
+        store.commit_ok(vec![b"A", b"B"], 5, 10);
+        store.get_ok(b"B", 20, b"B");
+
+        store.set_strict_batch_keys(false);
+        store.prewrite_ok(mutations(unsorted), b"B", 25);
+        store.commit_ok(unsorted.to_vec(), 25, 30);
+        store.get_ok(b"A", 40, b"A");
+    }
+
+    #[test]
+    fn test_txn_store_get_at() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"A", b"A10", 5, 10);
+        store.put_ok(b"A", b"A20", 15, 20);
+        store.delete_ok(b"A", 25, 30);
+        store.put_ok(b"A", b"A40", 35, 40);
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"A"), b"A60".to_vec()))], b"A", 50);
+
+        let key = make_key(b"A");
+        let expects: Vec<(u64, Option<&[u8]>)> = vec![(5, None),
+                                                      (10, Some(&b"A10"[..])),
+                                                      (15, Some(&b"A10"[..])),
+                                                      (20, Some(&b"A20"[..])),
+                                                      (30, None),
+                                                      (45, Some(&b"A40"[..]))];
+        // the store's own start_ts doesn't matter.
+        let snapshot = store.engine.snapshot(&Context::new()).unwrap();
+        let snap_store = SnapshotStore::new(snapshot.as_ref(), 1);
+        for (ts, expect) in expects {
+            let expect = expect.map(|v| v.to_vec());
+            assert_eq!(snap_store.get_at(&key, ts).unwrap(), expect);
+            assert_eq!(store.get_at(Context::new(), &key, ts).unwrap(), expect);
+        }
+
+        // the lock at 50 only blocks reads after it.
+        assert!(snap_store.get_at(&key, 55).is_err());
+        assert!(store.get_at(Context::new(), &key, 55).is_err());
+        assert!(snap_store.get(&key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_txn_store_batch_get() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"A", b"A10", 5, 10);
+        store.put_ok(b"B", b"B10", 5, 10);
+        store.put_ok(b"C", b"C10", 5, 10);
+        store.delete_ok(b"B", 15, 20);
+        for i in 0..50 {
+            store.put_ok(b"A", format!("A{}", 30 + i * 2).as_bytes(), 29 + i * 2, 30 + i * 2);
+        }
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"C"), b"C40".to_vec()))], b"C", 40);
+
+        // "A" twice to make sure duplicated keys are resolved independently.
+        let raw_keys: Vec<&[u8]> = vec![b"A", b"B", b"C", b"D", b"A"];
+        let keys: Vec<Key> = raw_keys.iter().map(|k| make_key(k)).collect();
+        for &ts in &[5, 10, 15, 20, 35, 50, 100, 200] {
+            let res = store.batch_get(Context::new(), &keys, ts).unwrap();
+            assert_eq!(res.len(), keys.len());
+            for (k, r) in keys.iter().zip(res) {
+                match store.get(Context::new(), k, ts) {
+                    Ok(v) => assert_eq!(r.unwrap(), v, "key {} at {}", k, ts),
+                    Err(_) => assert!(r.is_err(), "key {} at {}", k, ts),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_txn_store_batch_get_with_locks() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"A", b"A10", 5, 10);
+        store.put_ok(b"B", b"B10", 5, 10);
+        store.delete_ok(b"B", 15, 20);
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"C"), b"C30".to_vec())),
+                               Mutation::Lock(make_key(b"E"))],
+                          b"C",
+                          30);
+
+        let raw_keys: Vec<&[u8]> = vec![b"E", b"A", b"B", b"C", b"D"];
+        let keys: Vec<Key> = raw_keys.iter().map(|k| make_key(k)).collect();
+        let lock = |key: &[u8]| {
+            Err(LockInfo {
+                key: key.to_vec(),
+                primary: b"C".to_vec(),
+                ts: 30,
+            })
+        };
+        let res = store.batch_get_with_locks(Context::new(), &keys, 40).unwrap();
+        assert_eq!(res,
+                   vec![lock(b"E"), Ok(Some(b"A10".to_vec())), Ok(None), lock(b"C"), Ok(None)]);
+        // Locks after the read ts don't block it.
+        let res = store.batch_get_with_locks(Context::new(), &keys, 15).unwrap();
+        assert_eq!(res,
+                   vec![Ok(None), Ok(Some(b"A10".to_vec())), Ok(Some(b"B10".to_vec())), Ok(None),
+                        Ok(None)]);
+    }
+
+    #[test]
+    fn test_txn_store_scan_prefix() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"a", b"a", 5, 10);
+        store.put_ok(b"t1_r1", b"v11", 5, 10);
+        store.put_ok(b"t2_r1", b"v21", 5, 10);
+        store.put_ok(b"t1_r2", b"v12", 5, 10);
+        store.put_ok(b"t2_r2", b"v22", 5, 10);
+        store.put_ok(b"t1_r3", b"v13", 5, 10);
+        store.delete_ok(b"t1_r2", 15, 20);
+        store.put_ok(b"t1_r4", b"v14", 25, 30);
+
+        let scan_prefix = |prefix: &[u8], limit: usize, ts: u64| -> Vec<KvPair> {
+            store.scan_prefix(Context::new(), prefix, limit, ts)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+
+        // only the keys of the requested prefix visible at the ts are returned.
+        assert_eq!(scan_prefix(b"t1_", 10, 22),
+                   vec![(b"t1_r1".to_vec(), b"v11".to_vec()),
+                        (b"t1_r3".to_vec(), b"v13".to_vec())]);
+        assert_eq!(scan_prefix(b"t1_", 10, 12),
+                   vec![(b"t1_r1".to_vec(), b"v11".to_vec()),
+                        (b"t1_r2".to_vec(), b"v12".to_vec()),
+                        (b"t1_r3".to_vec(), b"v13".to_vec())]);
+        assert_eq!(scan_prefix(b"t1_", 2, 32),
+                   vec![(b"t1_r1".to_vec(), b"v11".to_vec()),
+                        (b"t1_r3".to_vec(), b"v13".to_vec())]);
+        assert_eq!(scan_prefix(b"t2_", 10, 32),
+                   vec![(b"t2_r1".to_vec(), b"v21".to_vec()),
+                        (b"t2_r2".to_vec(), b"v22".to_vec())]);
+        assert!(scan_prefix(b"t3_", 10, 32).is_empty());
+        assert_eq!(scan_prefix(b"", 10, 12).len(), 6);
+
+        // a lock out of the prefix should not be reported.
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"t2_r0"), b"v20".to_vec()))],
+                          b"t2_r0",
+                          35);
+        let res = store.scan_prefix(Context::new(), b"t1_", 10, 40).unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res.iter().all(|r| r.is_ok()));
+        let res = store.scan_prefix(Context::new(), b"t2_", 10, 40).unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res[0].is_err());
+    }
+
+    #[test]
+    fn test_txn_store_reverse_scan_prefix() {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+
+        store.put_ok(b"a1", b"a1", 5, 10);
+        store.put_ok(b"b1", b"b1", 5, 10);
+        store.put_ok(b"a2", b"a2", 5, 10);
+        store.put_ok(b"b2", b"b2", 5, 10);
+        store.put_ok(b"a3", b"a3", 5, 10);
+        store.put_ok(b"b3", b"b3", 5, 10);
+        store.put_ok(b"c1", b"c1", 5, 10);
+        store.put_ok(b"\xff\x01", b"ff", 5, 10);
+        store.delete_ok(b"a2", 15, 20);
+
+        let snapshot = store.engine.snapshot(&Context::new()).unwrap();
+        let snap_store = SnapshotStore::new(snapshot.as_ref(), 30);
+        let reverse_scan_prefix = |prefix: &[u8], limit: usize| -> Vec<Vec<u8>> {
+            let mut scanner = snap_store.scanner().unwrap();
+            scanner.reverse_scan_prefix(prefix, limit)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap().0)
+                .collect()
+        };
+
+        assert_eq!(reverse_scan_prefix(b"b", 10),
+                   vec![b"b3".to_vec(), b"b2".to_vec(), b"b1".to_vec()]);
+        assert_eq!(reverse_scan_prefix(b"b", 2),
+                   vec![b"b3".to_vec(), b"b2".to_vec()]);
+        assert_eq!(reverse_scan_prefix(b"a", 10), vec![b"a3".to_vec(), b"a1".to_vec()]);
+        assert_eq!(reverse_scan_prefix(b"c", 10), vec![b"c1".to_vec()]);
+        assert_eq!(reverse_scan_prefix(b"b4", 10), Vec::<Vec<u8>>::new());
+        assert_eq!(reverse_scan_prefix(b"d", 10), Vec::<Vec<u8>>::new());
+        assert_eq!(reverse_scan_prefix(b"\xff", 10), vec![b"\xff\x01".to_vec()]);
+
+        // a lock out of the prefix should not be reported.
+        store.prewrite_ok(vec![Mutation::Put((make_key(b"a4"), b"a4".to_vec()))],
+                          b"a4",
+                          25);
+        let snapshot = store.engine.snapshot(&Context::new()).unwrap();
+        let snap_store = SnapshotStore::new(snapshot.as_ref(), 30);
+        let mut scanner = snap_store.scanner().unwrap();
+        let res = scanner.reverse_scan_prefix(b"b", 10).unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res.iter().all(|r| r.is_ok()));
+        let mut scanner = snap_store.scanner().unwrap();
+        let res = scanner.reverse_scan_prefix(b"a", 10).unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res[0].is_err());
+    }
+
     use std::sync::{Arc, Mutex};
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::time::Duration;
@@ -882,6 +1590,73 @@ mod tests {
         });
     }
 
+    fn prepare_batch_get(n: usize) -> (TxnStore, Vec<Key>) {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+        let keys: Vec<_> = (0..n).map(format_key).collect();
+        for k in &keys {
+            store.put_ok(k, k, 5, 10);
+        }
+        (store, keys.iter().map(|k| make_key(k)).collect())
+    }
+
+    #[bench]
+    fn bench_txn_store_rocksdb_batch_get_x100(b: &mut Bencher) {
+        let (store, keys) = prepare_batch_get(100);
+
+        b.iter(|| {
+            store.batch_get(Context::new(), &keys, 20).unwrap();
+        });
+    }
+
+    #[bench]
+    fn bench_txn_store_rocksdb_get_x100(b: &mut Bencher) {
+        let (store, keys) = prepare_batch_get(100);
+
+        b.iter(|| {
+            for k in &keys {
+                store.get(Context::new(), k, 20).unwrap();
+            }
+        });
+    }
+
+    fn prewrite_x100(store: &TxnStore, oracle: &Oracle) -> (Vec<Key>, u64) {
+        let keys: Vec<_> = (0..100).map(|n| make_key(&format_key(n))).collect();
+        let mutations = keys.iter()
+            .map(|k| Mutation::Put((k.clone(), b"value".to_vec())))
+            .collect();
+        let start_ts = oracle.get_ts();
+        store.prewrite(Context::new(), mutations, b"key".to_vec(), start_ts).unwrap();
+        (keys, start_ts)
+    }
+
+    #[bench]
+    fn bench_txn_store_rocksdb_commit_x100(b: &mut Bencher) {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+        let oracle = Oracle::new();
+
+        b.iter(|| {
+            let (keys, start_ts) = prewrite_x100(&store, &oracle);
+            store.commit(Context::new(), keys, start_ts, oracle.get_ts()).unwrap();
+        });
+    }
+
+    #[bench]
+    fn bench_txn_store_rocksdb_commit_single_x100(b: &mut Bencher) {
+        let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
+        let store = TxnStore::new(Arc::new(engine));
+        let oracle = Oracle::new();
+
+        b.iter(|| {
+            let (keys, start_ts) = prewrite_x100(&store, &oracle);
+            let commit_ts = oracle.get_ts();
+            for k in keys {
+                store.commit(Context::new(), vec![k], start_ts, commit_ts).unwrap();
+            }
+        });
+    }
+
     #[bench]
     fn bench_txn_store_rocksdb_put_x100(b: &mut Bencher) {
         let engine = engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap();
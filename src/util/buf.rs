@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::io::{Result, Write};
+use std::cmp;
 use std::collections::VecDeque;
 
 use bytes::{ByteBuf, MutByteBuf, alloc};
@@ -54,6 +55,18 @@ impl SendBuffer {
         Ok(count)
     }
 
+    /// Returns the last `n` bytes, or all of them if there are fewer, as two
+    /// slices in order without consuming them, so a trailer can be peeked
+    /// before deciding how much to send.
+    pub fn tail(&self, n: usize) -> (&[u8], &[u8]) {
+        let (left, right) = self.buf.as_slices();
+        if n <= right.len() {
+            return (&right[..0], &right[right.len() - n..]);
+        }
+        let n = cmp::min(n - right.len(), left.len());
+        (&left[left.len() - n..], right)
+    }
+
     pub fn len(&self) -> usize {
         self.buf.len()
     }
@@ -100,4 +113,36 @@ mod tests {
         assert!(s.is_empty());
         assert_eq!(w, b"ab");
     }
+
+    // Where the buffer wraps depends on its capacity, so only the joined bytes
+    // of the tail are checked.
+    fn joined_tail(s: &SendBuffer, n: usize) -> Vec<u8> {
+        let (left, right) = s.tail(n);
+        let mut tail = left.to_vec();
+        tail.extend_from_slice(right);
+        tail
+    }
+
+    #[test]
+    fn test_send_buffer_tail() {
+        let mut s = SendBuffer::new(7);
+        assert_eq!(joined_tail(&s, 2), b"");
+
+        s.write(b"0123").unwrap();
+        assert_eq!(joined_tail(&s, 2), b"23");
+        assert_eq!(joined_tail(&s, 10), b"0123");
+
+        // make the buffer wrap around.
+        s.write(b"45").unwrap();
+        let mut out = [0; 4];
+        assert_eq!(s.send_to(&mut &mut out[..]).unwrap(), 4);
+        s.write(b"6789").unwrap();
+        assert_eq!(s.len(), 6);
+
+        assert_eq!(joined_tail(&s, 1), b"9");
+        assert_eq!(joined_tail(&s, 2), b"89");
+        assert_eq!(joined_tail(&s, 3), b"789");
+        assert_eq!(joined_tail(&s, 10), b"456789");
+        assert_eq!(s.len(), 6);
+    }
 }
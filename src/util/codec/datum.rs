@@ -21,7 +21,7 @@ use std::fmt::{self, Display, Formatter, Debug};
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use util::escape;
-use super::{number, Result, bytes, convert};
+use super::{number, Error, Result, bytes, convert};
 use super::mysql::{self, Duration, MAX_FSP, Decimal, DecimalEncoder, DecimalDecoder};
 
 pub const NIL_FLAG: u8 = 0;
@@ -364,7 +364,7 @@ impl Datum {
             (l, r) => return Err(invalid_type!("{:?} and {:?} can't be add together.", l, r)),
         };
         if let Datum::Null = res {
-            return Err(box_err!("overflow"));
+            return Err(Error::Overflow("add overflow".to_owned()));
         }
         Ok(res)
     }
@@ -61,6 +61,10 @@ quick_error! {
             description("invalid data type")
             display("{}", reason)
         }
+        Overflow(reason: String) {
+            description("arithmetic overflow")
+            display("{}", reason)
+        }
         Encoding(err: Utf8Error) {
             from()
             cause(err)
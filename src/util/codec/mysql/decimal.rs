@@ -16,7 +16,7 @@ use num::bigint::{Sign, BigUint, BigInt};
 use num::integer::Integer;
 use std::cmp::{self, Ordering};
 use std::io::Write;
-use std::ops::Add;
+use std::ops::{Add, Mul};
 use std::fmt::{self, Display, Formatter};
 use std::str::{self, FromStr};
 use std::{i32, u64};
@@ -44,6 +44,10 @@ pub struct Decimal {
 
 const MAX_FSP: usize = 30;
 
+/// Count of fraction digits a division result keeps beyond the dividend's,
+/// the same as MySQL's default `div_precision_increment`.
+pub const DIV_FRAC_INCR: usize = 4;
+
 macro_rules! enable_conv_for_int {
     ($t:ty) => {
         impl From<$t> for Decimal {
@@ -171,6 +175,37 @@ impl Decimal {
         })
     }
 
+    /// Divide the decimal by `rhs`.
+    ///
+    /// The quotient keeps `frac_incr` more fraction digits than `self`, the
+    /// extra digits are rounded half away from zero. Return None if `rhs` is zero.
+    pub fn checked_div(&self, rhs: &Decimal, frac_incr: usize) -> Option<Decimal> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let fsp = cmp::min(self.fsp + frac_incr, MAX_FSP);
+        let exp = -(fsp as i32);
+        // self / rhs = (self.coeff / rhs.coeff) * 10^(self.exp - rhs.exp), so scale
+        // one of the coefficients to make the quotient's exponent equal to `exp`.
+        let shift = self.exp - rhs.exp - exp;
+        let (dividend, divisor) = if shift >= 0 {
+            let factor = BigInt::from(num::pow(BigUint::from(10u8), shift as usize));
+            (self.coeff.clone() * factor, rhs.coeff.clone())
+        } else {
+            let factor = BigInt::from(num::pow(BigUint::from(10u8), -shift as usize));
+            (self.coeff.clone(), rhs.coeff.clone() * factor)
+        };
+        let (mut coeff, rem) = dividend.div_rem(&divisor);
+        if rem.abs() * BigInt::from(2) >= divisor.abs() {
+            if dividend.is_negative() == divisor.is_negative() {
+                coeff = coeff + BigInt::one();
+            } else {
+                coeff = coeff - BigInt::one();
+            }
+        }
+        Some(Decimal::new(coeff, exp, fsp).compact())
+    }
+
     /// Convert the decimal to float value.
     ///
     /// Please note that this convertion may lose precision.
@@ -402,6 +437,17 @@ impl Add<Decimal> for Decimal {
     }
 }
 
+impl Mul<Decimal> for Decimal {
+    type Output = Decimal;
+
+    fn mul(self, rhs: Decimal) -> Decimal {
+        // TODO: check overflow
+        let res = self.coeff.mul(&rhs.coeff);
+        let d = Decimal::new(res, self.exp + rhs.exp, self.fsp + rhs.fsp);
+        d.compact()
+    }
+}
+
 pub trait DecimalEncoder: BytesEncoder {
     /// Encode decimal to compareable bytes.
     ///
@@ -768,4 +814,44 @@ mod test {
             assert_eq!(res_str, exp.to_owned());
         }
     }
+
+    #[test]
+    fn test_decimal_mul() {
+        let cases = vec![
+            ("2", "3", "6"),
+            ("1.5", "1.5", "2.25"),
+            ("-1.5", "2", "-3.0"),
+            (".1", "-.1", "-0.01"),
+            ("2454495034", "3451204593", "8470964534836491162"),
+        ];
+        for (a, b, exp) in cases {
+            let lhs: Decimal = a.parse().unwrap();
+            let rhs: Decimal = b.parse().unwrap();
+            let res = lhs * rhs;
+            let res_str = format!("{}", res);
+            assert_eq!(res_str, exp.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_decimal_div() {
+        let cases = vec![
+            ("3", "2", Some("1.5000")),
+            ("8", "2", Some("4.0000")),
+            ("1", "3", Some("0.3333")),
+            ("2", "3", Some("0.6667")),
+            ("-2", "3", Some("-0.6667")),
+            ("2", "-3", Some("-0.6667")),
+            ("1.5", "3", Some("0.50000")),
+            ("100", "0.3", Some("333.3333")),
+            ("0", "7", Some("0.0000")),
+            ("1", "0", None),
+        ];
+        for (a, b, exp) in cases {
+            let lhs: Decimal = a.parse().unwrap();
+            let rhs: Decimal = b.parse().unwrap();
+            let res = lhs.checked_div(&rhs, DIV_FRAC_INCR).map(|d| format!("{}", d));
+            assert_eq!(res, exp.map(|s| s.to_owned()));
+        }
+    }
 }
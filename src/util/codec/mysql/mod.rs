@@ -16,5 +16,5 @@ mod decimal;
 pub mod types;
 
 pub use self::duration::{Duration, MAX_FSP, DEFAULT_FSP};
-pub use self::decimal::{Decimal, DecimalEncoder, DecimalDecoder, encoded_len};
+pub use self::decimal::{Decimal, DecimalEncoder, DecimalDecoder, DIV_FRAC_INCR, encoded_len};
 pub use self::types::{has_unsigned_flag, has_not_null_flag};
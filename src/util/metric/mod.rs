@@ -160,3 +160,8 @@ impl MetricSink for BufferedUdpMetricSink {
         self.append_to_buffer(metric)
     }
 }
+
+/// A metric client collecting all the metrics in memory. The integration
+/// tests include the same file from `tests/metric.rs`.
+#[cfg(test)]
+pub mod test;
@@ -0,0 +1,88 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A metric client collecting all the metrics in memory, so the tests can
+// check the metrics reported. The including module must provide
+// `set_metric_client`.
+
+use std::io;
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use cadence::{MetricSink, StatsdClient};
+
+use super::set_metric_client;
+
+const PREFIX: &'static str = "test";
+
+static INIT: Once = ONCE_INIT;
+static mut METRICS: Option<*const Mutex<Vec<String>>> = None;
+
+struct CollectSink;
+
+impl MetricSink for CollectSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        metrics().lock().unwrap().push(metric.to_owned());
+        Ok(metric.len())
+    }
+}
+
+fn metrics() -> &'static Mutex<Vec<String>> {
+    unsafe { &*METRICS.unwrap() }
+}
+
+/// Install the collecting client, the metric client can only be set once
+/// in a process, so all tests share it.
+pub fn init() {
+    INIT.call_once(|| {
+        unsafe {
+            METRICS = Some(Box::into_raw(box Mutex::new(vec![])));
+        }
+        let client = StatsdClient::from_sink(PREFIX, CollectSink);
+        set_metric_client(box client).unwrap();
+    });
+}
+
+/// Returns the sum of all the counts recorded for the key.
+pub fn counter(key: &str) -> i64 {
+    let prefix = format!("{}.{}:", PREFIX, key);
+    metrics()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|m| m.starts_with(&prefix) && m.ends_with("|c"))
+        .fold(0, |sum, m| sum + m[prefix.len()..m.len() - 2].parse::<i64>().unwrap())
+}
+
+/// Returns all the timings (ms) recorded for the key.
+pub fn timers(key: &str) -> Vec<u64> {
+    let prefix = format!("{}.{}:", PREFIX, key);
+    metrics()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|m| m.starts_with(&prefix) && m.ends_with("|ms"))
+        .map(|m| m[prefix.len()..m.len() - 3].parse().unwrap())
+        .collect()
+}
+
+/// Returns all the gauge values recorded for the key.
+pub fn gauges(key: &str) -> Vec<u64> {
+    let prefix = format!("{}.{}:", PREFIX, key);
+    metrics()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|m| m.starts_with(&prefix) && m.ends_with("|g"))
+        .map(|m| m[prefix.len()..m.len() - 2].parse().unwrap())
+        .collect()
+}
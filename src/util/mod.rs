@@ -337,7 +337,10 @@ mod tests {
     use std::net::{SocketAddr, AddrParseError};
     use std::time::Duration;
     use std::rc::Rc;
+    use std::thread;
+    use std::sync::{Arc, Mutex};
     use std::sync::atomic::{AtomicBool, Ordering};
+    use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
     use super::*;
 
     #[test]
@@ -374,6 +377,48 @@ mod tests {
         }
     }
 
+    // Captures the logs of the thread which sets it.
+    struct CaptureLogger {
+        thread: Option<String>,
+        logs: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _: &LogMetadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &LogRecord) {
+            if thread::current().name().map(From::from) == self.thread {
+                self.logs.lock().unwrap().push(format!("{}", record.args()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_slow_log() {
+        let logs = Arc::new(Mutex::new(vec![]));
+        let logs2 = logs.clone();
+        log::set_logger(move |filter| {
+                filter.set(LogLevelFilter::Warn);
+                Box::new(CaptureLogger {
+                    thread: thread::current().name().map(From::from),
+                    logs: logs2,
+                })
+            })
+            .unwrap();
+
+        let t = SlowTimer::from_millis(60 * 1000);
+        slow_log!(t, "fast");
+        assert!(logs.lock().unwrap().is_empty());
+
+        let t = SlowTimer::from_millis(0);
+        slow_log!(t, "slow");
+        let logs = logs.lock().unwrap();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].starts_with("slow"), "{:?}", logs);
+    }
+
     #[test]
     fn test_defer() {
         let should_panic = Rc::new(AtomicBool::new(true));
@@ -19,6 +19,21 @@ pub fn get_cf_handle<'a>(db: &'a DB, cf: &str) -> Result<&'a DBCFHandle, String>
         .ok_or_else(|| format!("cf {} not found.", cf))
 }
 
+/// Check that all the `cfs` have been opened in `db`, the error lists all
+/// the missing ones.
+pub fn check_cfs<S: AsRef<str>>(db: &DB, cfs: &[S]) -> Result<(), String> {
+    let missing: Vec<_> = cfs.iter()
+        .map(|cf| cf.as_ref())
+        .filter(|cf| db.cf_handle(cf).is_none())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!("cfs {:?} not found, the engine must be opened with {:?}",
+                missing,
+                cfs.iter().map(|cf| cf.as_ref()).collect::<Vec<_>>()))
+}
+
 pub fn new_engine(path: &str, cfs: &[&str]) -> Result<DB, String> {
     let opts = Options::new();
     new_engine_opt(opts, path, cfs)
@@ -1,8 +1,9 @@
 /// Worker contains all workers that do the expensive job in background.
 
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle, Builder};
+use std::time::{Duration, Instant};
 use std::io;
 use std::fmt::Display;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -81,6 +82,11 @@ impl<T: Display> Scheduler<T> {
     pub fn is_busy(&self) -> bool {
         self.counter.load(Ordering::SeqCst) > 0
     }
+
+    /// Get the number of tasks waiting to be handled.
+    pub fn pending_tasks(&self) -> usize {
+        self.counter.load(Ordering::SeqCst)
+    }
 }
 
 impl<T: Display> Clone for Scheduler<T> {
@@ -107,9 +113,14 @@ pub struct Worker<T: Display> {
     scheduler: Scheduler<T>,
     receiver: Option<Receiver<Option<T>>>,
     handle: Option<JoinHandle<()>>,
+    last_task_duration: Arc<Mutex<Option<Duration>>>,
 }
 
-fn poll<R, T>(mut runner: R, rx: Receiver<Option<T>>, counter: Arc<AtomicUsize>, batch_size: usize)
+fn poll<R, T>(mut runner: R,
+              rx: Receiver<Option<T>>,
+              counter: Arc<AtomicUsize>,
+              last_task_duration: Arc<Mutex<Option<Duration>>>,
+              batch_size: usize)
     where R: BatchRunnable<T> + Send + 'static,
           T: Display + Send + 'static
 {
@@ -132,7 +143,9 @@ fn poll<R, T>(mut runner: R, rx: Receiver<Option<T>>, counter: Arc<AtomicUsize>,
             }
         }
         counter.fetch_sub(buffer.len(), Ordering::SeqCst);
+        let timer = Instant::now();
         runner.run_batch(&mut buffer);
+        *last_task_duration.lock().unwrap() = Some(timer.elapsed());
         buffer.clear();
     }
 }
@@ -146,6 +159,7 @@ impl<T: Display + Send + 'static> Worker<T> {
             scheduler: Scheduler::new(AtomicUsize::new(0), tx),
             receiver: Some(rx),
             handle: None,
+            last_task_duration: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -165,9 +179,10 @@ impl<T: Display + Send + 'static> Worker<T> {
 
         let rx = self.receiver.take().unwrap();
         let counter = self.scheduler.counter.clone();
+        let last_task_duration = self.last_task_duration.clone();
         let h = try!(Builder::new()
             .name(thd_name!(self.name.clone()))
-            .spawn(move || poll(runner, rx, counter, batch_size)));
+            .spawn(move || poll(runner, rx, counter, last_task_duration, batch_size)));
         self.handle = Some(h);
         Ok(())
     }
@@ -189,6 +204,17 @@ impl<T: Display + Send + 'static> Worker<T> {
         self.handle.is_none() || self.scheduler.is_busy()
     }
 
+    /// Get the number of tasks waiting to be handled.
+    pub fn pending_tasks(&self) -> usize {
+        self.scheduler.pending_tasks()
+    }
+
+    /// Get how long the last task took to run, a batch of tasks is timed as a
+    /// whole. None if no task has run yet.
+    pub fn last_task_duration(&self) -> Option<Duration> {
+        *self.last_task_duration.lock().unwrap()
+    }
+
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
@@ -264,6 +290,48 @@ mod test {
         assert!(worker.is_busy());
     }
 
+    #[test]
+    fn test_pending_tasks() {
+        let mut worker = Worker::new("test-worker-pending");
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..5 {
+            worker.schedule(1).unwrap();
+        }
+        assert_eq!(worker.pending_tasks(), 5);
+        worker.start(CountRunner { count: count.clone() }).unwrap();
+        for _ in 0..100 {
+            if worker.pending_tasks() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(worker.pending_tasks(), 0);
+        worker.stop().unwrap().join().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_last_task_duration() {
+        let mut worker = Worker::new("test-worker-duration");
+        let count = Arc::new(AtomicUsize::new(0));
+        worker.schedule(1).unwrap();
+        worker.schedule(1).unwrap();
+        assert_eq!(worker.pending_tasks(), 2);
+        assert!(worker.last_task_duration().is_none());
+
+        worker.start(CountRunner { count: count.clone() }).unwrap();
+        for _ in 0..100 {
+            if count.load(Ordering::SeqCst) == 2 && worker.last_task_duration().is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(worker.pending_tasks(), 0);
+        // CountRunner sleeps 10ms for every task.
+        assert!(worker.last_task_duration().unwrap() >= Duration::from_millis(10));
+        worker.stop().unwrap().join().unwrap();
+    }
+
     #[test]
     fn test_threaded() {
         let mut worker = Worker::new("test-worker-threaded");
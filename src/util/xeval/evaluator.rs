@@ -22,7 +22,6 @@ use util::codec;
 
 use std::collections::HashMap;
 use std::cmp::Ordering;
-use std::ascii::AsciiExt;
 use tipb::expression::{Expr, ExprType};
 
 /// `Evaluator` evaluates `tipb::Expr`.
@@ -198,25 +197,9 @@ impl Evaluator {
         if Datum::Null == target || Datum::Null == pattern {
             return Ok(Datum::Null);
         }
-        let mut target_str = try!(target.into_string());
-        let mut pattern_str = try!(pattern.into_string());
-        if pattern_str.chars().any(|x| x.is_ascii() && x.is_alphabetic()) {
-            target_str = target_str.to_ascii_lowercase();
-            pattern_str = pattern_str.to_ascii_lowercase();
-        }
-        // for now, tidb ensures that pattern being pushed down must match ^%?[^\\_%]*%?$.
-        let len = pattern_str.len();
-        if pattern_str.starts_with('%') {
-            if pattern_str[1..].ends_with('%') {
-                Ok(target_str.contains(&pattern_str[1..len - 1]).into())
-            } else {
-                Ok(target_str.ends_with(&pattern_str[1..]).into())
-            }
-        } else if pattern_str.ends_with('%') {
-            Ok(target_str.starts_with(&pattern_str[..len - 1]).into())
-        } else {
-            Ok(target_str.eq(&pattern_str).into())
-        }
+        let target = try!(into_bytes(target));
+        let pattern = try!(into_bytes(pattern));
+        Ok(like_match(&target, &pattern).into())
     }
 
     fn eval_two_children_as_bool(&mut self, expr: &Expr) -> Result<(Option<bool>, Option<bool>)> {
@@ -299,6 +282,38 @@ fn check_in(target: Datum, value_list: &[Datum]) -> Result<bool> {
     Ok(pos.is_ok())
 }
 
+fn into_bytes(d: Datum) -> Result<Vec<u8>> {
+    match d {
+        Datum::Bytes(bs) => Ok(bs),
+        d => Ok(try!(d.into_string()).into_bytes()),
+    }
+}
+
+/// Check if `target` matches the LIKE `pattern` byte by byte, `%` matches any
+/// sequence of bytes and `_` matches exactly one byte.
+fn like_match(target: &[u8], pattern: &[u8]) -> bool {
+    let (mut t, mut p) = (0, 0);
+    // position of the last `%` in pattern and the target position it resumes from.
+    let mut backtrack = None;
+    while t < target.len() {
+        if p < pattern.len() && pattern[p] == b'%' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == b'_' || pattern[p] == target[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            // let the last `%` swallow one more byte and retry.
+            backtrack = Some((bp, bt + 1));
+            p = bp + 1;
+            t = bt + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'%')
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -485,16 +500,29 @@ mod test {
     test_eval!(test_eval_like,
                vec![
         (like_expr("a", ""), Datum::I64(0)),
+        (like_expr("", ""), Datum::I64(1)),
+        (like_expr("", "%"), Datum::I64(1)),
+        (like_expr("", "_"), Datum::I64(0)),
         (like_expr("a", "a"), Datum::I64(1)),
         (like_expr("a", "b"), Datum::I64(0)),
-        (like_expr("aAb", "AaB"), Datum::I64(1)),
+        (like_expr("aAb", "AaB"), Datum::I64(0)),
+        (like_expr("aAb", "aAb"), Datum::I64(1)),
         (like_expr("a", "%"), Datum::I64(1)),
-        (like_expr("aAD", "%d"), Datum::I64(1)),
+        (like_expr("aAD", "%D"), Datum::I64(1)),
+        (like_expr("aAD", "%d"), Datum::I64(0)),
         (like_expr("aAeD", "%e"), Datum::I64(0)),
-        (like_expr("aAb", "Aa%"), Datum::I64(1)),
-        (like_expr("abAb", "Aa%"), Datum::I64(0)),
-        (like_expr("aAcb", "%C%"), Datum::I64(1)),
-        (like_expr("aAb", "%C%"), Datum::I64(0)),
+        (like_expr("aAb", "aA%"), Datum::I64(1)),
+        (like_expr("abAb", "aA%"), Datum::I64(0)),
+        (like_expr("aAcb", "%c%"), Datum::I64(1)),
+        (like_expr("aAb", "%c%"), Datum::I64(0)),
+        (like_expr("abc", "a_c"), Datum::I64(1)),
+        (like_expr("abbc", "a_c"), Datum::I64(0)),
+        (like_expr("abc", "___"), Datum::I64(1)),
+        (like_expr("abc", "__"), Datum::I64(0)),
+        (like_expr("abcbd", "a%b_"), Datum::I64(1)),
+        (like_expr("abcbde", "a%b_"), Datum::I64(0)),
+        (like_expr("aXbXc", "%b%c"), Datum::I64(1)),
+        (like_expr("a%b", "a%%b"), Datum::I64(1)),
     ]);
 
     test_eval!(test_eval_arith,
@@ -9,7 +9,8 @@ use tikv::storage::engine::{self, Engine, TEMP_DIR};
 use tikv::storage::txn::TxnStore;
 use tikv::util::event::Event;
 use tikv::util::worker::Worker;
-use kvproto::coprocessor::{Request, KeyRange};
+use tikv::server::Config;
+use kvproto::coprocessor::{Request, Response, KeyRange};
 use tipb::select::{ByItem, SelectRequest, SelectResponse};
 use tipb::schema::{self, ColumnInfo};
 use tipb::expression::{Expr, ExprType};
@@ -17,7 +18,9 @@ use tipb::expression::{Expr, ExprType};
 use std::sync::Arc;
 use std::collections::{HashMap, BTreeMap};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::i64;
+use std::{i64, usize};
+use std::thread;
+use std::time::Duration;
 use protobuf::{RepeatedField, Message};
 
 static ID_GENERATOR: AtomicUsize = AtomicUsize::new(1);
@@ -214,6 +217,7 @@ struct Select<'a> {
     table: &'a Table,
     sel: SelectRequest,
     idx: i64,
+    range: Option<KeyRange>,
 }
 
 impl<'a> Select<'a> {
@@ -233,6 +237,7 @@ impl<'a> Select<'a> {
             table: table,
             sel: sel,
             idx: idx.map_or(0, |c| c.index),
+            range: None,
         }
     }
 
@@ -248,6 +253,35 @@ impl<'a> Select<'a> {
         self
     }
 
+    fn order_by(mut self, col: Column, desc: bool) -> Select<'a> {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ColumnRef);
+        expr.mut_val().encode_i64(col.id).unwrap();
+        let mut item = ByItem::new();
+        item.set_expr(expr);
+        item.set_desc(desc);
+        self.sel.mut_order_by().push(item);
+        self
+    }
+
+    /// Only scan the rows whose handles are between `start` and `end`, a bound
+    /// is included if its flag is true.
+    fn handle_range(mut self, start: (Datum, bool), end: (Datum, bool)) -> Select<'a> {
+        let table_id = self.table.id;
+        let encode = |handle: Datum| {
+            let mut buf = Vec::with_capacity(8);
+            buf.encode_i64(handle.i64()).unwrap();
+            table::encode_row_key(table_id, &buf)
+        };
+        let mut range = KeyRange::new();
+        range.set_start(encode(start.0));
+        range.set_start_exclusive(!start.1);
+        range.set_end(encode(end.0));
+        range.set_end_inclusive(end.1);
+        self.range = Some(range);
+        self
+    }
+
     fn count(mut self) -> Select<'a> {
         let mut expr = Expr::new();
         expr.set_tp(ExprType::Count);
@@ -278,6 +312,10 @@ impl<'a> Select<'a> {
         self.aggr_col(col, ExprType::Avg)
     }
 
+    fn variance(self, col: Column) -> Select<'a> {
+        self.aggr_col(col, ExprType::Variance)
+    }
+
     fn max(self, col: Column) -> Select<'a> {
         self.aggr_col(col, ExprType::Max)
     }
@@ -286,18 +324,43 @@ impl<'a> Select<'a> {
         self.aggr_col(col, ExprType::Min)
     }
 
+    fn where_expr(mut self, expr: Expr) -> Select<'a> {
+        self.sel.set_field_where(expr);
+        self
+    }
+
+    fn final_avg(mut self) -> Select<'a> {
+        self.sel.set_final_avg(true);
+        self
+    }
+
+    fn first_non_null(mut self) -> Select<'a> {
+        self.sel.set_first_non_null(true);
+        self
+    }
+
+    fn distinct(mut self) -> Select<'a> {
+        self.sel.set_distinct(true);
+        self
+    }
+
     fn group_by(mut self, cols: &[Column]) -> Select<'a> {
         for col in cols {
             let mut expr = Expr::new();
             expr.set_tp(ExprType::ColumnRef);
             expr.mut_val().encode_i64(col.id).unwrap();
-            let mut item = ByItem::new();
-            item.set_expr(expr);
-            self.sel.mut_group_by().push(item);
+            self = self.group_by_expr(expr);
         }
         self
     }
 
+    fn group_by_expr(mut self, expr: Expr) -> Select<'a> {
+        let mut item = ByItem::new();
+        item.set_expr(expr);
+        self.sel.mut_group_by().push(item);
+        self
+    }
+
     fn build(mut self) -> Request {
         let mut req = Request::new();
 
@@ -311,6 +374,11 @@ impl<'a> Select<'a> {
 
         req.set_data(self.sel.write_to_bytes().unwrap());
 
+        if let Some(range) = self.range.take() {
+            req.set_ranges(RepeatedField::from_vec(vec![range]));
+            return req;
+        }
+
         let mut range = KeyRange::new();
 
         let mut buf = Vec::with_capacity(8);
@@ -450,6 +518,22 @@ impl ProductTable {
 fn init_with_data(tbl: &ProductTable,
                   vals: &[(i64, Option<&str>, i64)])
                   -> (Store, Worker<RequestTask>) {
+    init_with_data_and_scan_limit(tbl, vals, usize::MAX)
+}
+
+fn init_with_data_and_scan_limit(tbl: &ProductTable,
+                                 vals: &[(i64, Option<&str>, i64)],
+                                 max_scan_keys: usize)
+                                 -> (Store, Worker<RequestTask>) {
+    let mut cfg = Config::new();
+    cfg.end_point_max_scan_keys = max_scan_keys;
+    init_with_data_and_cfg(tbl, vals, &cfg)
+}
+
+fn init_with_data_and_cfg(tbl: &ProductTable,
+                          vals: &[(i64, Option<&str>, i64)],
+                          cfg: &Config)
+                          -> (Store, Worker<RequestTask>) {
     let engine = Arc::new(engine::new_engine(Dsn::RocksDBPath(TEMP_DIR), DEFAULT_CFS).unwrap());
     let mut store = Store::new(engine.clone());
 
@@ -463,7 +547,7 @@ fn init_with_data(tbl: &ProductTable,
     }
     store.commit();
 
-    let runner = EndPointHost::new(engine);
+    let runner = EndPointHost::new(engine, cfg);
     let mut end_point = Worker::new("test select worker");
     end_point.start_batch(runner, 5).unwrap();
 
@@ -494,6 +578,56 @@ fn test_select() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_where_like() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:4"), 3),
+        (4, Some("Name:3"), 1),
+        (5, Some("name:13"), 4),
+        (6, Some("name_1"), 4),
+        (7, None, 4),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let like = |pattern: &str| {
+        let mut col_expr = Expr::new();
+        col_expr.set_tp(ExprType::ColumnRef);
+        col_expr.mut_val().encode_i64(product.name.id).unwrap();
+        let mut pattern_expr = Expr::new();
+        pattern_expr.set_tp(ExprType::Bytes);
+        pattern_expr.set_val(pattern.as_bytes().to_vec());
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::Like);
+        expr.mut_children().push(col_expr);
+        expr.mut_children().push(pattern_expr);
+        expr
+    };
+
+    let cases = vec![
+        ("name:%", vec![1, 2, 5]),
+        ("name_%", vec![1, 2, 5, 6]),
+        ("%:_", vec![1, 2, 4]),
+        ("name:1_", vec![5]),
+        // a literal pattern without wildcards only matches the exact value.
+        ("name:4", vec![2]),
+        ("name:", vec![]),
+    ];
+    for (pattern, exp_ids) in cases {
+        let req = Select::from(&product.table).where_expr(like(pattern)).build();
+        let resp = handle_select(&end_point, req);
+        let ids: Vec<i64> = resp.get_rows()
+            .iter()
+            .map(|row| row.get_handle().decode().unwrap()[0].i64())
+            .collect();
+        assert_eq!(ids, exp_ids, "pattern {}", pattern);
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_group_by() {
     let data = vec![
@@ -519,6 +653,84 @@ fn test_group_by() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_group_by_expr() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:2"), 1),
+        (4, Some("name:0"), 3),
+        (5, Some("name:1"), 2),
+        (6, Some("name:3"), 4),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let col_ref = |col: Column| {
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::ColumnRef);
+        expr.mut_val().encode_i64(col.id).unwrap();
+        expr
+    };
+    // group by id + count
+    let mut plus = Expr::new();
+    plus.set_tp(ExprType::Plus);
+    plus.mut_children().push(col_ref(product.id));
+    plus.mut_children().push(col_ref(product.count));
+
+    let exp = vec![(3, 2), (7, 2), (10, 1)];
+    let req = Select::from(&product.table).count().group_by_expr(plus).build();
+    let resp = handle_select(&end_point, req);
+    assert_eq!(resp.get_rows().len(), exp.len());
+    for (row, (sum, cnt)) in resp.get_rows().iter().zip(exp) {
+        let gk = datum::encode_value(&[Datum::I64(sum)]).unwrap();
+        let expected_datum = vec![Datum::Bytes(gk), Datum::U64(cnt)];
+        let expected_encoded = datum::encode_value(&expected_datum).unwrap();
+        assert_eq!(row.get_data(), &*expected_encoded);
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
+#[test]
+fn test_distinct() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:2"), 3),
+        (4, Some("name:0"), 1),
+        (5, Some("name:1"), 4),
+        (6, Some("name:2"), 4),
+        (7, None, 4),
+        (8, None, 5),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    // only the first row of every name is returned.
+    let exp = vec![data[0], data[1], data[3], data[5]];
+    let req = Select::from(&product.table).distinct().group_by(&[product.name]).build();
+    let resp = handle_select(&end_point, req);
+    assert_eq!(resp.get_rows().len(), exp.len());
+    for (row, &(id, name, cnt)) in resp.get_rows().iter().zip(&exp) {
+        let name_datum = name.map(|s| s.as_bytes()).into();
+        let expected_encoded = datum::encode_value(&[id.into(), name_datum, cnt.into()]).unwrap();
+        assert_eq!(row.get_data(), &*expected_encoded);
+    }
+
+    // limit counts the distinct rows.
+    let req = Select::from(&product.table).distinct().group_by(&[product.name]).limit(3).build();
+    let resp = handle_select(&end_point, req);
+    assert_eq!(resp.get_rows().len(), 3);
+    for (row, &(id, name, cnt)) in resp.get_rows().iter().zip(&exp) {
+        let name_datum = name.map(|s| s.as_bytes()).into();
+        let expected_encoded = datum::encode_value(&[id.into(), name_datum, cnt.into()]).unwrap();
+        assert_eq!(row.get_data(), &*expected_encoded);
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_aggr_count() {
     let data = vec![
@@ -576,6 +788,47 @@ fn test_aggr_count() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_aggr_count_where() {
+    let data: Vec<_> = (0..100)
+        .map(|i| (i, if i % 3 == 0 { None } else { Some("name:0") }, i % 7))
+        .collect();
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let gt = |v: i64| {
+        let mut col_expr = Expr::new();
+        col_expr.set_tp(ExprType::ColumnRef);
+        col_expr.mut_val().encode_i64(product.count.id).unwrap();
+        let mut val_expr = Expr::new();
+        val_expr.set_tp(ExprType::Int64);
+        val_expr.mut_val().encode_i64(v).unwrap();
+        let mut expr = Expr::new();
+        expr.set_tp(ExprType::GT);
+        expr.mut_children().push(col_expr);
+        expr.mut_children().push(val_expr);
+        expr
+    };
+
+    let gk = Datum::Bytes(coprocessor::SINGLE_GROUP.to_vec());
+    for v in vec![-1, 2, 5, 10] {
+        let req = Select::from(&product.table).where_expr(gt(v)).build();
+        let selected = handle_select(&end_point, req).get_rows().len();
+        assert_eq!(selected, data.iter().filter(|&&(_, _, c)| c > v).count());
+
+        // the count is returned as a single row even if nothing matches.
+        let req = Select::from(&product.table).count().where_expr(gt(v)).build();
+        let resp = handle_select(&end_point, req);
+        assert_eq!(resp.get_rows().len(), 1);
+        let expected_encoded = datum::encode_value(&[gk.clone(), Datum::U64(selected as u64)])
+            .unwrap();
+        assert_eq!(resp.get_rows()[0].get_data(), &*expected_encoded, "count > {}", v);
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_aggr_first() {
     let data = vec![
@@ -609,6 +862,50 @@ fn test_aggr_first() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_aggr_first_non_null() {
+    let data = vec![
+        (2, Some("name:0"), 2),
+        (4, Some("name:0"), 1),
+        (5, Some("name:5"), 4),
+    ];
+
+    let product = ProductTable::new();
+    let (mut store, mut end_point) = init_with_data(&product, &data);
+
+    store.begin();
+    for &(id, name) in &[(1, b"name:0"), (6, b"name:7")] {
+        store.insert_into(&product.table)
+            .set(product.id, Datum::I64(id))
+            .set(product.name, Datum::Bytes(name.to_vec()))
+            .set(product.count, Datum::Null)
+            .execute();
+    }
+    store.commit();
+
+    let names = vec![b"name:0", b"name:5", b"name:7"];
+    let cases = vec![
+        (false, vec![Datum::Null, Datum::I64(4), Datum::Null]),
+        (true, vec![Datum::I64(2), Datum::I64(4), Datum::Null]),
+    ];
+    for (first_non_null, exp) in cases {
+        let mut sel = Select::from(&product.table).first(product.count).group_by(&[product.name]);
+        if first_non_null {
+            sel = sel.first_non_null();
+        }
+        let resp = handle_select(&end_point, sel.build());
+        assert_eq!(resp.get_rows().len(), exp.len());
+        for (row, (name, first)) in resp.get_rows().iter().zip(names.iter().zip(exp)) {
+            let gk = datum::encode_value(&[Datum::Bytes(name.to_vec())]).unwrap();
+            let expected_datum = vec![Datum::Bytes(gk), first];
+            let expected_encoded = datum::encode_value(&expected_datum).unwrap();
+            assert_eq!(row.get_data(), &*expected_encoded);
+        }
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_aggr_avg() {
     let data = vec![
@@ -648,6 +945,97 @@ fn test_aggr_avg() {
     end_point.stop().unwrap();
 }
 
+#[test]
+fn test_aggr_variance() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:3"), 3),
+        (4, Some("name:0"), 1),
+        (5, Some("name:5"), 4),
+        (6, Some("name:5"), 4),
+        (7, None, 4),
+    ];
+
+    let product = ProductTable::new();
+    let (mut store, mut end_point) = init_with_data(&product, &data);
+
+    store.begin();
+    store.insert_into(&product.table)
+        .set(product.id, Datum::I64(8))
+        .set(product.name, Datum::Bytes(b"name:4".to_vec()))
+        .set(product.count, Datum::Null)
+        .execute();
+    store.commit();
+
+    let dec = |i: i64| Datum::Dec(i.into());
+    // (name, count, sum, sum of squares)
+    let exp = vec![(Datum::Bytes(b"name:0".to_vec()), 2, dec(3), dec(5)),
+                   (Datum::Bytes(b"name:3".to_vec()), 1, dec(3), dec(9)),
+                   (Datum::Bytes(b"name:5".to_vec()), 2, dec(8), dec(32)),
+                   (Datum::Null, 1, dec(4), dec(16)),
+                   (Datum::Bytes(b"name:4".to_vec()), 0, Datum::Null, Datum::Null)];
+    let req = Select::from(&product.table)
+        .variance(product.count)
+        .group_by(&[product.name])
+        .build();
+    let resp = handle_select(&end_point, req);
+    assert_eq!(resp.get_rows().len(), exp.len());
+    for (row, (name, cnt, sum, square_sum)) in resp.get_rows().iter().zip(exp) {
+        let gk = datum::encode_value(&[name]).unwrap();
+        let expected_datum = vec![Datum::Bytes(gk), Datum::U64(cnt), sum, square_sum];
+        let expected_encoded = datum::encode_value(&expected_datum).unwrap();
+        assert_eq!(row.get_data(), &*expected_encoded);
+    }
+    end_point.stop().unwrap();
+}
+
+#[test]
+fn test_aggr_final_avg() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:3"), 3),
+        (4, Some("name:0"), 1),
+        (5, Some("name:5"), 4),
+        (6, Some("name:5"), 5),
+        (7, None, 4),
+        (8, Some("name:6"), 1),
+        (9, Some("name:6"), 1),
+        (10, Some("name:6"), 2),
+    ];
+
+    let product = ProductTable::new();
+    let (mut store, mut end_point) = init_with_data(&product, &data);
+
+    store.begin();
+    store.insert_into(&product.table)
+        .set(product.id, Datum::I64(11))
+        .set(product.name, Datum::Bytes(b"name:4".to_vec()))
+        .set(product.count, Datum::Null)
+        .execute();
+    store.commit();
+
+    let exp = vec![(Datum::Bytes(b"name:0".to_vec()), Datum::Dec("1.5000".parse().unwrap())),
+                   (Datum::Bytes(b"name:3".to_vec()), Datum::Dec("3.0000".parse().unwrap())),
+                   (Datum::Bytes(b"name:5".to_vec()), Datum::Dec("4.5000".parse().unwrap())),
+                   (Datum::Null, Datum::Dec("4.0000".parse().unwrap())),
+                   (Datum::Bytes(b"name:6".to_vec()), Datum::Dec("1.3333".parse().unwrap())),
+                   (Datum::Bytes(b"name:4".to_vec()), Datum::Null)];
+    let req = Select::from(&product.table)
+        .avg(product.count)
+        .group_by(&[product.name])
+        .final_avg()
+        .build();
+    let resp = handle_select(&end_point, req);
+    assert_eq!(resp.get_rows().len(), exp.len());
+    for (row, (name, avg)) in resp.get_rows().iter().zip(exp) {
+        let gk = datum::encode_value(&[name]).unwrap();
+        let expected_datum = vec![Datum::Bytes(gk), avg];
+        let expected_encoded = datum::encode_value(&expected_datum).unwrap();
+        assert_eq!(row.get_data(), &*expected_encoded);
+    }
+    end_point.stop().unwrap();
+}
+
 #[test]
 fn test_aggr_sum() {
     let data = vec![
@@ -680,6 +1068,26 @@ fn test_aggr_sum() {
     end_point.stop().unwrap();
 }
 
+#[test]
+fn test_aggr_sum_overflow() {
+    let data = vec![
+        (1, Some("name:0"), i64::MAX),
+        (2, Some("name:1"), 2),
+        (4, Some("name:0"), 1),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let req = Select::from(&product.table).sum(product.count).group_by(&[product.name]).build();
+    let resp = handle_select(&end_point, req);
+    // the overflow is reported instead of a wrapped sum.
+    assert!(resp.get_rows().is_empty());
+    assert!(resp.get_error().get_msg().contains("aggregation overflow"),
+            format!("{:?}", resp));
+    end_point.stop().unwrap();
+}
+
 #[test]
 fn test_aggr_extre() {
     let data = vec![
@@ -753,6 +1161,53 @@ fn test_limit() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_scan_limit() {
+    let data: Vec<_> = (0..10).map(|i| (i, Some("name:0"), i)).collect();
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data_and_scan_limit(&product, &data, 4);
+
+    // Page through the table, every page continues after the last scanned key.
+    let mut req = Select::from(&product.table).build();
+    let mut ids = vec![];
+    let mut pages = 0;
+    loop {
+        let resp = handle_request(&end_point, req.clone());
+        let mut sel_resp = SelectResponse::new();
+        sel_resp.merge_from_bytes(resp.get_data()).unwrap();
+        assert!(sel_resp.get_rows().len() <= 4);
+        for row in sel_resp.get_rows() {
+            ids.push(row.get_handle().decode().unwrap()[0].i64());
+        }
+        pages += 1;
+        if !resp.get_more() {
+            break;
+        }
+        let last_id = *ids.last().unwrap();
+        assert_eq!(resp.get_last_key(), &*build_row_key(product.table.id, last_id));
+
+        let mut next_start = resp.get_last_key().to_vec();
+        next_start.push(0);
+        req.mut_ranges()[0].set_start(next_start);
+    }
+    assert_eq!(pages, 3);
+    assert_eq!(ids, (0..10).collect::<Vec<i64>>());
+
+    // The count only covers the scanned keys too.
+    let req = Select::from(&product.table).count().build();
+    let resp = handle_request(&end_point, req);
+    assert!(resp.get_more());
+    assert_eq!(resp.get_last_key(), &*build_row_key(product.table.id, 3));
+    let mut sel_resp = SelectResponse::new();
+    sel_resp.merge_from_bytes(resp.get_data()).unwrap();
+    let gk = Datum::Bytes(coprocessor::SINGLE_GROUP.to_vec());
+    let expected_encoded = datum::encode_value(&[gk, Datum::U64(4)]).unwrap();
+    assert_eq!(sel_resp.get_rows()[0].get_data(), &*expected_encoded);
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_reverse() {
     let mut data = vec![
@@ -780,7 +1235,95 @@ fn test_reverse() {
     end_point.stop().unwrap().join().unwrap();
 }
 
-fn handle_select(end_point: &Worker<RequestTask>, req: Request) -> SelectResponse {
+#[test]
+fn test_handle_range_bounds() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:3"), 3),
+        (4, Some("name:0"), 1),
+        (5, Some("name:5"), 4),
+        (6, Some("name:5"), 4),
+        (7, None, 4),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let cases = vec![
+        // 2 < pk < 6
+        ((2, false), (6, false), vec![4, 5]),
+        // 2 <= pk <= 6
+        ((2, true), (6, true), vec![2, 4, 5, 6]),
+        // 2 < pk <= 6
+        ((2, false), (6, true), vec![4, 5, 6]),
+        // pk > 4, the largest handle is included.
+        ((4, false), (i64::MAX, true), vec![5, 6, 7]),
+        // pk < 4
+        ((i64::MIN, true), (4, false), vec![1, 2]),
+        // 5 <= pk <= 5
+        ((5, true), (5, true), vec![5]),
+        // 5 < pk <= 5
+        ((5, false), (5, true), vec![]),
+    ];
+    for ((start, start_inc), (end, end_inc), expected) in cases {
+        for &desc in &[false, true] {
+            let req = Select::from(&product.table)
+                .handle_range((start.into(), start_inc), (end.into(), end_inc))
+                .order_by_pk(desc)
+                .build();
+            let resp = handle_select(&end_point, req);
+            let mut ids: Vec<i64> = resp.get_rows()
+                .iter()
+                .map(|row| row.get_handle().decode().unwrap()[0].i64())
+                .collect();
+            if desc {
+                ids.reverse();
+            }
+            assert_eq!(ids, expected, "{} {} {} {}", start, start_inc, end, end_inc);
+        }
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
+#[test]
+fn test_order_by_columns() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:3"), 1),
+        (4, Some("name:1"), 2),
+        (5, None, 1),
+        (6, Some("name:5"), 2),
+        (7, Some("name:2"), 1),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    // select * from t order by count asc, name desc limit 4
+    let req = Select::from(&product.table)
+        .order_by(product.count, false)
+        .order_by(product.name, true)
+        .limit(4)
+        .build();
+    let resp = handle_select(&end_point, req);
+    assert_eq!(resp.get_rows().len(), 4);
+    let expected = vec![
+        (2, Some("name:3"), 1),
+        (7, Some("name:2"), 1),
+        (5, None, 1),
+        (6, Some("name:5"), 2),
+    ];
+    for (row, (id, name, cnt)) in resp.get_rows().iter().zip(expected) {
+        let name_datum = name.map(|s| s.as_bytes()).into();
+        let expected_encoded = datum::encode_value(&[id.into(), name_datum, cnt.into()]).unwrap();
+        assert_eq!(row.get_data(), &*expected_encoded);
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
+fn handle_request(end_point: &Worker<RequestTask>, req: Request) -> Response {
     let finish = Event::new();
     let finish_clone = finish.clone();
     end_point.schedule(RequestTask::new(req,
@@ -789,13 +1332,111 @@ fn handle_select(end_point: &Worker<RequestTask>, req: Request) -> SelectRespons
                                    }))
         .unwrap();
     finish.wait_timeout(None);
-    let resp = finish.take().unwrap().take_cop_resp();
+    finish.take().unwrap().take_cop_resp()
+}
+
+fn handle_select(end_point: &Worker<RequestTask>, req: Request) -> SelectResponse {
+    let resp = handle_request(end_point, req);
     assert!(resp.has_data(), format!("{:?}", resp));
     let mut sel_resp = SelectResponse::new();
     sel_resp.merge_from_bytes(resp.get_data()).unwrap();
     sel_resp
 }
 
+#[test]
+fn test_busy() {
+    let data: Vec<_> = (0..5000).map(|i| (i, Some("name:0"), i)).collect();
+
+    let product = ProductTable::new();
+    let mut cfg = Config::new();
+    cfg.end_point_concurrency = 1;
+    cfg.end_point_max_tasks_per_worker = 1;
+    let (_, mut end_point) = init_with_data_and_cfg(&product, &data, &cfg);
+
+    // the requests are scheduled much faster than a full scan is finished,
+    // only the first ones are accepted.
+    let req = Select::from(&product.table).build();
+    let events: Vec<_> = (0..10)
+        .map(|_| {
+            let finish = Event::new();
+            let finish_clone = finish.clone();
+            end_point.schedule(RequestTask::new(req.clone(), box move |r| finish_clone.set(r)))
+                .unwrap();
+            finish
+        })
+        .collect();
+    let mut busy = 0;
+    for finish in events {
+        assert!(finish.wait_timeout(None));
+        let resp = finish.take().unwrap().take_cop_resp();
+        if resp.has_region_error() {
+            assert!(resp.get_region_error().has_server_is_busy(), format!("{:?}", resp));
+            busy += 1;
+        } else {
+            assert!(resp.has_data(), format!("{:?}", resp));
+        }
+    }
+    assert!(busy > 0 && busy < 10, "busy {}", busy);
+
+    // it accepts requests again after the running ones finish, the slot may
+    // be released a little after the response is sent.
+    for _ in 0..100 {
+        let resp = handle_request(&end_point, req.clone());
+        if !resp.has_region_error() {
+            assert!(resp.has_data(), format!("{:?}", resp));
+            end_point.stop().unwrap().join().unwrap();
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the end point is still busy");
+}
+
+#[test]
+fn test_cancel() {
+    let data: Vec<_> = (0..5000).map(|i| (i, Some("name:0"), i)).collect();
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    let req = Select::from(&product.table).count().build();
+    let resp = handle_select(&end_point, req.clone());
+    let gk = Datum::Bytes(coprocessor::SINGLE_GROUP.to_vec());
+    let expected_encoded = datum::encode_value(&[gk, Datum::U64(data.len() as u64)]).unwrap();
+    assert_eq!(resp.get_rows()[0].get_data(), &*expected_encoded);
+
+    let finish = Event::new();
+    let finish_clone = finish.clone();
+    let task = RequestTask::new(req.clone(), box move |r| finish_clone.set(r));
+    let handle = task.cancel_handle();
+    handle.cancel();
+    end_point.schedule(task).unwrap();
+    finish.wait_timeout(None);
+    let resp = finish.take().unwrap().take_cop_resp();
+    // the scan is aborted at the first check rather than going through all rows.
+    assert!(resp.get_other_error().contains("request cancelled after scanning 0 keys"),
+            format!("{:?}", resp));
+    let mut sel_resp = SelectResponse::new();
+    sel_resp.merge_from_bytes(resp.get_data()).unwrap();
+    assert!(sel_resp.get_rows().is_empty());
+    assert!(sel_resp.has_error());
+
+    // all the tasks sharing a handle, like the requests of a closed
+    // connection, are cancelled together.
+    let handle = CancelHandle::new();
+    let finish = Event::new();
+    let finish_clone = finish.clone();
+    let task = RequestTask::with_cancel_handle(req, box move |r| finish_clone.set(r), &handle);
+    handle.cancel();
+    end_point.schedule(task).unwrap();
+    finish.wait_timeout(None);
+    let resp = finish.take().unwrap().take_cop_resp();
+    assert!(resp.get_other_error().contains("request cancelled"),
+            format!("{:?}", resp));
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_index() {
     let data = vec![
@@ -866,6 +1507,45 @@ fn test_index_reverse_limit() {
     end_point.stop().unwrap().join().unwrap();
 }
 
+#[test]
+fn test_index_reverse_scan() {
+    let data = vec![
+        (1, Some("name:0"), 2),
+        (2, Some("name:3"), 3),
+        (4, Some("name:0"), 1),
+        (5, Some("name:5"), 4),
+        (6, Some("name:5"), 4),
+        (7, None, 4),
+    ];
+
+    let product = ProductTable::new();
+    let (_, mut end_point) = init_with_data(&product, &data);
+
+    // index entries are ordered by (count, handle).
+    let expect = vec![7, 6, 5, 2, 1, 4];
+    for limit in vec![expect.len() as i64, 4] {
+        let req = Select::from_index(&product.table, product.count)
+            .limit(limit)
+            .order_by_pk(true)
+            .build();
+        let resp = handle_select(&end_point, req);
+        assert_eq!(resp.get_rows().len(), limit as usize);
+        let mut handles = vec![];
+        for row in resp.get_rows() {
+            let datums = row.get_handle().decode().unwrap();
+            assert_eq!(datums.len(), 1);
+            if let Datum::I64(h) = datums[0] {
+                handles.push(h);
+            } else {
+                panic!("i64 expected, but got {:?}", datums[0]);
+            }
+        }
+        assert_eq!(handles, &expect[..limit as usize]);
+    }
+
+    end_point.stop().unwrap().join().unwrap();
+}
+
 #[test]
 fn test_del_select() {
     let mut data = vec![
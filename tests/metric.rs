@@ -0,0 +1,23 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The in-memory metric client is shared with the unit tests, see
+// `tikv::util::metric::test`. Not every helper is used here.
+#![allow(dead_code)]
+
+use tikv::util::metric::set_metric_client;
+
+#[path = "../src/util/metric/test.rs"]
+mod collector;
+
+pub use self::collector::*;
@@ -27,6 +27,7 @@ use kvproto::metapb::{self, RegionEpoch};
 use kvproto::raft_serverpb::RaftMessage;
 use tikv::pd::PdClient;
 use tikv::util::{HandyRwLock, escape, rocksdb};
+use tikv::util::event::Event;
 use tikv::server::Config as ServerConfig;
 use super::pd::TestPdClient;
 use tikv::raftstore::store::keys::data_key;
@@ -165,6 +166,26 @@ impl<T: Simulator> Cluster<T> {
         self.sim.rl().call_command(request, timeout)
     }
 
+    /// Send the request to the store without waiting, the returned event is
+    /// set when the callback of the request is called.
+    pub fn async_command_on_store(&self,
+                                  store_id: u64,
+                                  request: RaftCmdRequest)
+                                  -> Event<RaftCmdResponse> {
+        let finished = Event::new();
+        let finished2 = finished.clone();
+        let ch = self.sim.rl().get_store_sendch(store_id).unwrap();
+        ch.send(Msg::RaftCmd {
+                request: request,
+                callback: box move |resp| {
+                    finished2.set(resp);
+                    Ok(())
+                },
+            })
+            .unwrap();
+        finished
+    }
+
     pub fn call_command_on_leader(&mut self,
                                   mut request: RaftCmdRequest,
                                   timeout: Duration)
@@ -432,6 +453,23 @@ impl<T: Simulator> Cluster<T> {
         }
     }
 
+    pub fn must_batch_get(&mut self, keys: &[&[u8]]) -> Vec<Option<Vec<u8>>> {
+        let mut resp = self.request(keys[0], vec![new_batch_get_cmd(keys)], Duration::from_secs(5));
+        if resp.get_header().has_error() {
+            panic!("response {:?} has error", resp);
+        }
+        assert_eq!(resp.get_responses().len(), 1);
+        assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::BatchGet);
+        let mut batch_get = resp.mut_responses()[0].take_batch_get();
+        assert_eq!(batch_get.get_values().len(), keys.len());
+        assert_eq!(batch_get.get_found().len(), keys.len());
+        batch_get.take_values()
+            .into_iter()
+            .zip(batch_get.get_found())
+            .map(|(v, &found)| if found { Some(v) } else { None })
+            .collect()
+    }
+
     pub fn must_put(&mut self, key: &[u8], value: &[u8]) {
         let resp = self.request(key, vec![new_put_cmd(key, value)], Duration::from_secs(5));
         if resp.get_header().has_error() {
@@ -441,6 +479,59 @@ impl<T: Simulator> Cluster<T> {
         assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Put);
     }
 
+    pub fn must_put_return_prev(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let mut resp = self.request(key,
+                                    vec![new_put_return_prev_cmd(key, value)],
+                                    Duration::from_secs(5));
+        if resp.get_header().has_error() {
+            panic!("response {:?} has error", resp);
+        }
+        assert_eq!(resp.get_responses().len(), 1);
+        assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Put);
+        let mut put = resp.mut_responses()[0].take_put();
+        if put.has_prev_value() {
+            Some(put.take_prev_value())
+        } else {
+            None
+        }
+    }
+
+    pub fn must_append(&mut self, key: &[u8], value: &[u8]) -> u64 {
+        let resp = self.request(key, vec![new_append_cmd(key, value)], Duration::from_secs(5));
+        if resp.get_header().has_error() {
+            panic!("response {:?} has error", resp);
+        }
+        assert_eq!(resp.get_responses().len(), 1);
+        assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Append);
+        resp.get_responses()[0].get_append().get_length()
+    }
+
+    pub fn must_cas(&mut self,
+                    key: &[u8],
+                    expected: Option<&[u8]>,
+                    value: &[u8])
+                    -> CASResponse {
+        let mut resp = self.request(key,
+                                    vec![new_cas_cmd(key, expected, value)],
+                                    Duration::from_secs(5));
+        if resp.get_header().has_error() {
+            panic!("response {:?} has error", resp);
+        }
+        assert_eq!(resp.get_responses().len(), 1);
+        assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::CAS);
+        resp.mut_responses()[0].take_cas()
+    }
+
+    pub fn must_snap(&mut self, key: &[u8]) -> SnapResponse {
+        let mut resp = self.request(key, vec![new_snap_cmd()], Duration::from_secs(5));
+        if resp.get_header().has_error() {
+            panic!("response {:?} has error", resp);
+        }
+        assert_eq!(resp.get_responses().len(), 1);
+        assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Snap);
+        resp.mut_responses()[0].take_snap()
+    }
+
     pub fn must_seek(&mut self, key: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
         let resp = self.request(key, vec![new_seek_cmd(key)], Duration::from_secs(5));
         if resp.get_header().has_error() {
@@ -488,6 +579,63 @@ impl<T: Simulator> Cluster<T> {
         status_resp.take_region_detail()
     }
 
+    // Send the request to the store through its peer of region 1.
+    pub fn region_count(&mut self, store_id: u64) -> RegionCountResponse {
+        let status_cmd = new_region_count_cmd();
+        let peer = new_peer(store_id, store_id);
+        let req = new_status_request(1, peer, status_cmd);
+        let resp = self.call_command(req, Duration::from_secs(5));
+        assert!(resp.is_ok(), format!("{:?}", resp));
+
+        let mut resp = resp.unwrap();
+        assert!(resp.has_status_response());
+        let mut status_resp = resp.take_status_response();
+        assert_eq!(status_resp.get_cmd_type(), StatusCmdType::RegionCount);
+        assert!(status_resp.has_region_count());
+        status_resp.take_region_count()
+    }
+
+    // Send the request to the store through its peer of region 1.
+    pub fn store_regions(&mut self, store_id: u64) -> StoreRegionsResponse {
+        let status_cmd = new_store_regions_cmd();
+        let peer = new_peer(store_id, store_id);
+        let req = new_status_request(1, peer, status_cmd);
+        let resp = self.call_command(req, Duration::from_secs(5));
+        assert!(resp.is_ok(), format!("{:?}", resp));
+
+        let mut resp = resp.unwrap();
+        assert!(resp.has_status_response());
+        let mut status_resp = resp.take_status_response();
+        assert_eq!(status_resp.get_cmd_type(), StatusCmdType::StoreRegions);
+        assert!(status_resp.has_store_regions());
+        status_resp.take_store_regions()
+    }
+
+    // Send the request to the store through its peer of region 1.
+    pub fn store_ready(&mut self, store_id: u64) -> StoreReadyResponse {
+        let status_cmd = new_store_ready_cmd();
+        let peer = new_peer(store_id, store_id);
+        let req = new_status_request(1, peer, status_cmd);
+        let resp = self.call_command(req, Duration::from_secs(5));
+        assert!(resp.is_ok(), format!("{:?}", resp));
+
+        let mut resp = resp.unwrap();
+        assert!(resp.has_status_response());
+        let mut status_resp = resp.take_status_response();
+        assert_eq!(status_resp.get_cmd_type(), StatusCmdType::StoreReady);
+        assert!(status_resp.has_store_ready());
+        status_resp.take_store_ready()
+    }
+
+    // The response carries a NotLeader error if the peer isn't leader.
+    pub fn region_progress(&mut self, region_id: u64, peer: metapb::Peer) -> RaftCmdResponse {
+        let status_cmd = new_region_progress_cmd();
+        let req = new_status_request(region_id, peer, status_cmd);
+        let resp = self.call_command(req, Duration::from_secs(5));
+        assert!(resp.is_ok(), format!("{:?}", resp));
+        resp.unwrap()
+    }
+
     pub fn add_filter<F: FilterFactory>(&self, factory: F) {
         let sim = self.sim.wl();
         for node_id in sim.get_node_ids() {
@@ -29,3 +29,6 @@ mod test_transport;
 mod test_transfer_leader;
 mod test_stats;
 mod test_snap;
+mod test_pre_vote;
+mod test_bootstrap;
+mod test_consistency_check;
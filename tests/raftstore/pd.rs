@@ -21,7 +21,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use kvproto::metapb;
 use kvproto::pdpb;
 use kvproto::raftpb;
-use tikv::pd::{PdClient, Result, Error, Key};
+use tikv::pd::{PdClient, Result, Error, Key, RegionFlow};
 use tikv::raftstore::store::keys::{enc_end_key, enc_start_key, data_key};
 use tikv::raftstore::store::util::check_key_in_region;
 use tikv::util::{HandyRwLock, escape};
@@ -50,6 +50,8 @@ struct Cluster {
     rule: Option<Rule>,
 
     store_stats: HashMap<u64, pdpb::StoreStats>,
+    // accumulated flow reported by region heartbeats.
+    region_flows: HashMap<u64, RegionFlow>,
     split_count: usize,
 }
 
@@ -67,6 +69,7 @@ impl Cluster {
             base_id: AtomicUsize::new(1000),
             rule: None,
             store_stats: HashMap::new(),
+            region_flows: HashMap::new(),
             split_count: 0,
         }
     }
@@ -271,8 +274,16 @@ impl Cluster {
 
     fn region_heartbeat(&mut self,
                         region: metapb::Region,
-                        leader: metapb::Peer)
+                        leader: metapb::Peer,
+                        flow: RegionFlow)
                         -> Result<pdpb::RegionHeartbeatResponse> {
+        {
+            let total = self.region_flows.entry(region.get_id()).or_insert_with(Default::default);
+            total.written_bytes += flow.written_bytes;
+            total.written_keys += flow.written_keys;
+            total.read_bytes += flow.read_bytes;
+            total.read_keys += flow.read_keys;
+        }
         try!(self.handle_heartbeat_version(region.clone()));
         self.handle_heartbeat_conf_ver(region, leader)
     }
@@ -404,6 +415,17 @@ impl TestPdClient {
         self.must_have_peer(region_id, peer);
     }
 
+    pub fn must_add_learner(&self, region_id: u64, peer: metapb::Peer) {
+        let peer2 = peer.clone();
+        self.set_rule(box move |region: &metapb::Region, _: &metapb::Peer| {
+            if region.get_id() != region_id {
+                return None;
+            }
+            new_pd_add_learner_change_peer(region, peer2.clone())
+        });
+        self.must_have_peer(region_id, peer);
+    }
+
     pub fn remove_peer(&self, region_id: u64, peer: metapb::Peer) {
         self.set_rule(box move |region: &metapb::Region, _: &metapb::Peer| {
             if region.get_id() != region_id {
@@ -450,6 +472,11 @@ impl TestPdClient {
         self.cluster.rl().store_stats.get(&store_id).cloned()
     }
 
+    pub fn get_region_flow(&self, region_id: u64) -> RegionFlow {
+        let cluster = self.cluster.rl();
+        cluster.region_flows.get(&region_id).cloned().unwrap_or_else(RegionFlow::default)
+    }
+
     pub fn get_split_count(&self) -> usize {
         self.cluster.rl().split_count
     }
@@ -504,10 +531,11 @@ impl PdClient for TestPdClient {
 
     fn region_heartbeat(&self,
                         region: metapb::Region,
-                        leader: metapb::Peer)
+                        leader: metapb::Peer,
+                        flow: RegionFlow)
                         -> Result<pdpb::RegionHeartbeatResponse> {
         try!(self.check_bootstrap());
-        self.cluster.wl().region_heartbeat(region, leader)
+        self.cluster.wl().region_heartbeat(region, leader, flow)
     }
 
     fn ask_split(&self, region: metapb::Region) -> Result<pdpb::AskSplitResponse> {
@@ -143,7 +143,7 @@ impl Simulator for ServerCluster {
 
         // TODO: simplify creating raft server later.
         let mut event_loop = create_event_loop(&cfg).unwrap();
-        let sendch = SendCh::new(event_loop.channel());
+        let sendch = SendCh::new(event_loop.channel(), cfg.notify_capacity);
         let resolver = PdStoreAddrResolver::new(self.pd_client.clone()).unwrap();
         let trans = Arc::new(RwLock::new(ServerTransport::new(sendch.clone())));
 
@@ -171,6 +171,7 @@ impl Simulator for ServerCluster {
         self.storages.insert(node_id, store.get_engine());
 
         let mut server = Server::new(&mut event_loop,
+                                     sendch,
                                      &cfg,
                                      listener,
                                      store,
@@ -0,0 +1,49 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+
+use tempdir::TempDir;
+
+use tikv::server::Node;
+use tikv::raftstore::store::{self, create_event_loop};
+use tikv::pd::PdClient;
+use tikv::util::rocksdb;
+
+use super::node::ChannelTransport;
+use super::pd::TestPdClient;
+use super::transport_simulate::SimulateTransport;
+use super::util::new_server_config;
+
+#[test]
+fn test_node_start_missing_cf() {
+    let pd_client = Arc::new(TestPdClient::new(0));
+    let cfg = new_server_config(0);
+    let path = TempDir::new("test_node_start_missing_cf").unwrap();
+    // the engine is opened without the "lock" cf.
+    let engine = Arc::new(rocksdb::new_engine(path.path().to_str().unwrap(), &["default"])
+        .unwrap());
+    let snap_path = TempDir::new("test_node_start_missing_cf_snap").unwrap();
+
+    let mut event_loop = create_event_loop(&cfg.store_cfg).unwrap();
+    let mut node = Node::new(&mut event_loop, &cfg, pd_client.clone());
+    let snap_mgr = store::new_snap_mgr(snap_path.path().to_str().unwrap(),
+                                       Some(node.get_sendch()));
+    let trans = Arc::new(RwLock::new(SimulateTransport::new(ChannelTransport::new())));
+
+    let err = node.start(event_loop, engine, trans, snap_mgr).unwrap_err();
+    let msg = format!("{}", err);
+    assert!(msg.contains("lock") && msg.contains("not found"), "{}", msg);
+    // nothing should be bootstrapped into the misconfigured engine.
+    assert!(!pd_client.is_cluster_bootstrapped().unwrap());
+}
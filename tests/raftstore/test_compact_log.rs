@@ -14,6 +14,7 @@
 use std::collections::HashMap;
 
 use tikv::raftstore::store::*;
+use tikv::util::HandyRwLock;
 use kvproto::raft_serverpb::RaftApplyState;
 
 use super::util::*;
@@ -158,3 +159,90 @@ fn test_server_compact_limit() {
     let mut cluster = new_server_cluster(0, count);
     test_compact_limit(&mut cluster);
 }
+
+fn sst_files_size<T: Simulator>(cluster: &Cluster<T>) -> Vec<u64> {
+    cluster.engines
+        .values()
+        .map(|engine| engine.get_property_int("rocksdb.total-sst-files-size").unwrap())
+        .collect()
+}
+
+fn test_compact_tombstones<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.region_compact_check_interval = 100;
+    cluster.cfg.store_cfg.region_compact_tombstones_threshold = 50;
+    cluster.run();
+
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        cluster.must_put(key.as_bytes(), b"value");
+    }
+    // all the data is still in memtable.
+    assert!(sst_files_size(cluster).iter().all(|&size| size == 0));
+
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        cluster.must_delete(key.as_bytes());
+    }
+
+    // compacting the range flushes the memtable, so sst files will be generated.
+    for _ in 0..50 {
+        if sst_files_size(cluster).iter().all(|&size| size > 0) {
+            return;
+        }
+        sleep_ms(100);
+    }
+    panic!("range compaction is not triggered after deleting keys: {:?}",
+           sst_files_size(cluster));
+}
+
+#[test]
+fn test_node_compact_tombstones() {
+    let count = 3;
+    let mut cluster = new_node_cluster(0, count);
+    test_compact_tombstones(&mut cluster);
+}
+
+#[test]
+fn test_server_compact_tombstones() {
+    let count = 3;
+    let mut cluster = new_server_cluster(0, count);
+    test_compact_tombstones(&mut cluster);
+}
+
+fn test_manual_compact_range<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        cluster.must_put(key.as_bytes(), b"value");
+    }
+    assert!(sst_files_size(cluster).iter().all(|&size| size == 0));
+
+    // only the store receiving the message compacts the range.
+    let ch = cluster.sim.rl().get_store_sendch(1).unwrap();
+    ch.send(Msg::CompactRange {
+            start_key: b"key".to_vec(),
+            end_key: vec![],
+        })
+        .unwrap();
+    let engine = cluster.get_engine(1);
+    for _ in 0..50 {
+        if engine.get_property_int("rocksdb.total-sst-files-size").unwrap() > 0 {
+            return;
+        }
+        sleep_ms(100);
+    }
+    panic!("range is not compacted: {:?}", sst_files_size(cluster));
+}
+
+#[test]
+fn test_node_manual_compact_range() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_manual_compact_range(&mut cluster);
+}
+
+#[test]
+fn test_server_manual_compact_range() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_manual_compact_range(&mut cluster);
+}
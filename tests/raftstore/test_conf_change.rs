@@ -13,12 +13,14 @@
 
 use std::time::Duration;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::thread;
 
 use tikv::raftstore::store::*;
 use kvproto::raft_cmdpb::RaftResponseHeader;
 use kvproto::raft_serverpb::*;
 use kvproto::metapb;
+use kvproto::raftpb::{ConfChangeType, MessageType};
 use tikv::pd::PdClient;
 
 use super::cluster::{Cluster, Simulator};
@@ -400,6 +402,48 @@ fn test_server_after_remove_itself() {
     test_after_remove_itself(&mut cluster);
 }
 
+fn test_sync_log_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.sync_log = true;
+    // Compacting the raft log produces an admin result too, keep it away.
+    cluster.cfg.store_cfg.raft_log_gc_threshold = 1000;
+    let sync_writes = cluster.cfg.store_cfg.apply_sync_writes.clone();
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+
+    // Plain writes are still written without sync.
+    cluster.must_put(b"k1", b"v1");
+    let synced = sync_writes.load(Ordering::SeqCst);
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&cluster.get_engine(1), b"k2", b"v2");
+    assert_eq!(sync_writes.load(Ordering::SeqCst), synced);
+
+    // The conf change is written with sync.
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    must_get_equal(&cluster.get_engine(2), b"k2", b"v2");
+    for _ in 0..50 {
+        if sync_writes.load(Ordering::SeqCst) > synced {
+            return;
+        }
+        sleep_ms(100);
+    }
+    panic!("the conf change is not written with sync");
+}
+
+#[test]
+fn test_node_sync_log_conf_change() {
+    let mut cluster = new_node_cluster(0, 2);
+    test_sync_log_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_sync_log_conf_change() {
+    let mut cluster = new_server_cluster(0, 2);
+    test_sync_log_conf_change(&mut cluster);
+}
+
 fn test_split_brain<T: Simulator>(cluster: &mut Cluster<T>) {
     let pd_client = cluster.pd_client.clone();
     // Disable default max peer number check.
@@ -447,14 +491,13 @@ fn test_split_brain<T: Simulator>(cluster: &mut Cluster<T>) {
     let header0 = find_leader_response_header(cluster, r1, new_peer(2, 2));
     assert!(header0.get_error().has_region_not_found());
 
-    // at least wait for a round of election timeout and check again
-    let term = find_leader_response_header(cluster, r1, new_peer(1, 1)).get_current_term();
-    let mut current_term = term;
-    while current_term < term + 2 {
-        sleep_ms(10);
-        let header2 = find_leader_response_header(cluster, r1, new_peer(1, 1));
-        current_term = header2.get_current_term();
-    }
+    // at least wait for a round of election timeout and check again.
+    // With pre-vote, 1 no longer bumps its term while it can't win an election,
+    // so we can't use the term to detect elections here.
+    let store_cfg = &cluster.cfg.store_cfg;
+    let election_timeout = store_cfg.raft_base_tick_interval *
+                           store_cfg.raft_election_timeout_ticks as u64;
+    sleep_ms(election_timeout * 3);
 
     let header1 = find_leader_response_header(cluster, r1, new_peer(2, 2));
     assert!(header1.get_error().has_region_not_found());
@@ -482,3 +525,94 @@ fn test_node_split_brain() {
     let mut cluster = new_node_cluster(0, count);
     test_split_brain(&mut cluster);
 }
+
+fn test_add_duplicated_peer_id<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    cluster.must_put(b"k1", b"v1");
+
+    let region = pd_client.get_region_by_id(r1).unwrap();
+    // Peer id 2 is already used by the peer on store 2.
+    let add_peer = new_change_peer_cmd(ConfChangeType::AddNode, new_peer(3, 2));
+    let req = new_admin_request(r1, region.get_region_epoch(), add_peer);
+    let resp = cluster.call_command_on_leader(req, Duration::from_secs(3)).unwrap();
+    assert!(is_error_response(&resp), "{:?}", resp);
+
+    // The region must be unchanged and still serve requests.
+    cluster.must_put(b"k2", b"v2");
+    let region = pd_client.get_region_by_id(r1).unwrap();
+    assert_eq!(region.get_peers().len(), 2);
+    assert!(find_peer(&region, 3).is_none());
+    must_get_none(&cluster.get_engine(3), b"k1");
+}
+
+#[test]
+fn test_node_add_duplicated_peer_id() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    test_add_duplicated_peer_id(&mut cluster);
+}
+
+#[test]
+fn test_server_add_duplicated_peer_id() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    test_add_duplicated_peer_id(&mut cluster);
+}
+
+fn test_overlapped_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    cluster.must_put(b"k1", b"v1");
+
+    // hold the logs on the leader, so the first conf change is still pending
+    // when the second one is proposed.
+    cluster.add_filter(IsolateRegionStore::new(r1, 1)
+        .msg_type(MessageType::MsgAppend)
+        .direction(Direction::Send));
+
+    let epoch = pd_client.get_region_by_id(r1).unwrap().get_region_epoch().clone();
+    let mut events = vec![];
+    for id in 3..5 {
+        let add_peer = new_change_peer_cmd(ConfChangeType::AddNode, new_peer(id, id));
+        let mut req = new_admin_request(r1, &epoch, add_peer);
+        req.mut_header().set_peer(new_peer(1, 1));
+        events.push(cluster.async_command_on_store(1, req));
+    }
+    sleep_ms(100);
+    assert!(events.iter().all(|e| !e.is_set()));
+
+    // the second one is dropped by raft, but re-proposed after the first is applied.
+    cluster.clear_filters();
+    for finished in &events {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(5))));
+        let resp = finished.take().unwrap();
+        assert!(!resp.get_header().has_error(), format!("{:?}", resp));
+    }
+
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&cluster.get_engine(3), b"k2", b"v2");
+    must_get_equal(&cluster.get_engine(4), b"k2", b"v2");
+}
+
+#[test]
+fn test_node_overlapped_conf_change() {
+    let count = 4;
+    let mut cluster = new_node_cluster(0, count);
+    test_overlapped_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_overlapped_conf_change() {
+    let count = 4;
+    let mut cluster = new_server_cluster(0, count);
+    test_overlapped_conf_change(&mut cluster);
+}
@@ -0,0 +1,65 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+use std::thread;
+
+use rocksdb::Writable;
+use tikv::raftstore::store::keys::data_key;
+use metric;
+
+use super::util::*;
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+
+fn test_consistency_check<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.consistency_check_interval = 100;
+    metric::init();
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    for engine in cluster.engines.values() {
+        must_get_equal(engine, b"k1", b"v1");
+    }
+
+    // Corrupt the data of a follower behind raft.
+    let leader = cluster.leader_of_region(1).unwrap();
+    let follower = cluster.engines
+        .keys()
+        .cloned()
+        .find(|&id| id != leader.get_store_id())
+        .unwrap();
+    let failed = metric::counter("raftstore.consistency_check.failed");
+    cluster.engines[&follower].put(&data_key(b"k1"), b"v2").unwrap();
+
+    for _ in 0..50 {
+        if metric::counter("raftstore.consistency_check.failed") > failed {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("the corrupted replica is not reported");
+}
+
+#[test]
+fn test_node_consistency_check() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_consistency_check(&mut cluster);
+}
+
+#[test]
+fn test_server_consistency_check() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_consistency_check(&mut cluster);
+}
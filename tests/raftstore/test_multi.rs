@@ -404,3 +404,263 @@ fn test_server_remove_leader_with_uncommitted_log() {
     let mut cluster = new_server_cluster(0, 2);
     test_remove_leader_with_uncommitted_log(&mut cluster);
 }
+
+fn test_pending_proposals_limit<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.region_max_pending_proposals = 5;
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+
+    // the leader can't replicate logs, so all the proposals stay pending.
+    cluster.add_filter(IsolateRegionStore::new(1, 1)
+        .msg_type(MessageType::MsgAppend)
+        .direction(Direction::Send));
+
+    let epoch = cluster.get_region_epoch(1);
+    let mut events = vec![];
+    for i in 1..9 {
+        let key = format!("k{}", i);
+        let mut req = new_request(1, epoch.clone(), vec![new_put_cmd(key.as_bytes(), b"v")]);
+        req.mut_header().set_peer(new_peer(1, 1));
+        events.push(cluster.async_command_on_store(1, req));
+    }
+
+    // proposals beyond the limit are rejected at once.
+    for finished in &events[5..] {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(3))));
+        let resp = finished.take().unwrap();
+        assert!(resp.get_header().get_error().has_server_is_busy(),
+                format!("{:?} should be busy", resp));
+    }
+    for finished in &events[..5] {
+        assert!(!finished.is_set());
+    }
+
+    // earlier proposals still complete.
+    cluster.clear_filters();
+    for finished in &events[..5] {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(5))));
+        let resp = finished.take().unwrap();
+        assert!(!resp.get_header().has_error(), format!("{:?}", resp));
+    }
+    for i in 1..6 {
+        let key = format!("k{}", i);
+        assert_eq!(cluster.get(key.as_bytes()), Some(b"v".to_vec()));
+    }
+    for i in 6..9 {
+        let key = format!("k{}", i);
+        assert_eq!(cluster.get(key.as_bytes()), None);
+    }
+}
+
+#[test]
+fn test_node_pending_proposals_limit() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_pending_proposals_limit(&mut cluster);
+}
+
+#[test]
+fn test_server_pending_proposals_limit() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_pending_proposals_limit(&mut cluster);
+}
+
+fn test_step_down_notify_pending<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+
+    // the isolated leader steps down by check quorum, with the proposals pending.
+    cluster.partition(vec![1], vec![2, 3]);
+    let epoch = cluster.get_region_epoch(1);
+    let mut events = vec![];
+    for i in 1..4 {
+        let key = format!("k{}", i);
+        let mut req = new_request(1, epoch.clone(), vec![new_put_cmd(key.as_bytes(), b"v")]);
+        req.mut_header().set_peer(new_peer(1, 1));
+        events.push(cluster.async_command_on_store(1, req));
+    }
+
+    // the proposals are notified once the leader steps down, without waiting
+    // for the partition to recover. They may be committed by a new leader, so
+    // the result is unknown rather than not leader.
+    for finished in &events {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(3))));
+        let resp = finished.take().unwrap();
+        let err = resp.get_header().get_error();
+        assert!(!err.has_not_leader(), "{:?}", resp);
+        assert_eq!(err.get_message(), "command result is unknown");
+    }
+
+    cluster.clear_filters();
+    cluster.must_put(b"k4", b"v4");
+    for i in 1..4 {
+        let key = format!("k{}", i);
+        assert_eq!(cluster.get(key.as_bytes()), None);
+    }
+}
+
+#[test]
+fn test_node_step_down_notify_pending() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_step_down_notify_pending(&mut cluster);
+}
+
+#[test]
+fn test_server_step_down_notify_pending() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_step_down_notify_pending(&mut cluster);
+}
+
+fn test_read_only_store<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.pd_store_heartbeat_tick_interval = 50;
+    let pd_client = cluster.pd_client.clone();
+    pd_client.disable_default_rule();
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    cluster.must_put(b"k1", b"v1");
+
+    // restart store 3 as a read-only store, which reports the flag to pd.
+    cluster.stop_node(3);
+    cluster.cfg.store_cfg.read_only = true;
+    cluster.run_node(3);
+    cluster.cfg.store_cfg.read_only = false;
+    for _ in 0..100 {
+        if pd_client.get_store_stats(3).map_or(false, |s| s.get_read_only()) {
+            break;
+        }
+        sleep_ms(20);
+    }
+    assert!(pd_client.get_store_stats(3).unwrap().get_read_only());
+
+    // the learner placed on it still replicates the data.
+    pd_client.must_add_learner(r1, new_learner_peer(3, 3));
+    let engine_3 = cluster.get_engine(3);
+    must_get_equal(&engine_3, b"k1", b"v1");
+
+    // but rejects the writes, including the ones reading the old value.
+    let epoch = cluster.get_region_epoch(r1);
+    let writes = vec![new_put_cmd(b"k2", b"v2"),
+                      new_delete_cmd(b"k1"),
+                      new_append_cmd(b"k1", b"v2"),
+                      new_cas_cmd(b"k1", Some(b"v1"), b"v2")];
+    for write in writes {
+        let mut req = new_request(r1, epoch.clone(), vec![write]);
+        req.mut_header().set_peer(new_peer(3, 3));
+        let resp = cluster.call_command(req, Duration::from_secs(3)).unwrap();
+        assert!(resp.get_header().get_error().get_message().contains("read-only"),
+                format!("{:?}", resp));
+    }
+    must_get_equal(&engine_3, b"k1", b"v1");
+
+    // and never becomes leader.
+    cluster.transfer_leader(r1, new_peer(3, 3));
+    sleep_ms(500);
+    cluster.reset_leader_of_region(r1);
+    assert_eq!(cluster.leader_of_region(r1).unwrap().get_store_id(), 1);
+
+    // the learner doesn't count in the quorum, so store 1 can't commit alone.
+    cluster.stop_node(2);
+    let req = new_request(r1, epoch, vec![new_put_cmd(b"k2", b"v2")]);
+    let resp = cluster.call_command_on_leader(req, Duration::from_secs(1));
+    assert!(resp.map(|r| r.get_header().has_error()).unwrap_or(true));
+    must_get_none(&engine_3, b"k2");
+
+    cluster.run_node(2);
+    cluster.must_put(b"k3", b"v3");
+    must_get_equal(&engine_3, b"k3", b"v3");
+}
+
+#[test]
+fn test_node_read_only_store() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_read_only_store(&mut cluster);
+}
+
+#[test]
+fn test_server_read_only_store() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_read_only_store(&mut cluster);
+}
+
+fn test_lagging_follower_catch_up<T: Simulator>(cluster: &mut Cluster<T>) {
+    // keep the raft log, so the follower catches up with the evicted entries
+    // instead of a snapshot.
+    cluster.cfg.store_cfg.raft_entry_cache_size = 256;
+    cluster.cfg.store_cfg.raft_log_gc_threshold = 1000;
+    cluster.cfg.store_cfg.raft_log_gc_limit = 1000;
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+
+    cluster.partition(vec![1, 2], vec![3]);
+    for i in 1..50 {
+        let (k, v) = (format!("k{}", i), format!("v{}", i));
+        cluster.must_put(k.as_bytes(), v.as_bytes());
+    }
+
+    cluster.clear_filters();
+    let engine_3 = cluster.get_engine(3);
+    for i in 0..50 {
+        let (k, v) = (format!("k{}", i), format!("v{}", i));
+        must_get_equal(&engine_3, k.as_bytes(), v.as_bytes());
+    }
+}
+
+#[test]
+fn test_node_lagging_follower_catch_up() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_lagging_follower_catch_up(&mut cluster);
+}
+
+#[test]
+fn test_server_lagging_follower_catch_up() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_lagging_follower_catch_up(&mut cluster);
+}
+
+fn test_put_return_prev_in_one_ready<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+
+    // hold the logs on the leader, so they are committed and applied together.
+    cluster.add_filter(IsolateRegionStore::new(1, 1)
+        .msg_type(MessageType::MsgAppend)
+        .direction(Direction::Send));
+
+    let epoch = cluster.get_region_epoch(1);
+    let reqs = vec![new_request(1, epoch.clone(), vec![new_put_cmd(b"k1", b"v1")]),
+                    new_request(1, epoch, vec![new_put_return_prev_cmd(b"k1", b"v2")])];
+    let mut events = vec![];
+    for mut req in reqs {
+        req.mut_header().set_peer(new_peer(1, 1));
+        events.push(cluster.async_command_on_store(1, req));
+    }
+    sleep_ms(100);
+    assert!(events.iter().all(|e| !e.is_set()));
+
+    cluster.clear_filters();
+    let mut resps = vec![];
+    for finished in &events {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(5))));
+        let resp = finished.take().unwrap();
+        assert!(!resp.get_header().has_error(), format!("{:?}", resp));
+        resps.push(resp);
+    }
+    // the second put sees the first one even if it's not written yet.
+    assert_eq!(resps[1].get_responses()[0].get_put().get_prev_value(), b"v1");
+    assert_eq!(cluster.get(b"k1"), Some(b"v2".to_vec()));
+}
+
+#[test]
+fn test_node_put_return_prev_in_one_ready() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_put_return_prev_in_one_ready(&mut cluster);
+}
+
+#[test]
+fn test_server_put_return_prev_in_one_ready() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_put_return_prev_in_one_ready(&mut cluster);
+}
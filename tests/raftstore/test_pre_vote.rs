@@ -0,0 +1,72 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use kvproto::metapb;
+
+use super::cluster::{Cluster, Simulator};
+use super::transport_simulate::*;
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::util::*;
+
+fn current_term<T: Simulator>(cluster: &mut Cluster<T>, region_id: u64, peer: metapb::Peer) -> u64 {
+    let find_leader = new_status_request(region_id, peer, new_region_leader_cmd());
+    let resp = cluster.call_command(find_leader, Duration::from_secs(5)).unwrap();
+    resp.get_header().get_current_term()
+}
+
+fn test_isolated_follower_rejoin<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    pd_client.disable_default_rule();
+
+    cluster.cfg.store_cfg.raft_pre_vote = true;
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+
+    let term = current_term(cluster, 1, new_peer(1, 1));
+
+    // Isolate 3 and let its election timeout fire several times.
+    cluster.add_filter(Isolate::new(3));
+    cluster.must_put(b"k2", b"v2");
+    let election_timeout = cluster.cfg.store_cfg.raft_base_tick_interval *
+                           cluster.cfg.store_cfg.raft_election_timeout_ticks as u64;
+    sleep_ms(election_timeout * 5);
+    assert_eq!(current_term(cluster, 1, new_peer(1, 1)), term);
+
+    // 3 rejoins, it only ran pre-votes while isolated, so it must catch
+    // up with the leader without forcing a new election.
+    cluster.clear_filters();
+    sleep_ms(election_timeout * 2);
+    cluster.must_put(b"k3", b"v3");
+    must_get_equal(&cluster.get_engine(3), b"k2", b"v2");
+    must_get_equal(&cluster.get_engine(3), b"k3", b"v3");
+
+    assert_eq!(cluster.leader_of_region(1), Some(new_peer(1, 1)));
+    assert_eq!(current_term(cluster, 1, new_peer(1, 1)), term);
+}
+
+#[test]
+fn test_node_pre_vote_isolated_follower_rejoin() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_isolated_follower_rejoin(&mut cluster);
+}
+
+#[test]
+fn test_server_pre_vote_isolated_follower_rejoin() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_isolated_follower_rejoin(&mut cluster);
+}
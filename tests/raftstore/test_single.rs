@@ -11,7 +11,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::thread;
+use std::time::Duration;
+
+use metric;
+use tikv::raftstore::store::*;
+use kvproto::raft_serverpb::RaftApplyState;
+
+use super::util::*;
 use super::cluster::{Cluster, Simulator};
+use super::transport_simulate::Isolate;
 use super::node::new_node_cluster;
 use super::server::new_server_cluster;
 
@@ -96,6 +105,152 @@ fn test_seek<T: Simulator>(cluster: &mut Cluster<T>) {
             "seek should follow binary order");
 }
 
+fn test_batch_get<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+    cluster.must_put(b"k4", b"");
+
+    let keys: Vec<&[u8]> = vec![b"k3", b"k2", b"k1", b"k4", b"k5", b"k1"];
+    let values = cluster.must_batch_get(&keys);
+    assert_eq!(values,
+               vec![Some(b"v3".to_vec()),
+                    None,
+                    Some(b"v1".to_vec()),
+                    Some(vec![]),
+                    None,
+                    Some(b"v1".to_vec())]);
+}
+
+fn test_append<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    assert_eq!(cluster.must_append(b"k1", b"a"), 1);
+    assert_eq!(cluster.must_append(b"k1", b""), 1);
+    assert_eq!(cluster.must_append(b"k1", b"bc"), 3);
+    assert_eq!(cluster.must_append(b"k1", b"def"), 6);
+    assert_eq!(cluster.get(b"k1"), Some(b"abcdef".to_vec()));
+
+    cluster.must_put(b"k2", b"v");
+    assert_eq!(cluster.must_append(b"k2", b"2"), 2);
+    assert_eq!(cluster.get(b"k2"), Some(b"v2".to_vec()));
+
+    // The requests of one command see the writes of the previous ones.
+    let reqs = vec![new_append_cmd(b"k3", b"a"), new_append_cmd(b"k3", b"b")];
+    let resp = cluster.request(b"k3", reqs, Duration::from_secs(5));
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    assert_eq!(resp.get_responses()[0].get_append().get_length(), 1);
+    assert_eq!(resp.get_responses()[1].get_append().get_length(), 2);
+    assert_eq!(cluster.get(b"k3"), Some(b"ab".to_vec()));
+
+    let reqs = vec![new_put_cmd(b"k4", b"v"), new_append_cmd(b"k4", b"4")];
+    let resp = cluster.request(b"k4", reqs, Duration::from_secs(5));
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    assert_eq!(resp.get_responses()[1].get_append().get_length(), 2);
+    assert_eq!(cluster.get(b"k4"), Some(b"v4".to_vec()));
+}
+
+fn test_put_return_prev<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    assert_eq!(cluster.must_put_return_prev(b"k1", b"v1"), None);
+    assert_eq!(cluster.must_put_return_prev(b"k1", b"v2"), Some(b"v1".to_vec()));
+    assert_eq!(cluster.get(b"k1"), Some(b"v2".to_vec()));
+
+    // The value written by a plain put is returned as well.
+    cluster.must_put(b"k1", b"v3");
+    assert_eq!(cluster.must_put_return_prev(b"k1", b"v4"), Some(b"v3".to_vec()));
+}
+
+fn test_cas<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    // The key doesn't exist yet.
+    let resp = cluster.must_cas(b"k1", None, b"v1");
+    assert!(resp.get_succeeded());
+    assert_eq!(cluster.get(b"k1"), Some(b"v1".to_vec()));
+
+    // A mismatched swap returns the current value and writes nothing.
+    let resp = cluster.must_cas(b"k1", Some(b"v0"), b"v2");
+    assert!(!resp.get_succeeded());
+    assert_eq!(resp.get_current(), b"v1");
+    let resp = cluster.must_cas(b"k1", None, b"v2");
+    assert!(!resp.get_succeeded());
+    assert_eq!(resp.get_current(), b"v1");
+    assert_eq!(cluster.get(b"k1"), Some(b"v1".to_vec()));
+
+    let resp = cluster.must_cas(b"k1", Some(b"v1"), b"v2");
+    assert!(resp.get_succeeded());
+    assert_eq!(cluster.get(b"k1"), Some(b"v2".to_vec()));
+
+    let resp = cluster.must_cas(b"k2", Some(b""), b"v");
+    assert!(!resp.get_succeeded());
+    assert!(!resp.has_current());
+    assert_eq!(cluster.get(b"k2"), None);
+}
+
+fn test_apply_metrics<T: Simulator>(cluster: &mut Cluster<T>) {
+    metric::init();
+    cluster.run();
+
+    let propose_to_apply = metric::timers("raftstore.propose_to_apply").len();
+    let apply_batch = metric::timers("raftstore.apply_batch").len();
+    cluster.must_put(b"k1", b"v1");
+    assert!(metric::timers("raftstore.propose_to_apply").len() > propose_to_apply);
+    assert!(metric::timers("raftstore.apply_batch").len() > apply_batch);
+}
+
+fn test_apply_sync_interval_restart<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.apply_sync_interval_ms = 100;
+    metric::init();
+    cluster.run();
+
+    let syncs = metric::counter("raftstore.apply_sync");
+    cluster.must_put(b"k1", b"v1");
+    // The applied writes are synced within the interval even if the region
+    // becomes idle.
+    thread::sleep(Duration::from_millis(300));
+    assert!(metric::counter("raftstore.apply_sync") > syncs);
+
+    let engine = cluster.get_engine(1);
+    let applied_index = || {
+        let state: RaftApplyState =
+            engine.get_msg(&keys::apply_state_key(1)).unwrap().unwrap();
+        state.get_applied_index()
+    };
+    let before = applied_index();
+
+    // The applied index never goes back after a restart. A stop is not a crash,
+    // the unsynced writes are not lost here.
+    cluster.stop_node(1);
+    assert_eq!(applied_index(), before);
+    cluster.run_node(1);
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&engine, b"k1", b"v1");
+    must_get_equal(&engine, b"k2", b"v2");
+    assert!(applied_index() > before);
+}
+
+fn test_check_quorum<T: Simulator>(cluster: &mut Cluster<T>, check_quorum: bool) {
+    cluster.cfg.store_cfg.raft_check_quorum = check_quorum;
+    cluster.run();
+    cluster.must_put(b"k1", b"v1");
+
+    let leader = cluster.leader_of_region(1).unwrap();
+    cluster.add_filter(Isolate::new(leader.get_store_id()));
+
+    // many election timeouts pass without hearing from the other peers.
+    let election_timeout = cluster.cfg.store_cfg.raft_base_tick_interval *
+                           cluster.cfg.store_cfg.raft_election_timeout_ticks as u64;
+    sleep_ms(election_timeout * 10);
+
+    // the isolated leader steps down only when it checks the quorum.
+    let detail = cluster.region_detail(1, leader.get_id());
+    let still_leader = detail.get_leader().get_id() == leader.get_id();
+    assert_eq!(still_leader, !check_quorum);
+}
+
 #[test]
 fn test_node_put() {
     let mut cluster = new_node_cluster(0, 1);
@@ -114,6 +269,60 @@ fn test_node_seek() {
     test_seek(&mut cluster);
 }
 
+#[test]
+fn test_node_batch_get() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_batch_get(&mut cluster);
+}
+
+#[test]
+fn test_node_append() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_append(&mut cluster);
+}
+
+#[test]
+fn test_node_put_return_prev() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_put_return_prev(&mut cluster);
+}
+
+#[test]
+fn test_node_cas() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_cas(&mut cluster);
+}
+
+#[test]
+fn test_node_apply_metrics() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_apply_metrics(&mut cluster);
+}
+
+#[test]
+fn test_node_apply_sync_interval_restart() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_apply_sync_interval_restart(&mut cluster);
+}
+
+#[test]
+fn test_server_apply_sync_interval_restart() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_apply_sync_interval_restart(&mut cluster);
+}
+
+#[test]
+fn test_node_check_quorum() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_check_quorum(&mut cluster, true);
+}
+
+#[test]
+fn test_node_no_check_quorum() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_check_quorum(&mut cluster, false);
+}
+
 #[test]
 fn test_server_put() {
     let mut cluster = new_server_cluster(0, 1);
@@ -131,3 +340,45 @@ fn test_server_seek() {
     let mut cluster = new_server_cluster(0, 1);
     test_seek(&mut cluster);
 }
+
+#[test]
+fn test_server_batch_get() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_batch_get(&mut cluster);
+}
+
+#[test]
+fn test_server_append() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_append(&mut cluster);
+}
+
+#[test]
+fn test_server_put_return_prev() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_put_return_prev(&mut cluster);
+}
+
+#[test]
+fn test_server_cas() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_cas(&mut cluster);
+}
+
+#[test]
+fn test_server_apply_metrics() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_apply_metrics(&mut cluster);
+}
+
+#[test]
+fn test_server_check_quorum() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_check_quorum(&mut cluster, true);
+}
+
+#[test]
+fn test_server_no_check_quorum() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_check_quorum(&mut cluster, false);
+}
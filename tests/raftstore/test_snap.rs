@@ -12,12 +12,19 @@
 // limitations under the License.
 
 
-use std::fs;
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use tikv::pd::PdClient;
+use tikv::raftstore::Result;
+use tikv::raftstore::store::{self, keys, SnapKey, Peekable};
+use tikv::util::HandyRwLock;
 use kvproto::raftpb::MessageType;
+use kvproto::raft_serverpb::{RaftMessage, RegionLocalState, PeerState};
 
-use super::transport_simulate::IsolateRegionStore;
+use super::transport_simulate::{IsolateRegionStore, Filter, FilterFactory};
 use super::cluster::{Cluster, Simulator};
 use super::node::new_node_cluster;
 use super::server::new_server_cluster;
@@ -160,3 +167,134 @@ fn test_server_snap_gc() {
     let mut cluster = new_server_cluster(0, 3);
     test_snap_gc(&mut cluster);
 }
+
+fn test_snap_gc_timeout_by_size<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.snap_mgr_gc_tick_interval = 50;
+    cluster.cfg.store_cfg.snap_gc_timeout = 1;
+    cluster.cfg.store_cfg.snap_gc_timeout_per_mb = 1;
+    cluster.cfg.store_cfg.max_snap_gc_timeout = 3;
+    cluster.run();
+
+    // synthetic sending snapshots that are never compacted by the raft log gc.
+    let snap_dir = cluster.get_snap_dir(1);
+    let snap_mgr = store::new_snap_mgr(snap_dir, None);
+    let write_snap = |key: &SnapKey, size: usize| {
+        let mut f = snap_mgr.rl().get_snap_file(key, true).unwrap();
+        f.write_all(&vec![0; size]).unwrap();
+        f.save().unwrap();
+        f
+    };
+    let small = write_snap(&SnapKey::new(1, 1000, 100000), 1024);
+    // its timeout is scaled to 1 + 10 secs, but capped to 3 secs.
+    let big = write_snap(&SnapKey::new(1, 1000, 100001), 10 * 1024 * 1024);
+
+    let mut tried_cnt = 0;
+    while small.exists() {
+        if tried_cnt > 100 {
+            panic!("small snapshot {} is not deleted", small.path().display());
+        }
+        tried_cnt += 1;
+        sleep_ms(20);
+    }
+    assert!(big.exists());
+
+    let mut tried_cnt = 0;
+    while big.exists() {
+        if tried_cnt > 200 {
+            panic!("big snapshot {} is not deleted", big.path().display());
+        }
+        tried_cnt += 1;
+        sleep_ms(20);
+    }
+}
+
+#[test]
+fn test_node_snap_gc_timeout_by_size() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_snap_gc_timeout_by_size(&mut cluster);
+}
+
+#[test]
+fn test_server_snap_gc_timeout_by_size() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_snap_gc_timeout_by_size(&mut cluster);
+}
+
+// Writes a broken snapshot file to the receiver before the snapshot is sent, so
+// applying the first snapshot received by the store fails.
+struct CorruptSnapshot {
+    store_id: u64,
+    snap_dir: String,
+    corrupted: Arc<AtomicBool>,
+}
+
+impl Filter for CorruptSnapshot {
+    fn before(&self, msg: &RaftMessage) -> bool {
+        if msg.get_message().get_msg_type() != MessageType::MsgSnapshot ||
+           msg.get_to_peer().get_store_id() != self.store_id ||
+           self.corrupted.load(Ordering::SeqCst) {
+            return false;
+        }
+        let key = SnapKey::from_snap(msg.get_message().get_snapshot()).unwrap();
+        let snap_mgr = store::new_snap_mgr(self.snap_dir.clone(), None);
+        let snap_file = snap_mgr.rl().get_snap_file(&key, false).unwrap();
+        let mut f = File::create(snap_file.path()).unwrap();
+        f.write_all(b"corrupted snapshot data").unwrap();
+        self.corrupted.store(true, Ordering::SeqCst);
+        false
+    }
+
+    fn after(&self, res: Result<()>) -> Result<()> {
+        res
+    }
+}
+
+struct CorruptSnapshotFactory {
+    store_id: u64,
+    snap_dir: String,
+    corrupted: Arc<AtomicBool>,
+}
+
+impl FilterFactory for CorruptSnapshotFactory {
+    fn generate(&self, _: u64) -> Vec<Box<Filter>> {
+        vec![box CorruptSnapshot {
+                 store_id: self.store_id,
+                 snap_dir: self.snap_dir.clone(),
+                 corrupted: self.corrupted.clone(),
+             }]
+    }
+}
+
+#[test]
+fn test_node_snap_apply_failure() {
+    // Only node cluster is tested, because the snapshot file is copied to the
+    // receiver directly here.
+    let mut cluster = new_node_cluster(0, 2);
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    cluster.must_put(b"k1", b"v1");
+
+    let corrupted = Arc::new(AtomicBool::new(false));
+    cluster.add_filter(CorruptSnapshotFactory {
+        store_id: 2,
+        snap_dir: cluster.get_snap_dir(2),
+        corrupted: corrupted.clone(),
+    });
+
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+
+    // The first snapshot fails to apply, store 2 must survive and receive
+    // the region again by a new snapshot.
+    let engine_2 = cluster.get_engine(2);
+    must_get_equal(&engine_2, b"k1", b"v1");
+    assert!(corrupted.load(Ordering::SeqCst));
+
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&engine_2, b"k2", b"v2");
+    // the failed peer is recreated rather than left as a tombstone.
+    let state: RegionLocalState = engine_2.get_msg(&keys::region_state_key(r1)).unwrap().unwrap();
+    assert_eq!(state.get_state(), PeerState::Normal);
+}
@@ -25,7 +25,8 @@ use super::util;
 use tikv::pd::PdClient;
 use tikv::raftstore::store::keys::data_key;
 use tikv::raftstore::store::engine::Iterable;
-use super::transport_simulate::IsolateRegionStore;
+use metric;
+use super::transport_simulate::{IsolateRegionStore, Direction};
 
 pub const REGION_MAX_SIZE: u64 = 50000;
 pub const REGION_SPLIT_SIZE: u64 = 30000;
@@ -88,6 +89,45 @@ fn test_server_base_split_region() {
     test_base_split_region(&mut cluster);
 }
 
+fn test_max_ready_regions_per_tick<T: Simulator>(cluster: &mut Cluster<T>) {
+    metric::init();
+    // handle only one region's raft ready per event loop iteration.
+    cluster.cfg.store_cfg.max_ready_regions_per_tick = 1;
+    cluster.run();
+
+    let pd_client = cluster.pd_client.clone();
+    for split_key in &[b"k1", b"k2", b"k3"] {
+        let region = pd_client.get_region(*split_key).unwrap();
+        cluster.must_split(&region, *split_key);
+    }
+
+    let yields = metric::counter("raftstore.raft_ready.yield");
+    // keep all the regions busy, the remaining ready must still be handled
+    // without waiting for other events.
+    for i in 0..10 {
+        for key in &["k0", "k1", "k2", "k3"] {
+            let key = format!("{}{}", key, i);
+            cluster.must_put(key.as_bytes(), b"v");
+        }
+    }
+    for id in cluster.engines.keys() {
+        util::must_get_equal(&cluster.engines[id], b"k39", b"v");
+    }
+    assert!(metric::counter("raftstore.raft_ready.yield") > yields);
+}
+
+#[test]
+fn test_node_max_ready_regions_per_tick() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_max_ready_regions_per_tick(&mut cluster);
+}
+
+#[test]
+fn test_server_max_ready_regions_per_tick() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_max_ready_regions_per_tick(&mut cluster);
+}
+
 /// Keep puting random kvs until specified size limit is reached.
 fn put_till_size<T: Simulator>(cluster: &mut Cluster<T>,
                                limit: u64,
@@ -192,6 +232,40 @@ fn test_server_auto_split_region() {
     test_auto_split_region(&mut cluster);
 }
 
+fn test_max_regions_per_store<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.store_cfg.split_region_check_tick_interval = 100;
+    cluster.cfg.store_cfg.region_max_size = REGION_MAX_SIZE;
+    cluster.cfg.store_cfg.region_split_size = REGION_SPLIT_SIZE;
+    cluster.cfg.store_cfg.max_regions_per_store = 1;
+    metric::init();
+
+    let mut range = 1..;
+    cluster.run();
+
+    let pd_client = cluster.pd_client.clone();
+    let region = pd_client.get_region(b"").unwrap();
+    let skipped = metric::counter("raftstore.split.skip_max_regions");
+
+    let max_key = put_till_size(cluster, REGION_MAX_SIZE * 2, &mut range);
+    thread::sleep(Duration::from_secs(1));
+
+    // The store already has a region, so the region must not be split.
+    assert_eq!(pd_client.get_region(&max_key).unwrap(), region);
+    assert!(metric::counter("raftstore.split.skip_max_regions") > skipped);
+}
+
+#[test]
+fn test_node_max_regions_per_store() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_max_regions_per_store(&mut cluster);
+}
+
+#[test]
+fn test_server_max_regions_per_store() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_max_regions_per_store(&mut cluster);
+}
+
 fn test_delay_split_region<T: Simulator>(cluster: &mut Cluster<T>) {
     // We use three nodes for this test.
     cluster.run();
@@ -496,3 +570,239 @@ fn test_node_split_region_diff_check() {
     let mut cluster = new_node_cluster(0, count);
     test_split_region_diff_check(&mut cluster);
 }
+
+fn test_snap_detect_split<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+
+    let snap = cluster.must_snap(b"k1");
+    assert!(snap.get_applied_index() > 0);
+    assert_eq!(snap.get_region_epoch(), snap.get_region().get_region_epoch());
+
+    let region = snap.get_region().clone();
+    cluster.must_split(&region, b"k2");
+
+    // a follow-up request with the epoch of the earlier snap must fail.
+    let req = util::new_request(region.get_id(),
+                                snap.get_region_epoch().clone(),
+                                vec![util::new_get_cmd(b"k1")]);
+    let resp = cluster.call_command_on_leader(req, Duration::from_secs(5)).unwrap();
+    assert!(resp.get_header().get_error().has_stale_epoch(),
+            format!("{:?} should be stale epoch", resp));
+
+    let new_snap = cluster.must_snap(b"k1");
+    assert!(new_snap.get_region_epoch().get_version() > snap.get_region_epoch().get_version());
+    assert!(new_snap.get_applied_index() > snap.get_applied_index());
+}
+
+fn test_stale_epoch_new_regions<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, util::new_peer(1, 1));
+
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+    let region = cluster.get_region(b"k1");
+    cluster.must_split(&region, b"k2");
+    let left = cluster.get_region(b"k1");
+    let right = cluster.get_region(b"k3");
+
+    // the stale epoch error carries both regions split from the old one.
+    let req = util::new_request(region.get_id(),
+                                region.get_region_epoch().clone(),
+                                vec![util::new_put_cmd(b"k1", b"v2")]);
+    let resp = cluster.call_command_on_leader(req, Duration::from_secs(5)).unwrap();
+    let err = resp.get_header().get_error();
+    assert!(err.has_stale_epoch(), "{:?} should be stale epoch", resp);
+    let new_regions = err.get_stale_epoch().get_new_regions();
+    assert_eq!(new_regions.len(), 2, "{:?}", new_regions);
+    for (r, expect) in new_regions.iter().zip(&[left, right]) {
+        assert_eq!(r.get_id(), expect.get_id());
+        assert_eq!(r.get_start_key(), expect.get_start_key());
+        assert_eq!(r.get_end_key(), expect.get_end_key());
+        assert_eq!(r.get_region_epoch(), expect.get_region_epoch());
+    }
+}
+
+#[test]
+fn test_node_stale_epoch_new_regions() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_stale_epoch_new_regions(&mut cluster);
+}
+
+#[test]
+fn test_server_stale_epoch_new_regions() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_stale_epoch_new_regions(&mut cluster);
+}
+
+#[test]
+fn test_node_snap_detect_split() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_snap_detect_split(&mut cluster);
+}
+
+#[test]
+fn test_server_snap_detect_split() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_snap_detect_split(&mut cluster);
+}
+
+fn test_apply_batch_with_split<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, util::new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+
+    // hold the logs on the leader, so they are committed and applied together.
+    cluster.add_filter(IsolateRegionStore::new(1, 1)
+        .msg_type(MessageType::MsgAppend)
+        .direction(Direction::Send));
+
+    let region = cluster.get_region(b"k1");
+    let epoch = region.get_region_epoch().clone();
+    let pd_client = cluster.pd_client.clone();
+    let new_region_id = pd_client.alloc_id().unwrap();
+    let new_peer_ids = region.get_peers().iter().map(|_| pd_client.alloc_id().unwrap()).collect();
+
+    let reqs = vec![util::new_request(1, epoch.clone(), vec![util::new_put_cmd(b"k1", b"v1")]),
+                    util::new_request(1, epoch.clone(), vec![util::new_put_cmd(b"k3", b"v3")]),
+                    util::new_admin_request(1,
+                                            &epoch,
+                                            util::new_split_region_cmd(b"k2",
+                                                                       new_region_id,
+                                                                       new_peer_ids))];
+    let mut events = vec![];
+    for mut req in reqs {
+        req.mut_header().set_peer(util::new_peer(1, 1));
+        events.push(cluster.async_command_on_store(1, req));
+    }
+    util::sleep_ms(100);
+    assert!(events.iter().all(|e| !e.is_set()));
+
+    cluster.clear_filters();
+    for finished in &events {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(5))));
+        let resp = finished.take().unwrap();
+        assert!(!resp.get_header().has_error(), format!("{:?}", resp));
+    }
+
+    for _ in 0..100 {
+        if cluster.get_region(b"k3").get_id() == new_region_id {
+            break;
+        }
+        util::sleep_ms(20);
+    }
+    let left = cluster.get_region(b"k1");
+    let right = cluster.get_region(b"k3");
+    assert_eq!(left.get_id(), 1);
+    assert_eq!(left.get_end_key(), b"k2");
+    assert_eq!(right.get_id(), new_region_id);
+    assert_eq!(right.get_start_key(), b"k2");
+
+    for engine in cluster.engines.values() {
+        util::must_get_equal(engine, b"k1", b"v1");
+        util::must_get_equal(engine, b"k3", b"v3");
+    }
+    cluster.must_put(b"k1", b"v11");
+    cluster.must_put(b"k3", b"v33");
+    assert_eq!(cluster.get(b"k1"), Some(b"v11".to_vec()));
+    assert_eq!(cluster.get(b"k3"), Some(b"v33".to_vec()));
+}
+
+#[test]
+fn test_node_apply_batch_with_split() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_apply_batch_with_split(&mut cluster);
+}
+
+#[test]
+fn test_server_apply_batch_with_split() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_apply_batch_with_split(&mut cluster);
+}
+
+fn test_write_during_split<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, util::new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+
+    // hold the logs on the leader, so the writes are proposed before the split
+    // is applied, and applied right after it.
+    cluster.add_filter(IsolateRegionStore::new(1, 1)
+        .msg_type(MessageType::MsgAppend)
+        .direction(Direction::Send));
+
+    let region = cluster.get_region(b"k1");
+    let epoch = region.get_region_epoch().clone();
+    let pd_client = cluster.pd_client.clone();
+    let new_region_id = pd_client.alloc_id().unwrap();
+    let new_peer_ids = region.get_peers().iter().map(|_| pd_client.alloc_id().unwrap()).collect();
+
+    let split = util::new_split_region_cmd(b"k2", new_region_id, new_peer_ids);
+    let reqs = vec![util::new_admin_request(1, &epoch, split),
+                    util::new_request(1, epoch.clone(), vec![util::new_put_cmd(b"k1", b"v1")]),
+                    util::new_request(1,
+                                      epoch.clone(),
+                                      vec![util::new_put_cmd(b"k1", b"v1"),
+                                           util::new_put_cmd(b"k2", b"v2")])];
+    let mut events = vec![];
+    for mut req in reqs {
+        req.mut_header().set_peer(util::new_peer(1, 1));
+        events.push(cluster.async_command_on_store(1, req));
+    }
+    util::sleep_ms(100);
+    cluster.clear_filters();
+
+    let mut resps = vec![];
+    for finished in &events {
+        assert!(finished.wait_timeout(Some(Duration::from_secs(5))));
+        resps.push(finished.take().unwrap());
+    }
+    assert!(!resps[0].get_header().has_error(), format!("{:?}", resps[0]));
+    // the writes proposed against the old epoch are rejected after the split.
+    for resp in &resps[1..] {
+        assert!(resp.get_header().get_error().has_stale_epoch(),
+                format!("{:?}", resp));
+    }
+
+    for _ in 0..100 {
+        if cluster.get_region(b"k3").get_id() == new_region_id {
+            break;
+        }
+        util::sleep_ms(20);
+    }
+    let left = cluster.get_region(b"k1");
+    assert_eq!(left.get_end_key(), b"k2");
+
+    // a command with a key out of the region is rejected as a whole.
+    let req = util::new_request(1,
+                                left.get_region_epoch().clone(),
+                                vec![util::new_put_cmd(b"k1", b"v1"),
+                                     util::new_put_cmd(b"k3", b"v3")]);
+    let resp = cluster.call_command_on_leader(req, Duration::from_secs(5)).unwrap();
+    assert!(resp.get_header().get_error().has_key_not_in_region(),
+            format!("{:?}", resp));
+
+    for engine in cluster.engines.values() {
+        util::must_get_none(engine, b"k1");
+        util::must_get_none(engine, b"k2");
+        util::must_get_none(engine, b"k3");
+    }
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+    assert_eq!(cluster.get_region(b"k3").get_id(), new_region_id);
+    assert_eq!(cluster.get(b"k3"), Some(b"v3".to_vec()));
+}
+
+#[test]
+fn test_node_write_during_split() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_write_during_split(&mut cluster);
+}
+
+#[test]
+fn test_server_write_during_split() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_write_during_split(&mut cluster);
+}
@@ -101,6 +101,47 @@ fn test_server_simple_store_stats() {
     test_simple_store_stats(&mut cluster);
 }
 
+fn test_region_flow<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+
+    cluster.cfg.store_cfg.pd_heartbeat_tick_interval = 20;
+    cluster.run();
+
+    let region_id = pd_client.get_region(b"").unwrap().get_id();
+    let last_flow = pd_client.get_region_flow(region_id);
+
+    // 10 keys with 2 bytes key and 5 bytes value.
+    for i in 0..10 {
+        cluster.must_put(format!("k{}", i).as_bytes(), b"value");
+    }
+
+    // wait the leader reports the flow.
+    for _ in 0..100 {
+        sleep_ms(20);
+
+        let flow = pd_client.get_region_flow(region_id);
+        if flow.written_keys >= last_flow.written_keys + 10 {
+            break;
+        }
+    }
+
+    let flow = pd_client.get_region_flow(region_id);
+    assert_eq!(flow.written_keys, last_flow.written_keys + 10);
+    assert_eq!(flow.written_bytes, last_flow.written_bytes + 70);
+}
+
+#[test]
+fn test_node_region_flow() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_region_flow(&mut cluster);
+}
+
+#[test]
+fn test_server_region_flow() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_region_flow(&mut cluster);
+}
+
 #[test]
 fn test_server_store_snap_stats() {
     let mut cluster = new_server_cluster(0, 2);
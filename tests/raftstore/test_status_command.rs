@@ -11,7 +11,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::time::Duration;
+
+use kvproto::raft_cmdpb::{StatusCmdType, ProgressState};
+use tikv::pd::PdClient;
+use tikv::raftstore::store::Msg;
+use tikv::raftstore::store::util::find_peer;
+use tikv::util::HandyRwLock;
+
 use super::server::*;
+use super::util::*;
 
 #[test]
 fn test_region_detail() {
@@ -37,3 +46,127 @@ fn test_region_detail() {
     assert!(region_detail.has_leader());
     assert_eq!(region_detail.get_leader(), &leader);
 }
+
+#[test]
+fn test_region_count() {
+    let count = 3;
+    let mut cluster = new_server_cluster(0, count);
+    cluster.run();
+
+    // split (-inf, +inf) -> (-inf, k2), [k2, +inf]
+    let region = cluster.pd_client.get_region(b"").unwrap();
+    cluster.must_split(&region, b"k2");
+    let right = cluster.pd_client.get_region(b"k3").unwrap();
+    assert!(right.get_id() != 1);
+
+    // store 1 leads region 1 and store 2 leads the right region.
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    let right_peer = find_peer(&right, 2).unwrap().clone();
+    cluster.must_transfer_leader(right.get_id(), right_peer);
+    // make sure the split is applied on all the stores.
+    cluster.must_put(b"k3", b"v3");
+    for store_id in 1..count + 1 {
+        must_get_equal(&cluster.get_engine(store_id as u64), b"k3", b"v3");
+    }
+
+    for (store_id, leader_count) in vec![(1, 1), (2, 1), (3, 0)] {
+        let region_count = cluster.region_count(store_id);
+        assert_eq!(region_count.get_leader_count(), leader_count);
+        assert_eq!(region_count.get_follower_count(), 2 - leader_count);
+        assert_eq!(region_count.get_applying_count(), 0);
+        assert_eq!(region_count.get_normal_count(), 2);
+    }
+}
+
+#[test]
+fn test_store_regions() {
+    let count = 3;
+    let mut cluster = new_server_cluster(0, count);
+    cluster.run();
+
+    // split (-inf, +inf) -> (-inf, k2), [k2, k4), [k4, +inf]
+    let region = cluster.pd_client.get_region(b"").unwrap();
+    cluster.must_split(&region, b"k2");
+    let region = cluster.pd_client.get_region(b"k3").unwrap();
+    cluster.must_split(&region, b"k4");
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    // make sure the splits are applied on all the stores.
+    cluster.must_put(b"k5", b"v5");
+    for store_id in 1..count + 1 {
+        must_get_equal(&cluster.get_engine(store_id as u64), b"k5", b"v5");
+    }
+
+    let expected: Vec<_> = vec![b"k1", b"k3", b"k5"]
+        .into_iter()
+        .map(|k| cluster.pd_client.get_region(k).unwrap())
+        .collect();
+    for store_id in 1..count + 1 {
+        let regions = cluster.store_regions(store_id as u64).take_regions().into_vec();
+        // the regions are ordered by end key.
+        assert_eq!(regions.len(), expected.len(), "{:?}", regions);
+        for (info, region) in regions.iter().zip(&expected) {
+            assert_eq!(info.get_region_id(), region.get_id());
+            assert_eq!(info.get_start_key(), region.get_start_key());
+            assert_eq!(info.get_end_key(), region.get_end_key());
+            assert_eq!(info.get_peer_count(), 3);
+        }
+        // store 1 leads region 1, and all the stores know it.
+        assert_eq!(regions[0].get_region_id(), 1);
+        assert_eq!(regions[0].get_leader_id(), 1);
+    }
+}
+
+#[test]
+fn test_region_progress() {
+    let count = 3;
+    let mut cluster = new_server_cluster(0, count);
+    cluster.run();
+
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    for i in 0..10 {
+        let (k, v) = (format!("k{}", i), format!("v{}", i));
+        cluster.must_put(k.as_bytes(), v.as_bytes());
+    }
+    for store_id in 1..count + 1 {
+        must_get_equal(&cluster.get_engine(store_id as u64), b"k9", b"v9");
+    }
+    // wait for the leader to receive the last append responses.
+    sleep_ms(100);
+
+    let mut resp = cluster.region_progress(1, new_peer(1, 1));
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    let mut status_resp = resp.take_status_response();
+    assert_eq!(status_resp.get_cmd_type(), StatusCmdType::RegionProgress);
+    let progresses = status_resp.take_region_progress().take_progresses().into_vec();
+    let ids: Vec<_> = progresses.iter().map(|p| p.get_peer_id()).collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    // all the followers have caught up with the leader.
+    let matched = progresses[0].get_matched();
+    for p in &progresses {
+        assert_eq!(p.get_matched(), matched, "{:?}", progresses);
+        assert_eq!(p.get_next(), matched + 1, "{:?}", progresses);
+        assert_eq!(p.get_state(), ProgressState::Replicate, "{:?}", progresses);
+    }
+
+    // followers don't track progress.
+    let resp = cluster.region_progress(1, new_peer(2, 2));
+    assert!(resp.get_header().get_error().has_not_leader(), "{:?}", resp);
+}
+
+#[test]
+fn test_store_ready() {
+    let mut cluster = new_server_cluster(0, 1);
+    cluster.run();
+
+    let ready = cluster.store_ready(1);
+    assert!(ready.get_ready());
+    assert_eq!(ready.get_region_count(), 1);
+
+    // The store can't transfer its leader out, so it keeps running till the
+    // deadline but is no longer ready.
+    let ch = cluster.sim.rl().get_store_sendch(1).unwrap();
+    ch.send(Msg::PrepareShutdown { timeout: Duration::from_secs(10) }).unwrap();
+    let ready = cluster.store_ready(1);
+    assert!(!ready.get_ready());
+    assert_eq!(ready.get_region_count(), 1);
+}
@@ -14,6 +14,9 @@
 use std::time::Duration;
 
 use kvproto::raft_serverpb;
+use tikv::pd::PdClient;
+use tikv::raftstore::store::{keys, Iterable};
+use tikv::raftstore::store::util::find_peer;
 
 use super::cluster::{Cluster, Simulator};
 use super::node::new_node_cluster;
@@ -68,11 +71,29 @@ fn test_tombstone<T: Simulator>(cluster: &mut Cluster<T>) {
 
     cluster.send_raft_msg(raft_msg).unwrap();
 
-    // We must get RegionNotFound error.
+    // We must get RegionNotFound error, tagged as tombstone.
     let region_status = new_status_request(r1, new_peer(2, 2), new_region_leader_cmd());
     let resp = cluster.call_command(region_status, Duration::from_secs(5)).unwrap();
     assert!(resp.get_header().get_error().has_region_not_found(),
             format!("region must not found, but got {:?}", resp));
+    assert_eq!(resp.get_header().get_error().get_message(),
+               "region is tombstone");
+
+    // Normal commands get the tombstone error too.
+    let epoch = cluster.get_region_epoch(r1);
+    let mut put = new_request(r1, epoch, vec![new_put_cmd(b"k4", b"v4")]);
+    put.mut_header().set_peer(new_peer(2, 2));
+    let resp = cluster.call_command(put, Duration::from_secs(5)).unwrap();
+    assert!(resp.get_header().get_error().has_region_not_found(),
+            format!("region must not found, but got {:?}", resp));
+    assert_eq!(resp.get_header().get_error().get_message(),
+               "region is tombstone");
+
+    // A region never created in the store is still reported as not found.
+    let region_status = new_status_request(1000, new_peer(2, 2), new_region_leader_cmd());
+    let resp = cluster.call_command(region_status, Duration::from_secs(5)).unwrap();
+    assert_eq!(resp.get_header().get_error().get_message(),
+               "region is not found");
 }
 
 #[test]
@@ -88,3 +109,60 @@ fn test_server_tombstone() {
     let mut cluster = new_server_cluster(0, count);
     test_tombstone(&mut cluster);
 }
+
+fn test_tombstone_restart<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer number check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+
+    // split (-inf, +inf) -> (-inf, k2), [k2, +inf]
+    let region = pd_client.get_region(b"").unwrap();
+    cluster.must_split(&region, b"k2");
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+    let engine_2 = cluster.get_engine(2);
+    must_get_equal(&engine_2, b"k1", b"v1");
+    must_get_equal(&engine_2, b"k3", b"v3");
+
+    // Remove the peer of the left region from store 2, it becomes tombstone.
+    let left = pd_client.get_region(b"k1").unwrap();
+    let right = pd_client.get_region(b"k3").unwrap();
+    let peer = find_peer(&left, 2).unwrap().clone();
+    pd_client.must_remove_peer(left.get_id(), peer);
+    must_get_none(&engine_2, b"k1");
+
+    cluster.stop_node(2);
+    cluster.run_node(2);
+
+    // Only the live region is indexed and loaded.
+    let mut indexed = vec![];
+    engine_2.scan(keys::REGION_INDEX_MIN_KEY,
+              keys::REGION_INDEX_MAX_KEY,
+              &mut |key, _| {
+                  indexed.push(keys::decode_region_index_key(key).unwrap());
+                  Ok(true)
+              })
+        .unwrap();
+    assert_eq!(indexed, vec![right.get_id()]);
+    let regions = cluster.store_regions(2).take_regions().into_vec();
+    assert_eq!(regions.len(), 1, "{:?}", regions);
+    assert_eq!(regions[0].get_region_id(), right.get_id());
+
+    cluster.must_put(b"k4", b"v4");
+    must_get_equal(&engine_2, b"k4", b"v4");
+}
+
+#[test]
+fn test_node_tombstone_restart() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_tombstone_restart(&mut cluster);
+}
+
+#[test]
+fn test_server_tombstone_restart() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_tombstone_restart(&mut cluster);
+}
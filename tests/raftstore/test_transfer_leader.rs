@@ -17,7 +17,12 @@ use super::transport_simulate::*;
 use super::node::new_node_cluster;
 use super::server::new_server_cluster;
 use kvproto::raftpb::MessageType;
-use std::time::Duration;
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
+use tikv::raftstore::store::Msg;
+use tikv::raftstore::store::util::find_peer;
+use tikv::util::HandyRwLock;
+use tikv::util::event::Event;
+use std::time::{Duration, Instant};
 
 fn test_transfer_leader<T: Simulator>(cluster: &mut Cluster<T>) {
     cluster.run();
@@ -160,3 +165,128 @@ fn test_node_transfer_leader_during_snapshot() {
     let mut cluster = new_server_cluster(0, 3);
     test_transfer_leader_during_snapshot(&mut cluster);
 }
+
+fn transfer_leader_and_wait<T: Simulator>(cluster: &mut Cluster<T>,
+                                          store_id: u64,
+                                          req: RaftCmdRequest,
+                                          timeout: Duration)
+                                          -> RaftCmdResponse {
+    let finished = Event::new();
+    let finished2 = finished.clone();
+    let ch = cluster.sim.rl().get_store_sendch(store_id).unwrap();
+    ch.send(Msg::TransferLeaderAndWait {
+            request: req,
+            timeout: timeout,
+            callback: box move |resp| {
+                finished2.set(resp);
+                Ok(())
+            },
+        })
+        .unwrap();
+    assert!(finished.wait_timeout(Some(timeout * 2)));
+    finished.take().unwrap()
+}
+
+fn test_transfer_leader_and_wait<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+
+    // the target never receives MsgTimeoutNow, so the callback must
+    // only be called after the timeout.
+    cluster.add_filter(IsolateRegionStore::new(1, 2)
+        .msg_type(MessageType::MsgTimeoutNow)
+        .direction(Direction::Recv));
+    let epoch = cluster.get_region_epoch(1);
+    let mut req = new_admin_request(1, &epoch, new_transfer_leader_cmd(new_peer(2, 2)));
+    req.mut_header().set_peer(new_peer(1, 1));
+    let resp = transfer_leader_and_wait(cluster, 1, req, Duration::from_millis(500));
+    assert!(resp.get_header().has_error(), format!("{:?}", resp));
+    cluster.reset_leader_of_region(1);
+    assert_eq!(cluster.leader_of_region(1), Some(new_peer(1, 1)));
+
+    cluster.clear_filters();
+    let epoch = cluster.get_region_epoch(1);
+    let mut req = new_admin_request(1, &epoch, new_transfer_leader_cmd(new_peer(2, 2)));
+    req.mut_header().set_peer(new_peer(1, 1));
+    let resp = transfer_leader_and_wait(cluster, 1, req, Duration::from_secs(3));
+    assert!(!resp.get_header().has_error(), format!("{:?}", resp));
+
+    // when the callback is called, 2 must be leader already.
+    let find_leader = new_status_request(1, new_peer(2, 2), new_region_leader_cmd());
+    let resp = cluster.call_command(find_leader, Duration::from_secs(5)).unwrap();
+    assert_eq!(resp.get_status_response().get_region_leader().get_leader(),
+               &new_peer(2, 2));
+}
+
+#[test]
+fn test_node_transfer_leader_and_wait() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_transfer_leader_and_wait(&mut cluster);
+}
+
+#[test]
+fn test_server_transfer_leader_and_wait() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_transfer_leader_and_wait(&mut cluster);
+}
+
+fn test_prepare_shutdown<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    // split (-inf, +inf) -> (-inf, k2), [k2, +inf], and store 1 leads both.
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, b"k2");
+    let right = cluster.get_region(b"k3");
+    let right_peer = find_peer(&right, 1).unwrap().clone();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_transfer_leader(right.get_id(), right_peer);
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+
+    let ch = cluster.sim.rl().get_store_sendch(1).unwrap();
+    let start = Instant::now();
+    ch.send(Msg::PrepareShutdown { timeout: Duration::from_secs(5) }).unwrap();
+
+    // the leaders must move before an election could even be started.
+    let election_timeout = {
+        let store_cfg = &cluster.cfg.store_cfg;
+        Duration::from_millis(store_cfg.raft_base_tick_interval *
+                              store_cfg.raft_election_timeout_ticks as u64)
+    };
+    for region_id in vec![1, right.get_id()] {
+        loop {
+            let find_leader =
+                new_status_request(region_id, new_peer(2, 0), new_region_leader_cmd());
+            let resp = cluster.call_command(find_leader, Duration::from_secs(5)).unwrap();
+            let leader = resp.get_status_response().get_region_leader().get_leader();
+            if leader.get_store_id() == 2 || leader.get_store_id() == 3 {
+                break;
+            }
+            assert!(start.elapsed() < election_timeout,
+                    "region {} is still led by {:?} after {:?}",
+                    region_id,
+                    leader,
+                    start.elapsed());
+            sleep_ms(10);
+        }
+    }
+
+    cluster.stop_node(1);
+    cluster.reset_leader_of_region(1);
+    cluster.reset_leader_of_region(right.get_id());
+    cluster.must_put(b"k1", b"v11");
+    cluster.must_put(b"k3", b"v33");
+}
+
+#[test]
+fn test_node_prepare_shutdown() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_prepare_shutdown(&mut cluster);
+}
+
+#[test]
+fn test_server_prepare_shutdown() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_prepare_shutdown(&mut cluster);
+}
@@ -113,6 +113,12 @@ pub fn new_put_cmd(key: &[u8], value: &[u8]) -> Request {
     cmd
 }
 
+pub fn new_put_return_prev_cmd(key: &[u8], value: &[u8]) -> Request {
+    let mut cmd = new_put_cmd(key, value);
+    cmd.mut_put().set_return_prev(true);
+    cmd
+}
+
 pub fn new_get_cmd(key: &[u8]) -> Request {
     let mut cmd = Request::new();
     cmd.set_cmd_type(CmdType::Get);
@@ -120,6 +126,39 @@ pub fn new_get_cmd(key: &[u8]) -> Request {
     cmd
 }
 
+pub fn new_batch_get_cmd(keys: &[&[u8]]) -> Request {
+    let mut cmd = Request::new();
+    cmd.set_cmd_type(CmdType::BatchGet);
+    let keys = keys.iter().map(|k| k.to_vec()).collect();
+    cmd.mut_batch_get().set_keys(protobuf::RepeatedField::from_vec(keys));
+    cmd
+}
+
+pub fn new_snap_cmd() -> Request {
+    let mut cmd = Request::new();
+    cmd.set_cmd_type(CmdType::Snap);
+    cmd
+}
+
+pub fn new_append_cmd(key: &[u8], value: &[u8]) -> Request {
+    let mut cmd = Request::new();
+    cmd.set_cmd_type(CmdType::Append);
+    cmd.mut_append().set_key(key.to_vec());
+    cmd.mut_append().set_value(value.to_vec());
+    cmd
+}
+
+pub fn new_cas_cmd(key: &[u8], expected: Option<&[u8]>, value: &[u8]) -> Request {
+    let mut cmd = Request::new();
+    cmd.set_cmd_type(CmdType::CAS);
+    cmd.mut_cas().set_key(key.to_vec());
+    if let Some(expected) = expected {
+        cmd.mut_cas().set_expected(expected.to_vec());
+    }
+    cmd.mut_cas().set_value(value.to_vec());
+    cmd
+}
+
 pub fn new_delete_cmd(key: &[u8]) -> Request {
     let mut cmd = Request::new();
     cmd.set_cmd_type(CmdType::Delete);
@@ -150,6 +189,30 @@ pub fn new_region_detail_cmd() -> StatusRequest {
     cmd
 }
 
+pub fn new_region_count_cmd() -> StatusRequest {
+    let mut cmd = StatusRequest::new();
+    cmd.set_cmd_type(StatusCmdType::RegionCount);
+    cmd
+}
+
+pub fn new_region_progress_cmd() -> StatusRequest {
+    let mut cmd = StatusRequest::new();
+    cmd.set_cmd_type(StatusCmdType::RegionProgress);
+    cmd
+}
+
+pub fn new_store_regions_cmd() -> StatusRequest {
+    let mut cmd = StatusRequest::new();
+    cmd.set_cmd_type(StatusCmdType::StoreRegions);
+    cmd
+}
+
+pub fn new_store_ready_cmd() -> StatusRequest {
+    let mut cmd = StatusRequest::new();
+    cmd.set_cmd_type(StatusCmdType::StoreReady);
+    cmd
+}
+
 pub fn new_region_leader_cmd() -> StatusRequest {
     let mut cmd = StatusRequest::new();
     cmd.set_cmd_type(StatusCmdType::RegionLeader);
@@ -172,6 +235,26 @@ pub fn new_transfer_leader_cmd(peer: metapb::Peer) -> AdminRequest {
     cmd
 }
 
+pub fn new_split_region_cmd(split_key: &[u8],
+                            new_region_id: u64,
+                            new_peer_ids: Vec<u64>)
+                            -> AdminRequest {
+    let mut cmd = AdminRequest::new();
+    cmd.set_cmd_type(AdminCmdType::Split);
+    cmd.mut_split().set_split_key(split_key.to_vec());
+    cmd.mut_split().set_new_region_id(new_region_id);
+    cmd.mut_split().set_new_peer_ids(new_peer_ids);
+    cmd
+}
+
+pub fn new_change_peer_cmd(change_type: ConfChangeType, peer: metapb::Peer) -> AdminRequest {
+    let mut cmd = AdminRequest::new();
+    cmd.set_cmd_type(AdminCmdType::ChangePeer);
+    cmd.mut_change_peer().set_change_type(change_type);
+    cmd.mut_change_peer().set_peer(peer);
+    cmd
+}
+
 pub fn new_compact_log_cmd(index: u64) -> AdminRequest {
     let mut cmd = AdminRequest::new();
     cmd.set_cmd_type(AdminCmdType::CompactLog);
@@ -186,6 +269,12 @@ pub fn new_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
     peer
 }
 
+pub fn new_learner_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
+    let mut peer = new_peer(store_id, peer_id);
+    peer.set_is_learner(true);
+    peer
+}
+
 
 pub fn new_store(store_id: u64, addr: String) -> metapb::Store {
     let mut store = metapb::Store::new();
@@ -226,6 +315,17 @@ pub fn new_pd_add_change_peer(region: &metapb::Region,
     Some(new_pd_change_peer(ConfChangeType::AddNode, peer))
 }
 
+pub fn new_pd_add_learner_change_peer(region: &metapb::Region,
+                                      peer: metapb::Peer)
+                                      -> Option<RegionHeartbeatResponse> {
+    if let Some(p) = find_peer(region, peer.get_store_id()) {
+        assert_eq!(p.get_id(), peer.get_id());
+        return None;
+    }
+
+    Some(new_pd_change_peer(ConfChangeType::AddLearnerNode, peer))
+}
+
 pub fn new_pd_remove_change_peer(region: &metapb::Region,
                                  peer: metapb::Peer)
                                  -> Option<RegionHeartbeatResponse> {
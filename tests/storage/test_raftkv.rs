@@ -10,7 +10,7 @@ use raftstore::server::new_server_cluster_with_cfs;
 #[test]
 fn test_raftkv() {
     let count = 1;
-    let mut cluster = new_server_cluster_with_cfs(0, count, &["cf"]);
+    let mut cluster = new_server_cluster_with_cfs(0, count, &["default", "lock", "cf"]);
     cluster.run();
 
     // make sure leader has been elected.
@@ -30,6 +30,7 @@ fn test_raftkv() {
     seek(&ctx, storage.as_ref().as_ref());
     near_seek(&ctx, storage.as_ref().as_ref());
     cf(&ctx, storage.as_ref().as_ref());
+    seek_to_first_last(&ctx, storage.as_ref().as_ref());
     empty_write(&ctx, storage.as_ref().as_ref());
     // TODO: test multiple node
 }
@@ -158,6 +159,47 @@ fn cf(ctx: &Context, engine: &Engine) {
     assert_none_cf(ctx, engine, "cf", b"key");
 }
 
+fn assert_seek_to_first_last(cursor: &mut Cursor,
+                             first: (&[u8], &[u8]),
+                             last: (&[u8], &[u8])) {
+    assert!(cursor.seek_to_first());
+    assert_eq!(cursor.key(), &*bytes::encode_bytes(first.0));
+    assert_eq!(cursor.value(), first.1);
+    assert!(!cursor.prev());
+    assert!(cursor.seek_to_last());
+    assert_eq!(cursor.key(), &*bytes::encode_bytes(last.0));
+    assert_eq!(cursor.value(), last.1);
+    assert!(!cursor.next());
+}
+
+fn seek_to_first_last(ctx: &Context, engine: &Engine) {
+    let snapshot = engine.snapshot(ctx).unwrap();
+    assert!(!snapshot.iter_cf("cf").unwrap().seek_to_first());
+    assert!(!snapshot.iter_cf("cf").unwrap().seek_to_last());
+
+    // default cf still holds `x` written by `get_put`.
+    let keys: &[&[u8]] = &[b"m", b"a", b"zz"];
+    for k in keys {
+        must_put(ctx, engine, k, b"v");
+    }
+    let cf_keys: &[&[u8]] = &[b"a", b"b\xff", b"b"];
+    for (i, k) in cf_keys.iter().enumerate() {
+        must_put_cf(ctx, engine, "cf", k, format!("{}", i).as_bytes());
+    }
+    let snapshot = engine.snapshot(ctx).unwrap();
+    let mut cursor = snapshot.iter().unwrap();
+    assert_seek_to_first_last(cursor.as_mut(), (b"a", b"v"), (b"zz", b"v"));
+    let mut cursor = snapshot.iter_cf("cf").unwrap();
+    assert_seek_to_first_last(cursor.as_mut(), (b"a", b"0"), (b"b\xff", b"1"));
+
+    for k in keys {
+        must_delete(ctx, engine, k);
+    }
+    for k in cf_keys {
+        must_delete_cf(ctx, engine, "cf", k);
+    }
+}
+
 fn empty_write(ctx: &Context, engine: &Engine) {
     engine.write(ctx, vec![]).unwrap();
 }
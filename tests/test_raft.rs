@@ -86,6 +86,18 @@ pub fn new_test_raft(id: u64,
     Interface::new(Raft::new(&new_test_config(id, peers, election, heartbeat), storage))
 }
 
+pub fn new_test_learner_raft(id: u64,
+                             peers: Vec<u64>,
+                             learners: Vec<u64>,
+                             election: usize,
+                             heartbeat: usize,
+                             storage: MemStorage)
+                             -> Interface {
+    let mut cfg = new_test_config(id, peers, election, heartbeat);
+    cfg.learners = learners;
+    Interface::new(Raft::new(&cfg, storage))
+}
+
 fn read_messages<T: Storage>(raft: &mut Raft<T>) -> Vec<Message> {
     raft.msgs.drain(..).collect()
 }
@@ -149,9 +161,15 @@ impl Interface {
     fn initial(&mut self, id: u64, ids: &[u64]) {
         if self.raft.is_some() {
             self.id = id;
+            let learners = self.learners();
             self.prs = HashMap::with_capacity(ids.len());
             for id in ids {
-                self.prs.insert(*id, Progress { ..Default::default() });
+                let is_learner = learners.contains(id);
+                self.prs.insert(*id,
+                                Progress {
+                                    is_learner: is_learner,
+                                    ..Default::default()
+                                });
             }
             self.reset(0);
         }
@@ -953,6 +971,60 @@ fn test_pass_election_timeout() {
     }
 }
 
+#[test]
+fn test_randomized_election_timeout_range() {
+    let mut cfg = new_test_config(1, vec![1], 10, 1);
+    cfg.min_election_tick = 12;
+    cfg.max_election_tick = 15;
+    let mut sm = Raft::new(&cfg, new_storage());
+    for _ in 0..1000 {
+        sm.reset_randomized_election_timeout();
+        let timeout = sm.get_randomized_election_timeout();
+        assert!(timeout >= 12 && timeout < 15, "timeout {} out of range", timeout);
+    }
+
+    // without randomization, the timeout is always the election timeout.
+    cfg.min_election_tick = 10;
+    cfg.max_election_tick = 11;
+    let mut sm = Raft::new(&cfg, new_storage());
+    for _ in 0..100 {
+        sm.reset_randomized_election_timeout();
+        assert_eq!(sm.get_randomized_election_timeout(), 10);
+    }
+}
+
+#[test]
+fn test_randomized_election_elects_leader() {
+    let election = 10;
+    for _ in 0..50 {
+        let peers = (1..4)
+            .map(|id| {
+                let cfg = new_test_config(id, vec![1, 2, 3], election, 1);
+                Some(Interface::new(Raft::new(&cfg, new_storage())))
+            })
+            .collect();
+        let mut nt = Network::new(peers);
+
+        // all the peers start at the same time, the random timeouts must
+        // break the tie quickly.
+        let mut ticks = 0;
+        while nt.peers.values().all(|p| p.state != StateRole::Leader) {
+            assert!(ticks < 10 * election, "no leader after {} ticks", ticks);
+            for id in 1..4 {
+                let msgs = {
+                    let p = nt.peers.get_mut(&id).unwrap();
+                    p.tick();
+                    p.read_messages()
+                };
+                nt.send(msgs);
+            }
+            ticks += 1;
+        }
+        let max_term = nt.peers.values().map(|p| p.term).max().unwrap();
+        assert!(max_term <= 3, "too many terms {} for electing a leader", max_term);
+    }
+}
+
 // ensure that the Step function ignores the message from old term and does not pass it to the
 // actual stepX function.
 #[test]
@@ -1851,6 +1923,60 @@ fn test_remove_node() {
     assert_eq!(r.nodes(), vec![]);
 }
 
+// test_add_learner tests that addLearner adds a non-voting node, and a
+// following addNode promotes it.
+#[test]
+fn test_add_learner() {
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.add_learner(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learners(), vec![2]);
+
+    r.add_node(2);
+    assert_eq!(r.nodes(), vec![1, 2]);
+    assert_eq!(r.learners(), vec![]);
+}
+
+// test_learner_election_timeout tests that a learner never campaigns.
+#[test]
+fn test_learner_election_timeout() {
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    assert!(!n2.promotable());
+    let timeout = n2.get_election_timeout();
+    for _ in 0..2 * timeout {
+        n2.tick();
+    }
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.msgs.is_empty());
+
+    n2.step(new_message(1, 2, MessageType::MsgTimeoutNow, 0)).expect("");
+    assert_eq!(n2.state, StateRole::Follower);
+}
+
+// test_learner_log_replication tests that a learner receives the logs, but
+// is neither asked for votes nor counted in the commit quorum.
+#[test]
+fn test_learner_log_replication() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&2].vote, INVALID_ID);
+
+    nt.isolate(2);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, 2);
+    assert_eq!(nt.peers[&2].raft_log.committed, 1);
+
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(nt.peers[&2].raft_log.committed, 2);
+}
+
 #[test]
 fn test_promotable() {
     let id = 1u64;
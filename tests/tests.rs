@@ -33,6 +33,7 @@ extern crate kvproto;
 extern crate tipb;
 extern crate time;
 extern crate rustc_serialize;
+extern crate cadence;
 
 use std::env;
 
@@ -45,6 +46,7 @@ mod raftstore;
 mod coprocessor;
 mod storage;
 mod util;
+mod metric;
 mod pd;
 
 #[test]